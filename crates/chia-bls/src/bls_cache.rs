@@ -82,7 +82,22 @@ impl BlsCache {
         pks_msgs: impl IntoIterator<Item = (Pk, Msg)>,
         sig: &Signature,
     ) -> bool {
+        self.aggregate_verify_with_pairings(pks_msgs, sig).0
+    }
+
+    /// Like aggregate_verify(), but also returns the number of pairings that
+    /// were computed. That's one per public key and message pair not found in
+    /// the cache, plus one for the signature itself.
+    pub fn aggregate_verify_with_pairings<Pk: Borrow<PublicKey>, Msg: AsRef<[u8]>>(
+        &self,
+        pks_msgs: impl IntoIterator<Item = (Pk, Msg)>,
+        sig: &Signature,
+    ) -> (bool, u64) {
+        let mut items: u64 = 0;
+        let mut misses: u64 = 0;
         let iter = pks_msgs.into_iter().map(|(pk, msg)| -> GTElement {
+            items += 1;
+
             // Hash pubkey + message
             let mut hasher = Sha256::new();
             let mut aug_msg = pk.borrow().to_bytes().to_vec();
@@ -96,6 +111,7 @@ impl BlsCache {
             }
 
             // Otherwise, we need to calculate the pairing and add it to the cache.
+            misses += 1;
             let aug_hash = hash_to_g2(&aug_msg);
 
             let pairing = aug_hash.pair(pk.borrow());
@@ -103,7 +119,11 @@ impl BlsCache {
             pairing
         });
 
-        aggregate_verify_gt(sig, iter)
+        let result = aggregate_verify_gt(sig, iter);
+
+        // the signature is only paired if there's something to compare it to
+        let pairings = if items == 0 { 0 } else { misses + 1 };
+        (result, pairings)
     }
 
     pub fn update(&self, aug_msg: &[u8], gt: GTElement) {
@@ -256,6 +276,44 @@ pub mod tests {
         assert_eq!(bls_cache.len(), 1);
     }
 
+    #[test]
+    fn test_aggregate_verify_with_pairings() {
+        let bls_cache = BlsCache::default();
+
+        let sk = SecretKey::from_seed(&[0; 32]);
+        let pk = sk.public_key();
+        let msg1 = [106; 32];
+        let msg2 = [107; 32];
+
+        // no pairs means no pairings
+        let pks_msgs: [(PublicKey, [u8; 32]); 0] = [];
+        assert_eq!(
+            bls_cache.aggregate_verify_with_pairings(pks_msgs, &Signature::default()),
+            (true, 0)
+        );
+
+        // one pairing for the pair and one for the signature
+        let sig1 = sign(&sk, msg1);
+        assert_eq!(
+            bls_cache.aggregate_verify_with_pairings([(pk, msg1)], &sig1),
+            (true, 2)
+        );
+
+        // the first pair is found in the cache, the second one is not
+        let mut agg_sig = sig1.clone();
+        agg_sig.aggregate(&sign(&sk, msg2));
+        assert_eq!(
+            bls_cache.aggregate_verify_with_pairings([(pk, msg1), (pk, msg2)], &agg_sig),
+            (true, 2)
+        );
+
+        // now both are cached, only the signature is paired
+        assert_eq!(
+            bls_cache.aggregate_verify_with_pairings([(pk, msg1), (pk, msg2)], &agg_sig),
+            (true, 1)
+        );
+    }
+
     #[test]
     fn test_cache() {
         let bls_cache = BlsCache::default();
@@ -19,7 +19,7 @@ pub use public_key::{PublicKey, hash_to_g1, hash_to_g1_with_dst};
 pub use secret_key::SecretKey;
 pub use signature::{
     Signature, aggregate, aggregate_pairing, aggregate_verify, aggregate_verify_gt, hash_to_g2,
-    hash_to_g2_with_dst, sign, sign_raw, verify,
+    hash_to_g2_with_dst, missing_signers, sign, sign_raw, verify,
 };
 
 pub type G1Element = PublicKey;
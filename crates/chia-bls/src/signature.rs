@@ -9,6 +9,7 @@ use pyo3::prelude::*;
 #[cfg(feature = "py-bindings")]
 use pyo3::types::PyType;
 use std::borrow::Borrow;
+use std::collections::HashSet;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::io::Cursor;
@@ -343,6 +344,19 @@ where
     ret
 }
 
+// returns the keys in `required` that are not in `have`, preserving the order
+// of `required`. An aggregate signature doesn't reveal which keys contributed
+// to it, so `have` (the keys whose signatures have been aggregated so far)
+// needs to be tracked by the caller.
+pub fn missing_signers(required: &[PublicKey], have: &[PublicKey]) -> Vec<PublicKey> {
+    let have: HashSet<&PublicKey> = have.iter().collect();
+    required
+        .iter()
+        .filter(|pk| !have.contains(pk))
+        .copied()
+        .collect()
+}
+
 // verify a signature given a single public key and message using the augmented
 // scheme, i.e. the public key is pre-pended to the message before hashed to G2.
 pub fn verify<Msg: AsRef<[u8]>>(sig: &Signature, key: &PublicKey, msg: Msg) -> bool {
@@ -833,6 +847,22 @@ mod tests {
         SecretKey::from_seed(&data)
     }
 
+    #[test]
+    fn test_missing_signers() {
+        let mut rng = StdRng::seed_from_u64(1337);
+        let pk1 = random_sk(&mut rng).public_key();
+        let pk2 = random_sk(&mut rng).public_key();
+        let pk3 = random_sk(&mut rng).public_key();
+
+        assert_eq!(missing_signers(&[pk1, pk2], &[pk1]), vec![pk2]);
+        assert_eq!(missing_signers(&[pk1, pk2], &[pk2]), vec![pk1]);
+        assert_eq!(missing_signers(&[pk1, pk2], &[pk2, pk1]), vec![]);
+        assert_eq!(missing_signers(&[pk1, pk2], &[]), vec![pk1, pk2]);
+        // keys that aren't required don't matter
+        assert_eq!(missing_signers(&[pk1, pk2], &[pk3]), vec![pk1, pk2]);
+        assert_eq!(missing_signers(&[], &[pk3]), vec![]);
+    }
+
     #[test]
     fn test_aggregate_signature_separate_msg() {
         let mut rng = StdRng::seed_from_u64(1337);
@@ -29,7 +29,14 @@ fuzz_target!(|data: &[u8]| {
 
     #[allow(clippy::match_same_arms)]
     match (r1, r2) {
-        (Err(e), Ok(_)) if e.error_code() == ErrorCode::CostExceeded => {
+        (Err(e), Ok(_))
+            if matches!(
+                e.error_code(),
+                ErrorCode::CostExceeded
+                    | ErrorCode::ExecutionCostExceeded
+                    | ErrorCode::ConditionCostExceeded
+            ) =>
+        {
             // Since run_block_generator2 cost less, it's not a problem if the
             // original generator runs out of cost while the rust implementation
             // succeeds. This is part of its features.
@@ -15,6 +15,7 @@ use clvmr::chia_dialect::ChiaDialect;
 use clvmr::reduction::Reduction;
 use clvmr::run_program::run_program;
 use clvmr::serde::node_from_bytes_backrefs;
+use std::collections::HashSet;
 
 /// Run a *trusted* block generator and return its additions and removals. This
 /// function does not validate the block, it is assumed to be valid.
@@ -142,6 +143,97 @@ where
     Ok((additions, removals))
 }
 
+/// The changes to the coin set when reorging from one block to another, at
+/// the same height. Coins created (or spent) by both blocks are not affected
+/// by the reorg, and don't appear in the delta.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StateDelta {
+    /// Coins spent by the old block, but not by the new one. They must be
+    /// marked as unspent again.
+    pub unspent: Vec<(Bytes32, Coin)>,
+    /// Coins created by the old block, but not by the new one. They must be
+    /// removed from the coin set.
+    pub rolled_back: Vec<Coin>,
+    /// Coins spent by the new block, but not by the old one.
+    pub spent: Vec<(Bytes32, Coin)>,
+    /// Coins created by the new block, but not by the old one, with hints.
+    pub added: Vec<(Coin, Option<Bytes>)>,
+}
+
+/// Computes the changes to the coin set when rolling back the block whose
+/// additions and removals are `old` and applying the one whose additions and
+/// removals are `new`, as returned by additions_and_removals(). The coins in
+/// each list of the delta are in the same order as in the input.
+#[allow(clippy::type_complexity)]
+pub fn state_delta(
+    old: &(Vec<(Coin, Option<Bytes>)>, Vec<(Bytes32, Coin)>),
+    new: &(Vec<(Coin, Option<Bytes>)>, Vec<(Bytes32, Coin)>),
+) -> StateDelta {
+    let (old_additions, old_removals) = old;
+    let (new_additions, new_removals) = new;
+
+    let old_added: HashSet<Bytes32> = old_additions.iter().map(|(c, _)| c.coin_id()).collect();
+    let new_added: HashSet<Bytes32> = new_additions.iter().map(|(c, _)| c.coin_id()).collect();
+    let old_spent: HashSet<Bytes32> = old_removals.iter().map(|(id, _)| *id).collect();
+    let new_spent: HashSet<Bytes32> = new_removals.iter().map(|(id, _)| *id).collect();
+
+    StateDelta {
+        unspent: old_removals
+            .iter()
+            .filter(|(id, _)| !new_spent.contains(id))
+            .copied()
+            .collect(),
+        rolled_back: old_additions
+            .iter()
+            .filter(|(c, _)| !new_added.contains(&c.coin_id()))
+            .map(|(c, _)| *c)
+            .collect(),
+        spent: new_removals
+            .iter()
+            .filter(|(id, _)| !old_spent.contains(id))
+            .copied()
+            .collect(),
+        added: new_additions
+            .iter()
+            .filter(|(c, _)| !old_added.contains(&c.coin_id()))
+            .cloned()
+            .collect(),
+    }
+}
+
+/// The result of comparing two lists of additions, as returned by
+/// reconcile_additions().
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AdditionDiff {
+    /// Coins only in the first list.
+    pub only_mine: Vec<Coin>,
+    /// Coins only in the second list.
+    pub only_theirs: Vec<Coin>,
+    /// Coins in both lists.
+    pub common: Vec<Coin>,
+}
+
+/// Compares two lists of additions for the same block, e.g. as computed locally
+/// and as reported by a full node. Coins are matched by coin ID. The coins in
+/// each list of the diff are in the same order as in the input, with `common`
+/// following the order of `mine`.
+pub fn reconcile_additions(mine: &[Coin], theirs: &[Coin]) -> AdditionDiff {
+    let my_ids: HashSet<Bytes32> = mine.iter().map(Coin::coin_id).collect();
+    let their_ids: HashSet<Bytes32> = theirs.iter().map(Coin::coin_id).collect();
+
+    let (common, only_mine) = mine.iter().partition(|c| their_ids.contains(&c.coin_id()));
+
+    AdditionDiff {
+        only_mine,
+        only_theirs: theirs
+            .iter()
+            .filter(|c| !my_ids.contains(&c.coin_id()))
+            .copied()
+            .collect(),
+        common,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -150,7 +242,6 @@ mod test {
     use crate::run_block_generator::run_block_generator2;
     use chia_bls::Signature;
     use rstest::rstest;
-    use std::collections::HashSet;
 
     #[rstest]
     #[case("new-agg-sigs")]
@@ -335,4 +426,91 @@ mod test {
         assert_eq!(additions.len(), 1);
         assert!(additions[0].1.is_none(), "pair hint should be ignored");
     }
+
+    #[test]
+    fn test_state_delta() {
+        let coin = |n: u8| Coin::new([n; 32].into(), [0x11; 32].into(), u64::from(n));
+        let removal = |n: u8| (coin(n).coin_id(), coin(n));
+        let hint = Some(Bytes::from(vec![0x22; 32]));
+
+        // both blocks spend coin 2 and create coin 12 from it
+        let old = (
+            vec![(coin(11), None), (coin(12), hint.clone())],
+            vec![removal(1), removal(2)],
+        );
+        let new = (
+            vec![(coin(12), hint.clone()), (coin(13), hint.clone())],
+            vec![removal(2), removal(3)],
+        );
+
+        assert_eq!(
+            state_delta(&old, &new),
+            StateDelta {
+                unspent: vec![removal(1)],
+                rolled_back: vec![coin(11)],
+                spent: vec![removal(3)],
+                added: vec![(coin(13), hint.clone())],
+            }
+        );
+
+        // reorging to the same block changes nothing
+        assert_eq!(state_delta(&old, &old), StateDelta::default());
+
+        // reorging to an empty block rolls back everything
+        let empty = (vec![], vec![]);
+        assert_eq!(
+            state_delta(&old, &empty),
+            StateDelta {
+                unspent: vec![removal(1), removal(2)],
+                rolled_back: vec![coin(11), coin(12)],
+                spent: vec![],
+                added: vec![],
+            }
+        );
+        assert_eq!(
+            state_delta(&empty, &new),
+            StateDelta {
+                unspent: vec![],
+                rolled_back: vec![],
+                spent: vec![removal(2), removal(3)],
+                added: vec![(coin(12), hint.clone()), (coin(13), hint)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_reconcile_additions() {
+        let coin = |n: u8| Coin::new([n; 32].into(), [0x11; 32].into(), u64::from(n));
+
+        let mine = [coin(1), coin(2), coin(3)];
+        let theirs = [coin(3), coin(4), coin(2)];
+
+        assert_eq!(
+            reconcile_additions(&mine, &theirs),
+            AdditionDiff {
+                only_mine: vec![coin(1)],
+                only_theirs: vec![coin(4)],
+                common: vec![coin(2), coin(3)],
+            }
+        );
+        assert_eq!(
+            reconcile_additions(&theirs, &mine),
+            AdditionDiff {
+                only_mine: vec![coin(4)],
+                only_theirs: vec![coin(1)],
+                common: vec![coin(3), coin(2)],
+            }
+        );
+
+        // identical lists have nothing to reconcile
+        assert_eq!(
+            reconcile_additions(&mine, &mine),
+            AdditionDiff {
+                only_mine: vec![],
+                only_theirs: vec![],
+                common: mine.to_vec(),
+            }
+        );
+        assert_eq!(reconcile_additions(&[], &[]), AdditionDiff::default());
+    }
 }
@@ -2,11 +2,91 @@ use clvmr::allocator::Allocator;
 
 use crate::flags::ConsensusFlags;
 
+/// The heap-size limit (in bytes) of the allocators created by
+/// make_allocator() with the LIMIT_HEAP flag.
+pub const HEAP_LIMIT: usize = 500_000_000;
+
+/// The number of atoms an Allocator can hold, regardless of the heap-size
+/// limit. This is the same as clvmr's (private) limit.
+pub const MAX_NUM_ATOMS: usize = 62_500_000;
+
+/// The number of pairs an Allocator can hold, regardless of the heap-size
+/// limit. This is the same as clvmr's (private) limit.
+pub const MAX_NUM_PAIRS: usize = 62_500_000;
+
 /// Construct an Allocator with a heap-size limit or not, depending on the flags.
 pub fn make_allocator(flags: ConsensusFlags) -> Allocator {
+    make_allocator_with_limit(flags, HEAP_LIMIT)
+}
+
+/// The heap-size limit of an allocator created by
+/// make_allocator_with_limit(flags, limit).
+pub fn heap_limit(flags: ConsensusFlags, limit: usize) -> usize {
     if flags.contains(ConsensusFlags::LIMIT_HEAP) {
-        Allocator::new_limited(500_000_000)
+        limit
     } else {
-        Allocator::new_limited(u32::MAX as usize)
+        u32::MAX as usize
+    }
+}
+
+/// Like make_allocator(), but with the heap-size limit applied by the
+/// LIMIT_HEAP flag set to `limit` (in bytes).
+pub fn make_allocator_with_limit(flags: ConsensusFlags, limit: usize) -> Allocator {
+    Allocator::new_limited(heap_limit(flags, limit))
+}
+
+/// The number of atoms and pairs, and the number of bytes of atom data, used
+/// by an Allocator. These are the quantities the heap limits apply to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AllocatorUsage {
+    pub atoms: usize,
+    pub pairs: usize,
+    pub heap_bytes: usize,
+}
+
+/// Returns the current usage of `a`. An Allocator doesn't free anything (except
+/// when restoring a checkpoint), so after running a block generator this is
+/// (approximately) the peak usage, and indicates how close the block got to
+/// the limit set by make_allocator_with_limit().
+pub fn allocator_usage(a: &Allocator) -> AllocatorUsage {
+    AllocatorUsage {
+        atoms: a.atom_count(),
+        pairs: a.pair_count(),
+        heap_bytes: a.heap_size(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(ConsensusFlags::LIMIT_HEAP, 1_000_000, 999_000, true)]
+    #[case(ConsensusFlags::LIMIT_HEAP, 1_000_000, 1_001_000, false)]
+    #[case(ConsensusFlags::LIMIT_HEAP, 10_000_000, 1_001_000, true)]
+    #[case(ConsensusFlags::empty(), 1_000_000, 1_001_000, true)]
+    fn test_make_allocator_with_limit(
+        #[case] flags: ConsensusFlags,
+        #[case] limit: usize,
+        #[case] atom_size: usize,
+        #[case] expect_ok: bool,
+    ) {
+        let mut a = make_allocator_with_limit(flags, limit);
+        let buf = vec![0x55_u8; atom_size];
+        assert_eq!(a.new_atom(&buf).is_ok(), expect_ok);
+    }
+
+    #[test]
+    fn test_max_num_atoms_and_pairs() {
+        let mut a = make_allocator(ConsensusFlags::empty());
+        let atoms = a.atom_count();
+        let pairs = a.pair_count();
+        a.add_ghost_atom(MAX_NUM_ATOMS - atoms)
+            .expect("add_ghost_atom");
+        a.add_ghost_pair(MAX_NUM_PAIRS - pairs)
+            .expect("add_ghost_pair");
+        assert!(a.add_ghost_atom(1).is_err());
+        assert!(a.add_ghost_pair(1).is_err());
     }
 }
@@ -0,0 +1,1519 @@
+use crate::allocator::make_allocator;
+use crate::conditions::{MempoolVisitor, ParseState, SpendBundleConditions, process_single_spend};
+use crate::consensus_constants::ConsensusConstants;
+use crate::flags::{ConsensusFlags, MEMPOOL_MODE};
+use crate::owned_conditions::{OwnedSpendBundleConditions, OwnedSpendConditions};
+use crate::run_block_generator::{
+    get_coinspends_for_trusted_block, get_coinspends_with_conditions_for_trusted_block,
+    run_block_generator, run_block_generator2, subtract_cost,
+};
+use crate::validation_error::{ErrorCode, ValidationErr};
+use chia_bls::Signature;
+use chia_protocol::{Bytes32, Coin, CoinSpend, Program};
+use chia_traits::Streamable;
+use clvmr::chia_dialect::ChiaDialect;
+use clvmr::reduction::Reduction;
+use clvmr::run_program::run_program;
+use clvmr::serde::{node_from_bytes_backrefs, node_to_bytes};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+/// Runs a single spend in isolation and parses its conditions. Any check that
+/// depends on other spends in the block (announcements, concurrent spends,
+/// fees) is not performed.
+fn check_single_spend(
+    coin_spend: &CoinSpend,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<(), ValidationErr> {
+    let mut a = make_allocator(flags);
+    let puzzle = node_from_bytes_backrefs(&mut a, coin_spend.puzzle_reveal.as_ref())?;
+    let solution = node_from_bytes_backrefs(&mut a, coin_spend.solution.as_ref())?;
+    let dialect = ChiaDialect::new(flags.to_clvm_flags());
+
+    let mut cost_left = constants.max_block_cost_clvm;
+    let Reduction(clvm_cost, conditions) =
+        run_program(&mut a, &dialect, puzzle, solution, cost_left)?;
+    subtract_cost(&mut cost_left, clvm_cost)?;
+
+    let parent_id = a.new_atom(coin_spend.coin.parent_coin_info.as_ref())?;
+    let puzzle_hash = a.new_atom(coin_spend.coin.puzzle_hash.as_ref())?;
+    let amount = a.new_number(coin_spend.coin.amount.into())?;
+
+    let mut ret = SpendBundleConditions::default();
+    let mut state = ParseState::default();
+    process_single_spend::<MempoolVisitor>(
+        &a,
+        &mut ret,
+        &mut state,
+        parent_id,
+        puzzle_hash,
+        amount,
+        conditions,
+        flags,
+        &mut cost_left,
+        clvm_cost,
+        constants,
+    )?;
+    Ok(())
+}
+
+/// Identifies the spends that make a block non-standard. The block is first
+/// validated under the consensus `flags`. If it's valid, every spend is run
+/// again, on its own, with `MEMPOOL_MODE` added to the flags. The index (into
+/// the block's spend list) and error of every spend that fails is returned.
+/// Only the rules that apply to individual spends are checked in mempool mode,
+/// block-level limits, such as the number of spends, are not.
+pub fn mempool_only_failures<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf> + Clone>(
+    program: &[u8],
+    block_refs: I,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    constants: &ConsensusConstants,
+) -> Result<Vec<(usize, ErrorCode)>, ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    run_block_generator2(
+        program,
+        block_refs.clone(),
+        constants.max_block_cost_clvm,
+        flags,
+        signature,
+        None,
+        constants,
+    )?;
+
+    let generator = Program::from(program);
+    let coin_spends = get_coinspends_for_trusted_block(constants, &generator, block_refs, flags)?;
+
+    let mempool_flags = flags | MEMPOOL_MODE | ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+    let mut ret = Vec::new();
+    for (idx, coin_spend) in coin_spends.iter().enumerate() {
+        if let Err(e) = check_single_spend(coin_spend, mempool_flags, constants) {
+            ret.push((idx, e.error_code()));
+        }
+    }
+    Ok(ret)
+}
+
+/// The IDs of all coins the block generator spends more than once, in the
+/// order they are first spent. Unlike validating the block, which fails on the
+/// first double spend, this reports all of them. The spends are not validated,
+/// the generator is only run to extract them.
+pub fn duplicate_spent_coins<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<Vec<Bytes32>, ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let generator = Program::from(program);
+    let coin_spends = get_coinspends_for_trusted_block(constants, &generator, block_refs, flags)?;
+
+    let mut spent = HashSet::<Bytes32>::new();
+    let mut ret = Vec::new();
+    for coin_spend in &coin_spends {
+        let coin_id = coin_spend.coin.coin_id();
+        if !spent.insert(coin_id) && !ret.contains(&coin_id) {
+            ret.push(coin_id);
+        }
+    }
+    Ok(ret)
+}
+
+/// The Shannon entropy, in bits, of the condition opcodes used by the spends in
+/// the block generator. A block using a single kind of condition has an
+/// entropy of 0, and one using `n` kinds of conditions, equally often, has an
+/// entropy of `log2(n)`. A block without conditions has an entropy of 0.
+/// The spends are not validated, the generator is only run to extract them.
+pub fn condition_diversity<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<f64, ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let generator = Program::from(program);
+    let spends =
+        get_coinspends_with_conditions_for_trusted_block(constants, &generator, block_refs, flags)?;
+
+    let mut histogram = HashMap::<u32, u64>::new();
+    for (_coin_spend, conditions) in &spends {
+        for (opcode, _args) in conditions {
+            *histogram.entry(*opcode).or_default() += 1;
+        }
+    }
+
+    let total = histogram.values().sum::<u64>() as f64;
+    Ok(histogram
+        .values()
+        .map(|count| {
+            let p = *count as f64 / total;
+            -p * p.log2()
+        })
+        .sum())
+}
+
+/// What to do with conditions whose opcodes are reserved for future use, by
+/// check_future_conditions().
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutureConditionPolicy {
+    /// Any use of a reserved opcode fails with InvalidConditionOpcode.
+    Reject,
+    /// Every use of a reserved opcode costs this much.
+    Charge(u64),
+}
+
+/// Checks the conditions of the spends in the block generator against the
+/// opcodes in `reserved`, as if they were reserved for future use (e.g. by a
+/// soft-fork that hasn't been rolled out yet), applying `policy` to them.
+/// Returns the total cost charged for reserved conditions (which is 0 if there
+/// are none, or with the Reject policy). The spends are not validated, the
+/// generator is only run to extract them.
+pub fn check_future_conditions<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    reserved: RangeInclusive<u32>,
+    policy: FutureConditionPolicy,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<u64, ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let generator = Program::from(program);
+    let spends =
+        get_coinspends_with_conditions_for_trusted_block(constants, &generator, block_refs, flags)?;
+
+    let mut cost: u64 = 0;
+    for (_coin_spend, conditions) in &spends {
+        for (opcode, _args) in conditions {
+            if !reserved.contains(opcode) {
+                continue;
+            }
+            match policy {
+                FutureConditionPolicy::Reject => {
+                    return Err(ValidationErr::Err(ErrorCode::InvalidConditionOpcode));
+                }
+                FutureConditionPolicy::Charge(condition_cost) => {
+                    cost = cost
+                        .checked_add(condition_cost)
+                        .ok_or(ValidationErr::Err(ErrorCode::ConditionCostExceeded))?;
+                }
+            }
+        }
+    }
+    Ok(cost)
+}
+
+/// Runs the block generator under two sets of flags and returns the difference
+/// in cost between them, i.e. `cost(flags_a) - cost(flags_b)`. The block must
+/// be valid under both sets of flags.
+pub fn cost_delta<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf> + Clone>(
+    program: &[u8],
+    block_refs: I,
+    flags_a: ConsensusFlags,
+    flags_b: ConsensusFlags,
+    signature: &Signature,
+    constants: &ConsensusConstants,
+) -> Result<i64, ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let cost_a = cost_under_flags(program, block_refs.clone(), flags_a, signature, constants)?;
+    let cost_b = cost_under_flags(program, block_refs, flags_b, signature, constants)?;
+    Ok(cost_a as i64 - cost_b as i64)
+}
+
+/// Runs the block generator under `flags` and returns its cost. The flags need
+/// not be the ones the block was validated with, which allows projecting the
+/// cost of a historical block under a proposed rule change. Fails if the block
+/// is invalid under `flags`.
+pub fn cost_under_flags<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    constants: &ConsensusConstants,
+) -> Result<u64, ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let (_, conds) = run_block_generator2(
+        program,
+        block_refs,
+        constants.max_block_cost_clvm,
+        flags,
+        signature,
+        None,
+        constants,
+    )?;
+    Ok(conds.cost)
+}
+
+/// The ways a block may be interpreted differently before and after a fork.
+/// See assert_fork_neutral().
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForkDifference {
+    /// The block is valid under one set of flags but not the other, or fails
+    /// with different errors. `None` means the block is valid.
+    Validity {
+        pre_fork: Option<ErrorCode>,
+        post_fork: Option<ErrorCode>,
+    },
+    /// The block has a different number of spends
+    SpendCount { pre_fork: usize, post_fork: usize },
+    /// The spend at `index` has different conditions
+    Spend {
+        index: usize,
+        pre_fork: Box<OwnedSpendConditions>,
+        post_fork: Box<OwnedSpendConditions>,
+    },
+    /// The conditions that apply to the block as a whole differ, e.g. the
+    /// reserve fee or the absolute time-locks
+    Bundle {
+        pre_fork: Box<OwnedSpendBundleConditions>,
+        post_fork: Box<OwnedSpendBundleConditions>,
+    },
+}
+
+// clears the fields that are expected to change across a fork, i.e. cost and
+// resource usage. The CREATE_COIN conditions are sorted since their order is
+// not deterministic
+fn normalize_spend(mut spend: OwnedSpendConditions) -> OwnedSpendConditions {
+    spend.execution_cost = 0;
+    spend.condition_cost = 0;
+    spend.create_coin.sort();
+    spend
+}
+
+fn normalize_bundle(mut conds: OwnedSpendBundleConditions) -> OwnedSpendBundleConditions {
+    conds.spends = Vec::new();
+    conds.cost = 0;
+    conds.execution_cost = 0;
+    conds.condition_cost = 0;
+    conds.num_atoms = 0;
+    conds.num_pairs = 0;
+    conds.heap_size = 0;
+    conds.validated_signature = false;
+    // whether the simple generator rule applies depends on the flags
+    conds.simple_generator = false;
+    conds
+}
+
+/// Runs the block generator under the flags in effect before a fork
+/// (`pre_flags`) and after it (`post_flags`), and checks that the block is
+/// interpreted the same way under both. The block must be valid under both, or
+/// invalid with the same error, and its spends must have the same conditions.
+/// Only the cost is allowed to differ. The first difference found is returned.
+#[allow(clippy::too_many_arguments)]
+pub fn assert_fork_neutral<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf> + Clone>(
+    program: &[u8],
+    block_refs: I,
+    pre_flags: ConsensusFlags,
+    post_flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+    signature: &Signature,
+) -> Result<(), ForkDifference>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let run = |flags, block_refs| {
+        run_block_generator2(
+            program,
+            block_refs,
+            constants.max_block_cost_clvm,
+            flags,
+            signature,
+            None,
+            constants,
+        )
+        .map(|(a, conds)| OwnedSpendBundleConditions::from(&a, conds))
+    };
+
+    let (pre_fork, post_fork) = match (
+        run(pre_flags, block_refs.clone()),
+        run(post_flags, block_refs),
+    ) {
+        (Ok(pre_fork), Ok(post_fork)) => (pre_fork, post_fork),
+        (Err(pre_fork), Err(post_fork)) if pre_fork.error_code() == post_fork.error_code() => {
+            return Ok(());
+        }
+        (pre_fork, post_fork) => {
+            return Err(ForkDifference::Validity {
+                pre_fork: pre_fork.err().map(|e| e.error_code()),
+                post_fork: post_fork.err().map(|e| e.error_code()),
+            });
+        }
+    };
+
+    if pre_fork.spends.len() != post_fork.spends.len() {
+        return Err(ForkDifference::SpendCount {
+            pre_fork: pre_fork.spends.len(),
+            post_fork: post_fork.spends.len(),
+        });
+    }
+
+    for (index, (pre_spend, post_spend)) in
+        pre_fork.spends.iter().zip(&post_fork.spends).enumerate()
+    {
+        let pre_spend = normalize_spend(pre_spend.clone());
+        let post_spend = normalize_spend(post_spend.clone());
+        if pre_spend != post_spend {
+            return Err(ForkDifference::Spend {
+                index,
+                pre_fork: Box::new(pre_spend),
+                post_fork: Box::new(post_spend),
+            });
+        }
+    }
+
+    let pre_fork = normalize_bundle(pre_fork);
+    let post_fork = normalize_bundle(post_fork);
+    if pre_fork != post_fork {
+        return Err(ForkDifference::Bundle {
+            pre_fork: Box::new(pre_fork),
+            post_fork: Box::new(post_fork),
+        });
+    }
+    Ok(())
+}
+
+/// Re-serializes the block generator in its canonical form, i.e. without
+/// back-references, runs both the original and the canonical generator, and
+/// checks that they produce the same spends and conditions. Only the cost is
+/// allowed to differ, since it depends on the size of the serialized
+/// generator. The canonical generator is run without a cost limit, since
+/// back-references may make the original smaller. If the original generator is
+/// invalid, its error is returned. The signature is not validated.
+pub fn verify_canonical_roundtrip<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf> + Clone>(
+    program: &[u8],
+    block_refs: I,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<(), ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let canonical = {
+        let mut a = make_allocator(flags);
+        let generator = node_from_bytes_backrefs(&mut a, program)?;
+        node_to_bytes(&a, generator)?
+    };
+
+    let flags = flags | ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+    let run = |program: &[u8], max_cost, block_refs| {
+        run_block_generator2(
+            program,
+            block_refs,
+            max_cost,
+            flags,
+            &Signature::default(),
+            None,
+            constants,
+        )
+        .map(|(a, conds)| OwnedSpendBundleConditions::from(&a, conds))
+    };
+
+    let original = run(program, constants.max_block_cost_clvm, block_refs.clone())?;
+    let canonical = run(&canonical, u64::MAX, block_refs)
+        .map_err(|_| ValidationErr::Err(ErrorCode::CanonicalRoundtripMismatch))?;
+
+    let same_spends = original.spends.len() == canonical.spends.len()
+        && original
+            .spends
+            .iter()
+            .zip(&canonical.spends)
+            .all(|(a, b)| normalize_spend(a.clone()) == normalize_spend(b.clone()));
+    let mut original = normalize_bundle(original);
+    let mut canonical = normalize_bundle(canonical);
+    original.deserialization_cost = 0;
+    canonical.deserialization_cost = 0;
+    if !same_spends || original != canonical {
+        return Err(ValidationErr::Err(ErrorCode::CanonicalRoundtripMismatch));
+    }
+    Ok(())
+}
+
+// true if both results are failures, or if both are successful with the same
+// conditions, ignoring cost. The errors don't need to match, since
+// run_block_generator2() validates conditions after each spend rather than
+// after all spends
+fn generators_agree(
+    a: &Result<OwnedSpendBundleConditions, ValidationErr>,
+    b: &Result<OwnedSpendBundleConditions, ValidationErr>,
+) -> bool {
+    match (a, b) {
+        (Err(_), Err(_)) => true,
+        (Ok(a), Ok(b)) => {
+            a.spends.len() == b.spends.len()
+                && a.spends
+                    .iter()
+                    .zip(&b.spends)
+                    .all(|(a, b)| normalize_spend(a.clone()) == normalize_spend(b.clone()))
+                && normalize_bundle(a.clone()) == normalize_bundle(b.clone())
+        }
+        _ => false,
+    }
+}
+
+// true if the error codes match, or if the conditions match (including cost)
+fn same_result(
+    a: &Result<OwnedSpendBundleConditions, ValidationErr>,
+    b: &Result<OwnedSpendBundleConditions, ValidationErr>,
+) -> bool {
+    match (a, b) {
+        (Err(a), Err(b)) => a.error_code() == b.error_code(),
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Finds the smallest cost limit at which run_block_generator() and
+/// run_block_generator2() both produce the result they produce when limited
+/// only by `max_block_cost_clvm`. Since run_block_generator() charges for the
+/// generator ROM, it may fail with CostExceeded when run_block_generator2()
+/// succeeds. At, and above, the returned cost limit, the two agree. Returns
+/// `None` if they don't agree even at `max_block_cost_clvm`.
+pub fn min_agreeing_cost<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf> + Clone>(
+    program: &[u8],
+    block_refs: I,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+    signature: &Signature,
+) -> Option<u64>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let run = move |max_cost| {
+        let r1 = run_block_generator(
+            program,
+            block_refs.clone(),
+            max_cost,
+            flags,
+            signature,
+            None,
+            constants,
+        )
+        .map(|(a, conds)| OwnedSpendBundleConditions::from(&a, conds));
+        let r2 = run_block_generator2(
+            program,
+            block_refs.clone(),
+            max_cost,
+            flags,
+            signature,
+            None,
+            constants,
+        )
+        .map(|(a, conds)| OwnedSpendBundleConditions::from(&a, conds));
+        (r1, r2)
+    };
+
+    let max_cost = constants.max_block_cost_clvm;
+    let (final1, final2) = run(max_cost);
+    if !generators_agree(&final1, &final2) {
+        return None;
+    }
+
+    // once a generator produces its final result, raising the cost limit
+    // won't change it. So we can binary search for the lowest limit where
+    // both do
+    let mut low = 0;
+    let mut high = max_cost;
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let (r1, r2) = run(mid);
+        if same_result(&r1, &final1) && same_result(&r2, &final2) {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    Some(low)
+}
+
+/// The cost that can still be added to a block before reaching
+/// `max_block_cost`. Returns 0 if the block already exceeds it.
+pub fn remaining_cost_budget(conditions: &OwnedSpendBundleConditions, max_block_cost: u64) -> u64 {
+    max_block_cost.saturating_sub(conditions.cost)
+}
+
+/// The number of (public key, message) pairs, i.e. BLS pairings, that adding
+/// the spends in `conditions` to a block adds to its signature validation.
+/// This counts every AGG_SIG_* condition, including AGG_SIG_UNSAFE.
+pub fn marginal_signature_work(conditions: &OwnedSpendBundleConditions) -> usize {
+    conditions.agg_sig_unsafe.len()
+        + conditions
+            .spends
+            .iter()
+            .map(|spend| {
+                spend.agg_sig_me.len()
+                    + spend.agg_sig_parent.len()
+                    + spend.agg_sig_puzzle.len()
+                    + spend.agg_sig_amount.len()
+                    + spend.agg_sig_puzzle_amount.len()
+                    + spend.agg_sig_parent_amount.len()
+                    + spend.agg_sig_parent_puzzle.len()
+            })
+            .sum::<usize>()
+}
+
+/// The number of AGG_SIG_* conditions (as counted by marginal_signature_work())
+/// per unit of cost of the spends in `conditions`. Returns 0 if the spends have
+/// no cost.
+pub fn signature_density(conditions: &OwnedSpendBundleConditions) -> f64 {
+    if conditions.cost == 0 {
+        return 0.0;
+    }
+    marginal_signature_work(conditions) as f64 / conditions.cost as f64
+}
+
+/// The net value moved into (positive) or out of (negative) every puzzle hash
+/// by the spends in `conditions`. Created coins are counted as additions and
+/// spent coins as removals. Puzzle hashes whose additions and removals cancel
+/// out are included, with a value of 0.
+pub fn value_flow_by_puzzle_hash(
+    conditions: &OwnedSpendBundleConditions,
+) -> HashMap<Bytes32, i128> {
+    let mut ret = HashMap::<Bytes32, i128>::new();
+    for spend in &conditions.spends {
+        *ret.entry(spend.puzzle_hash).or_default() -= i128::from(spend.coin_amount);
+        for (puzzle_hash, amount, _hint) in &spend.create_coin {
+            *ret.entry(*puzzle_hash).or_default() += i128::from(*amount);
+        }
+    }
+    ret
+}
+
+/// Like value_flow_by_puzzle_hash(), but only includes the puzzle hashes
+/// whose value changed, i.e. that gained or lost value. For auditing, the
+/// result can be compared against the expected changes. Puzzle hashes not in
+/// the result are balanced.
+pub fn value_imbalance(conditions: &OwnedSpendBundleConditions) -> HashMap<Bytes32, i128> {
+    let mut ret = value_flow_by_puzzle_hash(conditions);
+    ret.retain(|_puzzle_hash, value| *value != 0);
+    ret
+}
+
+/// The net change in the number of unspent coins caused by the spends in
+/// `conditions`, i.e. the number of coins created minus the number of coins
+/// spent. An ephemeral coin, created and spent in the same block, is counted
+/// both ways and nets to zero. The reward coins of a block are not included.
+pub fn coin_set_delta(conditions: &OwnedSpendBundleConditions) -> i64 {
+    conditions
+        .spends
+        .iter()
+        .map(|spend| spend.create_coin.len() as i64 - 1)
+        .sum()
+}
+
+/// The total value of the coins created with `burn_puzzle_hash` by the spends
+/// in `conditions`. Coins sent to a burn address can never be spent. Like
+/// addition_amount, the sum may exceed 64 bits.
+pub fn burned_value(conditions: &OwnedSpendBundleConditions, burn_puzzle_hash: &Bytes32) -> u128 {
+    conditions
+        .spends
+        .iter()
+        .flat_map(|spend| spend.create_coin.iter())
+        .filter(|(puzzle_hash, _amount, _hint)| puzzle_hash == burn_puzzle_hash)
+        .map(|(_puzzle_hash, amount, _hint)| u128::from(*amount))
+        .sum()
+}
+
+/// The indices of the spends in `conditions` that recreate the coin they
+/// spend, i.e. that create exactly one coin, with the same puzzle hash and
+/// amount as the coin being spent. Hints are not considered.
+pub fn noop_spends(conditions: &OwnedSpendBundleConditions) -> Vec<usize> {
+    conditions
+        .spends
+        .iter()
+        .enumerate()
+        .filter(|(_, spend)| {
+            matches!(
+                spend.create_coin.as_slice(),
+                [(puzzle_hash, amount, _hint)]
+                    if *puzzle_hash == spend.puzzle_hash && *amount == spend.coin_amount
+            )
+        })
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// The total size, in bytes, of all coins created by the spends in
+/// `conditions`, in their streamable serialization. Hints are not included.
+pub fn additions_serialized_size(conditions: &OwnedSpendBundleConditions) -> usize {
+    let mut ret = 0;
+    for spend in &conditions.spends {
+        for (puzzle_hash, amount, _hint) in &spend.create_coin {
+            let coin = Coin::new(spend.coin_id, *puzzle_hash, *amount);
+            ret += coin.to_bytes().expect("serialize Coin").len();
+        }
+    }
+    ret
+}
+
+/// The coin IDs of all coins created by the spends in `conditions`, in the
+/// order they were created.
+pub fn additions_ids(conditions: &OwnedSpendBundleConditions) -> Vec<Bytes32> {
+    conditions
+        .spends
+        .iter()
+        .flat_map(|spend| {
+            spend
+                .create_coin
+                .iter()
+                .map(|(puzzle_hash, amount, _hint)| {
+                    Coin::new(spend.coin_id, *puzzle_hash, *amount).coin_id()
+                })
+        })
+        .collect()
+}
+
+/// Like additions_ids(), but the coin IDs are computed in parallel, on the
+/// rayon thread pool. The order of the returned IDs is the same.
+#[cfg(feature = "rayon")]
+pub fn additions_ids_parallel(conditions: &OwnedSpendBundleConditions) -> Vec<Bytes32> {
+    conditions
+        .spends
+        .par_iter()
+        .flat_map_iter(|spend| {
+            spend
+                .create_coin
+                .iter()
+                .map(|(puzzle_hash, amount, _hint)| {
+                    Coin::new(spend.coin_id, *puzzle_hash, *amount).coin_id()
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus_constants::TEST_CONSTANTS;
+    use crate::opcodes::{
+        ASSERT_HEIGHT_ABSOLUTE, ASSERT_SECONDS_ABSOLUTE, CREATE_COIN, GENERIC_CONDITION_COST,
+        REMARK, RESERVE_FEE, SPEND_COST,
+    };
+    use crate::solution_generator::solution_generator;
+    use chia_protocol::Bytes;
+    use clvm_traits::ToClvm;
+    use clvm_utils::{tree_hash_atom, tree_hash_from_bytes};
+    use clvmr::serde::node_to_bytes_backrefs;
+    use clvmr::{Allocator, NodePtr};
+    use rstest::rstest;
+
+    const IDENTITY_PUZZLE: &[u8] = &[1];
+
+    // builds a generator spending one coin per condition list. The identity
+    // puzzle is used, so the solution is returned as the conditions.
+    // solution_generator() reverses the order of the spends, so we pass them
+    // in reverse, to have the block's spends in the same order as the input
+    fn make_generator(conditions: &[Vec<(u32, u64)>]) -> Vec<u8> {
+        let puzzle_hash = Bytes32::from(tree_hash_atom(IDENTITY_PUZZLE).to_bytes());
+
+        let mut a = Allocator::new();
+        let solutions: Vec<Vec<u8>> = conditions
+            .iter()
+            .map(|conds| {
+                let conds: Vec<(u32, (u64, ()))> =
+                    conds.iter().map(|(op, arg)| (*op, (*arg, ()))).collect();
+                let node = conds.to_clvm(&mut a).unwrap();
+                node_to_bytes(&a, node).unwrap()
+            })
+            .collect();
+
+        let spends = solutions.iter().enumerate().rev().map(|(i, solution)| {
+            let mut parent = [0u8; 32];
+            parent[0..4].copy_from_slice(&(i as u32).to_be_bytes());
+            (
+                Coin::new(parent.into(), puzzle_hash, 1000),
+                IDENTITY_PUZZLE,
+                solution.as_slice(),
+            )
+        });
+        solution_generator(spends).expect("solution_generator")
+    }
+
+    #[rstest]
+    // all spends are standard
+    #[case(&[vec![], vec![(REMARK as u32, 1)]], &[])]
+    // the second spend has an unknown condition
+    #[case(&[vec![], vec![(1337, 1)]], &[(1, ErrorCode::InvalidConditionOpcode)])]
+    // the first and third spends have unknown conditions
+    #[case(
+        &[vec![(1337, 1)], vec![(REMARK as u32, 1)], vec![(0xffff, 0)]],
+        &[(0, ErrorCode::InvalidConditionOpcode), (2, ErrorCode::InvalidConditionOpcode)]
+    )]
+    fn test_mempool_only_failures(
+        #[case] conditions: &[Vec<(u32, u64)>],
+        #[case] expected: &[(usize, ErrorCode)],
+    ) {
+        let program = make_generator(conditions);
+        let blocks: &[&[u8]] = &[];
+        let failures = mempool_only_failures(
+            &program,
+            blocks,
+            ConsensusFlags::empty(),
+            &Signature::default(),
+            &TEST_CONSTANTS,
+        )
+        .expect("mempool_only_failures");
+        assert_eq!(failures, expected);
+    }
+
+    #[test]
+    fn test_duplicate_spent_coins() {
+        let puzzle_hash = Bytes32::from(tree_hash_atom(IDENTITY_PUZZLE).to_bytes());
+        let coin = |parent: u8| Coin::new(Bytes32::from([parent; 32]), puzzle_hash, 1000);
+        let nil: &[u8] = &[0x80];
+        let spends = |coins: &[Coin]| {
+            solution_generator(coins.iter().map(|c| (*c, IDENTITY_PUZZLE, nil)))
+                .expect("solution_generator")
+        };
+        let blocks: &[&[u8]] = &[];
+        let duplicates = |program: &[u8]| {
+            duplicate_spent_coins(program, blocks, ConsensusFlags::empty(), &TEST_CONSTANTS)
+                .expect("duplicate_spent_coins")
+        };
+
+        assert_eq!(duplicates(&spends(&[coin(1), coin(2), coin(3)])), vec![]);
+
+        // coin 1 is spent three times and coin 3 twice. solution_generator()
+        // reverses the order of the spends
+        let program = spends(&[coin(3), coin(1), coin(2), coin(3), coin(1), coin(1)]);
+        assert_eq!(
+            duplicates(&program),
+            vec![coin(1).coin_id(), coin(3).coin_id()]
+        );
+
+        // validating the block only reports the first double spend
+        let err = run_block_generator2(
+            &program,
+            blocks,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::DoubleSpend);
+    }
+
+    #[rstest]
+    #[case(FutureConditionPolicy::Reject, 0..=0, Ok(0))]
+    #[case(FutureConditionPolicy::Reject, 1000..=1999, Err(ErrorCode::InvalidConditionOpcode))]
+    #[case(FutureConditionPolicy::Reject, 1501..=1999, Ok(0))]
+    #[case(FutureConditionPolicy::Charge(100), 1000..=1999, Ok(300))]
+    #[case(FutureConditionPolicy::Charge(100), 1337..=1337, Ok(200))]
+    #[case(FutureConditionPolicy::Charge(100), 2000..=2999, Ok(0))]
+    fn test_check_future_conditions(
+        #[case] policy: FutureConditionPolicy,
+        #[case] reserved: RangeInclusive<u32>,
+        #[case] expected: Result<u64, ErrorCode>,
+    ) {
+        let program = make_generator(&[
+            vec![(REMARK as u32, 1), (1337, 1)],
+            vec![(1337, 2), (1500, 1)],
+        ]);
+        let blocks: &[&[u8]] = &[];
+        let result = check_future_conditions(
+            &program,
+            blocks,
+            reserved,
+            policy,
+            ConsensusFlags::empty(),
+            &TEST_CONSTANTS,
+        );
+        assert_eq!(result.map_err(|e| e.error_code()), expected);
+    }
+
+    #[test]
+    fn test_condition_diversity() {
+        let blocks: &[&[u8]] = &[];
+        let diversity = |conditions: &[Vec<(u32, u64)>]| {
+            condition_diversity(
+                &make_generator(conditions),
+                blocks,
+                ConsensusFlags::empty(),
+                &TEST_CONSTANTS,
+            )
+            .expect("condition_diversity")
+        };
+
+        let empty = diversity(&[vec![], vec![]]);
+        assert!(empty.abs() < f64::EPSILON);
+
+        let monoculture = diversity(&[vec![(REMARK as u32, 1); 4], vec![(REMARK as u32, 2); 4]]);
+        assert!(monoculture.abs() < f64::EPSILON);
+
+        // four kinds of conditions, used equally often
+        let uniform = diversity(&[
+            vec![(REMARK as u32, 1), (RESERVE_FEE as u32, 1)],
+            vec![
+                (ASSERT_HEIGHT_ABSOLUTE as u32, 1),
+                (ASSERT_SECONDS_ABSOLUTE as u32, 1),
+            ],
+        ]);
+        assert!((uniform - 2.0).abs() < f64::EPSILON);
+
+        // the same four kinds, but mostly REMARK
+        let skewed = diversity(&[
+            vec![(REMARK as u32, 1); 5],
+            vec![
+                (RESERVE_FEE as u32, 1),
+                (ASSERT_HEIGHT_ABSOLUTE as u32, 1),
+                (ASSERT_SECONDS_ABSOLUTE as u32, 1),
+            ],
+        ]);
+        assert!(monoculture < skewed);
+        assert!(skewed < uniform);
+    }
+
+    #[test]
+    fn test_min_agreeing_cost() {
+        let program = make_generator(&[
+            vec![(REMARK as u32, 1); 10],
+            vec![(RESERVE_FEE as u32, 100)],
+        ]);
+        let blocks: &[&[u8]] = &[];
+        let threshold = min_agreeing_cost(
+            &program,
+            blocks,
+            ConsensusFlags::empty(),
+            &TEST_CONSTANTS,
+            &Signature::default(),
+        )
+        .expect("min_agreeing_cost");
+
+        let run = |max_cost| {
+            let r1 = run_block_generator(
+                &program,
+                blocks,
+                max_cost,
+                ConsensusFlags::empty(),
+                &Signature::default(),
+                None,
+                &TEST_CONSTANTS,
+            )
+            .map(|(_, conds)| conds.cost);
+            let r2 = run_block_generator2(
+                &program,
+                blocks,
+                max_cost,
+                ConsensusFlags::empty(),
+                &Signature::default(),
+                None,
+                &TEST_CONSTANTS,
+            )
+            .map(|(_, conds)| conds.cost);
+            (r1, r2)
+        };
+
+        // the threshold is the cost of running the block with the generator
+        // ROM
+        let (r1, r2) = run(threshold);
+        assert_eq!(r1, Ok(threshold));
+        assert!(r2.expect("run_block_generator2") < threshold);
+
+        let (r1, r2) = run(threshold - 1);
+        assert_eq!(
+            r1.unwrap_err().error_code(),
+            ErrorCode::ExecutionCostExceeded
+        );
+        assert!(r2.is_ok());
+    }
+
+    #[test]
+    fn test_min_agreeing_cost_invalid_block() {
+        // the block fails the same way, regardless of cost
+        let program = make_generator(&[vec![(RESERVE_FEE as u32, 100_000)]]);
+        let blocks: &[&[u8]] = &[];
+        let threshold = min_agreeing_cost(
+            &program,
+            blocks,
+            ConsensusFlags::empty(),
+            &TEST_CONSTANTS,
+            &Signature::default(),
+        )
+        .expect("min_agreeing_cost");
+        let err = run_block_generator2(
+            &program,
+            blocks,
+            threshold,
+            ConsensusFlags::empty(),
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::ReserveFeeConditionFailed);
+    }
+
+    #[test]
+    fn test_cost_delta() {
+        // conditions are free without COST_CONDITIONS
+        let program =
+            make_generator(&[vec![(REMARK as u32, 1); 100], vec![(REMARK as u32, 2); 50]]);
+        let blocks: &[&[u8]] = &[];
+        let delta = |flags_a, flags_b| {
+            cost_delta(
+                &program,
+                blocks,
+                flags_a,
+                flags_b,
+                &Signature::default(),
+                &TEST_CONSTANTS,
+            )
+            .expect("cost_delta")
+        };
+        let expected = (2 * SPEND_COST + 150 * GENERIC_CONDITION_COST) as i64;
+        assert_eq!(
+            delta(ConsensusFlags::COST_CONDITIONS, ConsensusFlags::empty()),
+            expected
+        );
+        assert_eq!(
+            delta(ConsensusFlags::empty(), ConsensusFlags::COST_CONDITIONS),
+            -expected
+        );
+        assert_eq!(
+            delta(
+                ConsensusFlags::COST_CONDITIONS,
+                ConsensusFlags::COST_CONDITIONS
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn test_cost_under_flags() {
+        let program = make_generator(&[vec![(REMARK as u32, 1); 10]]);
+        let blocks: &[&[u8]] = &[];
+        let cost = |flags| {
+            cost_under_flags(
+                &program,
+                blocks,
+                flags,
+                &Signature::default(),
+                &TEST_CONSTANTS,
+            )
+            .expect("cost_under_flags")
+        };
+        // the block was created before the hard fork, where conditions were
+        // free. Under the hard fork rules, the spend and its conditions have a
+        // cost
+        let pre_fork = cost(ConsensusFlags::empty());
+        let post_fork = cost(ConsensusFlags::COST_CONDITIONS);
+        assert_eq!(
+            post_fork - pre_fork,
+            SPEND_COST + 10 * GENERIC_CONDITION_COST
+        );
+    }
+
+    #[rstest]
+    fn test_verify_canonical_roundtrip(#[values(false, true)] backrefs: bool) {
+        let mut program = make_generator(&[
+            vec![(ASSERT_HEIGHT_ABSOLUTE as u32, 0)],
+            vec![(REMARK as u32, 1); 10],
+            vec![(RESERVE_FEE as u32, 100)],
+        ]);
+        if backrefs {
+            let mut a = Allocator::new();
+            let generator = node_from_bytes_backrefs(&mut a, &program).unwrap();
+            program = node_to_bytes_backrefs(&a, generator).unwrap();
+        }
+        let blocks: &[&[u8]] = &[];
+        verify_canonical_roundtrip(&program, blocks, ConsensusFlags::empty(), &TEST_CONSTANTS)
+            .expect("verify_canonical_roundtrip");
+
+        // an invalid generator fails with its own error
+        let program = make_generator(&[vec![(1337, 1)]]);
+        let err = verify_canonical_roundtrip(
+            &program,
+            blocks,
+            ConsensusFlags::NO_UNKNOWN_CONDS,
+            &TEST_CONSTANTS,
+        )
+        .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::InvalidConditionOpcode);
+    }
+
+    #[test]
+    fn test_assert_fork_neutral() {
+        let program = make_generator(&[
+            vec![(REMARK as u32, 1); 10],
+            vec![(RESERVE_FEE as u32, 100)],
+        ]);
+        let blocks: &[&[u8]] = &[];
+        // the cost is different, but nothing else
+        assert_eq!(
+            assert_fork_neutral(
+                &program,
+                blocks,
+                ConsensusFlags::empty(),
+                ConsensusFlags::COST_CONDITIONS,
+                &TEST_CONSTANTS,
+                &Signature::default(),
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_assert_fork_neutral_invalid() {
+        let program = make_generator(&[vec![], vec![(1337, 1)]]);
+        let blocks: &[&[u8]] = &[];
+        // unknown conditions are only allowed before the fork
+        assert_eq!(
+            assert_fork_neutral(
+                &program,
+                blocks,
+                ConsensusFlags::empty(),
+                ConsensusFlags::NO_UNKNOWN_CONDS,
+                &TEST_CONSTANTS,
+                &Signature::default(),
+            ),
+            Err(ForkDifference::Validity {
+                pre_fork: None,
+                post_fork: Some(ErrorCode::InvalidConditionOpcode),
+            })
+        );
+
+        // a block that's invalid under both sets of flags, for the same
+        // reason, is neutral
+        assert_eq!(
+            assert_fork_neutral(
+                &program,
+                blocks,
+                ConsensusFlags::NO_UNKNOWN_CONDS,
+                ConsensusFlags::NO_UNKNOWN_CONDS | ConsensusFlags::COST_CONDITIONS,
+                &TEST_CONSTANTS,
+                &Signature::default(),
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_assert_fork_neutral_spend() {
+        // a puzzle returning:
+        // ((CREATE_COIN puzzle-hash 0 ((keccak256 "foobar"))))
+        // keccak256 is an unknown operator, evaluating to nil, unless
+        // ENABLE_KECCAK_OPS_OUTSIDE_GUARD is set
+        let mut a = Allocator::new();
+        let quote = |a: &mut Allocator, v: NodePtr| a.new_pair(a.one(), v).unwrap();
+        let call = |a: &mut Allocator, op: u8, args: &[NodePtr]| {
+            let mut list = a.nil();
+            for arg in args.iter().rev() {
+                list = a.new_pair(*arg, list).unwrap();
+            }
+            let op = a.new_small_number(op.into()).unwrap();
+            a.new_pair(op, list).unwrap()
+        };
+        let nil = a.nil();
+        let quoted_nil = quote(&mut a, nil);
+        let foobar = a.new_atom(b"foobar").unwrap();
+        let foobar = quote(&mut a, foobar);
+        let hint = call(&mut a, 62, &[foobar]);
+        let memos = call(&mut a, 4, &[hint, quoted_nil]);
+        let tail = call(&mut a, 4, &[memos, quoted_nil]);
+        let amount = call(&mut a, 4, &[quoted_nil, tail]);
+        let puzzle_hash = a.new_atom(&[0x22; 32]).unwrap();
+        let puzzle_hash = quote(&mut a, puzzle_hash);
+        let args = call(&mut a, 4, &[puzzle_hash, amount]);
+        let opcode = a.new_small_number(CREATE_COIN.into()).unwrap();
+        let opcode = quote(&mut a, opcode);
+        let condition = call(&mut a, 4, &[opcode, args]);
+        let puzzle = call(&mut a, 4, &[condition, quoted_nil]);
+        let puzzle = node_to_bytes(&a, puzzle).unwrap();
+
+        let coin = Coin::new(
+            Bytes32::from([0x11; 32]),
+            tree_hash_from_bytes(&puzzle).unwrap().into(),
+            1000,
+        );
+        let program = solution_generator([(coin, puzzle.as_slice(), &[0x80_u8][..])])
+            .expect("solution_generator");
+        let blocks: &[&[u8]] = &[];
+
+        let Err(ForkDifference::Spend {
+            index,
+            pre_fork,
+            post_fork,
+        }) = assert_fork_neutral(
+            &program,
+            blocks,
+            ConsensusFlags::empty(),
+            ConsensusFlags::ENABLE_KECCAK_OPS_OUTSIDE_GUARD,
+            &TEST_CONSTANTS,
+            &Signature::default(),
+        )
+        else {
+            panic!("expected a spend difference");
+        };
+        assert_eq!(index, 0);
+        assert_eq!(pre_fork.coin_id, coin.coin_id());
+        assert_eq!(
+            pre_fork.create_coin,
+            vec![(Bytes32::from([0x22; 32]), 0, None)]
+        );
+        assert_eq!(post_fork.create_coin.len(), 1);
+        assert_eq!(
+            post_fork.create_coin[0].2.as_ref().map(Bytes::len),
+            Some(32)
+        );
+    }
+
+    #[rstest]
+    #[case(0, 11_000_000_000, 11_000_000_000)]
+    #[case(1_000_000, 11_000_000_000, 10_999_000_000)]
+    #[case(11_000_000_000, 11_000_000_000, 0)]
+    #[case(11_000_000_001, 11_000_000_000, 0)]
+    fn test_remaining_cost_budget(
+        #[case] cost: u64,
+        #[case] max_block_cost: u64,
+        #[case] expected: u64,
+    ) {
+        let conditions = OwnedSpendBundleConditions {
+            cost,
+            ..Default::default()
+        };
+        assert_eq!(remaining_cost_budget(&conditions, max_block_cost), expected);
+    }
+
+    #[test]
+    fn test_value_flow_by_puzzle_hash() {
+        let alice = Bytes32::from([1_u8; 32]);
+        let bob = Bytes32::from([2_u8; 32]);
+        let carol = Bytes32::from([3_u8; 32]);
+        let spend = |puzzle_hash, coin_amount, create_coin| OwnedSpendConditions {
+            puzzle_hash,
+            coin_amount,
+            create_coin,
+            ..Default::default()
+        };
+        let conditions = OwnedSpendBundleConditions {
+            spends: vec![
+                // alice pays bob 600, keeps 300 as change and pays 100 in fees
+                spend(alice, 1000, vec![(bob, 600, None), (alice, 300, None)]),
+                // bob pays carol 50 and sends the rest back to the same puzzle hash
+                spend(bob, 200, vec![(carol, 50, None), (bob, 150, None)]),
+            ],
+            ..Default::default()
+        };
+        let flows = value_flow_by_puzzle_hash(&conditions);
+        assert_eq!(flows.len(), 3);
+        assert_eq!(flows[&alice], -700);
+        assert_eq!(flows[&bob], 550);
+        assert_eq!(flows[&carol], 50);
+        // the sum of all flows is the fee
+        assert_eq!(flows.values().sum::<i128>(), -100);
+    }
+
+    #[test]
+    fn test_value_imbalance() {
+        let alice = Bytes32::from([1_u8; 32]);
+        let bob = Bytes32::from([2_u8; 32]);
+        let carol = Bytes32::from([3_u8; 32]);
+        let spend = |puzzle_hash, coin_amount, create_coin| OwnedSpendConditions {
+            puzzle_hash,
+            coin_amount,
+            create_coin,
+            ..Default::default()
+        };
+        let conditions = OwnedSpendBundleConditions {
+            spends: vec![
+                // alice pays bob 600, keeps 300 as change and pays 100 in fees
+                spend(alice, 1000, vec![(bob, 600, None), (alice, 300, None)]),
+                // bob sends all of it back to the same puzzle hash
+                spend(bob, 200, vec![(bob, 200, None)]),
+                // carol splits her coin, to the same puzzle hash
+                spend(carol, 500, vec![(carol, 250, None), (carol, 250, None)]),
+            ],
+            ..Default::default()
+        };
+        let imbalance = value_imbalance(&conditions);
+        assert_eq!(imbalance, HashMap::from([(alice, -700), (bob, 600)]));
+
+        // nothing moved
+        let conditions = OwnedSpendBundleConditions {
+            spends: vec![spend(carol, 500, vec![(carol, 500, None)])],
+            ..Default::default()
+        };
+        assert!(value_imbalance(&conditions).is_empty());
+    }
+
+    #[test]
+    fn test_coin_set_delta() {
+        let puzzle_hash = Bytes32::from([1_u8; 32]);
+        let parent = Coin::new(Bytes32::from([2_u8; 32]), puzzle_hash, 1000);
+        let ephemeral = Coin::new(parent.coin_id(), puzzle_hash, 500);
+        let conditions = OwnedSpendBundleConditions {
+            spends: vec![
+                OwnedSpendConditions {
+                    coin_id: parent.coin_id(),
+                    puzzle_hash,
+                    coin_amount: 1000,
+                    create_coin: vec![
+                        (puzzle_hash, 500, None),
+                        (puzzle_hash, 300, None),
+                        (puzzle_hash, 200, None),
+                    ],
+                    ..Default::default()
+                },
+                // spends the first coin created above
+                OwnedSpendConditions {
+                    coin_id: ephemeral.coin_id(),
+                    parent_id: parent.coin_id(),
+                    puzzle_hash,
+                    coin_amount: 500,
+                    create_coin: vec![(puzzle_hash, 250, None), (puzzle_hash, 250, None)],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        // 5 coins are created and 2 are spent
+        assert_eq!(coin_set_delta(&conditions), 3);
+
+        // consolidating coins shrinks the coin set
+        let conditions = OwnedSpendBundleConditions {
+            spends: vec![
+                OwnedSpendConditions {
+                    create_coin: vec![(puzzle_hash, 2000, None)],
+                    ..Default::default()
+                },
+                OwnedSpendConditions::default(),
+                OwnedSpendConditions::default(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(coin_set_delta(&conditions), -2);
+        assert_eq!(coin_set_delta(&OwnedSpendBundleConditions::default()), 0);
+    }
+
+    #[test]
+    fn test_burned_value() {
+        let burn = Bytes32::from([0xde_u8; 32]);
+        let alice = Bytes32::from([1_u8; 32]);
+        let spend = |create_coin| OwnedSpendConditions {
+            puzzle_hash: alice,
+            coin_amount: 1000,
+            create_coin,
+            ..Default::default()
+        };
+        let conditions = OwnedSpendBundleConditions {
+            spends: vec![
+                spend(vec![(burn, 300, None), (alice, 700, None)]),
+                spend(vec![
+                    (burn, 1, Some(alice.to_vec().into())),
+                    (burn, 99, None),
+                ]),
+                spend(vec![(alice, 1000, None)]),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(burned_value(&conditions, &burn), 400);
+        assert_eq!(burned_value(&conditions, &alice), 1700);
+        assert_eq!(burned_value(&conditions, &Bytes32::default()), 0);
+
+        // the sum doesn't overflow
+        let conditions = OwnedSpendBundleConditions {
+            spends: vec![
+                spend(vec![(burn, u64::MAX, None)]),
+                spend(vec![(burn, u64::MAX, None)]),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(burned_value(&conditions, &burn), u128::from(u64::MAX) * 2);
+    }
+
+    #[test]
+    fn test_marginal_signature_work() {
+        let pk = chia_bls::PublicKey::default();
+        let msg = Bytes::from(b"hello".to_vec());
+        let spend = OwnedSpendConditions {
+            agg_sig_me: vec![(pk, msg.clone())],
+            agg_sig_puzzle_amount: vec![(pk, msg.clone())],
+            ..Default::default()
+        };
+        let mut conditions = OwnedSpendBundleConditions {
+            spends: vec![spend],
+            ..Default::default()
+        };
+        assert_eq!(marginal_signature_work(&conditions), 2);
+
+        conditions.agg_sig_unsafe.push((pk, msg.clone()));
+        conditions.spends.push(OwnedSpendConditions {
+            agg_sig_parent: vec![(pk, msg.clone()), (pk, msg)],
+            ..Default::default()
+        });
+        assert_eq!(marginal_signature_work(&conditions), 5);
+
+        assert_eq!(
+            marginal_signature_work(&OwnedSpendBundleConditions::default()),
+            0
+        );
+    }
+
+    #[test]
+    fn test_signature_density() {
+        let pk = chia_bls::PublicKey::default();
+        let msg = Bytes::from(b"hello".to_vec());
+        let conditions = |num_sigs: usize| OwnedSpendBundleConditions {
+            spends: vec![OwnedSpendConditions {
+                agg_sig_me: vec![(pk, msg.clone()); num_sigs],
+                ..Default::default()
+            }],
+            cost: 10_000_000,
+            ..Default::default()
+        };
+
+        let heavy = signature_density(&conditions(100));
+        let light = signature_density(&conditions(1));
+        assert!(heavy > light);
+        assert!((heavy - 100.0 / 10_000_000.0).abs() < f64::EPSILON);
+        assert!((light - 1.0 / 10_000_000.0).abs() < f64::EPSILON);
+        assert!(signature_density(&conditions(0)).abs() < f64::EPSILON);
+
+        // no cost
+        assert!(signature_density(&OwnedSpendBundleConditions::default()).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_noop_spends() {
+        let alice = Bytes32::from([1_u8; 32]);
+        let bob = Bytes32::from([2_u8; 32]);
+        let spend = |create_coin| OwnedSpendConditions {
+            puzzle_hash: alice,
+            coin_amount: 1000,
+            create_coin,
+            ..Default::default()
+        };
+        let conditions = OwnedSpendBundleConditions {
+            spends: vec![
+                // pays bob
+                spend(vec![(bob, 1000, None)]),
+                // self-transfer
+                spend(vec![(alice, 1000, None)]),
+                // self-transfer with a hint
+                spend(vec![(alice, 1000, Some(bob.to_vec().into()))]),
+                // pays a fee
+                spend(vec![(alice, 999, None)]),
+                // splits the coin
+                spend(vec![(alice, 500, None), (alice, 500, None)]),
+                // recreates the coin, but also creates another one
+                spend(vec![(alice, 1000, None), (bob, 0, None)]),
+                // creates nothing
+                spend(vec![]),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(noop_spends(&conditions), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_additions_serialized_size() {
+        let spend = |coin_id, create_coin| OwnedSpendConditions {
+            coin_id,
+            create_coin,
+            ..Default::default()
+        };
+        let conditions = OwnedSpendBundleConditions {
+            spends: vec![
+                spend(
+                    Bytes32::from([1_u8; 32]),
+                    vec![
+                        (Bytes32::from([2_u8; 32]), 0, None),
+                        (
+                            Bytes32::from([3_u8; 32]),
+                            u64::MAX,
+                            Some(vec![4_u8; 32].into()),
+                        ),
+                    ],
+                ),
+                spend(Bytes32::from([5_u8; 32]), vec![]),
+                spend(
+                    Bytes32::from([6_u8; 32]),
+                    vec![(Bytes32::from([7_u8; 32]), 1000, None)],
+                ),
+            ],
+            ..Default::default()
+        };
+        let expected: usize = conditions
+            .spends
+            .iter()
+            .flat_map(|s| {
+                s.create_coin
+                    .iter()
+                    .map(|(ph, amount, _)| Coin::new(s.coin_id, *ph, *amount))
+            })
+            .map(|coin| coin.to_bytes().unwrap().len())
+            .sum();
+        assert_eq!(expected, 3 * 72);
+        assert_eq!(additions_serialized_size(&conditions), expected);
+        assert_eq!(
+            additions_serialized_size(&OwnedSpendBundleConditions::default()),
+            0
+        );
+    }
+
+    #[rstest]
+    #[case(0, 0)]
+    #[case(1, 0)]
+    #[case(1, 1)]
+    #[case(10, 3)]
+    #[case(200, 20)]
+    fn test_additions_ids(#[case] num_spends: u8, #[case] coins_per_spend: u64) {
+        let conditions = OwnedSpendBundleConditions {
+            spends: (0..num_spends)
+                .map(|i| OwnedSpendConditions {
+                    coin_id: Bytes32::from([i; 32]),
+                    create_coin: (0..coins_per_spend)
+                        .map(|amount| (Bytes32::from([0x11; 32]), amount, None))
+                        .collect(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let mut expected = Vec::new();
+        for spend in &conditions.spends {
+            for (puzzle_hash, amount, _hint) in &spend.create_coin {
+                expected.push(Coin::new(spend.coin_id, *puzzle_hash, *amount).coin_id());
+            }
+        }
+        assert_eq!(
+            expected.len(),
+            usize::from(num_spends) * coins_per_spend as usize
+        );
+        assert_eq!(additions_ids(&conditions), expected);
+        #[cfg(feature = "rayon")]
+        assert_eq!(additions_ids_parallel(&conditions), expected);
+    }
+
+    #[test]
+    fn test_mempool_only_failures_invalid_block() {
+        // the reserve fee exceeds the value of the coin being spent, which is
+        // invalid under consensus rules
+        let program = make_generator(&[vec![(RESERVE_FEE as u32, 2000)]]);
+        let blocks: &[&[u8]] = &[];
+        let err = mempool_only_failures(
+            &program,
+            blocks,
+            ConsensusFlags::empty(),
+            &Signature::default(),
+            &TEST_CONSTANTS,
+        )
+        .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::ReserveFeeConditionFailed);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::owned_conditions::OwnedSpendBundleConditions;
+use chia_protocol::{Bytes32, Coin};
+
+/// A bloom filter over coin IDs, for light clients to test whether a block
+/// may have touched a coin. A coin that was inserted always tests positive.
+/// A coin that wasn't inserted tests positive with (approximately) the false
+/// positive rate the filter was sized for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for `num_items` coin IDs and a false
+    /// positive rate of `fp_rate`. The rate is clamped to the range
+    /// [0.000000001, 0.5].
+    pub fn new(num_items: usize, fp_rate: f64) -> Self {
+        let fp_rate = fp_rate.clamp(1e-9, 0.5);
+        let n = num_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = ((-n * fp_rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = (-fp_rate.ln() / ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    // coin IDs are hashes, so rather than hashing them again, two 64 bit
+    // integers are taken from the ID and combined into the bit indices
+    // (double hashing)
+    fn bit_indices(&self, coin_id: &Bytes32) -> impl Iterator<Item = u64> + '_ {
+        let h1 = u64::from_be_bytes(coin_id[0..8].try_into().unwrap());
+        let h2 = u64::from_be_bytes(coin_id[8..16].try_into().unwrap());
+        (0..u64::from(self.num_hashes))
+            .map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    pub fn insert(&mut self, coin_id: &Bytes32) {
+        let indices: Vec<u64> = self.bit_indices(coin_id).collect();
+        for idx in indices {
+            self.bits[(idx / 64) as usize] |= 1 << (idx % 64);
+        }
+    }
+
+    pub fn contains(&self, coin_id: &Bytes32) -> bool {
+        self.bit_indices(coin_id)
+            .all(|idx| self.bits[(idx / 64) as usize] & (1 << (idx % 64)) != 0)
+    }
+
+    /// The size of the filter, in bits.
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    /// The number of bits set per coin ID.
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+}
+
+/// Returns a bloom filter over the IDs of all coins spent and created by
+/// `conditions`, sized for a false positive rate of `fp_rate`.
+pub fn touched_coins_filter(conditions: &OwnedSpendBundleConditions, fp_rate: f64) -> BloomFilter {
+    let num_items = conditions
+        .spends
+        .iter()
+        .map(|spend| 1 + spend.create_coin.len())
+        .sum();
+    let mut filter = BloomFilter::new(num_items, fp_rate);
+    for spend in &conditions.spends {
+        filter.insert(&spend.coin_id);
+        for (puzzle_hash, amount, _hint) in &spend.create_coin {
+            filter.insert(&Coin::new(spend.coin_id, *puzzle_hash, *amount).coin_id());
+        }
+    }
+    filter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::owned_conditions::OwnedSpendConditions;
+    use chia_sha2::Sha256;
+    use rstest::rstest;
+
+    fn hash(n: u32) -> Bytes32 {
+        let mut hasher = Sha256::new();
+        hasher.update(n.to_be_bytes());
+        hasher.finalize().into()
+    }
+
+    fn conditions(num_spends: u32, coins_per_spend: u32) -> OwnedSpendBundleConditions {
+        let puzzle_hash = Bytes32::from([0x11; 32]);
+        let spends = (0..num_spends)
+            .map(|i| OwnedSpendConditions {
+                coin_id: Coin::new(hash(i), puzzle_hash, 1000).coin_id(),
+                parent_id: hash(i),
+                puzzle_hash,
+                coin_amount: 1000,
+                create_coin: (0..coins_per_spend)
+                    .map(|amount| (puzzle_hash, u64::from(amount), None))
+                    .collect(),
+                ..Default::default()
+            })
+            .collect();
+        OwnedSpendBundleConditions {
+            spends,
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case(0, 0)]
+    #[case(1, 0)]
+    #[case(1, 10)]
+    #[case(100, 5)]
+    fn test_touched_coins_filter(#[case] num_spends: u32, #[case] coins_per_spend: u32) {
+        let conds = conditions(num_spends, coins_per_spend);
+        let filter = touched_coins_filter(&conds, 0.01);
+
+        for spend in &conds.spends {
+            assert!(filter.contains(&spend.coin_id));
+            for (puzzle_hash, amount, _hint) in &spend.create_coin {
+                assert!(
+                    filter.contains(&Coin::new(spend.coin_id, *puzzle_hash, *amount).coin_id())
+                );
+            }
+        }
+
+        // coins that weren't touched only test positive at about the false
+        // positive rate
+        let false_positives = (1_000_000..1_010_000)
+            .filter(|i| filter.contains(&hash(*i)))
+            .count();
+        assert!(false_positives < 200, "false positives: {false_positives}");
+    }
+
+    #[rstest]
+    #[case(1000, 0.01, 9586, 7)]
+    #[case(1000, 0.001, 14378, 10)]
+    #[case(1, 0.01, 64, 7)]
+    #[case(1000, 0.0, 43_133, 30)]
+    #[case(1000, 1.0, 1443, 1)]
+    fn test_filter_size(
+        #[case] num_items: usize,
+        #[case] fp_rate: f64,
+        #[case] num_bits: u64,
+        #[case] num_hashes: u32,
+    ) {
+        let filter = BloomFilter::new(num_items, fp_rate);
+        assert_eq!(filter.num_bits(), num_bits);
+        assert_eq!(filter.num_hashes(), num_hashes);
+    }
+}
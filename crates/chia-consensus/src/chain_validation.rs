@@ -0,0 +1,567 @@
+use crate::check_time_locks::check_time_locks;
+use crate::consensus_constants::ConsensusConstants;
+use crate::flags::ConsensusFlags;
+use crate::owned_conditions::OwnedSpendBundleConditions;
+use crate::run_block_generator::{run_block_generator, run_block_generator2};
+use crate::solution_generator::solution_generator;
+use crate::spendbundle_validation::get_flags_for_height_and_constants;
+use crate::validation_error::{ErrorCode, ValidationErr};
+use chia_bls::Signature;
+use chia_protocol::{Bytes32, Coin, CoinRecord, SpendBundle};
+use std::collections::HashMap;
+
+/// The set of coins a chain of blocks is validated against.
+pub trait CoinStore {
+    /// Returns the record of the specified coin, whether it's spent or not.
+    fn get_coin_record(&self, coin_id: &Bytes32) -> Option<CoinRecord>;
+
+    /// Adds a new, unspent, coin.
+    fn add_coin(&mut self, record: CoinRecord);
+
+    /// Marks the specified coin as spent at `height`.
+    fn spend_coin(&mut self, coin_id: &Bytes32, height: u32);
+}
+
+impl CoinStore for HashMap<Bytes32, CoinRecord> {
+    fn get_coin_record(&self, coin_id: &Bytes32) -> Option<CoinRecord> {
+        self.get(coin_id).copied()
+    }
+
+    fn add_coin(&mut self, record: CoinRecord) {
+        self.insert(record.coin.coin_id(), record);
+    }
+
+    fn spend_coin(&mut self, coin_id: &Bytes32, height: u32) {
+        if let Some(record) = self.get_mut(coin_id) {
+            record.spent_block_index = height;
+        }
+    }
+}
+
+// a CoinStore recording changes on top of another one, without modifying it
+struct StagedCoinStore<'a, S: CoinStore> {
+    base: &'a S,
+    added: HashMap<Bytes32, CoinRecord>,
+    // coin ID -> the height it was spent at
+    spent: HashMap<Bytes32, u32>,
+}
+
+impl<'a, S: CoinStore> StagedCoinStore<'a, S> {
+    fn new(base: &'a S) -> Self {
+        Self {
+            base,
+            added: HashMap::new(),
+            spent: HashMap::new(),
+        }
+    }
+}
+
+impl<S: CoinStore> CoinStore for StagedCoinStore<'_, S> {
+    fn get_coin_record(&self, coin_id: &Bytes32) -> Option<CoinRecord> {
+        let mut record = self
+            .added
+            .get(coin_id)
+            .copied()
+            .or_else(|| self.base.get_coin_record(coin_id))?;
+        if let Some(height) = self.spent.get(coin_id) {
+            record.spent_block_index = *height;
+        }
+        Some(record)
+    }
+
+    fn add_coin(&mut self, record: CoinRecord) {
+        self.added.insert(record.coin.coin_id(), record);
+    }
+
+    fn spend_coin(&mut self, coin_id: &Bytes32, height: u32) {
+        if self.get_coin_record(coin_id).is_some() {
+            self.spent.insert(*coin_id, height);
+        }
+    }
+}
+
+/// A transaction block, to be validated by validate_chain().
+#[derive(Debug, Clone)]
+pub struct ChainBlock<GenBuf> {
+    pub generator: GenBuf,
+    pub block_refs: Vec<GenBuf>,
+    /// The coins created and spent by the block are recorded at this height.
+    pub height: u32,
+    /// The coins created by the block are recorded with this timestamp.
+    pub timestamp: u64,
+    /// The height of the previous transaction block. This determines which
+    /// consensus rules apply, and height time-locks are checked against it.
+    pub prev_transaction_block_height: u32,
+    /// Seconds time-locks are checked against the timestamp of the previous
+    /// transaction block.
+    pub prev_transaction_block_timestamp: u64,
+}
+
+fn validate_block<GenBuf: AsRef<[u8]>>(
+    block: &ChainBlock<GenBuf>,
+    constants: &ConsensusConstants,
+    coin_store: &mut impl CoinStore,
+) -> Result<(), ValidationErr> {
+    let prev_tx_height = block.prev_transaction_block_height;
+    let flags = get_flags_for_height_and_constants(prev_tx_height, constants)
+        | ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+
+    // after the hard fork, we run blocks without paying for the CLVM generator ROM
+    let (a, conds) = if prev_tx_height >= constants.hard_fork_height {
+        run_block_generator2(
+            block.generator.as_ref(),
+            &block.block_refs,
+            constants.max_block_cost_clvm,
+            flags,
+            &Signature::default(),
+            None,
+            constants,
+        )?
+    } else {
+        run_block_generator(
+            block.generator.as_ref(),
+            &block.block_refs,
+            constants.max_block_cost_clvm,
+            flags,
+            &Signature::default(),
+            None,
+            constants,
+        )?
+    };
+    let conds = OwnedSpendBundleConditions::from(&a, conds);
+
+    let new_record = |coin: Coin| CoinRecord {
+        coin,
+        confirmed_block_index: block.height,
+        spent_block_index: 0,
+        coinbase: false,
+        timestamp: block.timestamp,
+    };
+
+    let mut additions = HashMap::<Bytes32, Coin>::new();
+    for spend in &conds.spends {
+        for (puzzle_hash, amount, _hint) in &spend.create_coin {
+            let coin = Coin::new(spend.coin_id, *puzzle_hash, *amount);
+            let coin_id = coin.coin_id();
+            if additions.insert(coin_id, coin).is_some() {
+                return Err(ValidationErr::Err(ErrorCode::DuplicateOutput));
+            }
+        }
+    }
+
+    let mut removals = HashMap::<Bytes32, CoinRecord>::new();
+    for spend in &conds.spends {
+        // ephemeral coins are created and spent in the same block
+        let record = if let Some(coin) = additions.get(&spend.coin_id) {
+            new_record(*coin)
+        } else {
+            match coin_store.get_coin_record(&spend.coin_id) {
+                None => return Err(ValidationErr::Err(ErrorCode::UnknownUnspent)),
+                Some(record) if record.spent_block_index > 0 => {
+                    return Err(ValidationErr::Err(ErrorCode::DoubleSpend));
+                }
+                Some(record) => record,
+            }
+        };
+        removals.insert(spend.coin_id, record);
+    }
+
+    if additions
+        .keys()
+        .any(|coin_id| coin_store.get_coin_record(coin_id).is_some())
+    {
+        return Err(ValidationErr::Err(ErrorCode::DuplicateOutput));
+    }
+
+    check_time_locks(
+        &removals,
+        &conds,
+        prev_tx_height,
+        block.prev_transaction_block_timestamp,
+        false,
+    )?;
+
+    for coin in additions.into_values() {
+        coin_store.add_coin(new_record(coin));
+    }
+    for spend in &conds.spends {
+        coin_store.spend_coin(&spend.coin_id, block.height);
+    }
+    Ok(())
+}
+
+/// Validates a chain of transaction blocks, in order, against `coin_store`.
+/// The removals and additions of every block are applied to `coin_store`, to
+/// be visible to subsequent blocks. Since only the transactions are validated,
+/// reward coins must already be in the coin store. Signatures are not
+/// validated. On failure, the index of the first invalid block is returned
+/// along with the error.
+pub fn validate_chain<GenBuf: AsRef<[u8]>>(
+    blocks: &[ChainBlock<GenBuf>],
+    constants: &ConsensusConstants,
+    coin_store: &mut impl CoinStore,
+) -> Result<(), (usize, ValidationErr)> {
+    for (idx, block) in blocks.iter().enumerate() {
+        validate_block(block, constants, coin_store).map_err(|e| (idx, e))?;
+    }
+    Ok(())
+}
+
+/// A sequence of spend bundles, planned to be included in consecutive blocks.
+/// Later steps may spend coins created by earlier ones.
+#[derive(Debug, Default, Clone)]
+pub struct TransactionPlan {
+    steps: Vec<SpendBundle>,
+}
+
+impl TransactionPlan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a spend bundle, to be included in the block after the one of the
+    /// previous step.
+    pub fn add_step(&mut self, bundle: SpendBundle) {
+        self.steps.push(bundle);
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Validates the plan against `coin_store`, the same way validate_chain()
+    /// does, with every step in a transaction block of its own. The first step
+    /// is included in the block after the one at
+    /// `prev_transaction_block_height`, and each subsequent step in the block
+    /// after that. All blocks have the timestamp `timestamp`. Signatures are
+    /// not validated. If the whole plan is valid, the removals and additions of
+    /// every step are applied to `coin_store`. On failure, `coin_store` is left
+    /// unchanged, and the index of the first invalid step is returned along
+    /// with the error.
+    pub fn validate<S: CoinStore>(
+        &self,
+        prev_transaction_block_height: u32,
+        timestamp: u64,
+        constants: &ConsensusConstants,
+        coin_store: &mut S,
+    ) -> Result<(), (usize, ValidationErr)> {
+        let mut staged = StagedCoinStore::new(coin_store);
+        for (idx, bundle) in self.steps.iter().enumerate() {
+            let generator = solution_generator(
+                bundle
+                    .coin_spends
+                    .iter()
+                    .map(|cs| (cs.coin, cs.puzzle_reveal.as_slice(), cs.solution.as_slice())),
+            )
+            .map_err(|_| (idx, ValidationErr::Err(ErrorCode::InvalidCoinSolution)))?;
+            let (Some(prev_tx_height), Some(height)) = (
+                prev_transaction_block_height.checked_add(idx as u32),
+                prev_transaction_block_height.checked_add(idx as u32 + 1),
+            ) else {
+                return Err((idx, ValidationErr::Err(ErrorCode::InvalidHeight)));
+            };
+            let block = ChainBlock {
+                generator,
+                block_refs: vec![],
+                height,
+                timestamp,
+                prev_transaction_block_height: prev_tx_height,
+                prev_transaction_block_timestamp: timestamp,
+            };
+            validate_block(&block, constants, &mut staged).map_err(|e| (idx, e))?;
+        }
+
+        // the whole plan is valid, apply its changes
+        let StagedCoinStore { added, spent, .. } = staged;
+        for record in added.into_values() {
+            coin_store.add_coin(record);
+        }
+        for (coin_id, height) in spent {
+            coin_store.spend_coin(&coin_id, height);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus_constants::TEST_CONSTANTS;
+    use crate::opcodes::{
+        ASSERT_HEIGHT_RELATIVE, ASSERT_SECONDS_ABSOLUTE, CREATE_COIN, ConditionOpcode,
+    };
+    use clvm_traits::ToClvm;
+    use clvm_utils::tree_hash_atom;
+    use clvmr::Allocator;
+    use clvmr::serde::node_to_bytes;
+
+    const IDENTITY_PUZZLE: &[u8] = &[1];
+
+    fn puzzle_hash() -> Bytes32 {
+        tree_hash_atom(IDENTITY_PUZZLE).into()
+    }
+
+    fn coin(parent: u8, amount: u64) -> Coin {
+        Coin::new(Bytes32::new([parent; 32]), puzzle_hash(), amount)
+    }
+
+    // builds a generator spending the specified coins, with the identity
+    // puzzle. Each spend creates coins of the specified amounts
+    fn make_block(spends: &[(Coin, Vec<u64>)]) -> Vec<u8> {
+        let mut a = Allocator::new();
+        let solutions: Vec<Vec<u8>> = spends
+            .iter()
+            .map(|(_, amounts)| {
+                let conds: Vec<_> = amounts
+                    .iter()
+                    .map(|amount| (CREATE_COIN as u32, (puzzle_hash(), (*amount, ()))))
+                    .collect();
+                let node = conds.to_clvm(&mut a).unwrap();
+                node_to_bytes(&a, node).unwrap()
+            })
+            .collect();
+        solution_generator(
+            spends
+                .iter()
+                .zip(&solutions)
+                .map(|((coin, _), solution)| (*coin, IDENTITY_PUZZLE, solution.as_slice())),
+        )
+        .expect("solution_generator")
+    }
+
+    // a block with a single spend of `coin`, with the identity puzzle and the
+    // specified condition
+    fn make_block_with_condition(coin: Coin, opcode: ConditionOpcode, arg: u64) -> Vec<u8> {
+        let mut a = Allocator::new();
+        let conds = [(opcode as u32, (arg, ()))].to_clvm(&mut a).unwrap();
+        let solution = node_to_bytes(&a, conds).unwrap();
+        solution_generator([(coin, IDENTITY_PUZZLE, solution.as_slice())])
+            .expect("solution_generator")
+    }
+
+    // a transaction block at `height`, right after the transaction block
+    // before it. Blocks are 20 seconds apart
+    fn block(generator: Vec<u8>, height: u32) -> ChainBlock<Vec<u8>> {
+        ChainBlock {
+            generator,
+            block_refs: vec![],
+            height,
+            timestamp: u64::from(height) * 20,
+            prev_transaction_block_height: height - 1,
+            prev_transaction_block_timestamp: u64::from(height - 1) * 20,
+        }
+    }
+
+    fn coin_store(coins: &[Coin]) -> HashMap<Bytes32, CoinRecord> {
+        let mut store = HashMap::new();
+        for coin in coins {
+            store.add_coin(CoinRecord {
+                coin: *coin,
+                confirmed_block_index: 1,
+                spent_block_index: 0,
+                coinbase: true,
+                timestamp: 0,
+            });
+        }
+        store
+    }
+
+    #[test]
+    fn test_validate_chain() {
+        let reward = coin(1, 1000);
+        let child = Coin::new(reward.coin_id(), puzzle_hash(), 600);
+        let grandchild = Coin::new(child.coin_id(), puzzle_hash(), 600);
+        let ephemeral = Coin::new(reward.coin_id(), puzzle_hash(), 400);
+
+        let blocks = [
+            block(
+                make_block(&[(reward, vec![600, 400]), (ephemeral, vec![])]),
+                2,
+            ),
+            block(make_block(&[(child, vec![600])]), 3),
+        ];
+
+        let mut store = coin_store(&[reward]);
+        validate_chain(&blocks, &TEST_CONSTANTS, &mut store).expect("validate_chain");
+
+        assert_eq!(store[&reward.coin_id()].spent_block_index, 2);
+        assert_eq!(store[&child.coin_id()].confirmed_block_index, 2);
+        assert_eq!(store[&child.coin_id()].timestamp, 40);
+        assert_eq!(store[&child.coin_id()].spent_block_index, 3);
+        assert_eq!(store[&ephemeral.coin_id()].spent_block_index, 2);
+        assert_eq!(store[&grandchild.coin_id()].spent_block_index, 0);
+        assert_eq!(store[&grandchild.coin_id()].confirmed_block_index, 3);
+    }
+
+    #[test]
+    fn test_validate_chain_double_spend() {
+        let reward1 = coin(1, 1000);
+        let reward2 = coin(2, 1000);
+
+        let blocks = [
+            block(make_block(&[(reward1, vec![1000])]), 2),
+            block(make_block(&[(reward2, vec![1000])]), 3),
+            block(make_block(&[(reward1, vec![1000])]), 4),
+        ];
+
+        let mut store = coin_store(&[reward1, reward2]);
+        let (idx, err) = validate_chain(&blocks, &TEST_CONSTANTS, &mut store).unwrap_err();
+        assert_eq!(idx, 2);
+        assert_eq!(err.error_code(), ErrorCode::DoubleSpend);
+    }
+
+    #[test]
+    fn test_validate_chain_unknown_coin() {
+        let reward = coin(1, 1000);
+        let blocks = [block(make_block(&[(coin(2, 1000), vec![])]), 2)];
+
+        let mut store = coin_store(&[reward]);
+        let (idx, err) = validate_chain(&blocks, &TEST_CONSTANTS, &mut store).unwrap_err();
+        assert_eq!(idx, 0);
+        assert_eq!(err.error_code(), ErrorCode::UnknownUnspent);
+    }
+
+    #[test]
+    fn test_validate_chain_time_locks() {
+        let reward = coin(1, 1000);
+        let child = Coin::new(reward.coin_id(), puzzle_hash(), 1000);
+        let first = block(make_block(&[(reward, vec![1000])]), 2);
+
+        // the child is created at height 2. Height time-locks are checked
+        // against the previous transaction block, so spending it one block
+        // later doesn't satisfy a relative height of 1
+        let blocks = [
+            first.clone(),
+            block(
+                make_block_with_condition(child, ASSERT_HEIGHT_RELATIVE, 1),
+                3,
+            ),
+        ];
+        let mut store = coin_store(&[reward]);
+        let (idx, err) = validate_chain(&blocks, &TEST_CONSTANTS, &mut store).unwrap_err();
+        assert_eq!(idx, 1);
+        assert_eq!(err.error_code(), ErrorCode::AssertHeightRelativeFailed);
+
+        // it's satisfied two blocks later
+        let blocks = [
+            first.clone(),
+            block(
+                make_block_with_condition(child, ASSERT_HEIGHT_RELATIVE, 1),
+                4,
+            ),
+        ];
+        let mut store = coin_store(&[reward]);
+        validate_chain(&blocks, &TEST_CONSTANTS, &mut store).expect("validate_chain");
+
+        // seconds time-locks are checked against the timestamp of the
+        // previous transaction block, 60 for the block at height 4
+        for (seconds, expected) in [
+            (60, None),
+            (61, Some(ErrorCode::AssertSecondsAbsoluteFailed)),
+        ] {
+            let blocks = [
+                first.clone(),
+                block(
+                    make_block_with_condition(child, ASSERT_SECONDS_ABSOLUTE, seconds),
+                    4,
+                ),
+            ];
+            let mut store = coin_store(&[reward]);
+            let result = validate_chain(&blocks, &TEST_CONSTANTS, &mut store);
+            assert_eq!(result.err().map(|(_, e)| e.error_code()), expected);
+        }
+    }
+
+    // a spend bundle spending the specified coins, with the identity puzzle.
+    // Each spend creates coins of the specified amounts
+    fn make_bundle(spends: &[(Coin, Vec<u64>)]) -> SpendBundle {
+        use chia_bls::Signature;
+        use chia_protocol::{CoinSpend, Program};
+
+        let mut a = Allocator::new();
+        let coin_spends = spends
+            .iter()
+            .map(|(coin, amounts)| {
+                let conds: Vec<_> = amounts
+                    .iter()
+                    .map(|amount| (CREATE_COIN as u32, (puzzle_hash(), (*amount, ()))))
+                    .collect();
+                let node = conds.to_clvm(&mut a).unwrap();
+                CoinSpend::new(
+                    *coin,
+                    Program::from(IDENTITY_PUZZLE.to_vec()),
+                    Program::from(node_to_bytes(&a, node).unwrap()),
+                )
+            })
+            .collect();
+        SpendBundle::new(coin_spends, Signature::default())
+    }
+
+    #[test]
+    fn test_transaction_plan() {
+        let reward = coin(1, 1000);
+        let child = Coin::new(reward.coin_id(), puzzle_hash(), 600);
+        let grandchild = Coin::new(child.coin_id(), puzzle_hash(), 500);
+
+        let mut plan = TransactionPlan::new();
+        assert!(plan.is_empty());
+        plan.add_step(make_bundle(&[(reward, vec![600])]));
+        // the second step spends the output of the first one
+        plan.add_step(make_bundle(&[(child, vec![500])]));
+        assert_eq!(plan.len(), 2);
+
+        let mut store = coin_store(&[reward]);
+        plan.validate(9, 1000, &TEST_CONSTANTS, &mut store)
+            .expect("validate");
+        assert_eq!(store[&reward.coin_id()].spent_block_index, 10);
+        assert_eq!(store[&child.coin_id()].confirmed_block_index, 10);
+        assert_eq!(store[&child.coin_id()].spent_block_index, 11);
+        assert_eq!(store[&grandchild.coin_id()].confirmed_block_index, 11);
+        assert_eq!(store[&grandchild.coin_id()].spent_block_index, 0);
+
+        // the steps must be in order, the child doesn't exist yet in the first
+        // step
+        let mut plan = TransactionPlan::new();
+        plan.add_step(make_bundle(&[(child, vec![500])]));
+        plan.add_step(make_bundle(&[(reward, vec![600])]));
+        let mut store = coin_store(&[reward]);
+        let (idx, err) = plan
+            .validate(9, 1000, &TEST_CONSTANTS, &mut store)
+            .unwrap_err();
+        assert_eq!(idx, 0);
+        assert_eq!(err.error_code(), ErrorCode::UnknownUnspent);
+    }
+
+    #[test]
+    fn test_transaction_plan_failure_leaves_store_unchanged() {
+        let reward = coin(1, 1000);
+        let child = Coin::new(reward.coin_id(), puzzle_hash(), 600);
+
+        // the first step is valid, but the second one spends the reward again
+        let mut plan = TransactionPlan::new();
+        plan.add_step(make_bundle(&[(reward, vec![600])]));
+        plan.add_step(make_bundle(&[(reward, vec![600])]));
+        let mut store = coin_store(&[reward]);
+        let (idx, err) = plan
+            .validate(9, 1000, &TEST_CONSTANTS, &mut store)
+            .unwrap_err();
+        assert_eq!(idx, 1);
+        assert_eq!(err.error_code(), ErrorCode::DoubleSpend);
+        assert_eq!(store, coin_store(&[reward]));
+
+        // the second step would be past the last possible height
+        let mut plan = TransactionPlan::new();
+        plan.add_step(make_bundle(&[(reward, vec![600])]));
+        plan.add_step(make_bundle(&[(child, vec![500])]));
+        let (idx, err) = plan
+            .validate(u32::MAX - 1, 1000, &TEST_CONSTANTS, &mut store)
+            .unwrap_err();
+        assert_eq!(idx, 1);
+        assert_eq!(err.error_code(), ErrorCode::InvalidHeight);
+        assert_eq!(store, coin_store(&[reward]));
+    }
+}
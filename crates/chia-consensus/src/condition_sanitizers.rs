@@ -27,6 +27,27 @@ pub fn parse_amount(a: &Allocator, n: NodePtr, code: ErrorCode) -> Result<u64, V
     }
 }
 
+// like parse_amount(), but distinguishes between an amount that's not
+// canonically encoded and one that's out of range (negative or exceeding 2^64)
+pub fn sanitize_reserve_fee(a: &Allocator, n: NodePtr) -> Result<u64, ValidationErr> {
+    let _ = atom(
+        a,
+        n,
+        ValidationErr::Err(ErrorCode::ReserveFeeConditionFailed),
+    )?;
+    match sanitize_uint(
+        a,
+        n,
+        8,
+        ValidationErr::Err(ErrorCode::ReserveFeeNotCanonical),
+    )? {
+        SanitizedUint::NegativeOverflow | SanitizedUint::PositiveOverflow => {
+            Err(ValidationErr::Err(ErrorCode::ReserveFeeOutOfRange))
+        }
+        SanitizedUint::Ok(r) => Ok(r),
+    }
+}
+
 pub fn sanitize_announce_msg(
     a: &Allocator,
     n: NodePtr,
@@ -207,3 +228,30 @@ fn test_sanitize_amount() {
         Ok(0xffff_ffff_ffff_ffff)
     );
 }
+
+#[cfg(test)]
+#[rstest]
+#[case(&[], Ok(0))]
+#[case(&[0x7f], Ok(0x7f))]
+#[case(&[0, 0xff], Ok(0xff))]
+#[case(&[0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], Ok(0xffff_ffff_ffff_ffff))]
+#[case(&[0], Err(ErrorCode::ReserveFeeNotCanonical))]
+#[case(&[0, 0x7f], Err(ErrorCode::ReserveFeeNotCanonical))]
+#[case(&[0, 0, 0xff], Err(ErrorCode::ReserveFeeNotCanonical))]
+#[case(&[0x80], Err(ErrorCode::ReserveFeeOutOfRange))]
+#[case(&[0xff, 0], Err(ErrorCode::ReserveFeeOutOfRange))]
+#[case(&[1, 0, 0, 0, 0, 0, 0, 0, 0], Err(ErrorCode::ReserveFeeOutOfRange))]
+fn test_sanitize_reserve_fee(#[case] buf: &[u8], #[case] expect: Result<u64, ErrorCode>) {
+    let mut a = Allocator::new();
+    let n = a.new_atom(buf).unwrap();
+    assert_eq!(
+        sanitize_reserve_fee(&a, n).map_err(|e| e.error_code()),
+        expect
+    );
+
+    let pair = a.new_pair(n, n).unwrap();
+    assert_eq!(
+        sanitize_reserve_fee(&a, pair).unwrap_err().error_code(),
+        ErrorCode::ReserveFeeConditionFailed
+    );
+}
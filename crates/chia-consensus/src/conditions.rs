@@ -1,6 +1,6 @@
 use super::coin_id::compute_coin_id;
 use super::condition_sanitizers::{
-    parse_amount, sanitize_announce_msg, sanitize_hash, sanitize_message_mode,
+    parse_amount, sanitize_announce_msg, sanitize_hash, sanitize_message_mode, sanitize_reserve_fee,
 };
 use super::opcodes::{
     AGG_SIG_AMOUNT, AGG_SIG_COST, AGG_SIG_ME, AGG_SIG_PARENT, AGG_SIG_PARENT_AMOUNT,
@@ -21,6 +21,8 @@ use crate::consensus_constants::ConsensusConstants;
 use crate::flags::ConsensusFlags;
 use crate::make_aggsig_final_message::u64_to_bytes;
 use crate::messages::{Message, SpendId};
+use crate::owned_conditions::OwnedSpendBundleConditions;
+use crate::puzzle_fingerprint::compute_puzzle_fingerprint;
 use crate::spend_visitor::SpendVisitor;
 use crate::validation_error::check_nil;
 use chia_bls::{BlsCache, PublicKey, Signature, aggregate_verify};
@@ -28,6 +30,7 @@ use chia_protocol::{Bytes, Bytes32, Coin};
 use chia_sha2::Sha256;
 use clvmr::allocator::{Allocator, NodePtr, SExp};
 use clvmr::cost::Cost;
+use clvmr::serde::node_to_bytes;
 use std::cmp::{max, min};
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
@@ -63,8 +66,16 @@ pub const ELIGIBLE_FOR_FF: u32 = 4;
 
 pub const MAX_SPENDS_PER_BLOCK: usize = 6000;
 
+// the number of announcements and messages a spend may create or assert,
+// unless the COST_CONDITIONS flag is set (in which case they have a cost
+// instead)
+pub(crate) const MAX_ANNOUNCEMENTS_PER_SPEND: u32 = 1024;
+
+/// A SpendVisitor that does nothing. This is what consensus validation uses.
 pub struct EmptyVisitor {}
 
+pub type NullVisitor = EmptyVisitor;
+
 impl SpendVisitor for EmptyVisitor {
     fn new_spend(_spend: &mut SpendConditions) -> Self {
         Self {}
@@ -335,6 +346,239 @@ pub enum Condition {
     SkipRelativeCondition,
 }
 
+// the sort key of a condition is its opcode followed by its arguments. Nodes
+// are serialized, to make the concatenation of the arguments unambiguous
+fn condition_key(
+    a: &Allocator,
+    cond: &Condition,
+) -> Result<(ConditionOpcode, Vec<u8>), ValidationErr> {
+    let mut args = Vec::<u8>::new();
+    let mut node = |n: NodePtr| -> Result<(), ValidationErr> {
+        args.extend(node_to_bytes(a, n)?);
+        Ok(())
+    };
+    let opcode = match cond {
+        Condition::AggSigUnsafe(pk, msg) => {
+            node(*pk)?;
+            node(*msg)?;
+            AGG_SIG_UNSAFE
+        }
+        Condition::AggSigMe(pk, msg) => {
+            node(*pk)?;
+            node(*msg)?;
+            AGG_SIG_ME
+        }
+        Condition::AggSigParent(pk, msg) => {
+            node(*pk)?;
+            node(*msg)?;
+            AGG_SIG_PARENT
+        }
+        Condition::AggSigPuzzle(pk, msg) => {
+            node(*pk)?;
+            node(*msg)?;
+            AGG_SIG_PUZZLE
+        }
+        Condition::AggSigAmount(pk, msg) => {
+            node(*pk)?;
+            node(*msg)?;
+            AGG_SIG_AMOUNT
+        }
+        Condition::AggSigPuzzleAmount(pk, msg) => {
+            node(*pk)?;
+            node(*msg)?;
+            AGG_SIG_PUZZLE_AMOUNT
+        }
+        Condition::AggSigParentAmount(pk, msg) => {
+            node(*pk)?;
+            node(*msg)?;
+            AGG_SIG_PARENT_AMOUNT
+        }
+        Condition::AggSigParentPuzzle(pk, msg) => {
+            node(*pk)?;
+            node(*msg)?;
+            AGG_SIG_PARENT_PUZZLE
+        }
+        Condition::CreateCoin(ph, amount, hint) => {
+            node(*ph)?;
+            node(*hint)?;
+            args.extend(amount.to_be_bytes());
+            CREATE_COIN
+        }
+        Condition::ReserveFee(amount) => {
+            args.extend(amount.to_be_bytes());
+            RESERVE_FEE
+        }
+        Condition::CreateCoinAnnouncement(msg) => {
+            node(*msg)?;
+            CREATE_COIN_ANNOUNCEMENT
+        }
+        Condition::CreatePuzzleAnnouncement(msg) => {
+            node(*msg)?;
+            CREATE_PUZZLE_ANNOUNCEMENT
+        }
+        Condition::AssertCoinAnnouncement(id) => {
+            node(*id)?;
+            ASSERT_COIN_ANNOUNCEMENT
+        }
+        Condition::AssertPuzzleAnnouncement(id) => {
+            node(*id)?;
+            ASSERT_PUZZLE_ANNOUNCEMENT
+        }
+        Condition::AssertConcurrentSpend(id) => {
+            node(*id)?;
+            ASSERT_CONCURRENT_SPEND
+        }
+        Condition::AssertConcurrentPuzzle(ph) => {
+            node(*ph)?;
+            ASSERT_CONCURRENT_PUZZLE
+        }
+        Condition::AssertMyCoinId(id) => {
+            node(*id)?;
+            ASSERT_MY_COIN_ID
+        }
+        Condition::AssertMyParentId(id) => {
+            node(*id)?;
+            ASSERT_MY_PARENT_ID
+        }
+        Condition::AssertMyPuzzlehash(ph) => {
+            node(*ph)?;
+            ASSERT_MY_PUZZLEHASH
+        }
+        Condition::AssertMyAmount(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_MY_AMOUNT
+        }
+        Condition::AssertMyBirthSeconds(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_MY_BIRTH_SECONDS
+        }
+        Condition::AssertMyBirthHeight(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_MY_BIRTH_HEIGHT
+        }
+        Condition::AssertSecondsRelative(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_SECONDS_RELATIVE
+        }
+        Condition::AssertSecondsAbsolute(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_SECONDS_ABSOLUTE
+        }
+        Condition::AssertHeightRelative(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_HEIGHT_RELATIVE
+        }
+        Condition::AssertHeightAbsolute(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_HEIGHT_ABSOLUTE
+        }
+        Condition::AssertBeforeSecondsRelative(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_BEFORE_SECONDS_RELATIVE
+        }
+        Condition::AssertBeforeSecondsAbsolute(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_BEFORE_SECONDS_ABSOLUTE
+        }
+        Condition::AssertBeforeHeightRelative(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_BEFORE_HEIGHT_RELATIVE
+        }
+        Condition::AssertBeforeHeightAbsolute(v) => {
+            args.extend(v.to_be_bytes());
+            ASSERT_BEFORE_HEIGHT_ABSOLUTE
+        }
+        Condition::AssertEphemeral => ASSERT_EPHEMERAL,
+        Condition::Softfork(cost) => {
+            args.extend(cost.to_be_bytes());
+            SOFTFORK
+        }
+        Condition::SendMessage(mode, dst, msg) => {
+            args.push(*mode);
+            spend_id_key(a, dst, &mut args)?;
+            args.extend(node_to_bytes(a, *msg)?);
+            SEND_MESSAGE
+        }
+        Condition::ReceiveMessage(src, mode, msg) => {
+            args.push(*mode);
+            spend_id_key(a, src, &mut args)?;
+            args.extend(node_to_bytes(a, *msg)?);
+            RECEIVE_MESSAGE
+        }
+        // these don't have an opcode of their own. They sort last
+        Condition::Skip => ConditionOpcode::MAX - 1,
+        Condition::SkipRelativeCondition => ConditionOpcode::MAX,
+    };
+    Ok((opcode, args))
+}
+
+fn spend_id_key(a: &Allocator, id: &SpendId, out: &mut Vec<u8>) -> Result<(), ValidationErr> {
+    let mut node = |n: &NodePtr| -> Result<(), ValidationErr> {
+        out.extend(node_to_bytes(a, *n)?);
+        Ok(())
+    };
+    match id {
+        SpendId::OwnedCoinId(id) => out.extend_from_slice(id.as_ref()),
+        SpendId::CoinId(n) | SpendId::Parent(n) | SpendId::Puzzle(n) => node(n)?,
+        SpendId::Amount(v) => out.extend(v.to_be_bytes()),
+        SpendId::PuzzleAmount(n, v) | SpendId::ParentAmount(n, v) => {
+            node(n)?;
+            out.extend(v.to_be_bytes());
+        }
+        SpendId::ParentPuzzle(n1, n2) => {
+            node(n1)?;
+            node(n2)?;
+        }
+        SpendId::None => {}
+    }
+    Ok(())
+}
+
+/// Returns `conds` in a canonical order, sorted by opcode and then by
+/// arguments, with redundant duplicates removed. The order of the conditions
+/// of a spend doesn't affect its validity, nor its effects.
+/// Only assertions are deduplicated, i.e. all the ASSERT_* conditions, since
+/// asserting the same thing twice is the same as asserting it once. The same
+/// goes for conditions that are skipped. Other conditions are never removed,
+/// since each instance has an effect: CREATE_COIN creates a coin (and two
+/// identical ones fail), RESERVE_FEE and SOFTFORK add up, every AGG_SIG_*
+/// requires a signature, every announcement is created and counted, and every
+/// message must be matched by one on the other end.
+/// Removing conditions lowers the cost of the spend.
+/// Fails if any of the arguments can't be serialized.
+pub fn canonicalize_conditions(
+    a: &Allocator,
+    conds: Vec<Condition>,
+) -> Result<Vec<Condition>, ValidationErr> {
+    let mut keyed = conds
+        .into_iter()
+        .map(|c| Ok((condition_key(a, &c)?, c)))
+        .collect::<Result<Vec<((ConditionOpcode, Vec<u8>), Condition)>, ValidationErr>>()?;
+    keyed.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+    keyed.dedup_by(|next, prev| {
+        next.0 == prev.0
+            && !matches!(
+                next.1,
+                Condition::AggSigUnsafe(..)
+                    | Condition::AggSigMe(..)
+                    | Condition::AggSigParent(..)
+                    | Condition::AggSigPuzzle(..)
+                    | Condition::AggSigAmount(..)
+                    | Condition::AggSigPuzzleAmount(..)
+                    | Condition::AggSigParentAmount(..)
+                    | Condition::AggSigParentPuzzle(..)
+                    | Condition::CreateCoin(..)
+                    | Condition::ReserveFee(..)
+                    | Condition::CreateCoinAnnouncement(..)
+                    | Condition::CreatePuzzleAnnouncement(..)
+                    | Condition::Softfork(..)
+                    | Condition::SendMessage(..)
+                    | Condition::ReceiveMessage(..)
+            )
+    });
+    Ok(keyed.into_iter().map(|(_, c)| c).collect())
+}
+
 fn check_agg_sig_unsafe_message(
     a: &Allocator,
     msg: NodePtr,
@@ -442,6 +686,12 @@ pub fn parse_args(
                 // side, the list element
                 maybe_check_args_terminator(a, c, flags)?;
                 if let Ok(param) = first(a, params) {
+                    if flags.contains(ConsensusFlags::STRICT_HINTS)
+                        && !(matches!(a.sexp(param), SExp::Atom)
+                            && [0, 32].contains(&a.atom_len(param)))
+                    {
+                        return Err(ValidationErr::Err(ErrorCode::InvalidHint));
+                    }
                     // pull out the first item (param)
                     if let SExp::Atom = a.sexp(param) {
                         if a.atom_len(param) <= 32 {
@@ -484,7 +734,13 @@ pub fn parse_args(
         }
         RESERVE_FEE => {
             maybe_check_args_terminator(a, c, flags)?;
-            let fee = parse_amount(a, first(a, c)?, ErrorCode::ReserveFeeConditionFailed)?;
+            // in mempool mode, the amount encoding is reported with
+            // dedicated error codes. Consensus keeps the legacy code
+            let fee = if flags.contains(ConsensusFlags::NO_UNKNOWN_CONDS) {
+                sanitize_reserve_fee(a, first(a, c)?)?
+            } else {
+                parse_amount(a, first(a, c)?, ErrorCode::ReserveFeeConditionFailed)?
+            };
             Ok(Condition::ReserveFee(fee))
         }
         CREATE_COIN_ANNOUNCEMENT => {
@@ -816,10 +1072,14 @@ pub struct SpendConditions {
     // Flags describing properties of this spend. See flags above
     pub flags: u32,
 
-    /// the cost of executing the Chialisp for this puzzle
+    /// the cost of executing the Chialisp for this puzzle. This is only known
+    /// for blocks run by run_block_generator2() (and for spend bundles), with
+    /// run_block_generator() the puzzles are run by the generator and this is
+    /// 0
     pub execution_cost: u64,
 
-    /// the cost of the conditions generated by this puzzle
+    /// the cost of the conditions generated by this puzzle. The sum over all
+    /// spends is the condition_cost of the SpendBundleConditions
     pub condition_cost: u64,
 
     /// If this spend is eligible for dedup, this fingerprint is set to
@@ -899,6 +1159,37 @@ pub struct SpendBundleConditions {
     // the cost of the conditions
     pub condition_cost: u64,
 
+    // the part of condition_cost reserved by SOFTFORK conditions and by
+    // unknown conditions that have a cost (i.e. cost not charged for any known
+    // condition). This is also included in cost
+    pub softfork_cost: u64,
+
+    // the number of pairings performed when validating the aggregate
+    // signature. One per (public key, message) pair plus one for the signature
+    // itself. Pairings found in the BlsCache are not computed, and not
+    // counted. Zero if the signature wasn't validated
+    pub pairings: u64,
+
+    // the cost of the generator bytes, charged when deserializing it. Only set
+    // by run_block_generator() and run_block_generator2(). Together with the
+    // execution and condition cost, this adds up to the total cost
+    pub deserialization_cost: u64,
+
+    // true if the generator was run under the simple generator rules, i.e. it
+    // had to be a quoted list of spends, rather than a program. Only set by
+    // run_block_generator() and run_block_generator2(). A generator that
+    // breaks the rules fails with ComplexGeneratorReceived, so on success
+    // this is the same as the SIMPLE_GENERATOR flag
+    pub simple_generator: bool,
+
+    // the (public key, message, condition opcode) of every AGG_SIG condition,
+    // in the order they were parsed. The message is the one that's signed,
+    // i.e. including the suffix of AGG_SIG_ME and the other AGG_SIG_*
+    // conditions. Only set when the COLLECT_SIG_PAIRS flag is set, otherwise
+    // empty. Unlike the signature validation, this is not affected by the
+    // DONT_VALIDATE_SIGNATURE flag
+    pub sig_pairs: Vec<(PublicKey, Bytes, ConditionOpcode)>,
+
     // the sum of all values of all spent coins
     pub removal_amount: u128,
 
@@ -909,6 +1200,93 @@ pub struct SpendBundleConditions {
     pub validated_signature: bool,
 }
 
+/// The flat costs charged for conditions when the COST_CONDITIONS flag is
+/// set. The default is the cost schedule used by consensus. Other schedules
+/// are only meant for simulating changes to the costs, blocks validated with
+/// them are not valid under consensus rules. AGG_SIG conditions are charged
+/// `agg_sig` regardless of the COST_CONDITIONS flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConditionCosts {
+    /// charged once for every spend
+    pub spend: Cost,
+    pub create_coin: Cost,
+    pub agg_sig: Cost,
+    /// announcements, messages and concurrent spend assertions
+    pub message: Cost,
+    /// all other conditions, including unknown ones
+    pub generic: Cost,
+}
+
+impl Default for ConditionCosts {
+    fn default() -> Self {
+        Self {
+            spend: SPEND_COST,
+            create_coin: NEW_CREATE_COIN_COST,
+            agg_sig: AGG_SIG_COST,
+            message: MESSAGE_CONDITION_COST,
+            generic: GENERIC_CONDITION_COST,
+        }
+    }
+}
+
+/// Provides the costs charged for spends and conditions, replacing the
+/// built-in cost schedule. See parse_spends_with_cost_oracle(). The costs are
+/// subtracted from the cost limit, and added to the `condition_cost` of the
+/// spend and the spend bundle.
+pub trait CostOracle {
+    /// The cost charged once for every spend.
+    fn spend_cost(&self, flags: ConsensusFlags) -> Cost;
+    /// The cost charged for a condition. `op` is None for unknown conditions.
+    fn condition_cost(&self, op: Option<ConditionOpcode>, flags: ConsensusFlags) -> Cost;
+}
+
+/// The built-in cost schedule. Except for AGG_SIG conditions and (legacy)
+/// CREATE_COIN, conditions only have a cost with the COST_CONDITIONS flag.
+impl CostOracle for ConditionCosts {
+    fn spend_cost(&self, flags: ConsensusFlags) -> Cost {
+        if flags.contains(ConsensusFlags::COST_CONDITIONS) {
+            self.spend
+        } else {
+            0
+        }
+    }
+
+    fn condition_cost(&self, op: Option<ConditionOpcode>, flags: ConsensusFlags) -> Cost {
+        let cost_conditions = flags.contains(ConsensusFlags::COST_CONDITIONS);
+        match op {
+            Some(CREATE_COIN) => {
+                if cost_conditions {
+                    self.create_coin
+                } else {
+                    CREATE_COIN_COST
+                }
+            }
+            Some(
+                AGG_SIG_UNSAFE
+                | AGG_SIG_ME
+                | AGG_SIG_PUZZLE
+                | AGG_SIG_PUZZLE_AMOUNT
+                | AGG_SIG_PARENT
+                | AGG_SIG_AMOUNT
+                | AGG_SIG_PARENT_PUZZLE
+                | AGG_SIG_PARENT_AMOUNT,
+            ) => self.agg_sig,
+            _ if !cost_conditions => 0,
+            Some(
+                CREATE_COIN_ANNOUNCEMENT
+                | ASSERT_COIN_ANNOUNCEMENT
+                | CREATE_PUZZLE_ANNOUNCEMENT
+                | ASSERT_PUZZLE_ANNOUNCEMENT
+                | ASSERT_CONCURRENT_SPEND
+                | ASSERT_CONCURRENT_PUZZLE
+                | SEND_MESSAGE
+                | RECEIVE_MESSAGE,
+            ) => self.message,
+            _ => self.generic,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct ParseState {
     // hashing of the announcements is deferred until parsing is complete. This
@@ -964,6 +1342,11 @@ pub struct ParseState {
     // each item is the index into the SpendBundleConditions::spends vector
     assert_not_ephemeral: HashSet<usize>,
 
+    // the number of coin- and puzzle announcements created and asserted so
+    // far, across all spends
+    announcements_created: usize,
+    announcements_asserted: usize,
+
     // All public keys and messages emitted by the generator. We'll validate
     // these against the aggregate signature at the end, unless the
     // DONT_VALIDATE_SIGNATURE flag is set
@@ -972,6 +1355,49 @@ pub struct ParseState {
     pub pkm_pairs: Vec<(PublicKey, Bytes)>,
 }
 
+impl ParseState {
+    /// Returns the IDs of all coin- and puzzle announcements that were
+    /// created, but not asserted by any spend. The IDs are sorted.
+    pub fn unused_announcements(&self, a: &Allocator) -> Vec<Bytes32> {
+        let asserted: HashSet<Bytes32> = self.asserted_announcements(a).into_iter().collect();
+
+        let coin_announcements = self.announce_coin.iter().map(|(coin_id, announce)| {
+            let mut hasher = Sha256::new();
+            hasher.update(**coin_id);
+            hasher.update(a.atom(*announce));
+            Bytes32::from(hasher.finalize())
+        });
+        let puzzle_announcements = self.announce_puzzle.iter().map(|(puzzle_hash, announce)| {
+            let mut hasher = Sha256::new();
+            hasher.update(a.atom(*puzzle_hash));
+            hasher.update(a.atom(*announce));
+            Bytes32::from(hasher.finalize())
+        });
+
+        let mut ret: Vec<Bytes32> = coin_announcements
+            .chain(puzzle_announcements)
+            .filter(|id| !asserted.contains(id))
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    /// Returns the IDs of all coin- and puzzle announcements asserted by any
+    /// spend, whether they were created or not. The IDs are sorted.
+    pub fn asserted_announcements(&self, a: &Allocator) -> Vec<Bytes32> {
+        let mut ret: Vec<Bytes32> = self
+            .assert_coin
+            .iter()
+            .chain(&self.assert_puzzle)
+            .map(|id| a.atom(*id).as_ref().try_into().unwrap())
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+}
+
 // returns (parent-id, puzzle-hash, amount, condition-list)
 pub(crate) fn parse_single_spend(
     a: &Allocator,
@@ -1001,6 +1427,39 @@ pub fn process_single_spend<'a, V: SpendVisitor>(
     max_cost: &mut Cost,
     clvm_cost: Cost,
     constants: &ConsensusConstants,
+) -> Result<&'a mut SpendConditions, ValidationErr> {
+    process_single_spend_with_costs::<V, _>(
+        a,
+        ret,
+        state,
+        parent_id,
+        puzzle_hash,
+        amount,
+        conditions,
+        flags,
+        max_cost,
+        clvm_cost,
+        constants,
+        &ConditionCosts::default(),
+    )
+}
+
+/// Like process_single_spend(), but the costs of the spend and its conditions
+/// are provided by `costs`, instead of the built-in cost schedule.
+#[allow(clippy::too_many_arguments)]
+pub fn process_single_spend_with_costs<'a, V: SpendVisitor, O: CostOracle>(
+    a: &Allocator,
+    ret: &'a mut SpendBundleConditions,
+    state: &mut ParseState,
+    parent_id: NodePtr,
+    puzzle_hash: NodePtr,
+    amount: NodePtr,
+    conditions: NodePtr,
+    flags: ConsensusFlags,
+    max_cost: &mut Cost,
+    clvm_cost: Cost,
+    constants: &ConsensusConstants,
+    costs: &O,
 ) -> Result<&'a mut SpendConditions, ValidationErr> {
     let parent_id = sanitize_hash(a, parent_id, 32, ErrorCode::InvalidParentId)?;
     let puzzle_hash = sanitize_hash(a, puzzle_hash, 32, ErrorCode::InvalidPuzzleHash)?;
@@ -1030,18 +1489,18 @@ pub fn process_single_spend<'a, V: SpendVisitor>(
 
     let mut spend = SpendConditions::new(parent_id, my_amount, puzzle_hash, coin_id, clvm_cost);
 
-    if flags.contains(ConsensusFlags::COST_CONDITIONS) {
-        if *max_cost < SPEND_COST {
-            return Err(ValidationErr::Err(ErrorCode::CostExceeded));
-        }
-        *max_cost -= SPEND_COST;
-        ret.condition_cost += SPEND_COST;
-        spend.condition_cost += SPEND_COST;
+    let cost = costs.spend_cost(flags);
+    if *max_cost < cost {
+        return Err(ValidationErr::Err(ErrorCode::ConditionCostExceeded));
     }
+    *max_cost -= cost;
+    ret.condition_cost += cost;
+    spend.condition_cost += cost;
 
     let mut visitor = V::new_spend(&mut spend);
 
-    parse_conditions(
+    let mut announce_countdown = MAX_ANNOUNCEMENTS_PER_SPEND;
+    parse_conditions_with_costs(
         a,
         ret,
         state,
@@ -1051,6 +1510,8 @@ pub fn process_single_spend<'a, V: SpendVisitor>(
         max_cost,
         constants,
         &mut visitor,
+        costs,
+        &mut announce_countdown,
     )
 }
 
@@ -1082,8 +1543,58 @@ fn to_key(a: &Allocator, pk: NodePtr) -> Result<PublicKey, ValidationErr> {
     }
 }
 
+// records the public key and (final) message of an AGG_SIG condition, to be
+// validated against the aggregate signature and/or to be returned to the
+// caller
+fn push_sig_pair(
+    ret: &mut SpendBundleConditions,
+    state: &mut ParseState,
+    flags: ConsensusFlags,
+    pk: PublicKey,
+    msg: Bytes,
+    opcode: ConditionOpcode,
+) {
+    if flags.contains(ConsensusFlags::COLLECT_SIG_PAIRS) {
+        ret.sig_pairs.push((pk, msg.clone(), opcode));
+    }
+    if !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE) {
+        state.pkm_pairs.push((pk, msg));
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn parse_conditions<'a, V: SpendVisitor>(
+    a: &Allocator,
+    ret: &'a mut SpendBundleConditions,
+    state: &mut ParseState,
+    spend: SpendConditions,
+    iter: NodePtr,
+    flags: ConsensusFlags,
+    max_cost: &mut Cost,
+    constants: &ConsensusConstants,
+    visitor: &mut V,
+) -> Result<&'a mut SpendConditions, ValidationErr> {
+    let mut announce_countdown = MAX_ANNOUNCEMENTS_PER_SPEND;
+    parse_conditions_with_costs(
+        a,
+        ret,
+        state,
+        spend,
+        iter,
+        flags,
+        max_cost,
+        constants,
+        visitor,
+        &ConditionCosts::default(),
+        &mut announce_countdown,
+    )
+}
+
+// like parse_conditions(), but with the costs provided by `costs`. The
+// announcements and messages count down `announce_countdown`, which allows
+// parsing the conditions of a spend in more than one call
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn parse_conditions_with_costs<'a, V: SpendVisitor, O: CostOracle>(
     a: &Allocator,
     ret: &'a mut SpendBundleConditions,
     state: &mut ParseState,
@@ -1093,8 +1604,11 @@ pub fn parse_conditions<'a, V: SpendVisitor>(
     max_cost: &mut Cost,
     constants: &ConsensusConstants,
     visitor: &mut V,
+    costs: &O,
+    announce_countdown: &mut u32,
 ) -> Result<&'a mut SpendConditions, ValidationErr> {
-    let mut announce_countdown: u32 = 1024;
+    let collect_pairs = !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE)
+        || flags.contains(ConsensusFlags::COLLECT_SIG_PAIRS);
 
     while let Some((mut c, next)) = next(a, iter)? {
         iter = next;
@@ -1106,76 +1620,25 @@ pub fn parse_conditions<'a, V: SpendVisitor>(
             }
             // in consensus-mode, we ignore unknown conditions, but still charge
             // cost for them
-            if flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                if *max_cost < GENERIC_CONDITION_COST {
-                    return Err(ValidationErr::Err(ErrorCode::CostExceeded));
-                }
-                *max_cost -= GENERIC_CONDITION_COST;
-                ret.condition_cost += GENERIC_CONDITION_COST;
-                spend.condition_cost += GENERIC_CONDITION_COST;
+            let cost = costs.condition_cost(None, flags);
+            if *max_cost < cost {
+                return Err(ValidationErr::Err(ErrorCode::ConditionCostExceeded));
             }
+            *max_cost -= cost;
+            ret.condition_cost += cost;
+            spend.condition_cost += cost;
             continue;
         };
 
         // subtract the max_cost based on the current condition
         // in case we exceed the limit, we want to fail as early as possible
-        match op {
-            CREATE_COIN => {
-                let cost = if flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    NEW_CREATE_COIN_COST
-                } else {
-                    CREATE_COIN_COST
-                };
-                if *max_cost < cost {
-                    return Err(ValidationErr::Err(ErrorCode::CostExceeded));
-                }
-                *max_cost -= cost;
-                ret.condition_cost += cost;
-                spend.condition_cost += cost;
-            }
-            AGG_SIG_UNSAFE
-            | AGG_SIG_ME
-            | AGG_SIG_PUZZLE
-            | AGG_SIG_PUZZLE_AMOUNT
-            | AGG_SIG_PARENT
-            | AGG_SIG_AMOUNT
-            | AGG_SIG_PARENT_PUZZLE
-            | AGG_SIG_PARENT_AMOUNT => {
-                if *max_cost < AGG_SIG_COST {
-                    return Err(ValidationErr::Err(ErrorCode::CostExceeded));
-                }
-                *max_cost -= AGG_SIG_COST;
-                ret.condition_cost += AGG_SIG_COST;
-                spend.condition_cost += AGG_SIG_COST;
-            }
-            CREATE_COIN_ANNOUNCEMENT
-            | ASSERT_COIN_ANNOUNCEMENT
-            | CREATE_PUZZLE_ANNOUNCEMENT
-            | ASSERT_PUZZLE_ANNOUNCEMENT
-            | ASSERT_CONCURRENT_SPEND
-            | ASSERT_CONCURRENT_PUZZLE
-            | SEND_MESSAGE
-            | RECEIVE_MESSAGE => {
-                if flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    if *max_cost < MESSAGE_CONDITION_COST {
-                        return Err(ValidationErr::Err(ErrorCode::CostExceeded));
-                    }
-                    *max_cost -= MESSAGE_CONDITION_COST;
-                    ret.condition_cost += MESSAGE_CONDITION_COST;
-                    spend.condition_cost += MESSAGE_CONDITION_COST;
-                }
-            }
-            _ => {
-                if flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    if *max_cost < GENERIC_CONDITION_COST {
-                        return Err(ValidationErr::Err(ErrorCode::CostExceeded));
-                    }
-                    *max_cost -= GENERIC_CONDITION_COST;
-                    ret.condition_cost += GENERIC_CONDITION_COST;
-                    spend.condition_cost += GENERIC_CONDITION_COST;
-                }
-            }
+        let cost = costs.condition_cost(Some(op), flags);
+        if *max_cost < cost {
+            return Err(ValidationErr::Err(ErrorCode::ConditionCostExceeded));
         }
+        *max_cost -= cost;
+        ret.condition_cost += cost;
+        spend.condition_cost += cost;
         c = rest(a, c)?;
         let cva = parse_args(a, c, op, flags)?;
         visitor.condition(&mut spend, &cva);
@@ -1188,13 +1651,20 @@ pub fn parse_conditions<'a, V: SpendVisitor>(
                     .ok_or(ValidationErr::Err(ErrorCode::ReserveFeeConditionFailed))?;
             }
             Condition::CreateCoin(ph, amount, hint) => {
+                let puzzle_hash: Bytes32 = a.atom(ph).as_ref().try_into().unwrap();
                 let new_coin = NewCoin {
-                    puzzle_hash: a.atom(ph).as_ref().try_into().unwrap(),
+                    puzzle_hash,
                     amount,
                     hint,
                 };
+                // a coin's ID commits to its parent, so two spends can only
+                // create the same coin if they spend the same coin, which is
+                // already rejected as a double spend. Checking the outputs of
+                // each spend is sufficient to catch duplicates across the
+                // whole block
                 if !spend.create_coin.insert(new_coin) {
-                    return Err(ValidationErr::Err(ErrorCode::DuplicateOutput));
+                    let coin_id = Coin::new(*spend.coin_id, puzzle_hash, amount).coin_id();
+                    return Err(ValidationErr::Spend(ErrorCode::DuplicateOutput, coin_id));
                 }
                 ret.addition_amount += amount as u128;
             }
@@ -1305,7 +1775,10 @@ pub fn parse_conditions<'a, V: SpendVisitor>(
             }
             Condition::AssertMyAmount(amount) => {
                 if amount != spend.coin_amount {
-                    return Err(ValidationErr::Err(ErrorCode::AssertMyAmountFailed));
+                    return Err(ValidationErr::Spend(
+                        ErrorCode::AssertMyAmountFailed,
+                        *spend.coin_id,
+                    ));
                 }
             }
             Condition::AssertMyBirthSeconds(s) => {
@@ -1343,104 +1816,150 @@ pub fn parse_conditions<'a, V: SpendVisitor>(
             }
             Condition::CreateCoinAnnouncement(msg) => {
                 if !flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    decrement(&mut announce_countdown)?;
+                    decrement(announce_countdown)?;
                 }
+                state.announcements_created += 1;
                 state.announce_coin.insert((spend.coin_id.clone(), msg));
             }
             Condition::CreatePuzzleAnnouncement(msg) => {
                 if !flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    decrement(&mut announce_countdown)?;
+                    decrement(announce_countdown)?;
                 }
+                state.announcements_created += 1;
                 state.announce_puzzle.insert((spend.puzzle_hash, msg));
             }
             Condition::AssertCoinAnnouncement(msg) => {
                 if !flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    decrement(&mut announce_countdown)?;
+                    decrement(announce_countdown)?;
                 }
+                state.announcements_asserted += 1;
                 state.assert_coin.insert(msg);
             }
             Condition::AssertPuzzleAnnouncement(msg) => {
                 if !flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    decrement(&mut announce_countdown)?;
+                    decrement(announce_countdown)?;
                 }
+                state.announcements_asserted += 1;
                 state.assert_puzzle.insert(msg);
             }
             Condition::AssertConcurrentSpend(id) => {
                 if !flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    decrement(&mut announce_countdown)?;
+                    decrement(announce_countdown)?;
                 }
                 state.assert_concurrent_spend.insert(id);
             }
             Condition::AssertConcurrentPuzzle(id) => {
                 if !flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    decrement(&mut announce_countdown)?;
+                    decrement(announce_countdown)?;
                 }
                 state.assert_concurrent_puzzle.insert(id);
             }
             Condition::AggSigMe(pk, msg) => {
                 spend.agg_sig_me.push((to_key(a, pk)?, msg));
-                if !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE) {
+                if collect_pairs {
                     let mut msg = a.atom(msg).as_ref().to_vec();
                     msg.extend((*spend.coin_id).as_slice());
                     msg.extend(constants.agg_sig_me_additional_data.as_slice());
-                    state.pkm_pairs.push((to_key(a, pk)?, msg.into()));
+                    push_sig_pair(ret, state, flags, to_key(a, pk)?, msg.into(), AGG_SIG_ME);
                 }
             }
             Condition::AggSigParent(pk, msg) => {
                 spend.agg_sig_parent.push((to_key(a, pk)?, msg));
-                if !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE) {
+                if collect_pairs {
                     let mut msg = a.atom(msg).as_ref().to_vec();
                     msg.extend(a.atom(spend.parent_id).as_ref());
                     msg.extend(constants.agg_sig_parent_additional_data.as_slice());
-                    state.pkm_pairs.push((to_key(a, pk)?, msg.into()));
+                    push_sig_pair(
+                        ret,
+                        state,
+                        flags,
+                        to_key(a, pk)?,
+                        msg.into(),
+                        AGG_SIG_PARENT,
+                    );
                 }
             }
             Condition::AggSigPuzzle(pk, msg) => {
                 spend.agg_sig_puzzle.push((to_key(a, pk)?, msg));
-                if !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE) {
+                if collect_pairs {
                     let mut msg = a.atom(msg).as_ref().to_vec();
                     msg.extend(a.atom(spend.puzzle_hash).as_ref());
                     msg.extend(constants.agg_sig_puzzle_additional_data.as_slice());
-                    state.pkm_pairs.push((to_key(a, pk)?, msg.into()));
+                    push_sig_pair(
+                        ret,
+                        state,
+                        flags,
+                        to_key(a, pk)?,
+                        msg.into(),
+                        AGG_SIG_PUZZLE,
+                    );
                 }
             }
             Condition::AggSigAmount(pk, msg) => {
                 spend.agg_sig_amount.push((to_key(a, pk)?, msg));
-                if !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE) {
+                if collect_pairs {
                     let mut msg = a.atom(msg).as_ref().to_vec();
                     msg.extend(u64_to_bytes(spend.coin_amount).as_slice());
                     msg.extend(constants.agg_sig_amount_additional_data.as_slice());
-                    state.pkm_pairs.push((to_key(a, pk)?, msg.into()));
+                    push_sig_pair(
+                        ret,
+                        state,
+                        flags,
+                        to_key(a, pk)?,
+                        msg.into(),
+                        AGG_SIG_AMOUNT,
+                    );
                 }
             }
             Condition::AggSigPuzzleAmount(pk, msg) => {
                 spend.agg_sig_puzzle_amount.push((to_key(a, pk)?, msg));
-                if !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE) {
+                if collect_pairs {
                     let mut msg = a.atom(msg).as_ref().to_vec();
                     msg.extend(a.atom(spend.puzzle_hash).as_ref());
                     msg.extend(u64_to_bytes(spend.coin_amount).as_slice());
                     msg.extend(constants.agg_sig_puzzle_amount_additional_data.as_slice());
-                    state.pkm_pairs.push((to_key(a, pk)?, msg.into()));
+                    push_sig_pair(
+                        ret,
+                        state,
+                        flags,
+                        to_key(a, pk)?,
+                        msg.into(),
+                        AGG_SIG_PUZZLE_AMOUNT,
+                    );
                 }
             }
             Condition::AggSigParentAmount(pk, msg) => {
                 spend.agg_sig_parent_amount.push((to_key(a, pk)?, msg));
-                if !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE) {
+                if collect_pairs {
                     let mut msg = a.atom(msg).as_ref().to_vec();
                     msg.extend(a.atom(spend.parent_id).as_ref());
                     msg.extend(u64_to_bytes(spend.coin_amount).as_slice());
                     msg.extend(constants.agg_sig_parent_amount_additional_data.as_slice());
-                    state.pkm_pairs.push((to_key(a, pk)?, msg.into()));
+                    push_sig_pair(
+                        ret,
+                        state,
+                        flags,
+                        to_key(a, pk)?,
+                        msg.into(),
+                        AGG_SIG_PARENT_AMOUNT,
+                    );
                 }
             }
             Condition::AggSigParentPuzzle(pk, msg) => {
                 spend.agg_sig_parent_puzzle.push((to_key(a, pk)?, msg));
-                if !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE) {
+                if collect_pairs {
                     let mut msg = a.atom(msg).as_ref().to_vec();
                     msg.extend(a.atom(spend.parent_id).as_ref());
                     msg.extend(a.atom(spend.puzzle_hash).as_ref());
                     msg.extend(constants.agg_sig_parent_puzzle_additional_data.as_slice());
-                    state.pkm_pairs.push((to_key(a, pk)?, msg.into()));
+                    push_sig_pair(
+                        ret,
+                        state,
+                        flags,
+                        to_key(a, pk)?,
+                        msg.into(),
+                        AGG_SIG_PARENT_PUZZLE,
+                    );
                 }
             }
             Condition::AggSigUnsafe(pk, msg) => {
@@ -1448,23 +1967,23 @@ pub fn parse_conditions<'a, V: SpendVisitor>(
                 // suffix added to other AGG_SIG_* conditions
                 check_agg_sig_unsafe_message(a, msg, constants)?;
                 ret.agg_sig_unsafe.push((to_key(a, pk)?, msg));
-                if !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE) {
-                    state
-                        .pkm_pairs
-                        .push((to_key(a, pk)?, a.atom(msg).as_ref().to_vec().into()));
+                if collect_pairs {
+                    let msg = a.atom(msg).as_ref().to_vec().into();
+                    push_sig_pair(ret, state, flags, to_key(a, pk)?, msg, AGG_SIG_UNSAFE);
                 }
             }
             Condition::Softfork(cost) => {
                 if *max_cost < cost {
-                    return Err(ValidationErr::Err(ErrorCode::CostExceeded));
+                    return Err(ValidationErr::Err(ErrorCode::ConditionCostExceeded));
                 }
                 *max_cost -= cost;
                 ret.condition_cost += cost;
+                ret.softfork_cost += cost;
                 spend.condition_cost += cost;
             }
             Condition::SendMessage(src_mode, dst, msg) => {
                 if !flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    decrement(&mut announce_countdown)?;
+                    decrement(announce_countdown)?;
                 }
                 let src = SpendId::from_self(
                     src_mode,
@@ -1482,7 +2001,7 @@ pub fn parse_conditions<'a, V: SpendVisitor>(
             }
             Condition::ReceiveMessage(src, dst_mode, msg) => {
                 if !flags.contains(ConsensusFlags::COST_CONDITIONS) {
-                    decrement(&mut announce_countdown)?;
+                    decrement(announce_countdown)?;
                 }
                 let dst = SpendId::from_self(
                     dst_mode,
@@ -1535,6 +2054,43 @@ fn is_ephemeral(
     })
 }
 
+// if the COMPUTE_FINGERPRINT flag is set, and `spend` is eligible for dedup,
+// sets its fingerprint, computed from its `conditions`
+pub(crate) fn compute_fingerprint(
+    a: &Allocator,
+    spend: &mut SpendConditions,
+    conditions: NodePtr,
+    flags: ConsensusFlags,
+) -> Result<(), ValidationErr> {
+    if (spend.flags & ELIGIBLE_FOR_DEDUP) != 0
+        && flags.contains(ConsensusFlags::COMPUTE_FINGERPRINT)
+    {
+        spend.fingerprint = compute_puzzle_fingerprint(a, conditions)?;
+    }
+    Ok(())
+}
+
+/// Returns the list of spends from the output of a block generator, which is
+/// in the form (spends . extra). Fails with MalformedSpendList if the output
+/// isn't a pair, or if the spends aren't a proper (nil terminated) list.
+/// parse_spends() and the block generators don't use this, they report
+/// malformed spend lists with the error codes consensus has always used
+/// (InvalidCondition and GeneratorRuntimeError).
+pub fn spend_list(a: &Allocator, output: NodePtr) -> Result<NodePtr, ValidationErr> {
+    let malformed = ValidationErr::Err(ErrorCode::MalformedSpendList);
+    let SExp::Pair(spends, _) = a.sexp(output) else {
+        return Err(malformed);
+    };
+    let mut iter = spends;
+    while let SExp::Pair(_, rest) = a.sexp(iter) {
+        iter = rest;
+    }
+    if a.atom_len(iter) != 0 {
+        return Err(malformed);
+    }
+    Ok(spends)
+}
+
 // This function parses, and validates aspects of, the above structure and
 // returns a list of all spends, along with all conditions, organized by
 // condition op-code
@@ -1548,6 +2104,59 @@ pub fn parse_spends<V: SpendVisitor>(
     aggregate_signature: &Signature,
     bls_cache: Option<&BlsCache>,
     constants: &ConsensusConstants,
+) -> Result<SpendBundleConditions, ValidationErr> {
+    parse_spends_with_costs::<V, _>(
+        a,
+        spends,
+        max_cost,
+        clvm_cost,
+        flags,
+        aggregate_signature,
+        bls_cache,
+        constants,
+        &ConditionCosts::default(),
+    )
+}
+
+/// Like parse_spends(), but the costs of spends and conditions are provided by
+/// `oracle`, instead of the built-in cost schedule. The built-in schedule is
+/// `ConditionCosts::default()`.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_spends_with_cost_oracle<V: SpendVisitor, O: CostOracle>(
+    a: &Allocator,
+    spends: NodePtr,
+    max_cost: Cost,
+    clvm_cost: Cost,
+    flags: ConsensusFlags,
+    aggregate_signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+    oracle: &O,
+) -> Result<SpendBundleConditions, ValidationErr> {
+    parse_spends_with_costs::<V, _>(
+        a,
+        spends,
+        max_cost,
+        clvm_cost,
+        flags,
+        aggregate_signature,
+        bls_cache,
+        constants,
+        oracle,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn parse_spends_with_costs<V: SpendVisitor, O: CostOracle>(
+    a: &Allocator,
+    spends: NodePtr,
+    max_cost: Cost,
+    clvm_cost: Cost,
+    flags: ConsensusFlags,
+    aggregate_signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+    costs: &O,
 ) -> Result<SpendBundleConditions, ValidationErr> {
     let mut ret = SpendBundleConditions::default();
     let mut state = ParseState::default();
@@ -1574,7 +2183,7 @@ pub fn parse_spends<V: SpendVisitor>(
         // as well as updates it with any conditions
         let (parent_id, puzzle_hash, amount, conds) = parse_single_spend(a, spend)?;
 
-        process_single_spend::<V>(
+        let spend = process_single_spend_with_costs::<V, _>(
             a,
             &mut ret,
             &mut state,
@@ -1586,12 +2195,310 @@ pub fn parse_spends<V: SpendVisitor>(
             &mut cost_left,
             clvm_cost,
             constants,
+            costs,
         )?;
+        compute_fingerprint(a, spend, conds, flags)?;
     }
 
     V::post_process(a, &state, &mut ret)?;
     validate_conditions(a, &ret, &state, flags)?;
-    validate_signature(&state, aggregate_signature, flags, bls_cache)?;
+    ret.pairings = validate_signature(&state, aggregate_signature, flags, bls_cache)?;
+    ret.validated_signature = !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE);
+
+    ret.cost = max_cost - cost_left;
+    Ok(ret)
+}
+
+// Parses the spend list (in the same format as `parse_spends()`) and calls `f`
+// with the conditions of every spend, and the state, once the spend has been
+// parsed. Every spend is parsed and validated on its own, but the spend list
+// is not validated as a whole, nor is the signature.
+fn parse_each_spend(
+    a: &Allocator,
+    spends: NodePtr,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+    mut f: impl FnMut(&SpendConditions, &ParseState) -> Result<(), ValidationErr>,
+) -> Result<(SpendBundleConditions, ParseState), ValidationErr> {
+    let mut ret = SpendBundleConditions::default();
+    let mut state = ParseState::default();
+    let mut cost_left = constants.max_block_cost_clvm;
+
+    let mut iter = spend_list(a, spends)?;
+    while let Some((spend, next)) = next(a, iter)? {
+        iter = next;
+        let (parent_id, puzzle_hash, amount, conds) = parse_single_spend(a, spend)?;
+        let spend = process_single_spend::<EmptyVisitor>(
+            a,
+            &mut ret,
+            &mut state,
+            parent_id,
+            puzzle_hash,
+            amount,
+            conds,
+            flags,
+            &mut cost_left,
+            0,
+            constants,
+        )?;
+        f(spend, &state)?;
+    }
+    Ok((ret, state))
+}
+
+/// Parses the spend list (in the same format as `parse_spends()`) and returns
+/// the IDs of all announcements that are created but never asserted. The
+/// conditions are parsed, but the spend list is not validated as a whole, nor
+/// is the signature.
+pub fn unused_announcements(
+    a: &Allocator,
+    spends: NodePtr,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<Vec<Bytes32>, ValidationErr> {
+    let (_, state) = parse_each_spend(a, spends, flags, constants, |_, _| Ok(()))?;
+    Ok(state.unused_announcements(a))
+}
+
+/// Parses the spend list (in the same format as `parse_spends()`) and returns
+/// the IDs of all announcements asserted by ASSERT_COIN_ANNOUNCEMENT and
+/// ASSERT_PUZZLE_ANNOUNCEMENT conditions, whether they are created by the
+/// spend list or not. This lets a mempool find the spend bundles creating the
+/// announcements another one depends on. The IDs are sorted. The conditions
+/// are parsed, but the spend list is not validated as a whole, nor is the
+/// signature.
+pub fn assertion_targets(
+    a: &Allocator,
+    spends: NodePtr,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<Vec<Bytes32>, ValidationErr> {
+    let (_, state) = parse_each_spend(a, spends, flags, constants, |_, _| Ok(()))?;
+    Ok(state.asserted_announcements(a))
+}
+
+/// Parses the spend list (in the same format as `parse_spends()`) and returns,
+/// for every spend, the number of announcements it creates and the number of
+/// announcements it asserts, as `(created, asserted)`. Both coin- and puzzle
+/// announcements are counted. This is cheaper than computing the announcement
+/// IDs. Every spend is parsed and validated on its own, but the spend list is
+/// not validated as a whole, nor is the signature.
+pub fn announcement_counts(
+    a: &Allocator,
+    spends: NodePtr,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<Vec<(usize, usize)>, ValidationErr> {
+    let mut counts = Vec::new();
+    // the counters in the state are totals for all spends so far
+    let mut totals = (0, 0);
+    parse_each_spend(a, spends, flags, constants, |_, state| {
+        let created = state.announcements_created - totals.0;
+        let asserted = state.announcements_asserted - totals.1;
+        totals = (state.announcements_created, state.announcements_asserted);
+        counts.push((created, asserted));
+        Ok(())
+    })?;
+    Ok(counts)
+}
+
+/// Parses the spend list (in the same format as `parse_spends()`) and fails
+/// with TooManyAnnouncementsForCost if any spend creates more announcements
+/// than its cost pays for. Each announcement a spend creates (coin- or puzzle
+/// announcement) requires `cost_per_announcement` of the spend's cost, i.e.
+/// the number of announcements allowed scales with the cost. The cost of a
+/// spend is the cost of its conditions plus its execution cost, which is
+/// taken from `bundle_conds`, the conditions of the same spends (e.g. as
+/// returned by `run_block_generator2()`). Spends are matched by coin ID, a
+/// spend missing from `bundle_conds` has no execution cost.
+/// A `cost_per_announcement` of 0 disables the limit. The error carries the
+/// coin ID of the first spend to exceed the limit. This is not a consensus
+/// rule, it's meant for the mempool to limit cheap spam of announcements.
+pub fn check_announcement_cost_limit(
+    a: &Allocator,
+    spends: NodePtr,
+    bundle_conds: &OwnedSpendBundleConditions,
+    cost_per_announcement: u64,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<(), ValidationErr> {
+    let execution_costs: HashMap<Bytes32, Cost> = bundle_conds
+        .spends
+        .iter()
+        .map(|s| (s.coin_id, s.execution_cost))
+        .collect();
+
+    // the counter in the state is the total for all spends so far
+    let mut total_created = 0;
+    parse_each_spend(a, spends, flags, constants, |spend, state| {
+        let created = (state.announcements_created - total_created) as u64;
+        total_created = state.announcements_created;
+        let execution_cost = execution_costs.get(&*spend.coin_id).copied();
+        let cost = execution_cost.unwrap_or(0) + spend.condition_cost;
+        let allowed = cost.checked_div(cost_per_announcement).unwrap_or(u64::MAX);
+        if created > allowed {
+            return Err(ValidationErr::Spend(
+                ErrorCode::TooManyAnnouncementsForCost,
+                *spend.coin_id,
+            ));
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Parses the spend list (in the same format as `parse_spends()`) and fails
+/// with AnnouncementTooLarge if any coin- or puzzle announcement message is
+/// longer than `max_msg_size` bytes. Messages longer than 1024 bytes are
+/// invalid regardless of this limit. This is not a consensus rule, it's meant
+/// for the mempool to limit the size of announcements it relays.
+pub fn check_announcement_size(
+    a: &Allocator,
+    spends: NodePtr,
+    max_msg_size: u32,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<(), ValidationErr> {
+    let (_, state) = parse_each_spend(a, spends, flags, constants, |_, _| Ok(()))?;
+    let coin_messages = state.announce_coin.iter().map(|(_, msg)| msg);
+    let puzzle_messages = state.announce_puzzle.iter().map(|(_, msg)| msg);
+    if coin_messages
+        .chain(puzzle_messages)
+        .any(|msg| a.atom_len(*msg) > max_msg_size as usize)
+    {
+        return Err(ValidationErr::Err(ErrorCode::AnnouncementTooLarge));
+    }
+    Ok(())
+}
+
+// the structure of the spend list, as extracted by a single pass over it. If
+// the list is malformed, the error is recorded along with whether it was
+// encountered on a list element (as opposed to the list itself). This affects
+// whether TooManySpends takes precedence over it or not.
+struct SpendList {
+    spends: Vec<(NodePtr, NodePtr, NodePtr, NodePtr)>,
+    error: Option<(ErrorCode, bool)>,
+}
+
+fn collect_spends(a: &Allocator, spends: NodePtr) -> SpendList {
+    let mut ret = SpendList {
+        spends: Vec::new(),
+        error: None,
+    };
+    let mut iter = match first(a, spends) {
+        Ok(n) => n,
+        Err(e) => {
+            ret.error = Some((e.error_code(), false));
+            return ret;
+        }
+    };
+    loop {
+        match next(a, iter) {
+            Ok(Some((spend, next))) => {
+                iter = next;
+                match parse_single_spend(a, spend) {
+                    Ok(s) => ret.spends.push(s),
+                    Err(e) => {
+                        ret.error = Some((e.error_code(), true));
+                        return ret;
+                    }
+                }
+            }
+            Ok(None) => return ret,
+            Err(e) => {
+                ret.error = Some((e.error_code(), false));
+                return ret;
+            }
+        }
+    }
+}
+
+/// Parse the same spend list under multiple sets of flags. The spend list
+/// structure is only traversed once and shared between all flag sets. The
+/// result for each flag set is the same as calling `parse_spends()` with
+/// those flags.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_spends_multi<V: SpendVisitor>(
+    a: &Allocator,
+    spends: NodePtr,
+    max_cost: Cost,
+    clvm_cost: Cost,
+    flags: &[ConsensusFlags],
+    aggregate_signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+) -> Vec<Result<SpendBundleConditions, ValidationErr>> {
+    let list = collect_spends(a, spends);
+    flags
+        .iter()
+        .map(|f| {
+            parse_collected_spends::<V>(
+                a,
+                &list,
+                max_cost,
+                clvm_cost,
+                *f,
+                aggregate_signature,
+                bls_cache,
+                constants,
+            )
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn parse_collected_spends<V: SpendVisitor>(
+    a: &Allocator,
+    list: &SpendList,
+    max_cost: Cost,
+    clvm_cost: Cost,
+    flags: ConsensusFlags,
+    aggregate_signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+) -> Result<SpendBundleConditions, ValidationErr> {
+    let mut ret = SpendBundleConditions::default();
+    let mut state = ParseState::default();
+
+    let mut cost_left = max_cost;
+
+    let mut spends_left: usize = if flags.contains(ConsensusFlags::LIMIT_SPENDS) {
+        MAX_SPENDS_PER_BLOCK
+    } else {
+        usize::MAX
+    };
+
+    for (parent_id, puzzle_hash, amount, conds) in &list.spends {
+        if spends_left == 0 {
+            return Err(ValidationErr::Err(ErrorCode::TooManySpends));
+        }
+        spends_left -= 1;
+        let spend = process_single_spend::<V>(
+            a,
+            &mut ret,
+            &mut state,
+            *parent_id,
+            *puzzle_hash,
+            *amount,
+            *conds,
+            flags,
+            &mut cost_left,
+            clvm_cost,
+            constants,
+        )?;
+        compute_fingerprint(a, spend, *conds, flags)?;
+    }
+
+    if let Some((code, on_element)) = list.error {
+        if on_element && spends_left == 0 {
+            return Err(ValidationErr::Err(ErrorCode::TooManySpends));
+        }
+        return Err(ValidationErr::Err(code));
+    }
+
+    V::post_process(a, &state, &mut ret)?;
+    validate_conditions(a, &ret, &state, flags)?;
+    ret.pairings = validate_signature(&state, aggregate_signature, flags, bls_cache)?;
     ret.validated_signature = !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE);
 
     ret.cost = max_cost - cost_left;
@@ -1610,9 +2517,14 @@ pub fn validate_conditions(
         return Err(ValidationErr::Err(ErrorCode::MintingCoin));
     }
 
-    if ret.removal_amount - ret.addition_amount < ret.reserve_fee as u128 {
-        // the actual fee is lower than the reserved fee
-        return Err(ValidationErr::Err(ErrorCode::ReserveFeeConditionFailed));
+    let fee = ret.removal_amount - ret.addition_amount;
+    if fee < ret.reserve_fee as u128 {
+        // the actual fee is lower than the reserved fee, so it also fits in a
+        // u64
+        return Err(ValidationErr::ReserveFee {
+            required: ret.reserve_fee,
+            available: fee as u64,
+        });
     }
 
     if let Some(bh) = ret.before_height_absolute {
@@ -1744,43 +2656,60 @@ pub fn validate_conditions(
     Ok(())
 }
 
+/// Validates the aggregate signature against all public key and message pairs
+/// collected in `state`. Returns the number of pairings this requires.
 pub fn validate_signature(
     state: &ParseState,
     signature: &Signature,
     flags: ConsensusFlags,
     bls_cache: Option<&BlsCache>,
-) -> Result<(), ValidationErr> {
+) -> Result<u64, ValidationErr> {
     if flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE) {
-        return Ok(());
+        return Ok(0);
     }
 
+    // the BlsCache computes the pairings one at a time, to cache them. Only
+    // the ones not found in the cache are computed
+    let bls_cache = bls_cache.filter(|_| !flags.contains(ConsensusFlags::BATCH_VERIFY_SIGS));
     if let Some(bls_cache) = bls_cache {
-        if !bls_cache.aggregate_verify(
+        let (valid, pairings) = bls_cache.aggregate_verify_with_pairings(
             state.pkm_pairs.iter().map(|(pk, msg)| (pk, msg.as_slice())),
             signature,
-        ) {
+        );
+        if !valid {
             return Err(ValidationErr::Err(ErrorCode::BadAggregateSignature));
         }
-    } else if !aggregate_verify(
+        return Ok(pairings);
+    }
+
+    if !aggregate_verify(
         signature,
         state.pkm_pairs.iter().map(|(pk, msg)| (pk, msg.as_slice())),
     ) {
         return Err(ValidationErr::Err(ErrorCode::BadAggregateSignature));
     }
-    Ok(())
+
+    // aggregate_verify() pairs every public key and message, and the
+    // signature. Without any pairs, the signature is compared against the
+    // identity signature, which doesn't require a pairing
+    if state.pkm_pairs.is_empty() {
+        Ok(0)
+    } else {
+        Ok(state.pkm_pairs.len() as u64 + 1)
+    }
 }
 
 #[cfg(test)]
 use crate::consensus_constants::TEST_CONSTANTS;
 #[cfg(test)]
-use crate::flags::MEMPOOL_MODE;
+use crate::flags::{MEMPOOL_MODE, STRICT_CANONICAL};
+#[cfg(test)]
+use crate::opcodes::KnownConditions;
 #[cfg(test)]
 use chia_protocol::Bytes48;
 #[cfg(test)]
 use clvmr::number::Number;
 #[cfg(test)]
-use clvmr::serde::node_to_bytes;
-#[cfg(test)]
 use hex::FromHex;
 #[cfg(test)]
 use hex_literal::hex;
@@ -2678,6 +3607,65 @@ fn test_reserve_fee_insufficient_fee() {
     );
 }
 
+#[test]
+fn test_reserve_fee_shortfall() {
+    // RESERVE_FEE
+    // Two coins worth 123 and 100 create coins worth 24 and 150, leaving a fee
+    // of 49. The reserve fees add up to 80, i.e. 31 short
+    let err = cond_test(
+        "((({h1} ({h2} (123 (((52 (30 ) ((51 ({h2} (24 ))) (({h2} ({h1} (100 (((52 (50 ) ((51 ({h1} (150 )) )))",
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        ValidationErr::ReserveFee {
+            required: 80,
+            available: 49
+        }
+    );
+    assert_eq!(err.error_code(), ErrorCode::ReserveFeeConditionFailed);
+    assert_eq!(err.reserve_fee_shortfall(), Some(31));
+
+    // reserving more than the spent coins are worth
+    let err = cond_test("((({h1} ({h2} (123 (((52 (124 ) ))))").unwrap_err();
+    assert_eq!(err.reserve_fee_shortfall(), Some(1));
+
+    // the fields are public, so they may not describe a shortfall
+    let err = ValidationErr::ReserveFee {
+        required: 10,
+        available: u64::MAX,
+    };
+    assert_eq!(err.reserve_fee_shortfall(), Some(0));
+
+    // other errors don't have a shortfall
+    assert_eq!(
+        ValidationErr::Err(ErrorCode::ReserveFeeConditionFailed).reserve_fee_shortfall(),
+        None
+    );
+}
+
+#[cfg(test)]
+#[rstest]
+// redundant leading zero
+#[case("0x0064", ErrorCode::ReserveFeeNotCanonical)]
+// negative
+#[case("-1", ErrorCode::ReserveFeeOutOfRange)]
+// exceeds 2^64
+#[case("0x010000000000000000", ErrorCode::ReserveFeeOutOfRange)]
+fn test_reserve_fee_encoding(#[case] amount: &str, #[case] expected: ErrorCode) {
+    // RESERVE_FEE
+    let input = format!("((({{h1}} ({{h2}} (123 (((52 ({amount} ) ))))");
+
+    // consensus reports all encoding errors as ReserveFeeConditionFailed,
+    // whether or not canonical integers are required
+    for flags in [ConsensusFlags::empty(), ConsensusFlags::CANONICAL_INTS] {
+        let err = cond_test_flag(&input, flags).unwrap_err().error_code();
+        assert_eq!(err, ErrorCode::ReserveFeeConditionFailed);
+        assert_eq!(err.code(), 48);
+    }
+    assert_eq!(cond_test(&input).unwrap_err().error_code(), expected);
+}
+
 // TOOD: test announcement across coins
 
 #[test]
@@ -2849,13 +3837,24 @@ fn test_multiple_failing_assert_my_amount() {
 }
 
 #[test]
-fn test_single_failing_assert_my_amount() {
+fn test_single_failing_assert_my_amount() {
+    // ASSERT_MY_AMOUNT
+    assert_eq!(
+        cond_test("((({h1} ({h2} (123 (((73 (124 ) ))))")
+            .unwrap_err()
+            .error_code(),
+        ErrorCode::AssertMyAmountFailed
+    );
+}
+
+#[test]
+fn test_failing_assert_my_amount_coin_id() {
     // ASSERT_MY_AMOUNT
+    // the error identifies the spend asserting the wrong amount
     assert_eq!(
-        cond_test("((({h1} ({h2} (123 (((73 (124 ) ))))")
-            .unwrap_err()
-            .error_code(),
-        ErrorCode::AssertMyAmountFailed
+        cond_test("((({h1} ({h2} (123 (((73 (123 ) )) (({h2} ({h1} (123 (((73 (124 ) ))))")
+            .unwrap_err(),
+        ValidationErr::Spend(ErrorCode::AssertMyAmountFailed, test_coin_id(H2, H1, 123))
     );
 }
 
@@ -3269,6 +4268,155 @@ fn test_create_coin_with_pair_hint() {
     assert_eq!(spend.flags, 0);
 }
 
+#[cfg(test)]
+#[rstest]
+#[case("{h1}", None)]
+#[case("0x ", None)]
+#[case("{msg1}", Some(ErrorCode::InvalidHint))]
+#[case(
+    "0x02020202020202020202020202020202020202020202020202020202020202 ",
+    Some(ErrorCode::InvalidHint)
+)]
+#[case("{long}", Some(ErrorCode::InvalidHint))]
+#[case("({h1} )", Some(ErrorCode::InvalidHint))]
+fn test_create_coin_strict_hints(#[case] hint: &str, #[case] expected: Option<ErrorCode>) {
+    // CREATE_COIN
+    let input = format!("((({{h1}} ({{h2}} (123 (((51 ({{h2}} (42 (({hint}))))))");
+
+    // without the STRICT_HINTS flag, any hint is allowed
+    cond_test(&input).expect("valid hint");
+
+    let result = cond_test_flag(&input, MEMPOOL_MODE | ConsensusFlags::STRICT_HINTS);
+    match expected {
+        None => {
+            result.expect("valid hint");
+        }
+        Some(err) => {
+            assert_eq!(result.unwrap_err().error_code(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+struct DoubleCosts;
+
+#[cfg(test)]
+impl CostOracle for DoubleCosts {
+    fn spend_cost(&self, flags: ConsensusFlags) -> Cost {
+        ConditionCosts::default().spend_cost(flags) * 2
+    }
+    fn condition_cost(&self, op: Option<ConditionOpcode>, flags: ConsensusFlags) -> Cost {
+        ConditionCosts::default().condition_cost(op, flags) * 2
+    }
+}
+
+#[cfg(test)]
+#[rstest]
+fn test_cost_oracle(
+    #[values(ConsensusFlags::empty(), ConsensusFlags::COST_CONDITIONS)] flags: ConsensusFlags,
+) {
+    // CREATE_COIN, AGG_SIG_ME, CREATE_COIN_ANNOUNCEMENT, REMARK and an unknown
+    // condition
+    let input = "((({h1} ({h2} (123 (((51 ({h2} (42 ) ((50 ({pubkey} ({msg1} ) ((60 ({msg1} ) ((1 ) ((0xfe ) ))))";
+    let flags = flags | ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+    let mut a = Allocator::new();
+    let n = parse_list(&mut a, input, &None);
+
+    let conds = parse_spends::<MempoolVisitor>(
+        &a,
+        n,
+        11_000_000_000,
+        0,
+        flags,
+        &Signature::default(),
+        None,
+        &TEST_CONSTANTS,
+    )
+    .expect("parse_spends");
+    let doubled = parse_spends_with_cost_oracle::<MempoolVisitor, _>(
+        &a,
+        n,
+        11_000_000_000,
+        0,
+        flags,
+        &Signature::default(),
+        None,
+        &TEST_CONSTANTS,
+        &DoubleCosts,
+    )
+    .expect("parse_spends_with_cost_oracle");
+
+    let expected = if flags.contains(ConsensusFlags::COST_CONDITIONS) {
+        SPEND_COST
+            + NEW_CREATE_COIN_COST
+            + AGG_SIG_COST
+            + MESSAGE_CONDITION_COST
+            + 2 * GENERIC_CONDITION_COST
+    } else {
+        CREATE_COIN_COST + AGG_SIG_COST
+    };
+    assert_eq!(conds.condition_cost, expected);
+    assert_eq!(conds.cost, expected);
+    assert_eq!(conds.spends[0].condition_cost, expected);
+    assert_eq!(doubled.condition_cost, expected * 2);
+    assert_eq!(doubled.cost, expected * 2);
+    assert_eq!(doubled.spends[0].condition_cost, expected * 2);
+}
+
+#[cfg(test)]
+#[rstest]
+// RESERVE_FEE with a redundant leading zero. Without the flag it's still
+// invalid, but not reported as non-canonical
+#[case(
+    "((52 (0x0064 )",
+    ConsensusFlags::NO_UNKNOWN_CONDS,
+    ErrorCode::ReserveFeeNotCanonical,
+    Some(ErrorCode::ReserveFeeConditionFailed)
+)]
+// ASSERT_SECONDS_RELATIVE with an extra argument
+#[case(
+    "((80 (50 (1337 )",
+    ConsensusFlags::STRICT_ARGS_COUNT,
+    ErrorCode::InvalidCondition,
+    None
+)]
+// CREATE_COIN with a hint that's not 32 bytes
+#[case(
+    "((51 ({h2} (42 (({msg1}) )",
+    ConsensusFlags::STRICT_HINTS,
+    ErrorCode::InvalidHint,
+    None
+)]
+// an unknown condition
+#[case(
+    "((0xfe (1337 )",
+    ConsensusFlags::NO_UNKNOWN_CONDS,
+    ErrorCode::InvalidConditionOpcode,
+    None
+)]
+fn test_strict_canonical(
+    #[case] conditions: &str,
+    #[case] flag: ConsensusFlags,
+    #[case] expected: ErrorCode,
+    #[case] without_flag: Option<ErrorCode>,
+) {
+    let input = format!("((({{h1}} ({{h2}} (123 ({conditions} ))))");
+
+    assert_eq!(
+        cond_test_flag(&input, ConsensusFlags::empty())
+            .err()
+            .map(|e| e.error_code()),
+        without_flag
+    );
+    assert!(STRICT_CANONICAL.contains(flag));
+    for flags in [flag, STRICT_CANONICAL] {
+        assert_eq!(
+            cond_test_flag(&input, flags).unwrap_err().error_code(),
+            expected
+        );
+    }
+}
+
 #[test]
 fn test_create_coin_with_cons_hint() {
     // CREATE_COIN
@@ -3346,7 +4494,7 @@ fn test_create_coin_exceed_cost() {
         )
         .unwrap_err()
         .error_code(),
-        ErrorCode::CostExceeded
+        ErrorCode::ConditionCostExceeded
     );
 }
 
@@ -3361,6 +4509,20 @@ fn test_duplicate_create_coin() {
     );
 }
 
+#[test]
+fn test_duplicate_create_coin_id() {
+    // the error identifies the coin that's created twice
+    let spend_id = test_coin_id(H1, H2, 123);
+    let err = cond_test("((({h1} ({h2} (123 (((51 ({h2} (42 ) ((51 ({h2} (42 ) ))))").unwrap_err();
+    assert_eq!(
+        err,
+        ValidationErr::Spend(
+            ErrorCode::DuplicateOutput,
+            test_coin_id(&spend_id.into(), H2, 42)
+        )
+    );
+}
+
 #[test]
 fn test_duplicate_create_coin_with_hint() {
     // CREATE_COIN
@@ -3581,7 +4743,7 @@ fn test_agg_sig_exceed_cost(#[case] condition: ConditionOpcode) {
         )
         .unwrap_err()
         .error_code(),
-        ErrorCode::CostExceeded
+        ErrorCode::ConditionCostExceeded
     );
 }
 
@@ -3888,7 +5050,7 @@ fn test_agg_sig_unsafe_exceed_cost() {
         )
         .unwrap_err()
         .error_code(),
-        ErrorCode::CostExceeded
+        ErrorCode::ConditionCostExceeded
     );
 }
 
@@ -4361,6 +5523,84 @@ fn test_cost_aggsig_conds(#[case] count: usize) {
     );
 }
 
+// the relative time-locks of a spend can't be met before the coin was
+// created. Without a birth assertion, the coin may have been created at 0.
+// This isn't a consensus rule, it's checked by check_time_lock_constraints()
+#[cfg(test)]
+#[rstest]
+#[case(&[(ASSERT_HEIGHT_RELATIVE, 100), (ASSERT_BEFORE_HEIGHT_ABSOLUTE, 100)], true)]
+#[case(&[(ASSERT_HEIGHT_RELATIVE, 99), (ASSERT_BEFORE_HEIGHT_ABSOLUTE, 100)], false)]
+#[case(&[(ASSERT_SECONDS_RELATIVE, 100), (ASSERT_BEFORE_SECONDS_ABSOLUTE, 100)], true)]
+#[case(&[(ASSERT_SECONDS_RELATIVE, 99), (ASSERT_BEFORE_SECONDS_ABSOLUTE, 100)], false)]
+// order shouldn't matter
+#[case(&[(ASSERT_BEFORE_HEIGHT_ABSOLUTE, 100), (ASSERT_HEIGHT_RELATIVE, 100)], true)]
+#[case(&[(ASSERT_BEFORE_SECONDS_ABSOLUTE, 100), (ASSERT_SECONDS_RELATIVE, 100)], true)]
+// with the coin's birth asserted, it's known exactly when the relative
+// time-lock is met
+#[case(
+    &[(ASSERT_MY_BIRTH_HEIGHT, 50), (ASSERT_HEIGHT_RELATIVE, 50), (ASSERT_BEFORE_HEIGHT_ABSOLUTE, 100)],
+    true
+)]
+#[case(
+    &[(ASSERT_MY_BIRTH_HEIGHT, 50), (ASSERT_HEIGHT_RELATIVE, 49), (ASSERT_BEFORE_HEIGHT_ABSOLUTE, 100)],
+    false
+)]
+#[case(
+    &[(ASSERT_MY_BIRTH_SECONDS, 50), (ASSERT_SECONDS_RELATIVE, 50), (ASSERT_BEFORE_SECONDS_ABSOLUTE, 100)],
+    true
+)]
+#[case(
+    &[(ASSERT_MY_BIRTH_SECONDS, 50), (ASSERT_SECONDS_RELATIVE, 49), (ASSERT_BEFORE_SECONDS_ABSOLUTE, 100)],
+    false
+)]
+#[case(
+    &[(ASSERT_MY_BIRTH_HEIGHT, 50), (ASSERT_BEFORE_HEIGHT_RELATIVE, 50), (ASSERT_HEIGHT_ABSOLUTE, 100)],
+    true
+)]
+#[case(
+    &[(ASSERT_MY_BIRTH_HEIGHT, 50), (ASSERT_BEFORE_HEIGHT_RELATIVE, 50), (ASSERT_HEIGHT_ABSOLUTE, 99)],
+    false
+)]
+#[case(
+    &[(ASSERT_MY_BIRTH_SECONDS, 50), (ASSERT_BEFORE_SECONDS_RELATIVE, 50), (ASSERT_SECONDS_ABSOLUTE, 100)],
+    true
+)]
+#[case(
+    &[(ASSERT_MY_BIRTH_SECONDS, 50), (ASSERT_BEFORE_SECONDS_RELATIVE, 50), (ASSERT_SECONDS_ABSOLUTE, 99)],
+    false
+)]
+// without the coin's birth, the latest the relative time-lock can be met is
+// unknown
+#[case(&[(ASSERT_BEFORE_HEIGHT_RELATIVE, 50), (ASSERT_HEIGHT_ABSOLUTE, 100)], false)]
+#[case(&[(ASSERT_BEFORE_SECONDS_RELATIVE, 50), (ASSERT_SECONDS_ABSOLUTE, 100)], false)]
+fn test_impossible_relative_and_absolute_constraints(
+    #[case] conditions: &[(ConditionOpcode, u64)],
+    #[case] impossible: bool,
+) {
+    use std::fmt::Write;
+    let mut conds = String::new();
+    for (op, value) in conditions {
+        write!(conds, "(({} ({} ) ", *op as u8, value).unwrap();
+    }
+    let test = format!("((({{h1}} ({{h2}} (123 ({conds}))))");
+
+    let (a, conds) = cond_test(&test).expect("cond_test");
+    let r = crate::mempool_policy::check_time_lock_constraints(&OwnedSpendBundleConditions::from(
+        &a, conds,
+    ));
+    if impossible {
+        assert_eq!(
+            r.unwrap_err(),
+            ValidationErr::Spend(
+                ErrorCode::ImpossibleTimeLockConstraints,
+                test_coin_id(H1, H2, 123)
+            )
+        );
+    } else {
+        assert!(r.is_ok());
+    }
+}
+
 // the relative constraints clash because they are on the same coin spend
 #[cfg(test)]
 #[rstest]
@@ -4768,6 +6008,37 @@ fn test_assert_ephemeral_wrong_ph() {
     );
 }
 
+#[test]
+fn test_assert_ephemeral_unknown_at_launch() {
+    // ASSERT_EPHEMERAL was added by soft-fork 2. With the set of conditions
+    // known at launch, it's an unknown condition, and ignored
+    let test = "(\
+       (({h1} ({h1} (123 (\
+           ((51 ({h2} (123 ) \
+           ))\
+       (({coin11} ({h1} (123 (\
+           ((76 ) \
+           ))\
+       ))";
+
+    for (known, expect_ok) in [
+        (KnownConditions::Launch, true),
+        (KnownConditions::SoftFork2, false),
+        (KnownConditions::HardFork, false),
+    ] {
+        let result = cond_test_flag(test, known.flags());
+        if expect_ok {
+            let (_, conds) = result.expect("cond_test");
+            assert_eq!(conds.spends.len(), 2);
+        } else {
+            assert_eq!(
+                result.unwrap_err().error_code(),
+                ErrorCode::AssertEphemeralFailed
+            );
+        }
+    }
+}
+
 #[test]
 fn test_assert_ephemeral_wrong_amount() {
     // ASSERT_EPHEMERAL
@@ -4935,6 +6206,8 @@ fn test_softfork_condition(#[case] conditions: &str, #[case] expected_cost: Cost
     )
     .unwrap();
     assert_eq!(spends.cost, expected_cost);
+    assert_eq!(spends.softfork_cost, expected_cost);
+    assert_eq!(spends.condition_cost, expected_cost);
 }
 
 #[cfg(test)]
@@ -4944,7 +6217,7 @@ fn test_softfork_condition(#[case] conditions: &str, #[case] expected_cost: Cost
 // the cost argument may not exceed 2^32-1
 #[case("((90 (0x0100000000 )", ErrorCode::InvalidSoftforkCost)]
 // the test has a cost limit of 11000000000
-#[case("((90 (0x00ffffffff )", ErrorCode::CostExceeded)]
+#[case("((90 (0x00ffffffff )", ErrorCode::ConditionCostExceeded)]
 #[case("((90 )", ErrorCode::InvalidCondition)]
 fn test_softfork_condition_failures(#[case] conditions: &str, #[case] expected_err: ErrorCode) {
     // SOFTFORK (90)
@@ -6299,15 +7572,369 @@ fn test_limit_spends_parse_spends(
         None,
         &TEST_CONSTANTS,
     );
-    match (expected_err, result) {
-        (Some(err), Err(e)) => {
-            assert_eq!(e.error_code(), err);
+    let multi = parse_spends_multi::<MempoolVisitor>(
+        &a,
+        spends,
+        11_000_000_000,
+        0,
+        &[flags | ConsensusFlags::DONT_VALIDATE_SIGNATURE],
+        &Signature::default(),
+        None,
+        &TEST_CONSTANTS,
+    );
+    for result in [result].into_iter().chain(multi) {
+        match (expected_err, result) {
+            (Some(err), Err(e)) => {
+                assert_eq!(e.error_code(), err);
+            }
+            (None, Ok(conds)) => {
+                assert_eq!(conds.spends.len(), num_spends);
+            }
+            _ => {
+                panic!("mismatch");
+            }
         }
-        (None, Ok(conds)) => {
-            assert_eq!(conds.spends.len(), num_spends);
+    }
+}
+
+#[cfg(test)]
+#[rstest]
+// no conditions
+#[case("((({h1} ({h2} (123 ())))")]
+// unknown condition. Fails in mempool mode only
+#[case("((({h1} ({h2} (123 (((90 (1 ) ))))")]
+// RESERVE_FEE
+#[case("((({h1} ({h2} (123 (((52 (100 ) ))))")]
+// RESERVE_FEE exceeding the amount being spent
+#[case("((({h1} ({h2} (123 (((52 (124 ) ))))")]
+// CREATE_COIN
+#[case("((({h1} ({h2} (123 (((51 ({h2} (42 ) ))))")]
+// double spend
+#[case("((({h1} ({h2} (123 ()) (({h1} ({h2} (123 ())))")]
+// invalid spend list
+#[case("((({h1} ({h2} (123 (()) 8 ))")]
+// invalid spend
+#[case("((({h1} ({h2} (123 ()) (8 ))")]
+fn test_parse_spends_multi(#[case] input: &str) {
+    let all_flags = [
+        ConsensusFlags::empty(),
+        MEMPOOL_MODE,
+        ConsensusFlags::COST_CONDITIONS,
+        MEMPOOL_MODE | ConsensusFlags::COST_CONDITIONS,
+    ];
+
+    let mut a = Allocator::new();
+    let n = parse_list(&mut a, input, &None);
+
+    let multi = parse_spends_multi::<MempoolVisitor>(
+        &a,
+        n,
+        11_000_000_000,
+        0,
+        &all_flags,
+        &Signature::default(),
+        None,
+        &TEST_CONSTANTS,
+    );
+    assert_eq!(multi.len(), all_flags.len());
+
+    for (flags, multi) in all_flags.iter().zip(multi) {
+        let single = parse_spends::<MempoolVisitor>(
+            &a,
+            n,
+            11_000_000_000,
+            0,
+            *flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        );
+        match (single, multi) {
+            (Ok(single), Ok(multi)) => {
+                assert_eq!(single.cost, multi.cost);
+                assert_eq!(single.condition_cost, multi.condition_cost);
+                assert_eq!(single.reserve_fee, multi.reserve_fee);
+                assert_eq!(single.removal_amount, multi.removal_amount);
+                assert_eq!(single.addition_amount, multi.addition_amount);
+                assert_eq!(single.spends.len(), multi.spends.len());
+                for (s, m) in single.spends.iter().zip(&multi.spends) {
+                    assert_eq!(s.coin_id, m.coin_id);
+                    assert_eq!(s.flags, m.flags);
+                    assert_eq!(s.create_coin, m.create_coin);
+                }
+            }
+            (Err(single), Err(multi)) => {
+                assert_eq!(single.error_code(), multi.error_code());
+            }
+            (single, multi) => {
+                panic!("mismatch for {flags:?}: {single:?} vs. {multi:?}");
+            }
         }
-        _ => {
-            panic!("mismatch");
+    }
+}
+
+#[cfg(test)]
+#[rstest]
+// no announcements
+#[case("((({h1} ({h2} (123 ())))", &[])]
+// CREATE_COIN_ANNOUNCEMENT, asserted by ASSERT_COIN_ANNOUNCEMENT
+#[case("((({h1} ({h2} (123 (((60 ({msg1} ) ((61 ({c11} ) ))))", &[])]
+// CREATE_COIN_ANNOUNCEMENT, not asserted
+#[case("((({h1} ({h2} (123 (((60 ({msg1} ) ))))", &["c11"])]
+// CREATE_PUZZLE_ANNOUNCEMENT, asserted by ASSERT_PUZZLE_ANNOUNCEMENT
+#[case("((({h1} ({h2} (123 (((62 ({msg1} ) ((63 ({p21} ) ))))", &[])]
+// CREATE_PUZZLE_ANNOUNCEMENT, not asserted
+#[case("((({h1} ({h2} (123 (((62 ({msg1} ) ))))", &["p21"])]
+// one coin- and one puzzle announcement, only the coin announcement is
+// asserted, by another spend
+#[case(
+    "((({h1} ({h2} (123 (((60 ({msg1} ) ((62 ({msg1} ) )) (({h2} ({h2} (123 (((61 ({c11} ) ))))",
+    &["p21"]
+)]
+// asserting an announcement that doesn't exist is not reported
+#[case("((({h1} ({h2} (123 (((61 ({c11} ) ))))", &[])]
+fn test_unused_announcements(#[case] input: &str, #[case] expected: &[&str]) {
+    let mut a = Allocator::new();
+    let n = parse_list(&mut a, input, &None);
+    let unused =
+        unused_announcements(&a, n, ConsensusFlags::empty(), &TEST_CONSTANTS).expect("unused");
+
+    // the expected IDs refer to the substitutions used by parse_list()
+    let mut expected: Vec<Bytes32> = expected
+        .iter()
+        .map(|sub| {
+            let n = parse_list(&mut a, &format!("{{{sub}}}"), &None);
+            a.atom(n).as_ref().try_into().unwrap()
+        })
+        .collect();
+    expected.sort();
+    assert_eq!(unused, expected);
+}
+
+#[cfg(test)]
+#[rstest]
+// no assertions
+#[case("((({h1} ({h2} (123 (((60 ({msg1} ) ))))", &[])]
+// asserting two announcements, one created by the spend itself and one that
+// doesn't exist in the spend list
+#[case(
+    "((({h1} ({h2} (123 (((60 ({msg1} ) ((61 ({c11} ) ((63 ({p21} ) ))))",
+    &["c11", "p21"]
+)]
+// the same announcement asserted by two spends is only reported once
+#[case(
+    "((({h1} ({h2} (123 (((61 ({c11} ) )) (({h2} ({h2} (123 (((61 ({c11} ) ))))",
+    &["c11"]
+)]
+fn test_assertion_targets(#[case] input: &str, #[case] expected: &[&str]) {
+    let mut a = Allocator::new();
+    let n = parse_list(&mut a, input, &None);
+    let targets =
+        assertion_targets(&a, n, ConsensusFlags::empty(), &TEST_CONSTANTS).expect("targets");
+
+    // the expected IDs refer to the substitutions used by parse_list()
+    let mut expected: Vec<Bytes32> = expected
+        .iter()
+        .map(|sub| {
+            let n = parse_list(&mut a, &format!("{{{sub}}}"), &None);
+            a.atom(n).as_ref().try_into().unwrap()
+        })
+        .collect();
+    expected.sort();
+    assert_eq!(targets, expected);
+}
+
+#[cfg(test)]
+#[rstest]
+// no announcements
+#[case("((({h1} ({h2} (123 ())))", &[(0, 0)])]
+// creating two announcements and asserting one
+#[case(
+    "((({h1} ({h2} (123 (((60 ({msg1} ) ((62 ({msg1} ) ((61 ({c11} ) ))))",
+    &[(2, 1)]
+)]
+// the announcement is asserted by another spend
+#[case(
+    "((({h1} ({h2} (123 (((60 ({msg1} ) )) (({h2} ({h2} (123 (((61 ({c11} ) ((63 ({p21} ) ))))",
+    &[(1, 0), (0, 2)]
+)]
+fn test_announcement_counts(#[case] input: &str, #[case] expected: &[(usize, usize)]) {
+    let mut a = Allocator::new();
+    let n = parse_list(&mut a, input, &None);
+    let counts = announcement_counts(&a, n, ConsensusFlags::empty(), &TEST_CONSTANTS)
+        .expect("announcement_counts");
+    assert_eq!(counts, expected);
+}
+
+#[cfg(test)]
+#[rstest]
+// no announcements, no cost
+#[case("((({h1} ({h2} (123 ())))", 0, 900_000, None)]
+// two announcements, but the spend doesn't cost anything
+#[case(
+    "((({h1} ({h2} (123 (((60 ({msg1} ) ((62 ({msg1} ) ))))",
+    0,
+    900_000,
+    Some(ErrorCode::TooManyAnnouncementsForCost)
+)]
+// the same announcements, paid for by the cost of a CREATE_COIN
+#[case(
+    "((({h1} ({h2} (123 (((60 ({msg1} ) ((62 ({msg1} ) ((51 ({h2} (42 ) ))))",
+    0,
+    900_000,
+    None
+)]
+// the CREATE_COIN only pays for one of them
+#[case(
+    "((({h1} ({h2} (123 (((60 ({msg1} ) ((62 ({msg1} ) ((51 ({h2} (42 ) ))))",
+    0,
+    900_001,
+    Some(ErrorCode::TooManyAnnouncementsForCost)
+)]
+// the same announcements, paid for by the execution cost
+#[case(
+    "((({h1} ({h2} (123 (((60 ({msg1} ) ((62 ({msg1} ) ))))",
+    1_800_000,
+    900_000,
+    None
+)]
+// the execution cost only pays for one of them
+#[case(
+    "((({h1} ({h2} (123 (((60 ({msg1} ) ((62 ({msg1} ) ))))",
+    1_799_999,
+    900_000,
+    Some(ErrorCode::TooManyAnnouncementsForCost)
+)]
+// the limit is disabled
+#[case("((({h1} ({h2} (123 (((60 ({msg1} ) ((62 ({msg1} ) ))))", 0, 0, None)]
+fn test_announcement_cost_limit(
+    #[case] input: &str,
+    #[case] execution_cost: u64,
+    #[case] cost_per_announcement: u64,
+    #[case] expected_err: Option<ErrorCode>,
+) {
+    let mut a = Allocator::new();
+    let n = parse_list(&mut a, input, &None);
+    let conds = parse_spends::<EmptyVisitor>(
+        &a,
+        n,
+        11_000_000_000,
+        execution_cost,
+        ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+        &Signature::default(),
+        None,
+        &TEST_CONSTANTS,
+    )
+    .expect("parse_spends");
+    let bundle_conds = OwnedSpendBundleConditions::from(&a, conds);
+    let ret = check_announcement_cost_limit(
+        &a,
+        n,
+        &bundle_conds,
+        cost_per_announcement,
+        ConsensusFlags::empty(),
+        &TEST_CONSTANTS,
+    );
+    match expected_err {
+        None => ret.expect("check_announcement_cost_limit"),
+        Some(code) => {
+            let err = ret.unwrap_err();
+            assert_eq!(err.error_code(), code);
+            assert!(matches!(err, ValidationErr::Spend(_, id) if id == test_coin_id(H1, H2, 123)));
         }
     }
 }
+
+#[cfg(test)]
+#[rstest]
+#[case(CREATE_COIN_ANNOUNCEMENT, 1024, None)]
+#[case(CREATE_PUZZLE_ANNOUNCEMENT, 1024, None)]
+#[case(CREATE_COIN_ANNOUNCEMENT, 13, None)]
+#[case(CREATE_PUZZLE_ANNOUNCEMENT, 13, None)]
+#[case(CREATE_COIN_ANNOUNCEMENT, 12, Some(ErrorCode::AnnouncementTooLarge))]
+#[case(CREATE_PUZZLE_ANNOUNCEMENT, 12, Some(ErrorCode::AnnouncementTooLarge))]
+#[case(CREATE_PUZZLE_ANNOUNCEMENT, 0, Some(ErrorCode::AnnouncementTooLarge))]
+fn test_max_announcement_msg_size(
+    #[case] condition: ConditionOpcode,
+    #[case] max_size: u32,
+    #[case] expected: Option<ErrorCode>,
+) {
+    // msg1 is 13 bytes
+    let mut a = Allocator::new();
+    let input = format!(
+        "((({{h1}} ({{h2}} (123 ((({} ({{msg1}} )))))",
+        condition as u8
+    );
+    let n = parse_list(&mut a, &input, &None);
+
+    let result = check_announcement_size(&a, n, max_size, MEMPOOL_MODE, &TEST_CONSTANTS);
+    assert_eq!(result.err().map(|e| e.error_code()), expected);
+
+    // the limit is not a consensus rule
+    parse_spends::<MempoolVisitor>(
+        &a,
+        n,
+        11_000_000_000,
+        0,
+        MEMPOOL_MODE,
+        &Signature::default(),
+        None,
+        &TEST_CONSTANTS,
+    )
+    .expect("parse_spends");
+}
+
+#[test]
+fn test_canonicalize_conditions() {
+    let mut a = Allocator::new();
+    let ph = a.new_atom(&[0x11; 32]).unwrap();
+    let other_ph = a.new_atom(&[0x22; 32]).unwrap();
+    let hint = a.nil();
+    let conds = vec![
+        Condition::AssertHeightAbsolute(100),
+        Condition::CreateCoin(ph, 1, hint),
+        Condition::AssertConcurrentPuzzle(ph),
+        Condition::AssertHeightAbsolute(100),
+        Condition::CreateCoin(ph, 1, hint),
+        Condition::AssertHeightAbsolute(99),
+        Condition::AssertConcurrentPuzzle(other_ph),
+        Condition::AssertConcurrentPuzzle(ph),
+        Condition::ReserveFee(10),
+        Condition::ReserveFee(10),
+    ];
+    let canonical: Vec<String> = canonicalize_conditions(&a, conds)
+        .expect("canonicalize_conditions")
+        .iter()
+        .map(|c| format!("{c:?}"))
+        .collect();
+
+    // duplicate assertions are collapsed. CREATE_COIN and RESERVE_FEE are not
+    let expected: Vec<String> = [
+        Condition::CreateCoin(ph, 1, hint),
+        Condition::CreateCoin(ph, 1, hint),
+        Condition::ReserveFee(10),
+        Condition::ReserveFee(10),
+        Condition::AssertConcurrentPuzzle(ph),
+        Condition::AssertConcurrentPuzzle(other_ph),
+        Condition::AssertHeightAbsolute(99),
+        Condition::AssertHeightAbsolute(100),
+    ]
+    .iter()
+    .map(|c| format!("{c:?}"))
+    .collect();
+    assert_eq!(canonical, expected);
+}
+
+#[test]
+fn test_canonicalize_conditions_too_large() {
+    // arguments that are too large to serialize can't be told apart, so
+    // rather than risking treating them as duplicates, canonicalizing fails
+    let mut a = Allocator::new();
+    let id1 = a.new_atom(&vec![1; 2_000_001]).unwrap();
+    let id2 = a.new_atom(&vec![2; 2_000_001]).unwrap();
+    let conds = vec![
+        Condition::AssertConcurrentSpend(id1),
+        Condition::AssertConcurrentSpend(id2),
+    ];
+    assert!(canonicalize_conditions(&a, conds).is_err());
+}
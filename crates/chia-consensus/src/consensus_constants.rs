@@ -1,5 +1,6 @@
 use chia_protocol::Bytes32;
 use chia_streamable_macro::streamable;
+use chia_traits::Streamable;
 
 #[cfg(feature = "py-bindings")]
 use chia_py_streamable_macro::{PyGetters, PyJsonDict, PyStreamable};
@@ -181,6 +182,112 @@ pub struct ConsensusConstants {
     testnet: bool,
 }
 
+impl ConsensusConstants {
+    /// The hash of the canonical (streamable) serialization of all fields.
+    /// Constants for different networks, or with any parameter changed, have
+    /// different hashes. This is suitable as a key for caches that depend on
+    /// the network parameters.
+    pub fn hash(&self) -> Bytes32 {
+        Streamable::hash(self).into()
+    }
+
+    /// Returns a builder for constants that are the same as `base`, except
+    /// for the fields that are overridden.
+    pub fn builder(base: &ConsensusConstants) -> ConsensusConstantsBuilder {
+        ConsensusConstantsBuilder {
+            constants: base.clone(),
+        }
+    }
+}
+
+/// Builds ConsensusConstants from a base (e.g. `TEST_CONSTANTS` or
+/// `MAINNET_CONSTANTS`), overriding individual fields. Fields that aren't
+/// overridden keep the value of the base.
+#[derive(Debug, Clone)]
+pub struct ConsensusConstantsBuilder {
+    constants: ConsensusConstants,
+}
+
+// defines a setter, named after the field, for every field
+macro_rules! builder_setters {
+    ($($field:ident: $ty:ty,)*) => {
+        impl ConsensusConstantsBuilder {
+            $(
+                #[must_use]
+                pub fn $field(mut self, value: $ty) -> Self {
+                    self.constants.$field = value;
+                    self
+                }
+            )*
+        }
+    };
+}
+
+builder_setters! {
+    slot_blocks_target: u32,
+    min_blocks_per_challenge_block: u8,
+    max_sub_slot_blocks: u32,
+    num_sps_sub_slot: u8,
+    sub_slot_iters_starting: u64,
+    difficulty_constant_factor: u128,
+    difficulty_starting: u64,
+    difficulty_change_max_factor: u32,
+    sub_epoch_blocks: u32,
+    epoch_blocks: u32,
+    significant_bits: u8,
+    discriminant_size_bits: u16,
+    number_zero_bits_plot_filter_v1: u8,
+    number_zero_bits_plot_filter_v2: u8,
+    min_plot_size_v1: u8,
+    max_plot_size_v1: u8,
+    plot_size_v2: u8,
+    sub_slot_time_target: u16,
+    num_sp_intervals_extra: u8,
+    max_future_time2: u32,
+    number_of_timestamps: u8,
+    genesis_challenge: Bytes32,
+    agg_sig_me_additional_data: Bytes32,
+    agg_sig_parent_additional_data: Bytes32,
+    agg_sig_puzzle_additional_data: Bytes32,
+    agg_sig_amount_additional_data: Bytes32,
+    agg_sig_puzzle_amount_additional_data: Bytes32,
+    agg_sig_parent_amount_additional_data: Bytes32,
+    agg_sig_parent_puzzle_additional_data: Bytes32,
+    genesis_pre_farm_pool_puzzle_hash: Bytes32,
+    genesis_pre_farm_farmer_puzzle_hash: Bytes32,
+    max_vdf_witness_size: u8,
+    mempool_block_buffer: u8,
+    max_coin_amount: u64,
+    max_block_cost_clvm: u64,
+    cost_per_byte: u64,
+    weight_proof_threshold: u8,
+    weight_proof_recent_blocks: u32,
+    max_block_count_per_requests: u32,
+    blocks_cache_size: u32,
+    max_generator_ref_list_size: u32,
+    pool_sub_slot_iters: u64,
+    hard_fork_height: u32,
+    hard_fork2_height: u32,
+    soft_fork8_height: u32,
+    soft_fork9_height: u32,
+    plot_v1_phase_out_epoch_bits: u8,
+    plot_filter_128_height: u32,
+    plot_filter_64_height: u32,
+    plot_filter_32_height: u32,
+    min_plot_strength: u8,
+    max_plot_strength: u8,
+    plot_filter_v2_relative_height: [u32; 9],
+    filter_window_size: u8,
+    max_effective_plot_filter_bits: u8,
+    testnet: bool,
+}
+
+impl ConsensusConstantsBuilder {
+    pub fn build(self) -> ConsensusConstants {
+        self.constants
+    }
+}
+
 pub const TEST_CONSTANTS: ConsensusConstants = ConsensusConstants {
     slot_blocks_target: 32,
     min_blocks_per_challenge_block: 16,
@@ -272,3 +379,131 @@ pub const TEST_CONSTANTS: ConsensusConstants = ConsensusConstants {
 
     testnet: true,
 };
+
+/// The mainnet constants, including the mainnet genesis challenge, the AGG_SIG
+/// additional data and the fork heights. Use these rather than hard coding
+/// mainnet parameters. `TEST_CONSTANTS` only differ in the `testnet` field.
+pub const MAINNET_CONSTANTS: ConsensusConstants = ConsensusConstants {
+    testnet: false,
+    ..TEST_CONSTANTS
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::{
+        AGG_SIG_AMOUNT, AGG_SIG_PARENT, AGG_SIG_PARENT_AMOUNT, AGG_SIG_PARENT_PUZZLE,
+        AGG_SIG_PUZZLE, AGG_SIG_PUZZLE_AMOUNT,
+    };
+    use chia_sha2::Sha256;
+
+    #[test]
+    fn test_constants_hash() {
+        // this hash must be stable. If it changes, the serialization of the
+        // constants changed
+        assert_eq!(
+            MAINNET_CONSTANTS.hash(),
+            Bytes32::new(hex!(
+                "30ce927d51e55b7cdd6748b90c65e163a3f34e29eca57e14a7ceb5d1457bcb97"
+            ))
+        );
+        assert_eq!(MAINNET_CONSTANTS.hash(), MAINNET_CONSTANTS.clone().hash());
+        assert_ne!(MAINNET_CONSTANTS.hash(), TEST_CONSTANTS.hash());
+
+        let mut other = MAINNET_CONSTANTS.clone();
+        other.max_block_cost_clvm += 1;
+        assert_ne!(MAINNET_CONSTANTS.hash(), other.hash());
+
+        let mut other = MAINNET_CONSTANTS.clone();
+        other.genesis_challenge = Bytes32::new([0x42; 32]);
+        assert_ne!(MAINNET_CONSTANTS.hash(), other.hash());
+    }
+
+    #[test]
+    fn test_mainnet_constants() {
+        const { assert!(!MAINNET_CONSTANTS.testnet) };
+        assert_eq!(
+            MAINNET_CONSTANTS.genesis_challenge,
+            Bytes32::new(hex!(
+                "ccd5bb71183532bff220ba46c268991a3ff07eb358e8255a65c30a2dce0e5fbb"
+            ))
+        );
+        assert_eq!(
+            MAINNET_CONSTANTS.agg_sig_me_additional_data,
+            MAINNET_CONSTANTS.genesis_challenge
+        );
+        assert_eq!(MAINNET_CONSTANTS.hard_fork_height, 5_496_000);
+        assert_eq!(MAINNET_CONSTANTS.soft_fork8_height, 8_655_000);
+        assert_eq!(MAINNET_CONSTANTS.plot_filter_128_height, 10_542_000);
+        assert_eq!(MAINNET_CONSTANTS.plot_filter_64_height, 15_592_000);
+        assert_eq!(MAINNET_CONSTANTS.plot_filter_32_height, 20_643_000);
+
+        // the additional data of the other AGG_SIG_* conditions is the hash of
+        // the AGG_SIG_ME additional data followed by the condition opcode
+        for (opcode, data) in [
+            (
+                AGG_SIG_PARENT,
+                MAINNET_CONSTANTS.agg_sig_parent_additional_data,
+            ),
+            (
+                AGG_SIG_PUZZLE,
+                MAINNET_CONSTANTS.agg_sig_puzzle_additional_data,
+            ),
+            (
+                AGG_SIG_AMOUNT,
+                MAINNET_CONSTANTS.agg_sig_amount_additional_data,
+            ),
+            (
+                AGG_SIG_PUZZLE_AMOUNT,
+                MAINNET_CONSTANTS.agg_sig_puzzle_amount_additional_data,
+            ),
+            (
+                AGG_SIG_PARENT_AMOUNT,
+                MAINNET_CONSTANTS.agg_sig_parent_amount_additional_data,
+            ),
+            (
+                AGG_SIG_PARENT_PUZZLE,
+                MAINNET_CONSTANTS.agg_sig_parent_puzzle_additional_data,
+            ),
+        ] {
+            let mut hasher = Sha256::new();
+            hasher.update(MAINNET_CONSTANTS.agg_sig_me_additional_data);
+            hasher.update([u8::try_from(opcode).unwrap()]);
+            assert_eq!(data, Bytes32::new(hasher.finalize()));
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        assert_eq!(
+            ConsensusConstants::builder(&TEST_CONSTANTS).build(),
+            TEST_CONSTANTS
+        );
+        assert_eq!(
+            ConsensusConstants::builder(&MAINNET_CONSTANTS).build(),
+            MAINNET_CONSTANTS
+        );
+
+        let constants = ConsensusConstants::builder(&TEST_CONSTANTS)
+            .hard_fork_height(100)
+            .build();
+        let mut expected = TEST_CONSTANTS.clone();
+        expected.hard_fork_height = 100;
+        assert_eq!(constants, expected);
+        assert_ne!(constants, TEST_CONSTANTS);
+
+        let constants = ConsensusConstants::builder(&MAINNET_CONSTANTS)
+            .agg_sig_me_additional_data(Bytes32::new([0x42; 32]))
+            .build();
+        let mut expected = MAINNET_CONSTANTS.clone();
+        expected.agg_sig_me_additional_data = Bytes32::new([0x42; 32]);
+        assert_eq!(constants, expected);
+
+        // overriding a field with its current value doesn't change anything
+        let constants = ConsensusConstants::builder(&TEST_CONSTANTS)
+            .max_block_cost_clvm(TEST_CONSTANTS.max_block_cost_clvm)
+            .testnet(true)
+            .build();
+        assert_eq!(constants, TEST_CONSTANTS);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::allocator::make_allocator;
+use crate::consensus_constants::ConsensusConstants;
+use crate::flags::ConsensusFlags;
+use crate::run_block_generator::{
+    check_generator_node, check_generator_quote, extract_n, setup_generator_args,
+    subtract_byte_cost, subtract_cost,
+};
+use crate::validation_error::{ErrorCode, ValidationErr, first};
+use clvm_utils::tree_hash;
+use clvmr::allocator::{Allocator, NodePtr};
+use clvmr::chia_dialect::{ChiaDialect, ClvmFlags};
+use clvmr::cost::Cost;
+use clvmr::dialect::{Dialect, OperatorSet};
+use clvmr::reduction::{Reduction, Response};
+use clvmr::run_program::run_program;
+use clvmr::serde::node_from_bytes_backrefs;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// the frame that the cost not attributed to any operator is recorded under.
+// This is the cost of quote, apply and path lookups
+const EVAL_FRAME: &str = "[eval]";
+
+fn op_name(a: &Allocator, op: NodePtr) -> String {
+    let atom = a.atom(op);
+    let name = match atom.as_ref() {
+        [3] => "i",
+        [4] => "c",
+        [5] => "f",
+        [6] => "r",
+        [7] => "l",
+        [8] => "x",
+        [9] => "=",
+        [10] => ">s",
+        [11] => "sha256",
+        [12] => "substr",
+        [13] => "strlen",
+        [14] => "concat",
+        [16] => "+",
+        [17] => "-",
+        [18] => "*",
+        [19] => "/",
+        [20] => "divmod",
+        [21] => ">",
+        [22] => "ash",
+        [23] => "lsh",
+        [24] => "logand",
+        [25] => "logior",
+        [26] => "logxor",
+        [27] => "lognot",
+        [29] => "point_add",
+        [30] => "pubkey_for_exp",
+        [32] => "not",
+        [33] => "any",
+        [34] => "all",
+        [48] => "coinid",
+        [49] => "g1_subtract",
+        [50] => "g1_multiply",
+        [51] => "g1_negate",
+        [52] => "g2_add",
+        [53] => "g2_subtract",
+        [54] => "g2_multiply",
+        [55] => "g2_negate",
+        [56] => "g1_map",
+        [57] => "g2_map",
+        [58] => "bls_pairing_identity",
+        [59] => "bls_verify",
+        [60] => "modpow",
+        [61] => "%",
+        [62] => "keccak256",
+        [63] => "sha256tree",
+        [0x13, 0xd6, 0x1f, 0x00] => "secp256k1_verify",
+        [0x1c, 0x3a, 0x8f, 0x00] => "secp256r1_verify",
+        _ => {
+            return format!("op_0x{}", hex::encode(atom.as_ref()));
+        }
+    };
+    name.to_string()
+}
+
+// wraps the ChiaDialect and records the number of invocations and the cost of
+// every operator
+struct ProfilingDialect {
+    inner: ChiaDialect,
+    ops: RefCell<BTreeMap<String, (u64, Cost)>>,
+}
+
+impl Dialect for ProfilingDialect {
+    fn quote_kw(&self) -> u32 {
+        self.inner.quote_kw()
+    }
+    fn apply_kw(&self) -> u32 {
+        self.inner.apply_kw()
+    }
+    fn softfork_kw(&self) -> u32 {
+        self.inner.softfork_kw()
+    }
+    fn softfork_extension(&self, ext: u32) -> OperatorSet {
+        self.inner.softfork_extension(ext)
+    }
+    fn flags(&self) -> ClvmFlags {
+        self.inner.flags()
+    }
+    fn gc_candidate(&self, allocator: &Allocator, op: NodePtr) -> bool {
+        self.inner.gc_candidate(allocator, op)
+    }
+    fn op(
+        &self,
+        allocator: &mut Allocator,
+        op: NodePtr,
+        args: NodePtr,
+        max_cost: Cost,
+        extensions: OperatorSet,
+    ) -> Response {
+        let name = op_name(allocator, op);
+        let ret = self.inner.op(allocator, op, args, max_cost, extensions)?;
+        let mut ops = self.ops.borrow_mut();
+        let entry = ops.entry(name).or_default();
+        entry.0 += 1;
+        entry.1 += ret.0;
+        Ok(ret)
+    }
+    fn allow_unknown_ops(&self) -> bool {
+        self.inner.allow_unknown_ops()
+    }
+}
+
+/// The CLVM cost of running a block generator, broken down by the program
+/// being run (the generator itself or a puzzle, identified by its puzzle hash)
+/// and the operator.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CostProfile {
+    /// Maps a stack of frames, separated by `;`, to the number of times it
+    /// was hit and its accumulated cost.
+    pub stacks: BTreeMap<String, (u64, Cost)>,
+}
+
+impl CostProfile {
+    // moves the operators recorded by the dialect into the profile, under the
+    // specified frame. Any cost not attributed to an operator is recorded as
+    // EVAL_FRAME
+    fn record(&mut self, frame: &str, dialect: &ProfilingDialect, clvm_cost: Cost) {
+        let mut op_cost = 0;
+        for (name, (count, cost)) in dialect.ops.take() {
+            let entry = self.stacks.entry(format!("{frame};{name}")).or_default();
+            entry.0 += count;
+            entry.1 += cost;
+            op_cost += cost;
+        }
+        let entry = self
+            .stacks
+            .entry(format!("{frame};{EVAL_FRAME}"))
+            .or_default();
+        entry.0 += 1;
+        entry.1 += clvm_cost - op_cost;
+    }
+
+    /// The sum of the cost of all stacks. This is the CLVM execution cost of
+    /// the block.
+    pub fn total_cost(&self) -> Cost {
+        self.stacks.values().map(|(_, cost)| cost).sum()
+    }
+
+    /// Prints the profile in the "folded stacks" format, one stack per line
+    /// followed by its cost. This can be rendered by flamegraph tools, such as
+    /// inferno.
+    pub fn to_folded(&self) -> String {
+        let mut ret = String::new();
+        for (stack, (_, cost)) in &self.stacks {
+            writeln!(ret, "{stack} {cost}").expect("writing to a String cannot fail");
+        }
+        ret
+    }
+}
+
+/// Runs the block generator and all its puzzles, the same way
+/// `run_block_generator2()` does, and records the cost of every operator
+/// invocation. Conditions are not parsed or validated, so only the CLVM
+/// execution cost is covered by the profile. This is slower than running the
+/// generator normally, and is only meant for analysis.
+pub fn profile_block_generator<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<CostProfile, ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    check_generator_quote(program, flags)?;
+
+    let mut a = make_allocator(flags);
+    let mut cost_left = max_cost;
+    subtract_byte_cost(
+        &mut cost_left,
+        program.len() as u64 * constants.cost_per_byte,
+    )?;
+    let program = node_from_bytes_backrefs(&mut a, program)?;
+
+    check_generator_node(&a, program, flags)?;
+
+    let args = setup_generator_args(&mut a, block_refs, flags)?;
+    let dialect = ProfilingDialect {
+        inner: ChiaDialect::new(flags.to_clvm_flags()),
+        ops: RefCell::default(),
+    };
+
+    let mut ret = CostProfile::default();
+
+    let Reduction(clvm_cost, all_spends) = run_program(&mut a, &dialect, program, args, cost_left)?;
+    subtract_cost(&mut cost_left, clvm_cost)?;
+    ret.record("block;generator", &dialect, clvm_cost);
+
+    let mut iter = first(&a, all_spends)?;
+    while let Some((spend, rest)) = a.next(iter) {
+        iter = rest;
+        let [_, puzzle, _, solution, _] = extract_n::<5>(&a, spend, ErrorCode::InvalidCondition)?;
+
+        let Reduction(clvm_cost, _) = run_program(&mut a, &dialect, puzzle, solution, cost_left)?;
+        subtract_cost(&mut cost_left, clvm_cost)?;
+
+        let puzzle_hash = tree_hash(&a, puzzle);
+        ret.record(&format!("block;{puzzle_hash}"), &dialect, clvm_cost);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus_constants::TEST_CONSTANTS;
+    use crate::run_block_generator::run_block_generator2;
+    use crate::solution_generator::solution_generator;
+    use chia_bls::Signature;
+    use chia_protocol::{Bytes32, Coin};
+    use clvm_utils::tree_hash_from_bytes;
+    use hex_literal::hex;
+    use rstest::rstest;
+
+    // (r (c (q . 1) 1))
+    // returns the solution, as the conditions
+    const PUZZLE: &[u8] = &hex!("ff06ffff04ffff0101ff018080");
+    // ((51 <puzzle-hash> 1))
+    const SOLUTION: &[u8] = &hex!(
+        "ffff33ffa0abababababababababababababababababababababababababababababababab"
+        "ff018080"
+    );
+
+    #[rstest]
+    fn test_profile_block_generator(
+        #[values(1, 2, 10)] num_spends: u8,
+        #[values(ConsensusFlags::empty(), ConsensusFlags::COST_CONDITIONS)] flags: ConsensusFlags,
+    ) {
+        let puzzle_hash = Bytes32::from(tree_hash_from_bytes(PUZZLE).unwrap());
+        let spends: Vec<_> = (0..num_spends)
+            .map(|i| {
+                (
+                    Coin::new(Bytes32::new([i; 32]), puzzle_hash, 100),
+                    PUZZLE,
+                    SOLUTION,
+                )
+            })
+            .collect();
+        let generator = solution_generator(spends).expect("solution_generator");
+        let blocks: &[&[u8]] = &[];
+
+        let profile = profile_block_generator(
+            &generator,
+            blocks,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            flags,
+            &TEST_CONSTANTS,
+        )
+        .expect("profile_block_generator");
+
+        let (_, conds) = run_block_generator2(
+            &generator,
+            blocks,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            flags | ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator2");
+
+        assert_eq!(profile.total_cost(), conds.execution_cost);
+
+        // every spend invokes c and r once
+        let frame = format!("block;{puzzle_hash}");
+        for op in ["c", "r"] {
+            let (count, _) = profile.stacks[&format!("{frame};{op}")];
+            assert_eq!(count, num_spends as u64);
+        }
+        assert_eq!(
+            profile.stacks[&format!("{frame};{EVAL_FRAME}")].0,
+            num_spends as u64
+        );
+
+        // the generator is just a quoted list, so none of its cost is
+        // attributed to operators
+        let generator_stacks: Vec<&String> = profile
+            .stacks
+            .keys()
+            .filter(|s| s.starts_with("block;generator;"))
+            .collect();
+        assert_eq!(generator_stacks, [&format!("block;generator;{EVAL_FRAME}")]);
+
+        let folded = profile.to_folded();
+        assert_eq!(folded.lines().count(), profile.stacks.len());
+        let (_, cost) = profile.stacks[&format!("{frame};c")];
+        assert!(folded.contains(&format!("{frame};c {cost}\n")));
+    }
+}
@@ -1,8 +1,15 @@
+use crate::consensus_constants::ConsensusConstants;
+use crate::spendbundle_validation::get_flags_for_height_and_constants;
 use bitflags::bitflags;
 use clvmr::MEMPOOL_MODE as CLVM_MEMPOOL_MODE;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
 
 #[cfg(feature = "py-bindings")]
-use pyo3::{Bound, FromPyObject, IntoPyObject, PyAny, PyErr, PyResult, Python, types::PyInt};
+use pyo3::{
+    Bound, FromPyObject, IntoPyObject, PyAny, PyErr, PyResult, Python, pyfunction, types::PyInt,
+};
 
 bitflags! {
     /// Full flag set for CLVM execution and consensus (condition parsing, validation, generator mode).
@@ -42,6 +49,20 @@ bitflags! {
         /// Conditions require the exact supported argument count (mempool-mode).
         const STRICT_ARGS_COUNT = 0x8_0000;
 
+        /// The hint of CREATE_COIN (the first memo) must be empty or exactly 32
+        /// bytes. This is a policy, not part of mempool-mode.
+        const STRICT_HINTS = 0x10_0000;
+
+        /// Only the conditions known at mainnet launch are known, later ones
+        /// are treated as unknown. For replaying historical blocks. See
+        /// KnownConditions.
+        const KNOWN_CONDITIONS_LAUNCH = 0x40_0000;
+
+        /// Only the conditions known after soft-fork 2 are known, the ones
+        /// added by the 2.0 hard fork are treated as unknown. For replaying
+        /// historical blocks. See KnownConditions.
+        const KNOWN_CONDITIONS_SOFT_FORK2 = 0x400_0000;
+
         /// Add flat cost to conditions (active after hard fork 2).
         const COST_CONDITIONS = 0x80_0000;
 
@@ -55,10 +76,37 @@ bitflags! {
         /// the INTERNED (canonical) tree so atom/pair limits and cost apply to the same
         /// structure independent of serialization.
         const INTERNED_GENERATOR = 0x0800_0000;
+
+        /// Verify the aggregate signature with a single multi-pairing over all
+        /// (public key, message) pairs, even when a BlsCache is passed. Without
+        /// this flag, the pairings are computed (and cached) one at a time when
+        /// there's a BlsCache. The result is the same either way.
+        const BATCH_VERIFY_SIGS = 0x1000_0000;
+
+        /// Return the public key and message of every AGG_SIG condition in
+        /// the sig_pairs field of SpendBundleConditions, e.g. to validate the
+        /// signature separately, with DONT_VALIDATE_SIGNATURE.
+        const COLLECT_SIG_PAIRS = 0x2000_0000;
     }
 }
 
 impl ConsensusFlags {
+    /// The flags to validate a block with, whose previous transaction block is
+    /// at `height`, according to the fork heights in `constants`. This is the
+    /// same as get_flags_for_height_and_constants(). Each flag is set once
+    /// `height` reaches the fork height:
+    ///
+    /// * `hard_fork2_height`: ENABLE_KECCAK_OPS_OUTSIDE_GUARD, COST_CONDITIONS,
+    ///   ENABLE_SECP_OPS and RELAXED_BLS
+    /// * `soft_fork8_height`: DISABLE_OP
+    /// * `soft_fork9_height`: SIMPLE_GENERATOR, CANONICAL_INTS and LIMIT_SPENDS
+    ///
+    /// Policy flags, like MEMPOOL_MODE or DONT_VALIDATE_SIGNATURE, are never
+    /// set.
+    pub fn for_height(height: u32, constants: &ConsensusConstants) -> Self {
+        get_flags_for_height_and_constants(height, constants)
+    }
+
     /// Convert clvmr's ClvmFlags to the corresponding ConsensusFlags (shared flags only).
     /// For each clvmr flag we check whether it is set (using contains()), then set our corresponding flag.
     #[must_use]
@@ -149,12 +197,168 @@ pub const MEMPOOL_MODE: ConsensusFlags = ConsensusFlags::from_clvm_flags(CLVM_ME
     .union(ConsensusFlags::STRICT_ARGS_COUNT)
     .union(ConsensusFlags::LIMIT_SPENDS);
 
+/// All strictness policies at once, for the most pedantic validation, e.g. in
+/// conformance tests. Integers (including condition arguments) must be
+/// canonically encoded, conditions must have the exact number of arguments,
+/// hints must be 32 bytes and unknown operators and conditions are
+/// disallowed. Unlike MEMPOOL_MODE, no limits are imposed.
+pub const STRICT_CANONICAL: ConsensusFlags = ConsensusFlags::CANONICAL_INTS
+    .union(ConsensusFlags::NO_UNKNOWN_OPS)
+    .union(ConsensusFlags::NO_UNKNOWN_CONDS)
+    .union(ConsensusFlags::STRICT_ARGS_COUNT)
+    .union(ConsensusFlags::STRICT_HINTS);
+
 impl Default for ConsensusFlags {
     fn default() -> Self {
         Self::empty()
     }
 }
 
+/// The error returned when parsing a ConsensusFlags from a string fails. It
+/// carries the name that isn't a known flag.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unknown consensus flag \"{0}\"")]
+pub struct ParseConsensusFlagsError(pub String);
+
+/// Parses flag names, as defined above, separated by `|` or `,`. Whitespace
+/// around the names is ignored, and an empty string is parsed as no flags set.
+/// e.g. `"NO_UNKNOWN_CONDS | STRICT_ARGS_COUNT"`
+/// Everything printed by Display can be parsed back, i.e. `(empty)` and bits
+/// in hex, like `0x40000000`.
+impl FromStr for ConsensusFlags {
+    type Err = ParseConsensusFlagsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "(empty)" {
+            return Ok(ConsensusFlags::empty());
+        }
+        let mut ret = ConsensusFlags::empty();
+        for name in s.split(['|', ',']).map(str::trim) {
+            if name.is_empty() {
+                continue;
+            }
+            ret |= parse_flag(name).ok_or_else(|| ParseConsensusFlagsError(name.to_string()))?;
+        }
+        Ok(ret)
+    }
+}
+
+// parses a single flag name, or bits in hex
+fn parse_flag(name: &str) -> Option<ConsensusFlags> {
+    if let Some(hex) = name.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+            .ok()
+            .map(ConsensusFlags::from_bits_retain)
+    } else {
+        ConsensusFlags::from_name(name)
+    }
+}
+
+impl ConsensusFlags {
+    /// The names of the flags that are set, in the order they are defined. Set
+    /// bits that aren't a named flag are included last, in hex. This is the
+    /// same decomposition Display prints.
+    pub fn names(self) -> Vec<String> {
+        let mut ret: Vec<String> = self
+            .iter_names()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        let unknown = self.bits() & !ConsensusFlags::all().bits();
+        if unknown != 0 {
+            ret.push(format!("0x{unknown:x}"));
+        }
+        ret
+    }
+}
+
+/// Prints the names of the flags that are set, separated by ` | `, in the
+/// order they are defined. Set bits that aren't a named flag are printed last,
+/// in hex. If no flags are set, this prints `(empty)`.
+impl fmt::Display for ConsensusFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("(empty)");
+        }
+        f.write_str(&self.names().join(" | "))
+    }
+}
+
+// The python binding converts ConsensusFlags to and from a plain integer.
+// serde uses a list of the names of the flags instead, to keep configuration
+// files readable and independent of the bit positions. Set bits that aren't
+// a named flag are included in hex, like Display prints them.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ConsensusFlags {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_seq(self.names())
+    }
+}
+
+#[cfg(feature = "serde")]
+struct ConsensusFlagsVisitor {
+    deny_unknown: bool,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for ConsensusFlagsVisitor {
+    type Value = ConsensusFlags;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a list of consensus flag names, or an integer")
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        let bits = u32::try_from(v).map_err(|_| E::custom(format!("flags out of range: {v}")))?;
+        Ok(ConsensusFlags::from_bits_retain(bits))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut ret = ConsensusFlags::empty();
+        while let Some(name) = seq.next_element::<String>()? {
+            match parse_flag(&name) {
+                Some(flag) => ret |= flag,
+                None if self.deny_unknown => {
+                    return Err(serde::de::Error::custom(ParseConsensusFlagsError(name)));
+                }
+                None => {}
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Accepts a list of flag names, as serialized, or a plain integer, for
+/// backwards compatibility. Unknown flag names are an error. To ignore them
+/// instead, use deserialize_ignore_unknown().
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ConsensusFlags {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ConsensusFlagsVisitor { deny_unknown: true })
+    }
+}
+
+/// Like the Deserialize implementation of ConsensusFlags, but ignores flag
+/// names it doesn't know about, e.g. from a newer version. This is meant to be
+/// used with `#[serde(deserialize_with = "deserialize_ignore_unknown")]`.
+#[cfg(feature = "serde")]
+pub fn deserialize_ignore_unknown<'de, D>(deserializer: D) -> Result<ConsensusFlags, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserializer.deserialize_any(ConsensusFlagsVisitor {
+        deny_unknown: false,
+    })
+}
+
 #[cfg(feature = "py-bindings")]
 impl<'py> FromPyObject<'py, 'py> for ConsensusFlags {
     type Error = PyErr;
@@ -176,11 +380,21 @@ impl<'py> IntoPyObject<'py> for ConsensusFlags {
     }
 }
 
+/// The python binding passes ConsensusFlags as a plain integer. This returns
+/// the names of the flags set in `bits`, like ConsensusFlags::names().
+#[cfg(feature = "py-bindings")]
+#[pyfunction]
+pub fn flag_names(bits: u32) -> Vec<String> {
+    ConsensusFlags::from_bits_retain(bits).names()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::ConsensusFlags;
+    use super::{ConsensusFlags, MEMPOOL_MODE, ParseConsensusFlagsError};
+    use crate::consensus_constants::{ConsensusConstants, MAINNET_CONSTANTS, TEST_CONSTANTS};
     use bitflags::Flags;
     use clvmr::chia_dialect::ClvmFlags;
+    use rstest::rstest;
 
     /// No two flags may share any bit
     #[test]
@@ -262,4 +476,246 @@ mod tests {
             "consensus-only flags must not appear in to_clvm_flags output"
         );
     }
+
+    #[rstest]
+    #[case("", ConsensusFlags::empty())]
+    #[case("  ", ConsensusFlags::empty())]
+    #[case("LIMIT_HEAP", ConsensusFlags::LIMIT_HEAP)]
+    #[case(
+        "NO_UNKNOWN_CONDS | STRICT_ARGS_COUNT",
+        ConsensusFlags::NO_UNKNOWN_CONDS | ConsensusFlags::STRICT_ARGS_COUNT
+    )]
+    #[case(
+        " NO_UNKNOWN_CONDS,STRICT_ARGS_COUNT ,\tLIMIT_SPENDS ",
+        ConsensusFlags::NO_UNKNOWN_CONDS | ConsensusFlags::STRICT_ARGS_COUNT | ConsensusFlags::LIMIT_SPENDS
+    )]
+    #[case("LIMIT_HEAP | LIMIT_HEAP", ConsensusFlags::LIMIT_HEAP)]
+    #[case(
+        "LIMIT_HEAP | 0x40000000",
+        ConsensusFlags::LIMIT_HEAP | ConsensusFlags::from_bits_retain(0x4000_0000)
+    )]
+    fn parse_flags(#[case] input: &str, #[case] expected: ConsensusFlags) {
+        assert_eq!(input.parse::<ConsensusFlags>(), Ok(expected));
+    }
+
+    #[rstest]
+    #[case("FOOBAR", "FOOBAR")]
+    #[case("LIMIT_HEAP | limit_heap", "limit_heap")]
+    #[case("LIMIT_HEAP | 0xfoo", "0xfoo")]
+    #[case("LIMIT_HEAP | 4", "4")]
+    #[case("LIMIT_HEAP | 0x4000_0000", "0x4000_0000")]
+    #[case("LIMIT_HEAP STRICT_ARGS_COUNT", "LIMIT_HEAP STRICT_ARGS_COUNT")]
+    fn parse_flags_unknown_name(#[case] input: &str, #[case] name: &str) {
+        let err = input.parse::<ConsensusFlags>().unwrap_err();
+        assert_eq!(err, ParseConsensusFlagsError(name.to_string()));
+        assert_eq!(
+            err.to_string(),
+            format!("unknown consensus flag \"{name}\"")
+        );
+    }
+
+    #[test]
+    fn parse_every_flag() {
+        for flag in ConsensusFlags::FLAGS {
+            assert_eq!(flag.name().parse::<ConsensusFlags>(), Ok(*flag.value()));
+        }
+        let all_names: Vec<&str> = ConsensusFlags::FLAGS
+            .iter()
+            .map(bitflags::Flag::name)
+            .collect();
+        assert_eq!(
+            all_names.join(",").parse::<ConsensusFlags>(),
+            Ok(ConsensusFlags::all())
+        );
+        let mempool_names: Vec<&str> = MEMPOOL_MODE.iter_names().map(|(name, _)| name).collect();
+        assert_eq!(
+            mempool_names.join(" | ").parse::<ConsensusFlags>(),
+            Ok(MEMPOOL_MODE)
+        );
+    }
+
+    #[test]
+    fn display_every_flag() {
+        for flag in ConsensusFlags::FLAGS {
+            let value = *flag.value();
+            assert_eq!(value.to_string(), flag.name());
+            assert_eq!(value.to_string().parse::<ConsensusFlags>(), Ok(value));
+        }
+    }
+
+    #[rstest]
+    #[case(ConsensusFlags::empty(), "(empty)")]
+    #[case(
+        ConsensusFlags::STRICT_ARGS_COUNT | ConsensusFlags::LIMIT_HEAP,
+        "LIMIT_HEAP | STRICT_ARGS_COUNT"
+    )]
+    #[case(
+        ConsensusFlags::LIMIT_HEAP | ConsensusFlags::STRICT_ARGS_COUNT,
+        "LIMIT_HEAP | STRICT_ARGS_COUNT"
+    )]
+    #[case(
+        MEMPOOL_MODE,
+        "CANONICAL_INTS | NO_UNKNOWN_OPS | LIMIT_HEAP | LIMIT_SOFTFORK | DISABLE_OP | NO_UNKNOWN_CONDS | STRICT_ARGS_COUNT | LIMIT_SPENDS"
+    )]
+    #[case(ConsensusFlags::from_bits_retain(0x4000_0000), "0x40000000")]
+    #[case(
+        ConsensusFlags::from_bits_retain(0xc000_0000) | ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+        "DONT_VALIDATE_SIGNATURE | 0xc0000000"
+    )]
+    fn display_flags(#[case] flags: ConsensusFlags, #[case] expected: &str) {
+        assert_eq!(flags.to_string(), expected);
+        assert_eq!(expected.parse::<ConsensusFlags>(), Ok(flags));
+    }
+
+    #[rstest]
+    #[case(ConsensusFlags::empty(), &[])]
+    #[case(
+        MEMPOOL_MODE,
+        &[
+            "CANONICAL_INTS",
+            "NO_UNKNOWN_OPS",
+            "LIMIT_HEAP",
+            "LIMIT_SOFTFORK",
+            "DISABLE_OP",
+            "NO_UNKNOWN_CONDS",
+            "STRICT_ARGS_COUNT",
+            "LIMIT_SPENDS",
+        ]
+    )]
+    #[case(
+        ConsensusFlags::from_bits_retain(0xc000_0000) | ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+        &["DONT_VALIDATE_SIGNATURE", "0xc0000000"]
+    )]
+    fn flag_names(#[case] flags: ConsensusFlags, #[case] expected: &[&str]) {
+        assert_eq!(flags.names(), expected);
+        #[cfg(feature = "py-bindings")]
+        assert_eq!(super::flag_names(flags.bits()), expected);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_mempool_mode() {
+        let json = serde_json::to_string(&MEMPOOL_MODE).expect("serialize");
+        assert_eq!(
+            json,
+            r#"["CANONICAL_INTS","NO_UNKNOWN_OPS","LIMIT_HEAP","LIMIT_SOFTFORK","DISABLE_OP","NO_UNKNOWN_CONDS","STRICT_ARGS_COUNT","LIMIT_SPENDS"]"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ConsensusFlags>(&json).expect("deserialize"),
+            MEMPOOL_MODE
+        );
+
+        // a bare integer is accepted too
+        let json = MEMPOOL_MODE.bits().to_string();
+        assert_eq!(
+            serde_json::from_str::<ConsensusFlags>(&json).expect("deserialize"),
+            MEMPOOL_MODE
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[rstest]
+    #[case(ConsensusFlags::empty(), "[]")]
+    #[case(ConsensusFlags::LIMIT_HEAP, r#"["LIMIT_HEAP"]"#)]
+    #[case(
+        ConsensusFlags::from_bits_retain(0x4000_0000) | ConsensusFlags::LIMIT_HEAP,
+        r#"["LIMIT_HEAP","0x40000000"]"#
+    )]
+    fn serde_round_trip(#[case] flags: ConsensusFlags, #[case] json: &str) {
+        assert_eq!(serde_json::to_string(&flags).expect("serialize"), json);
+        assert_eq!(
+            serde_json::from_str::<ConsensusFlags>(json).expect("deserialize"),
+            flags
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_unknown_name() {
+        let json = r#"["LIMIT_HEAP","FOOBAR"]"#;
+        let err = serde_json::from_str::<ConsensusFlags>(json).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(r#"unknown consensus flag "FOOBAR""#),
+            "{err}"
+        );
+        assert!(serde_json::from_str::<ConsensusFlags>("[1]").is_err());
+        assert!(serde_json::from_str::<ConsensusFlags>("4294967296").is_err());
+
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        assert_eq!(
+            super::deserialize_ignore_unknown(&mut deserializer).expect("deserialize"),
+            ConsensusFlags::LIMIT_HEAP
+        );
+    }
+
+    #[rstest]
+    #[case(0, ConsensusFlags::empty())]
+    #[case(99, ConsensusFlags::empty())]
+    #[case(
+        100,
+        ConsensusFlags::ENABLE_KECCAK_OPS_OUTSIDE_GUARD
+            | ConsensusFlags::COST_CONDITIONS
+            | ConsensusFlags::ENABLE_SECP_OPS
+            | ConsensusFlags::RELAXED_BLS
+    )]
+    #[case(
+        199,
+        ConsensusFlags::ENABLE_KECCAK_OPS_OUTSIDE_GUARD
+            | ConsensusFlags::COST_CONDITIONS
+            | ConsensusFlags::ENABLE_SECP_OPS
+            | ConsensusFlags::RELAXED_BLS
+    )]
+    #[case(
+        200,
+        ConsensusFlags::ENABLE_KECCAK_OPS_OUTSIDE_GUARD
+            | ConsensusFlags::COST_CONDITIONS
+            | ConsensusFlags::ENABLE_SECP_OPS
+            | ConsensusFlags::RELAXED_BLS
+            | ConsensusFlags::DISABLE_OP
+    )]
+    #[case(
+        300,
+        ConsensusFlags::ENABLE_KECCAK_OPS_OUTSIDE_GUARD
+            | ConsensusFlags::COST_CONDITIONS
+            | ConsensusFlags::ENABLE_SECP_OPS
+            | ConsensusFlags::RELAXED_BLS
+            | ConsensusFlags::DISABLE_OP
+            | ConsensusFlags::SIMPLE_GENERATOR
+            | ConsensusFlags::CANONICAL_INTS
+            | ConsensusFlags::LIMIT_SPENDS
+    )]
+    #[case(u32::MAX, ConsensusFlags::for_height(300, &fork_constants()))]
+    fn flags_for_height(#[case] height: u32, #[case] expected: ConsensusFlags) {
+        assert_eq!(
+            ConsensusFlags::for_height(height, &fork_constants()),
+            expected
+        );
+    }
+
+    fn fork_constants() -> ConsensusConstants {
+        let mut constants = TEST_CONSTANTS.clone();
+        constants.hard_fork2_height = 100;
+        constants.soft_fork8_height = 200;
+        constants.soft_fork9_height = 300;
+        constants
+    }
+
+    #[test]
+    fn flags_for_height_mainnet() {
+        let height = MAINNET_CONSTANTS.soft_fork8_height;
+        let flags = ConsensusFlags::for_height(height, &MAINNET_CONSTANTS);
+        assert!(flags.contains(ConsensusFlags::DISABLE_OP));
+        assert!(
+            !ConsensusFlags::for_height(height - 1, &MAINNET_CONSTANTS)
+                .contains(ConsensusFlags::DISABLE_OP)
+        );
+        // policy flags are never set
+        assert!(!flags.intersects(
+            ConsensusFlags::NO_UNKNOWN_CONDS
+                | ConsensusFlags::STRICT_ARGS_COUNT
+                | ConsensusFlags::STRICT_HINTS
+                | ConsensusFlags::DONT_VALIDATE_SIGNATURE
+        ));
+    }
 }
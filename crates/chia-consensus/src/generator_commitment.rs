@@ -0,0 +1,116 @@
+//! The commitments a transaction block makes to its generator, in the
+//! `generator_root` and `generator_refs_root` fields of `TransactionsInfo`.
+//! These must match what full nodes compute when validating the block body.
+
+use chia_protocol::Bytes32;
+use chia_sha2::Sha256;
+
+/// The generator root of a block without a generator.
+pub const NO_GENERATOR_ROOT: Bytes32 = Bytes32::new([0; 32]);
+
+/// The generator refs root of a block without any generator references.
+pub const NO_GENERATOR_REFS_ROOT: Bytes32 = Bytes32::new([1; 32]);
+
+/// The `generator_root` and `generator_refs_root` a block header commits to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GeneratorCommitment {
+    pub generator_root: Bytes32,
+    pub generator_refs_root: Bytes32,
+}
+
+/// The hash of the serialized generator, exactly as it's included in the
+/// block. Re-serializing the generator (e.g. with or without back-references)
+/// changes the root.
+pub fn generator_root(program: &[u8]) -> Bytes32 {
+    let mut hasher = Sha256::new();
+    hasher.update(program);
+    Bytes32::new(hasher.finalize())
+}
+
+/// The hash of the concatenation of the block heights the generator refers
+/// to, each serialized as a 4 byte big-endian integer, in the order they are
+/// listed in the block. A block without references has the root
+/// `NO_GENERATOR_REFS_ROOT`.
+pub fn generator_refs_root(refs: &[u32]) -> Bytes32 {
+    if refs.is_empty() {
+        return NO_GENERATOR_REFS_ROOT;
+    }
+    let mut hasher = Sha256::new();
+    for height in refs {
+        hasher.update(height.to_be_bytes());
+    }
+    Bytes32::new(hasher.finalize())
+}
+
+/// Computes both roots a transaction block commits to, given its generator (if
+/// any) and the heights of the blocks it refers to.
+pub fn generator_commitment(program: Option<&[u8]>, refs: &[u32]) -> GeneratorCommitment {
+    GeneratorCommitment {
+        generator_root: program.map_or(NO_GENERATOR_ROOT, generator_root),
+        generator_refs_root: generator_refs_root(refs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+    use rstest::rstest;
+    use std::fs::read_to_string;
+
+    // the generators of mainnet transaction blocks, as included in the blocks.
+    // Block 834752 has no generator references
+    #[rstest]
+    #[case(
+        "block-834752",
+        hex!("7577936bebff76fe556909f32d6ba9060f8f7387294d037f75ae585420e426aa")
+    )]
+    #[case(
+        "block-225758",
+        hex!("d985695161c9b0d617e1b278a90add859d95a59f7c8e6814de30b161d6e53f27")
+    )]
+    #[case(
+        "block-4671894",
+        hex!("7c85606f4eb3894cf974ab9e77ccc4490abc138363c0326116f0dfc70bc6bcc4")
+    )]
+    fn test_mainnet_generator_root(#[case] name: &str, #[case] expected: [u8; 32]) {
+        let test_file = read_to_string(format!("../../generator-tests/{name}.txt"))
+            .expect("test file not found");
+        let (generator, _) = test_file.split_once('\n').expect("invalid test file");
+        let generator = hex::decode(generator).expect("invalid hex encoded generator");
+
+        assert_eq!(generator_root(&generator), Bytes32::new(expected));
+        assert_eq!(
+            generator_commitment(Some(&generator), &[]),
+            GeneratorCommitment {
+                generator_root: Bytes32::new(expected),
+                generator_refs_root: NO_GENERATOR_REFS_ROOT,
+            }
+        );
+    }
+
+    #[rstest]
+    #[case(&[], [1; 32])]
+    #[case(
+        &[1, 2, 3],
+        hex!("7b0b5ea3ff36958c8e32ccf24b71da9ac68e51d0881bf75e62b837ec9ea6f3a5")
+    )]
+    #[case(
+        &[225_758],
+        hex!("542e115b2b10b833faad968a2167422934a4354489ced6b0b520e1c107ca45c8")
+    )]
+    fn test_generator_refs_root(#[case] refs: &[u32], #[case] expected: [u8; 32]) {
+        assert_eq!(generator_refs_root(refs), Bytes32::new(expected));
+    }
+
+    #[test]
+    fn test_no_generator() {
+        assert_eq!(
+            generator_commitment(None, &[]),
+            GeneratorCommitment {
+                generator_root: Bytes32::new([0; 32]),
+                generator_refs_root: Bytes32::new([1; 32]),
+            }
+        );
+    }
+}
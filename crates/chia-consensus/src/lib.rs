@@ -3,19 +3,26 @@
 
 pub mod additions_and_removals;
 pub mod allocator;
+pub mod block_analysis;
+pub mod bloom_filter;
 pub mod build_compressed_block;
 pub mod build_interned_block;
+pub mod chain_validation;
 pub mod check_time_locks;
 mod coin_id;
 mod condition_sanitizers;
 pub mod conditions;
 pub mod consensus_constants;
+#[cfg(feature = "cost-profile")]
+pub mod cost_profile;
 pub mod error;
 pub mod fast_forward;
 pub mod flags;
+pub mod generator_commitment;
 pub mod generator_cost;
 pub mod get_puzzle_and_solution;
 pub mod make_aggsig_final_message;
+pub mod mempool_policy;
 pub mod merkle_set;
 pub mod merkle_tree;
 pub mod messages;
@@ -26,10 +33,15 @@ pub mod run_block_generator;
 pub mod sanitize_int;
 pub mod serde_2026;
 pub mod solution_generator;
+pub mod spend_builder;
+pub mod spend_id;
 pub mod spend_visitor;
 pub mod spendbundle_conditions;
 pub mod spendbundle_validation;
+pub mod typed_solution;
 pub mod validation_error;
+pub mod validation_profile;
+pub mod validation_time;
 
 #[cfg(test)]
 pub(crate) mod test_generators;
@@ -4,6 +4,7 @@ use crate::opcodes::{
     AGG_SIG_PUZZLE, AGG_SIG_PUZZLE_AMOUNT, ConditionOpcode,
 };
 use crate::owned_conditions::OwnedSpendConditions;
+use crate::validation_error::{ErrorCode, ValidationErr};
 use chia_protocol::Coin;
 
 pub fn make_aggsig_final_message(
@@ -50,6 +51,25 @@ pub fn make_aggsig_final_message(
     }
 }
 
+/// AGG_SIG_ME messages commit to the ID of the coin being spent, which
+/// make_aggsig_final_message() derives from the parent ID, puzzle hash and
+/// amount of `spend`. Fails with AggSigMeCoinIdMismatch if that doesn't match
+/// the `coin_id` field of `spend`, since the signature would then be validated
+/// against a different coin than the one reported as spent.
+pub fn check_agg_sig_me_coin_id(spend: &OwnedSpendConditions) -> Result<(), ValidationErr> {
+    if spend.agg_sig_me.is_empty() {
+        return Ok(());
+    }
+    let coin = Coin::new(spend.parent_id, spend.puzzle_hash, spend.coin_amount);
+    if coin.coin_id() != spend.coin_id {
+        return Err(ValidationErr::Spend(
+            ErrorCode::AggSigMeCoinIdMismatch,
+            spend.coin_id,
+        ));
+    }
+    Ok(())
+}
+
 pub fn u64_to_bytes(val: u64) -> Vec<u8> {
     let amount_bytes: [u8; 8] = val.to_be_bytes();
     if val >= 0x8000_0000_0000_0000_u64 {
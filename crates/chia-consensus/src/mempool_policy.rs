@@ -0,0 +1,664 @@
+use crate::owned_conditions::{OwnedSpendBundleConditions, OwnedSpendConditions};
+use crate::validation_error::{ErrorCode, ValidationErr};
+use chia_bls::Signature;
+use chia_protocol::{Bytes32, Coin, CoinSpend};
+use chia_traits::Streamable;
+use std::collections::{HashMap, HashSet};
+
+/// The fee implied by the spend bundle, i.e. the value of all removals not
+/// accounted for by additions.
+fn implied_fee(bundle_conds: &OwnedSpendBundleConditions) -> u128 {
+    bundle_conds
+        .removal_amount
+        .saturating_sub(bundle_conds.addition_amount)
+}
+
+/// The lowest and highest fee the spend bundle may pay. All amounts in
+/// `bundle_conds` are known, so both bounds are the fee implied by the bundle.
+/// A bundle with amounts that aren't resolved yet would have a wider range. A
+/// fee that doesn't fit in a u64 is capped at `u64::MAX`.
+pub fn fee_range(bundle_conds: &OwnedSpendBundleConditions) -> (u64, u64) {
+    let fee = u64::try_from(implied_fee(bundle_conds)).unwrap_or(u64::MAX);
+    (fee, fee)
+}
+
+/// Fails with FeeTooHigh if the fee implied by the spend bundle exceeds
+/// `max_fee`. This is not a consensus rule, it's meant to protect wallets from
+/// broadcasting a spend bundle with a fee that's (most likely) a mistake.
+pub fn check_max_fee(
+    bundle_conds: &OwnedSpendBundleConditions,
+    max_fee: u64,
+) -> Result<(), ValidationErr> {
+    if implied_fee(bundle_conds) > max_fee as u128 {
+        return Err(ValidationErr::Err(ErrorCode::FeeTooHigh));
+    }
+    Ok(())
+}
+
+/// Fails with ReserveFeeTooHigh if the total fee reserved by RESERVE_FEE
+/// conditions in the spend bundle exceeds `max_reserve`. Like
+/// `check_max_fee()`, this is not a consensus rule.
+pub fn check_reserve_fee_bound(
+    bundle_conds: &OwnedSpendBundleConditions,
+    max_reserve: u64,
+) -> Result<(), ValidationErr> {
+    if bundle_conds.reserve_fee > max_reserve {
+        return Err(ValidationErr::Err(ErrorCode::ReserveFeeTooHigh));
+    }
+    Ok(())
+}
+
+/// Fails with BlockedPuzzleHash if any spend in the bundle spends a coin
+/// with, or creates a coin with, a puzzle hash in `blocked`. The error carries
+/// the ID of the first offending coin. This is not a consensus rule, it's
+/// meant for operators enforcing their own policy on what they relay.
+pub fn check_blocklist(
+    bundle_conds: &OwnedSpendBundleConditions,
+    blocked: &HashSet<Bytes32>,
+) -> Result<(), ValidationErr> {
+    for spend in &bundle_conds.spends {
+        if blocked.contains(&spend.puzzle_hash) {
+            return Err(ValidationErr::Spend(
+                ErrorCode::BlockedPuzzleHash,
+                spend.coin_id,
+            ));
+        }
+        for (puzzle_hash, amount, _hint) in &spend.create_coin {
+            if blocked.contains(puzzle_hash) {
+                return Err(ValidationErr::Spend(
+                    ErrorCode::BlockedPuzzleHash,
+                    Coin::new(spend.coin_id, *puzzle_hash, *amount).coin_id(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fails with PuzzleHashRateExceeded if more than `max_per_puzzle` spends in
+/// the bundle spend coins with the same puzzle hash. The error carries the
+/// first puzzle hash to exceed the limit, in the order of the spends. This is
+/// not a consensus rule, it's meant for relays limiting spam to a single
+/// puzzle hash.
+pub fn check_puzzle_hash_rate(
+    bundle_conds: &OwnedSpendBundleConditions,
+    max_per_puzzle: usize,
+) -> Result<(), ValidationErr> {
+    let mut counts = HashMap::<Bytes32, usize>::new();
+    for spend in &bundle_conds.spends {
+        let count = counts.entry(spend.puzzle_hash).or_insert(0);
+        *count += 1;
+        if *count > max_per_puzzle {
+            return Err(ValidationErr::Spend(
+                ErrorCode::PuzzleHashRateExceeded,
+                spend.puzzle_hash,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Fails with SpendBeforeCreation if an ephemeral coin is spent before the
+/// spend that creates it, in the order of the spends in the bundle. The error
+/// carries the ID of the coin spent too early. Consensus rules don't require
+/// any particular order, but some block formats do.
+pub fn check_spend_ordering(
+    bundle_conds: &OwnedSpendBundleConditions,
+) -> Result<(), ValidationErr> {
+    let mut spent_so_far = HashSet::<Bytes32>::new();
+    for spend in &bundle_conds.spends {
+        for (puzzle_hash, amount, _hint) in &spend.create_coin {
+            let coin_id = Coin::new(spend.coin_id, *puzzle_hash, *amount).coin_id();
+            if spent_so_far.contains(&coin_id) {
+                return Err(ValidationErr::Spend(
+                    ErrorCode::SpendBeforeCreation,
+                    coin_id,
+                ));
+            }
+        }
+        spent_so_far.insert(spend.coin_id);
+    }
+    Ok(())
+}
+
+/// Fails with DuplicateOutput if two CREATE_COIN conditions, in any of the
+/// spends, create a coin with the same ID. The error carries the ID of the
+/// first such coin, like when parsing the conditions. This is a consensus
+/// rule, and parsing the conditions of a single spend bundle already enforces
+/// it. This check is meant for conditions combined from multiple spend bundles.
+pub fn check_unique_additions(
+    bundle_conds: &OwnedSpendBundleConditions,
+) -> Result<(), ValidationErr> {
+    let mut additions = HashSet::<Bytes32>::new();
+    for spend in &bundle_conds.spends {
+        for (puzzle_hash, amount, _hint) in &spend.create_coin {
+            let coin_id = Coin::new(spend.coin_id, *puzzle_hash, *amount).coin_id();
+            if !additions.insert(coin_id) {
+                return Err(ValidationErr::Spend(ErrorCode::DuplicateOutput, coin_id));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fails with TooManyOutputs if the spends in the bundle create more than
+/// `max_create_coins` coins in total. This is not a consensus rule, it's meant
+/// to bound the growth of the coin set from a single block or spend bundle.
+pub fn check_create_coin_count(
+    bundle_conds: &OwnedSpendBundleConditions,
+    max_create_coins: usize,
+) -> Result<(), ValidationErr> {
+    let count: usize = bundle_conds
+        .spends
+        .iter()
+        .map(|spend| spend.create_coin.len())
+        .sum();
+    if count > max_create_coins {
+        return Err(ValidationErr::Err(ErrorCode::TooManyOutputs));
+    }
+    Ok(())
+}
+
+/// Fails with ImpossibleTimeLockConstraints if a relative time-lock of a spend
+/// contradicts the absolute time-locks of the bundle. A relative time-lock
+/// can't be met before the coin was created, which is 0 at the earliest, or
+/// the coin's birth, if it's asserted. The error carries the ID of the coin.
+/// This is not a consensus rule, a bundle like this can never be included in a
+/// block, so there's no point in keeping it in the mempool.
+pub fn check_time_lock_constraints(
+    bundle_conds: &OwnedSpendBundleConditions,
+) -> Result<(), ValidationErr> {
+    let err = |spend: &OwnedSpendConditions| {
+        Err(ValidationErr::Spend(
+            ErrorCode::ImpossibleTimeLockConstraints,
+            spend.coin_id,
+        ))
+    };
+    for spend in &bundle_conds.spends {
+        if let (Some(bh), Some(hr)) = (bundle_conds.before_height_absolute, spend.height_relative) {
+            if u64::from(bh) <= u64::from(spend.birth_height.unwrap_or(0)) + u64::from(hr) {
+                return err(spend);
+            }
+        }
+        if let (Some(bs), Some(sr)) = (bundle_conds.before_seconds_absolute, spend.seconds_relative)
+        {
+            if bs <= spend.birth_seconds.unwrap_or(0).saturating_add(sr) {
+                return err(spend);
+            }
+        }
+        // the latest a relative time-lock can be met is only known if the
+        // coin's birth is asserted
+        if let (Some(bhr), Some(birth)) = (spend.before_height_relative, spend.birth_height) {
+            if u64::from(birth) + u64::from(bhr) <= u64::from(bundle_conds.height_absolute) {
+                return err(spend);
+            }
+        }
+        if let (Some(bsr), Some(birth)) = (spend.before_seconds_relative, spend.birth_seconds) {
+            if birth.saturating_add(bsr) <= bundle_conds.seconds_absolute {
+                return err(spend);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the coins created with a value below `min_value`, as the index of
+/// the spend creating it and the coin ID, in the order they are created.
+pub fn dust_outputs(
+    bundle_conds: &OwnedSpendBundleConditions,
+    min_value: u64,
+) -> Vec<(usize, Bytes32)> {
+    let mut ret = Vec::new();
+    for (idx, spend) in bundle_conds.spends.iter().enumerate() {
+        for (puzzle_hash, amount, _hint) in &spend.create_coin {
+            if *amount < min_value {
+                ret.push((
+                    idx,
+                    Coin::new(spend.coin_id, *puzzle_hash, *amount).coin_id(),
+                ));
+            }
+        }
+    }
+    ret
+}
+
+/// Returns the size of the spend bundle made up of `spends` and `signature`,
+/// serialized the way it's relayed to peers. Fails with SpendBundleTooLarge if
+/// the size exceeds `max_size`.
+pub fn check_bundle_size(
+    spends: &[CoinSpend],
+    signature: &Signature,
+    max_size: usize,
+) -> Result<usize, ValidationErr> {
+    // this is how SpendBundle is streamed, without having to copy the spends
+    // into one. We stop as soon as the limit is exceeded
+    let too_large = || ValidationErr::Err(ErrorCode::SpendBundleTooLarge);
+    let mut out = Vec::<u8>::new();
+    let num_spends = u32::try_from(spends.len()).map_err(|_| too_large())?;
+    num_spends.stream(&mut out).map_err(|_| too_large())?;
+    for cs in spends {
+        cs.stream(&mut out).map_err(|_| too_large())?;
+        if out.len() > max_size {
+            return Err(too_large());
+        }
+    }
+    signature.stream(&mut out).map_err(|_| too_large())?;
+    if out.len() > max_size {
+        return Err(too_large());
+    }
+    Ok(out.len())
+}
+
+/// Fails with SolutionTooLarge if the serialized solution of any of the
+/// `spends` is larger than `max_solution_size` bytes. The error carries the ID
+/// of the first coin with a solution that's too large. This is not a consensus
+/// rule, it's meant to protect the mempool from oversized solutions, which are
+/// costly to relay even when the puzzle is small.
+pub fn check_solution_size(
+    spends: &[CoinSpend],
+    max_solution_size: usize,
+) -> Result<(), ValidationErr> {
+    for cs in spends {
+        if cs.solution.len() > max_solution_size {
+            return Err(ValidationErr::Spend(
+                ErrorCode::SolutionTooLarge,
+                cs.coin.coin_id(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the indices of the spends whose fee is below `floor`, even if the
+/// bundle as a whole pays enough. The fee of the bundle is attributed to the
+/// spends by value: a spend contributes the amount of the coin it spends,
+/// minus the amounts of the coins it creates. A spend that creates more value
+/// than it spends (funded by other spends in the bundle) contributes a
+/// negative fee. Value passed between spends through ephemeral coins is
+/// attributed to the spend creating the coin as an output, and to the spend
+/// spending it as an input, so the sum of the fees of all spends is always
+/// the fee of the bundle.
+pub fn per_spend_fee_check(bundle_conds: &OwnedSpendBundleConditions, floor: u64) -> Vec<usize> {
+    bundle_conds
+        .spends
+        .iter()
+        .enumerate()
+        .filter(|(_, spend)| {
+            let created: i128 = spend
+                .create_coin
+                .iter()
+                .map(|(_puzzle_hash, amount, _hint)| i128::from(*amount))
+                .sum();
+            i128::from(spend.coin_amount) - created < i128::from(floor)
+        })
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chia_protocol::{Program, SpendBundle};
+    use rstest::rstest;
+
+    fn bundle(removal_amount: u128, addition_amount: u128) -> OwnedSpendBundleConditions {
+        OwnedSpendBundleConditions {
+            removal_amount,
+            addition_amount,
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case::no_fee(bundle(1000, 1000), 0, Ok(()))]
+    #[case::below_max(bundle(1000, 900), 101, Ok(()))]
+    #[case::at_max(bundle(1000, 900), 100, Ok(()))]
+    #[case::above_max(bundle(1000, 900), 99, Err(ErrorCode::FeeTooHigh))]
+    #[case::absurd_fee(
+        bundle(1_000_000_000_000_000, 1),
+        10_000_000_000,
+        Err(ErrorCode::FeeTooHigh)
+    )]
+    #[case::exceeds_u64(bundle(u128::from(u64::MAX) * 2, 0), u64::MAX, Err(ErrorCode::FeeTooHigh))]
+    fn test_check_max_fee(
+        #[case] bundle_conds: OwnedSpendBundleConditions,
+        #[case] max_fee: u64,
+        #[case] expected: Result<(), ErrorCode>,
+    ) {
+        assert_eq!(
+            check_max_fee(&bundle_conds, max_fee).map_err(|e| e.error_code()),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::no_fee(bundle(1000, 1000), 0)]
+    #[case::fee(bundle(1000, 900), 100)]
+    #[case::no_additions(bundle(1000, 0), 1000)]
+    #[case::exceeds_u64(bundle(u128::from(u64::MAX) * 2, 0), u64::MAX)]
+    fn test_fee_range(#[case] bundle_conds: OwnedSpendBundleConditions, #[case] fee: u64) {
+        assert_eq!(fee_range(&bundle_conds), (fee, fee));
+    }
+
+    fn spend(
+        parent_id: Bytes32,
+        coin_amount: u64,
+        create_coin: Vec<(Bytes32, u64)>,
+    ) -> OwnedSpendConditions {
+        let puzzle_hash = Bytes32::from([0x11; 32]);
+        OwnedSpendConditions {
+            coin_id: Coin::new(parent_id, puzzle_hash, coin_amount).coin_id(),
+            parent_id,
+            puzzle_hash,
+            coin_amount,
+            create_coin: create_coin
+                .into_iter()
+                .map(|(ph, amount)| (ph, amount, None))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case::no_reserve(0, 0, Ok(()))]
+    #[case::below_max(100, 101, Ok(()))]
+    #[case::at_max(100, 100, Ok(()))]
+    #[case::above_max(100, 99, Err(ErrorCode::ReserveFeeTooHigh))]
+    #[case::max_u64(u64::MAX, u64::MAX - 1, Err(ErrorCode::ReserveFeeTooHigh))]
+    fn test_check_reserve_fee_bound(
+        #[case] reserve_fee: u64,
+        #[case] max_reserve: u64,
+        #[case] expected: Result<(), ErrorCode>,
+    ) {
+        let bundle_conds = OwnedSpendBundleConditions {
+            reserve_fee,
+            ..Default::default()
+        };
+        assert_eq!(
+            check_reserve_fee_bound(&bundle_conds, max_reserve).map_err(|e| e.error_code()),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::no_spends(0, 0, 1, Ok(()))]
+    #[case::below_max(2, 1, 3, Ok(()))]
+    #[case::at_max(3, 1, 3, Ok(()))]
+    #[case::above_max(4, 1, 3, Err(0x11))]
+    #[case::other_puzzle_above_max(1, 4, 3, Err(0x22))]
+    #[case::both_at_max(3, 3, 3, Ok(()))]
+    #[case::zero_max(1, 0, 0, Err(0x11))]
+    fn test_check_puzzle_hash_rate(
+        #[case] num_first: u8,
+        #[case] num_second: u8,
+        #[case] max_per_puzzle: usize,
+        #[case] expected: Result<(), u8>,
+    ) {
+        let make_spends = |puzzle_hash: u8, count: u8| {
+            (0..count).map(move |i| {
+                let mut s = spend(Bytes32::from([i; 32]), 1000, vec![]);
+                s.puzzle_hash = Bytes32::from([puzzle_hash; 32]);
+                s
+            })
+        };
+        let bundle_conds = OwnedSpendBundleConditions {
+            spends: make_spends(0x11, num_first)
+                .chain(make_spends(0x22, num_second))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            check_puzzle_hash_rate(&bundle_conds, max_per_puzzle),
+            expected.map_err(|ph| ValidationErr::Spend(
+                ErrorCode::PuzzleHashRateExceeded,
+                Bytes32::from([ph; 32])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_check_blocklist() {
+        let puzzle_hash = Bytes32::from([0x11; 32]);
+        let blocked_puzzle_hash = Bytes32::from([0x22; 32]);
+        let blocked = HashSet::from([blocked_puzzle_hash]);
+        let bundle = |spends| OwnedSpendBundleConditions {
+            spends,
+            ..Default::default()
+        };
+
+        let ok = spend(Bytes32::from([0x33; 32]), 1000, vec![(puzzle_hash, 1000)]);
+        assert_eq!(check_blocklist(&bundle(vec![ok.clone()]), &blocked), Ok(()));
+        assert_eq!(
+            check_blocklist(&bundle(vec![ok.clone()]), &HashSet::new()),
+            Ok(())
+        );
+
+        // spending from a blocked puzzle hash
+        let mut spend_from = spend(Bytes32::from([0x44; 32]), 1000, vec![(puzzle_hash, 1000)]);
+        spend_from.puzzle_hash = blocked_puzzle_hash;
+        assert_eq!(
+            check_blocklist(&bundle(vec![ok.clone(), spend_from.clone()]), &blocked),
+            Err(ValidationErr::Spend(
+                ErrorCode::BlockedPuzzleHash,
+                spend_from.coin_id
+            ))
+        );
+
+        // creating a coin with a blocked puzzle hash
+        let create_to = spend(
+            Bytes32::from([0x55; 32]),
+            1000,
+            vec![(puzzle_hash, 400), (blocked_puzzle_hash, 600)],
+        );
+        assert_eq!(
+            check_blocklist(&bundle(vec![ok, create_to.clone()]), &blocked),
+            Err(ValidationErr::Spend(
+                ErrorCode::BlockedPuzzleHash,
+                Coin::new(create_to.coin_id, blocked_puzzle_hash, 600).coin_id()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_check_spend_ordering() {
+        let puzzle_hash = Bytes32::from([0x11; 32]);
+        let parent = spend(Bytes32::from([0x33; 32]), 1000, vec![(puzzle_hash, 900)]);
+        let ephemeral = spend(parent.coin_id, 900, vec![(puzzle_hash, 900)]);
+        // a grandchild, created by the ephemeral spend
+        let grandchild = spend(ephemeral.coin_id, 900, vec![]);
+        let unrelated = spend(Bytes32::from([0x44; 32]), 1000, vec![]);
+        let bundle = |spends: &[&OwnedSpendConditions]| OwnedSpendBundleConditions {
+            spends: spends.iter().map(|s| (*s).clone()).collect(),
+            ..Default::default()
+        };
+
+        for spends in [
+            vec![&parent, &ephemeral],
+            vec![&parent, &unrelated, &ephemeral],
+            vec![&unrelated, &parent, &ephemeral, &grandchild],
+            vec![&unrelated],
+            vec![],
+        ] {
+            assert_eq!(check_spend_ordering(&bundle(&spends)), Ok(()));
+        }
+
+        for (spends, coin_id) in [
+            (vec![&ephemeral, &parent], ephemeral.coin_id),
+            (vec![&ephemeral, &unrelated, &parent], ephemeral.coin_id),
+            (vec![&parent, &grandchild, &ephemeral], grandchild.coin_id),
+        ] {
+            assert_eq!(
+                check_spend_ordering(&bundle(&spends)),
+                Err(ValidationErr::Spend(
+                    ErrorCode::SpendBeforeCreation,
+                    coin_id
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_unique_additions() {
+        let puzzle_hash = Bytes32::from([0x11; 32]);
+        let other_puzzle_hash = Bytes32::from([0x22; 32]);
+        let coin1 = spend(Bytes32::from([0x33; 32]), 1000, vec![(puzzle_hash, 900)]);
+        let coin2 = spend(
+            Bytes32::from([0x44; 32]),
+            1000,
+            vec![(puzzle_hash, 900), (other_puzzle_hash, 100)],
+        );
+        let bundle = |spends: &[&OwnedSpendConditions]| OwnedSpendBundleConditions {
+            spends: spends.iter().map(|s| (*s).clone()).collect(),
+            ..Default::default()
+        };
+
+        // the same puzzle hash and amount, but different parents
+        assert_eq!(check_unique_additions(&bundle(&[&coin1, &coin2])), Ok(()));
+        assert_eq!(check_unique_additions(&bundle(&[])), Ok(()));
+
+        // two spends of the same coin (e.g. from two spend bundles) create
+        // identical coins
+        let duplicate = Coin::new(coin2.coin_id, other_puzzle_hash, 100).coin_id();
+        let mut coin2_again = coin2.clone();
+        coin2_again.create_coin = vec![(other_puzzle_hash, 100, None)];
+        assert_eq!(
+            check_unique_additions(&bundle(&[&coin1, &coin2, &coin2_again])),
+            Err(ValidationErr::Spend(ErrorCode::DuplicateOutput, duplicate))
+        );
+    }
+
+    #[rstest]
+    #[case::no_limit(usize::MAX, Ok(()))]
+    #[case::at_max(3, Ok(()))]
+    #[case::above_max(2, Err(ErrorCode::TooManyOutputs))]
+    #[case::zero_max(0, Err(ErrorCode::TooManyOutputs))]
+    fn test_check_create_coin_count(
+        #[case] max_create_coins: usize,
+        #[case] expected: Result<(), ErrorCode>,
+    ) {
+        // the limit applies across spends. The first spend creates two coins
+        // and the second spend creates one
+        let puzzle_hash = Bytes32::from([0x11; 32]);
+        let bundle_conds = OwnedSpendBundleConditions {
+            spends: vec![
+                spend(
+                    Bytes32::from([0x33; 32]),
+                    1000,
+                    vec![(puzzle_hash, 1), (puzzle_hash, 2)],
+                ),
+                spend(Bytes32::from([0x44; 32]), 1000, vec![(puzzle_hash, 3)]),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            check_create_coin_count(&bundle_conds, max_create_coins).map_err(|e| e.error_code()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_check_bundle_size() {
+        let coin = Coin::new([0x11; 32].into(), [0x22; 32].into(), 1000);
+        let spend = |puzzle: &[u8], solution: &[u8]| {
+            CoinSpend::new(
+                coin,
+                Program::from(puzzle.to_vec()),
+                Program::from(solution.to_vec()),
+            )
+        };
+        let spends = vec![
+            spend(&[0x01], &[0x80]),
+            spend(&[0xff, 0x01, 0x80], &[0x84, 1, 2, 3, 4]),
+        ];
+        let signature = Signature::default();
+
+        let expected = SpendBundle::new(spends.clone(), signature.clone())
+            .to_bytes()
+            .expect("to_bytes")
+            .len();
+        assert_eq!(
+            check_bundle_size(&spends, &signature, expected),
+            Ok(expected)
+        );
+        assert_eq!(check_bundle_size(&[], &signature, 100), Ok(4 + 96));
+        assert_eq!(
+            check_bundle_size(&spends, &signature, expected - 1),
+            Err(ValidationErr::Err(ErrorCode::SpendBundleTooLarge))
+        );
+    }
+
+    #[rstest]
+    #[case::below_max(1, Ok(()))]
+    #[case::at_max(0, Ok(()))]
+    #[case::above_max(-1, Err(1))]
+    fn test_check_solution_size(#[case] delta: isize, #[case] expected: Result<(), usize>) {
+        let coin_spend = |parent: u8, solution: &[u8]| {
+            CoinSpend::new(
+                Coin::new(Bytes32::from([parent; 32]), Bytes32::from([0x11; 32]), 1000),
+                Program::from(vec![0x80]),
+                Program::from(solution.to_vec()),
+            )
+        };
+        let spends = vec![
+            coin_spend(0x33, &[0x80]),
+            coin_spend(0x44, &[0xff, 0x01, 0x80]),
+        ];
+        let max_solution_size = (3 + delta) as usize;
+        assert_eq!(
+            check_solution_size(&spends, max_solution_size),
+            expected.map_err(|idx| ValidationErr::Spend(
+                ErrorCode::SolutionTooLarge,
+                spends[idx].coin.coin_id()
+            ))
+        );
+        assert_eq!(check_solution_size(&[], 0), Ok(()));
+    }
+
+    #[test]
+    fn test_dust_outputs() {
+        let puzzle_hash = Bytes32::from([0x22; 32]);
+        let coin1 = spend(
+            Bytes32::from([0x33; 32]),
+            1000,
+            vec![(puzzle_hash, 900), (puzzle_hash, 1)],
+        );
+        let coin2 = spend(Bytes32::from([0x44; 32]), 1000, vec![(puzzle_hash, 99)]);
+        let bundle_conds = OwnedSpendBundleConditions {
+            spends: vec![coin1.clone(), coin2.clone()],
+            ..Default::default()
+        };
+
+        let dust1 = Coin::new(coin1.coin_id, puzzle_hash, 1).coin_id();
+        let dust2 = Coin::new(coin2.coin_id, puzzle_hash, 99).coin_id();
+        assert_eq!(
+            dust_outputs(&bundle_conds, 100),
+            vec![(0, dust1), (1, dust2)]
+        );
+        assert_eq!(dust_outputs(&bundle_conds, 99), vec![(0, dust1)]);
+        assert_eq!(dust_outputs(&bundle_conds, 0), vec![]);
+    }
+
+    #[test]
+    fn test_per_spend_fee_check() {
+        let puzzle_hash = Bytes32::from([0x22; 32]);
+        let bundle_conds = OwnedSpendBundleConditions {
+            spends: vec![
+                // pays a fee of 100
+                spend(Bytes32::from([0x33; 32]), 1000, vec![(puzzle_hash, 900)]),
+                // pays a fee of 10
+                spend(Bytes32::from([0x44; 32]), 1000, vec![(puzzle_hash, 990)]),
+                // funded by the first spend
+                spend(Bytes32::from([0x55; 32]), 50, vec![(puzzle_hash, 60)]),
+                // spends a coin without creating any, paying a fee of 25
+                spend(Bytes32::from([0x66; 32]), 25, vec![]),
+            ],
+            ..Default::default()
+        };
+
+        // the bundle as a whole pays 125
+        assert_eq!(per_spend_fee_check(&bundle_conds, 20), vec![1, 2]);
+        assert_eq!(per_spend_fee_check(&bundle_conds, 10), vec![2]);
+        assert_eq!(per_spend_fee_check(&bundle_conds, 0), vec![2]);
+        assert_eq!(per_spend_fee_check(&bundle_conds, 101), vec![0, 1, 2, 3]);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::flags::ConsensusFlags;
 use clvmr::allocator::{Allocator, NodePtr, SExp};
 use clvmr::cost::Cost;
 
@@ -115,11 +116,76 @@ pub fn compute_unknown_condition_cost(op: ConditionOpcode) -> Cost {
     }
 }
 
-pub fn parse_opcode(
-    a: &Allocator,
-    op: NodePtr,
-    _flags: crate::flags::ConsensusFlags,
-) -> Option<ConditionOpcode> {
+/// The set of condition opcodes known to consensus. The set has grown over
+/// forks. When replaying historical blocks, conditions added after the block
+/// was farmed must be treated as unknown, like they were at the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum KnownConditions {
+    /// The conditions known at mainnet launch
+    Launch,
+    /// Soft-fork 2 added ASSERT_CONCURRENT_SPEND, ASSERT_CONCURRENT_PUZZLE,
+    /// ASSERT_MY_BIRTH_SECONDS, ASSERT_MY_BIRTH_HEIGHT, ASSERT_EPHEMERAL,
+    /// ASSERT_BEFORE_* and SOFTFORK
+    SoftFork2,
+    /// The 2.0 hard fork added AGG_SIG_PARENT, AGG_SIG_PUZZLE, AGG_SIG_AMOUNT,
+    /// AGG_SIG_PUZZLE_AMOUNT, AGG_SIG_PARENT_AMOUNT, AGG_SIG_PARENT_PUZZLE,
+    /// SEND_MESSAGE and RECEIVE_MESSAGE
+    #[default]
+    HardFork,
+}
+
+impl KnownConditions {
+    /// The set of known conditions selected by the KNOWN_CONDITIONS_* flags.
+    /// If none of them are set, all conditions are known.
+    pub fn from_flags(flags: ConsensusFlags) -> Self {
+        if flags.contains(ConsensusFlags::KNOWN_CONDITIONS_LAUNCH) {
+            Self::Launch
+        } else if flags.contains(ConsensusFlags::KNOWN_CONDITIONS_SOFT_FORK2) {
+            Self::SoftFork2
+        } else {
+            Self::HardFork
+        }
+    }
+
+    /// The flags to pass to parse_spends() or run_block_generator2() to
+    /// validate with this set of known conditions.
+    pub fn flags(self) -> ConsensusFlags {
+        match self {
+            Self::Launch => ConsensusFlags::KNOWN_CONDITIONS_LAUNCH,
+            Self::SoftFork2 => ConsensusFlags::KNOWN_CONDITIONS_SOFT_FORK2,
+            Self::HardFork => ConsensusFlags::empty(),
+        }
+    }
+
+    /// Returns false if `op` was added after this set. Opcodes that aren't
+    /// known conditions at all are not affected.
+    pub fn contains(self, op: ConditionOpcode) -> bool {
+        let added = match op {
+            ASSERT_CONCURRENT_SPEND
+            | ASSERT_CONCURRENT_PUZZLE
+            | ASSERT_MY_BIRTH_SECONDS
+            | ASSERT_MY_BIRTH_HEIGHT
+            | ASSERT_EPHEMERAL
+            | ASSERT_BEFORE_SECONDS_RELATIVE
+            | ASSERT_BEFORE_SECONDS_ABSOLUTE
+            | ASSERT_BEFORE_HEIGHT_RELATIVE
+            | ASSERT_BEFORE_HEIGHT_ABSOLUTE
+            | SOFTFORK => Self::SoftFork2,
+            AGG_SIG_PARENT
+            | AGG_SIG_PUZZLE
+            | AGG_SIG_AMOUNT
+            | AGG_SIG_PUZZLE_AMOUNT
+            | AGG_SIG_PARENT_AMOUNT
+            | AGG_SIG_PARENT_PUZZLE
+            | SEND_MESSAGE
+            | RECEIVE_MESSAGE => Self::HardFork,
+            _ => Self::Launch,
+        };
+        added <= self
+    }
+}
+
+pub fn parse_opcode(a: &Allocator, op: NodePtr, flags: ConsensusFlags) -> Option<ConditionOpcode> {
     let buf = match a.sexp(op) {
         SExp::Atom => a.atom(op),
         SExp::Pair(..) => return None,
@@ -170,7 +236,9 @@ pub fn parse_opcode(
             | AGG_SIG_PARENT_AMOUNT
             | AGG_SIG_PARENT_PUZZLE
             | SEND_MESSAGE
-            | RECEIVE_MESSAGE => Some(b0),
+            | RECEIVE_MESSAGE => {
+                Some(b0).filter(|op| KnownConditions::from_flags(flags).contains(*op))
+            }
             _ => None,
         }
     } else {
@@ -253,3 +321,29 @@ fn test_parse_invalid_opcode() {
         None
     );
 }
+
+#[cfg(test)]
+#[rstest]
+#[case(CREATE_COIN, KnownConditions::Launch)]
+#[case(REMARK, KnownConditions::Launch)]
+#[case(ASSERT_HEIGHT_ABSOLUTE, KnownConditions::Launch)]
+#[case(ASSERT_EPHEMERAL, KnownConditions::SoftFork2)]
+#[case(ASSERT_BEFORE_HEIGHT_ABSOLUTE, KnownConditions::SoftFork2)]
+#[case(SOFTFORK, KnownConditions::SoftFork2)]
+#[case(AGG_SIG_PARENT, KnownConditions::HardFork)]
+#[case(SEND_MESSAGE, KnownConditions::HardFork)]
+#[case(RECEIVE_MESSAGE, KnownConditions::HardFork)]
+fn test_known_conditions(#[case] op: ConditionOpcode, #[case] added: KnownConditions) {
+    let mut a = Allocator::new();
+    for known in [
+        KnownConditions::Launch,
+        KnownConditions::SoftFork2,
+        KnownConditions::HardFork,
+    ] {
+        assert_eq!(KnownConditions::from_flags(known.flags()), known);
+        assert_eq!(known.contains(op), added <= known);
+        // a condition added later than the set is parsed as an unknown one
+        let expected = if added <= known { Some(op) } else { None };
+        assert_eq!(opcode_tester(&mut a, &[op as u8], known.flags()), expected);
+    }
+}
@@ -81,6 +81,11 @@ pub struct OwnedSpendBundleConditions {
     pub num_atoms: u32,
     pub num_pairs: u32,
     pub heap_size: u32,
+    /// the cost of the generator bytes
+    pub deserialization_cost: u64,
+    /// set if the generator was run under the simple generator rules, i.e.
+    /// the SIMPLE_GENERATOR flag was set
+    pub simple_generator: bool,
 }
 
 impl OwnedSpendConditions {
@@ -169,6 +174,8 @@ impl OwnedSpendBundleConditions {
             num_atoms: a.atom_count() as u32,
             num_pairs: a.pair_count() as u32,
             heap_size: a.allocated_heap_size() as u32,
+            deserialization_cost: sb.deserialization_cost,
+            simple_generator: sb.simple_generator,
         }
     }
 }
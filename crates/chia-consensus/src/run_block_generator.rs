@@ -1,8 +1,13 @@
 use crate::allocator::make_allocator;
+#[cfg(feature = "rayon")]
+use crate::allocator::{
+    AllocatorUsage, HEAP_LIMIT, MAX_NUM_ATOMS, MAX_NUM_PAIRS, allocator_usage, heap_limit,
+};
 use crate::condition_sanitizers::parse_amount;
 use crate::conditions::{
-    EmptyVisitor, MAX_SPENDS_PER_BLOCK, ParseState, SpendBundleConditions, parse_spends,
-    process_single_spend, validate_conditions, validate_signature,
+    ConditionCosts, CostOracle, EmptyVisitor, MAX_SPENDS_PER_BLOCK, ParseState,
+    SpendBundleConditions, SpendConditions, parse_spends, process_single_spend_with_costs,
+    validate_conditions, validate_signature,
 };
 use crate::consensus_constants::ConsensusConstants;
 use crate::flags::ConsensusFlags;
@@ -17,24 +22,144 @@ use chia_protocol::{BytesImpl, Coin, CoinSpend, Program};
 use chia_puzzles::{CHIALISP_DESERIALISATION, ROM_BOOTSTRAP_GENERATOR};
 use clvm_traits::FromClvm;
 use clvm_traits::MatchByte;
+#[cfg(feature = "rayon")]
+use clvm_utils::tree_hash;
 use clvm_utils::{TreeCache, tree_hash_cached};
 use clvmr::SExp;
 use clvmr::allocator::{Allocator, NodePtr};
-use clvmr::chia_dialect::ChiaDialect;
+use clvmr::chia_dialect::{ChiaDialect, ClvmFlags};
 use clvmr::cost::Cost;
-use clvmr::reduction::Reduction;
+use clvmr::dialect::{Dialect, OperatorSet};
+use clvmr::error::EvalErr;
+use clvmr::reduction::{Reduction, Response};
 use clvmr::run_program::run_program;
-use clvmr::serde::{InternedTree, intern_tree_limited, node_from_bytes, node_from_bytes_backrefs};
+use clvmr::serde::{
+    InternedTree, intern_tree_limited, node_from_bytes, node_from_bytes_backrefs,
+    node_to_bytes_backrefs, serialized_length_from_bytes_trusted,
+};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::cell::Cell;
+#[cfg(feature = "rayon")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// The number of operator calls between checks of the deadline, when running
+/// a block generator with one. Reading the clock is much more expensive than
+/// most operators, so it's only sampled.
+const DEADLINE_CHECK_INTERVAL: u32 = 1024;
+
+/// Wraps a dialect to abort the CLVM run loop once `deadline` has passed. The
+/// deadline is checked every DEADLINE_CHECK_INTERVAL operator calls, and a
+/// program that times out fails with an EvalErr. timed_out() tells such
+/// failures apart from other errors.
+struct DeadlineDialect<D> {
+    inner: D,
+    deadline: Option<Instant>,
+    ops_left: Cell<u32>,
+    timed_out: Cell<bool>,
+}
+
+impl<D: Dialect> DeadlineDialect<D> {
+    fn new(inner: D, deadline: Option<Instant>) -> Self {
+        Self {
+            inner,
+            deadline,
+            ops_left: Cell::new(DEADLINE_CHECK_INTERVAL),
+            timed_out: Cell::new(false),
+        }
+    }
+
+    fn timed_out(&self) -> bool {
+        self.timed_out.get()
+    }
+
+    fn run(
+        &self,
+        a: &mut Allocator,
+        program: NodePtr,
+        env: NodePtr,
+        max_cost: Cost,
+    ) -> Result<Reduction, ValidationErr> {
+        run_program(a, self, program, env, max_cost).map_err(|e| {
+            if self.timed_out() {
+                ValidationErr::Err(ErrorCode::Timeout)
+            } else {
+                e.into()
+            }
+        })
+    }
+}
+
+impl<D: Dialect> Dialect for DeadlineDialect<D> {
+    fn quote_kw(&self) -> u32 {
+        self.inner.quote_kw()
+    }
+    fn apply_kw(&self) -> u32 {
+        self.inner.apply_kw()
+    }
+    fn softfork_kw(&self) -> u32 {
+        self.inner.softfork_kw()
+    }
+    fn softfork_extension(&self, ext: u32) -> OperatorSet {
+        self.inner.softfork_extension(ext)
+    }
+    fn flags(&self) -> ClvmFlags {
+        self.inner.flags()
+    }
+    fn gc_candidate(&self, allocator: &Allocator, op: NodePtr) -> bool {
+        self.inner.gc_candidate(allocator, op)
+    }
+    fn op(
+        &self,
+        allocator: &mut Allocator,
+        op: NodePtr,
+        args: NodePtr,
+        max_cost: Cost,
+        extensions: OperatorSet,
+    ) -> Response {
+        if let Some(deadline) = self.deadline {
+            let ops_left = self.ops_left.get() - 1;
+            if ops_left == 0 {
+                if Instant::now() >= deadline {
+                    self.timed_out.set(true);
+                    return Err(EvalErr::InternalError(op, "deadline exceeded".to_string()));
+                }
+                self.ops_left.set(DEADLINE_CHECK_INTERVAL);
+            } else {
+                self.ops_left.set(ops_left);
+            }
+        }
+        self.inner.op(allocator, op, args, max_cost, extensions)
+    }
+    fn allow_unknown_ops(&self) -> bool {
+        self.inner.allow_unknown_ops()
+    }
+}
 
+/// Subtracts the cost of running CLVM from `cost_left`, failing with
+/// ExecutionCostExceeded if there isn't enough left. The cost of conditions is
+/// charged while parsing them, and fails with ConditionCostExceeded.
 pub fn subtract_cost(cost_left: &mut Cost, subtract: Cost) -> Result<(), ValidationErr> {
     if subtract > *cost_left {
-        Err(ValidationErr::Err(ErrorCode::CostExceeded))
+        Err(ValidationErr::Err(ErrorCode::ExecutionCostExceeded))
     } else {
         *cost_left -= subtract;
         Ok(())
     }
 }
 
+/// Subtracts the cost of the generator's bytes from `cost_left`, failing with
+/// CostExceeded if there isn't enough left.
+pub fn subtract_byte_cost(cost_left: &mut Cost, byte_cost: Cost) -> Result<(), ValidationErr> {
+    if byte_cost > *cost_left {
+        Err(ValidationErr::Err(ErrorCode::CostExceeded))
+    } else {
+        *cost_left -= byte_cost;
+        Ok(())
+    }
+}
+
 /// Prepares the arguments passed to the block generator. They are in the form:
 /// (DESERIALIZER_MOD (block1 block2 block3 ...))
 pub fn setup_generator_args<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
@@ -96,16 +221,92 @@ pub fn run_block_generator<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
 where
     <I as IntoIterator>::IntoIter: DoubleEndedIterator,
 {
-    check_generator_quote(program, flags)?;
+    run_block_generator_with_deadline(
+        program, block_refs, max_cost, flags, signature, bls_cache, constants, None,
+    )
+}
+
+/// Like run_block_generator(), but fails with Timeout if running the
+/// generator is still in progress at `deadline`. The clock is only sampled
+/// every DEADLINE_CHECK_INTERVAL operator calls, so the run may overshoot the
+/// deadline slightly. A `deadline` of None means no time limit.
+#[allow(clippy::too_many_arguments)]
+pub fn run_block_generator_with_deadline<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+    deadline: Option<Instant>,
+) -> Result<(Allocator, SpendBundleConditions), ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
     let mut a = make_allocator(flags);
+    let result = run_block_generator_impl(
+        &mut a, program, block_refs, max_cost, flags, signature, bls_cache, constants, deadline,
+    )?;
+    Ok((a, result))
+}
+
+/// Like run_block_generator(), but uses the caller's allocator, `a`, instead
+/// of creating one. This saves setting up a new allocator for every block, when
+/// validating many blocks. The allocator doesn't need to be empty, the nodes
+/// already in it are left untouched. The NodePtr fields of the returned
+/// conditions point into `a`. If the block is invalid, `a` is restored to the
+/// state it was in before the call. The caller is expected to restore a
+/// checkpoint of its own between blocks, to not exhaust the allocator's heap
+/// limit.
+#[allow(clippy::too_many_arguments)]
+pub fn run_block_generator_in<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    a: &mut Allocator,
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+) -> Result<SpendBundleConditions, ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let checkpoint = a.checkpoint();
+    let result = run_block_generator_impl(
+        a, program, block_refs, max_cost, flags, signature, bls_cache, constants, None,
+    );
+    if result.is_err() {
+        a.restore_checkpoint(&checkpoint);
+    }
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_block_generator_impl<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    a: &mut Allocator,
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+    deadline: Option<Instant>,
+) -> Result<SpendBundleConditions, ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    check_generator_quote(program, flags)?;
     let mut cost_left = max_cost;
     let byte_cost = program.len() as u64 * constants.cost_per_byte;
 
-    subtract_cost(&mut cost_left, byte_cost)?;
+    subtract_byte_cost(&mut cost_left, byte_cost)?;
 
-    let rom_generator = node_from_bytes(&mut a, &ROM_BOOTSTRAP_GENERATOR)?;
-    let program = node_from_bytes_backrefs(&mut a, program)?;
-    check_generator_node(&a, program, flags)?;
+    let rom_generator = node_from_bytes(a, &ROM_BOOTSTRAP_GENERATOR)?;
+    let program = node_from_bytes_backrefs(a, program)?;
+    check_generator_node(a, program, flags)?;
 
     // this is setting up the arguments to be passed to the generator ROM,
     // not the actual generator (the ROM does that).
@@ -121,16 +322,15 @@ where
     let args = a.new_pair(args, a.nil())?;
     let args = a.new_pair(program, args)?;
 
-    let dialect = ChiaDialect::new(flags.to_clvm_flags());
-    let Reduction(clvm_cost, generator_output) =
-        run_program(&mut a, &dialect, rom_generator, args, cost_left)?;
+    let dialect = DeadlineDialect::new(ChiaDialect::new(flags.to_clvm_flags()), deadline);
+    let Reduction(clvm_cost, generator_output) = dialect.run(a, rom_generator, args, cost_left)?;
 
     subtract_cost(&mut cost_left, clvm_cost)?;
 
     // we pass in what's left of max_cost here, to fail early in case the
     // cost of a condition brings us over the cost limit
     let mut result = parse_spends::<EmptyVisitor>(
-        &a,
+        a,
         generator_output,
         cost_left,
         0, // clvm_cost is not known per puzzle pre-hard fork
@@ -141,10 +341,12 @@ where
     )?;
     result.cost += max_cost - cost_left;
     result.execution_cost = clvm_cost;
-    Ok((a, result))
+    result.deserialization_cost = byte_cost;
+    result.simple_generator = flags.contains(ConsensusFlags::SIMPLE_GENERATOR);
+    Ok(result)
 }
 
-fn extract_n<const N: usize>(
+pub(crate) fn extract_n<const N: usize>(
     a: &Allocator,
     mut n: NodePtr,
     e: ErrorCode,
@@ -178,6 +380,106 @@ pub fn check_generator_quote(program: &[u8], flags: ConsensusFlags) -> Result<()
     }
 }
 
+/// Checks that deserializing `program` creates no more than `budget` nodes.
+/// Every atom, pair and back-reference counts as one node. The serialized
+/// form is scanned without allocating anything, and the scan stops as soon as
+/// the budget is exceeded, failing with DeserializationCostExceeded.
+/// This is independent of the CLVM cost of running the program.
+pub fn check_deserialization_cost(program: &[u8], budget: u64) -> Result<(), ValidationErr> {
+    let mut cost: u64 = 0;
+    let mut pos: usize = 0;
+    let mut nodes_left: u64 = 1;
+    while nodes_left > 0 {
+        nodes_left -= 1;
+        cost += 1;
+        if cost > budget {
+            return Err(ValidationErr::Err(ErrorCode::DeserializationCostExceeded));
+        }
+        let Some(b) = program.get(pos) else {
+            return Err(ValidationErr::Eval(EvalErr::SerializationError));
+        };
+        match *b {
+            0xff => {
+                pos += 1;
+                nodes_left += 2;
+            }
+            // a back-reference is followed by the path, as an atom
+            0xfe => {
+                pos += 1;
+                pos += serialized_length_from_bytes_trusted(&program[pos..])? as usize;
+            }
+            _ => {
+                pos += serialized_length_from_bytes_trusted(&program[pos..])? as usize;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like run_block_generator2(), but fails with DeserializationCostExceeded,
+/// before anything is deserialized or run, if the generator `program` consists
+/// of more than `deserialization_budget` nodes. See
+/// check_deserialization_cost(). The budget does not apply to `block_refs`.
+#[allow(clippy::too_many_arguments)]
+pub fn run_block_generator2_with_deserialization_budget<
+    GenBuf: AsRef<[u8]>,
+    I: IntoIterator<Item = GenBuf>,
+>(
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    deserialization_budget: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+) -> Result<(Allocator, SpendBundleConditions), ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    check_deserialization_cost(program, deserialization_budget)?;
+    run_block_generator2(
+        program, block_refs, max_cost, flags, signature, bls_cache, constants,
+    )
+}
+
+/// Identifies the table of CLVM operator costs implemented by the linked
+/// version of clvmr. This is bumped whenever a clvmr upgrade changes the cost
+/// of any operator (or any other part of the cost model), since that changes
+/// the result of validating blocks close to the cost limit. clvmr doesn't
+/// expose a version of its cost table, so the tests pin this version to the
+/// cost of a selection of operators. A clvmr upgrade that changes any of them
+/// fails the tests until this is bumped.
+pub const CLVM_COST_TABLE_VERSION: u32 = 1;
+
+/// Like run_block_generator2(), but pins the CLVM cost table to
+/// `cost_table_version`. Historical blocks can then be replayed knowing that
+/// costs are computed exactly the way they were when the result was first
+/// recorded. clvmr only implements its current cost table, so this fails with
+/// UnsupportedCostTable, before anything is run, unless `cost_table_version`
+/// is CLVM_COST_TABLE_VERSION.
+#[allow(clippy::too_many_arguments)]
+pub fn run_block_generator2_pinned<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+    cost_table_version: u32,
+) -> Result<(Allocator, SpendBundleConditions), ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    if cost_table_version != CLVM_COST_TABLE_VERSION {
+        return Err(ValidationErr::Err(ErrorCode::UnsupportedCostTable));
+    }
+    run_block_generator2(
+        program, block_refs, max_cost, flags, signature, bls_cache, constants,
+    )
+}
+
 // this function is mostly the same as above but is a double check in case of
 // discrepancies in serialized vs deserialized forms
 #[inline]
@@ -219,118 +521,726 @@ pub fn run_block_generator2<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>
 where
     <I as IntoIterator>::IntoIter: DoubleEndedIterator,
 {
-    check_generator_quote(program, flags)?;
-
-    let (mut a, base_cost, program) = if flags.contains(ConsensusFlags::INTERNED_GENERATOR) {
-        let mut decode_allocator = Allocator::new();
-        let program_node = node_from_bytes_backrefs(&mut decode_allocator, program)?;
-        let interned = intern_tree_limited(&decode_allocator, program_node, u32::MAX as usize)
-            .map_err(|_| ValidationErr::Err(ErrorCode::GeneratorRuntimeError))?;
-        let cost = interned_vbytes(&interned) * constants.cost_per_byte;
-        let InternedTree {
-            allocator, root, ..
-        } = interned;
-        drop(decode_allocator);
-        (allocator, cost, root)
-    } else {
-        let mut a = make_allocator(flags);
-        let byte_cost = program.len() as u64 * constants.cost_per_byte;
-        let program = node_from_bytes_backrefs(&mut a, program)?;
-        (a, byte_cost, program)
-    };
-
-    let mut cost_left = max_cost;
-    subtract_cost(&mut cost_left, base_cost)?;
-
-    check_generator_node(&a, program, flags)?;
-
-    let args = setup_generator_args(&mut a, block_refs, flags)?;
-    let dialect = ChiaDialect::new(flags.to_clvm_flags());
-
-    let Reduction(clvm_cost, all_spends) = run_program(&mut a, &dialect, program, args, cost_left)?;
-
-    subtract_cost(&mut cost_left, clvm_cost)?;
-
-    let mut ret = SpendBundleConditions::default();
-
-    let all_spends = first(&a, all_spends)?;
-    ret.execution_cost += clvm_cost;
-
-    // at this point all_spends is a list of:
-    // (parent-coin-id puzzle-reveal amount solution . extra)
-    // where extra may be nil, or additional extension data
+    run_block_generator2_with_deadline(
+        program, block_refs, max_cost, flags, signature, bls_cache, constants, None,
+    )
+}
 
-    let mut state = ParseState::default();
-    let mut cache = TreeCache::default();
+/// Like run_block_generator2(), but fails with Timeout if running the
+/// generator, or any of the puzzles, is still in progress at `deadline`. The
+/// clock is only sampled every DEADLINE_CHECK_INTERVAL operator calls, so the
+/// run may overshoot the deadline slightly. A `deadline` of None means no time
+/// limit.
+#[allow(clippy::too_many_arguments)]
+pub fn run_block_generator2_with_deadline<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+    deadline: Option<Instant>,
+) -> Result<(Allocator, SpendBundleConditions), ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let (mut a, base_cost, generator) = load_generator(program, flags, constants)?;
+    let mut spends = BlockGeneratorSpends::with_generator(
+        &mut a,
+        base_cost,
+        generator,
+        block_refs,
+        max_cost,
+        flags,
+        constants,
+        deadline,
+        ConditionCosts::default(),
+    )?;
+    while let Some(result) = spends.next_spend() {
+        result?;
+    }
+    let conds = spends.finish(signature, bls_cache)?;
+    Ok((a, conds))
+}
 
-    // first iterate over all puzzle reveals to find duplicate nodes, to know
-    // what to memoize during tree hash computations. This is managed by
-    // TreeCache
-    let mut iter = all_spends;
-    while let Some((spend, rest)) = a.next(iter) {
-        iter = rest;
-        let [_, puzzle, _] = extract_n::<3>(&a, spend, ErrorCode::InvalidCondition)?;
-        cache.visit_tree(&a, puzzle);
+/// Like run_block_generator2(), but charges the costs in `costs` for
+/// conditions, instead of the consensus cost schedule. This is meant for
+/// analyzing the effects of changing the costs, the resulting cost of the
+/// block is not the consensus cost (unless `costs` is the default).
+#[allow(clippy::too_many_arguments)]
+pub fn run_block_generator2_with_costs<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+    costs: ConditionCosts,
+) -> Result<(Allocator, SpendBundleConditions), ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let (mut a, base_cost, generator) = load_generator(program, flags, constants)?;
+    let mut spends = BlockGeneratorSpends::with_generator(
+        &mut a, base_cost, generator, block_refs, max_cost, flags, constants, None, costs,
+    )?;
+    while let Some(result) = spends.next_spend() {
+        result?;
     }
+    let conds = spends.finish(signature, bls_cache)?;
+    Ok((a, conds))
+}
+
+/// Like run_block_generator2(), but the puzzles of the spends are run in
+/// parallel, on the rayon thread pool. Each puzzle is run in an allocator of
+/// its own, and its conditions are then copied into the block's allocator and
+/// processed one spend at a time, in the order of the block. Any spend that
+/// fails, or exceeds the cost left once the spends before it are accounted
+/// for, is run again in the block's allocator. So the result, including which
+/// error is reported for an invalid block, is the same as
+/// run_block_generator2().
+///
+/// The block's allocator doesn't hold the intermediate values of the puzzles
+/// run in parallel, so its limits (the number of atoms and pairs, and the heap
+/// size with LIMIT_HEAP) could be exceeded by run_block_generator2() but not
+/// here. If the block's allocator, along with those values, exceeds its limits,
+/// the block is run again by run_block_generator2().
+///
+/// The puzzles share the cost left after running the generator. Once it's
+/// used up, or a puzzle fails, no more puzzles are started in parallel. So the
+/// work done on an invalid block is bounded by the block's cost limit (times
+/// the number of threads), not by the number of spends.
+#[cfg(feature = "rayon")]
+#[allow(clippy::too_many_arguments)]
+pub fn run_block_generator2_parallel<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+) -> Result<(Allocator, SpendBundleConditions), ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    run_block_generator2_parallel_with_deadline(
+        program, block_refs, max_cost, flags, signature, bls_cache, constants, None,
+    )
+}
 
-    let mut spends_left: usize = if flags.contains(ConsensusFlags::LIMIT_SPENDS) {
-        MAX_SPENDS_PER_BLOCK
+/// Like run_block_generator2_parallel(), but fails with Timeout if running
+/// the generator, or any of the puzzles, is still in progress at `deadline`.
+/// See run_block_generator2_with_deadline().
+#[cfg(feature = "rayon")]
+#[allow(clippy::too_many_arguments)]
+pub fn run_block_generator2_parallel_with_deadline<
+    GenBuf: AsRef<[u8]>,
+    I: IntoIterator<Item = GenBuf>,
+>(
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+    deadline: Option<Instant>,
+) -> Result<(Allocator, SpendBundleConditions), ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    // the block references are needed again if the block is run serially
+    let block_refs: Vec<GenBuf> = block_refs.into_iter().collect();
+    let (mut a, base_cost, generator) = load_generator(program, flags, constants)?;
+    // the generator is interned into an allocator without a heap limit
+    let heap_limit = if flags.contains(ConsensusFlags::INTERNED_GENERATOR) {
+        u32::MAX as usize
     } else {
-        usize::MAX
+        heap_limit(flags, HEAP_LIMIT)
     };
-
-    let mut iter = all_spends;
-    while let Some((spend, rest)) = a.next(iter) {
-        iter = rest;
-        if spends_left == 0 {
-            return Err(ValidationErr::Err(ErrorCode::TooManySpends));
-        }
-        spends_left -= 1;
-        // process the spend
-        let [parent_id, puzzle, amount, solution, _spend_level_extra] =
-            extract_n::<5>(&a, spend, ErrorCode::InvalidCondition)?;
-
-        let Reduction(clvm_cost, conditions) =
-            run_program(&mut a, &dialect, puzzle, solution, cost_left)?;
-
-        subtract_cost(&mut cost_left, clvm_cost)?;
-        ret.execution_cost += clvm_cost;
-
-        let buf = tree_hash_cached(&a, puzzle, &mut cache);
-        let puzzle_hash = a.new_atom(&buf)?;
-
-        process_single_spend::<EmptyVisitor>(
-            &a,
-            &mut ret,
-            &mut state,
-            parent_id,
-            puzzle_hash,
-            amount,
-            conditions,
+    match run_generator_parallel(
+        &mut a,
+        heap_limit,
+        base_cost,
+        generator,
+        &block_refs,
+        max_cost,
+        flags,
+        signature,
+        bls_cache,
+        constants,
+        deadline,
+    ) {
+        Some(result) => result.map(|conds| (a, conds)),
+        None => run_block_generator2_with_deadline(
+            program,
+            &block_refs,
+            max_cost,
             flags,
-            &mut cost_left,
-            clvm_cost,
+            signature,
+            bls_cache,
             constants,
-        )?;
+            deadline,
+        ),
     }
-    if a.atom_len(iter) != 0 {
-        return Err(ValidationErr::Err(ErrorCode::GeneratorRuntimeError));
-    }
-
-    validate_conditions(&a, &ret, &state, flags)?;
-    validate_signature(&state, signature, flags, bls_cache)?;
-    ret.validated_signature = !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE);
-
-    ret.cost = max_cost - cost_left;
-    Ok((a, ret))
 }
 
-// this function is less capable of handling problematic generators as they are
-// returning serialized puzzles, which may not be possible. They will simply ignore many of the bad cases.
-pub fn get_coinspends_for_trusted_block<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
-    constants: &ConsensusConstants,
-    generator: &Program,
-    refs: I,
+// runs the generator, already deserialized into `a`, with the puzzles run in
+// parallel. `heap_limit` is the heap-size limit of `a`. Returns None if the
+// result may differ from running the puzzles serially, because the block
+// would have exceeded the limits of `a`
+#[cfg(feature = "rayon")]
+#[allow(clippy::too_many_arguments)]
+fn run_generator_parallel<GenBuf: AsRef<[u8]>>(
+    a: &mut Allocator,
+    heap_limit: usize,
+    base_cost: Cost,
+    generator: NodePtr,
+    block_refs: &[GenBuf],
+    max_cost: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+    deadline: Option<Instant>,
+) -> Option<Result<SpendBundleConditions, ValidationErr>> {
+    let mut intermediate = AllocatorUsage::default();
+    let result = BlockGeneratorSpends::with_generator(
+        a,
+        base_cost,
+        generator,
+        block_refs,
+        max_cost,
+        flags,
+        constants,
+        deadline,
+        ConditionCosts::default(),
+    )
+    .and_then(|mut spends| {
+        spends.run_spends_parallel(heap_limit, &mut intermediate)?;
+        spends.finish(signature, bls_cache)
+    });
+
+    // An allocator never frees anything, so the usage with the intermediate
+    // values added is at least the peak usage of running the block serially
+    let used = allocator_usage(a);
+    if used.atoms + intermediate.atoms > MAX_NUM_ATOMS
+        || used.pairs + intermediate.pairs > MAX_NUM_PAIRS
+        || used.heap_bytes + intermediate.heap_bytes > heap_limit
+    {
+        return None;
+    }
+    // an allocation that would exceed the limits fails without being counted,
+    // so running out isn't necessarily reflected in the usage
+    if matches!(
+        result,
+        Err(ValidationErr::Eval(
+            EvalErr::OutOfMemory | EvalErr::TooManyAtoms | EvalErr::TooManyPairs
+        ))
+    ) {
+        return None;
+    }
+    Some(result)
+}
+
+/// Like run_block_generator2(), but uses the caller's allocator, `a`, instead
+/// of creating one. This saves setting up a new allocator for every block, when
+/// validating many blocks. The allocator doesn't need to be empty, the nodes
+/// already in it are left untouched. The NodePtr fields of the returned
+/// conditions point into `a`. If the block is invalid, `a` is restored to the
+/// state it was in before the call. The caller is expected to restore a
+/// checkpoint of its own between blocks, to not exhaust the allocator's heap
+/// limit. With INTERNED_GENERATOR, the cost of the generator is computed from
+/// its interned form, like run_block_generator2(), but the generator is then
+/// copied into `a` (with identical sub trees shared), rather than run in the
+/// allocator it was interned into. So the allocator usage may differ from
+/// run_block_generator2(), but the conditions do not.
+#[allow(clippy::too_many_arguments)]
+pub fn run_block_generator2_in<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    a: &mut Allocator,
+    program: &[u8],
+    block_refs: I,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    signature: &Signature,
+    bls_cache: Option<&BlsCache>,
+    constants: &ConsensusConstants,
+) -> Result<SpendBundleConditions, ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    let checkpoint = a.checkpoint();
+    let result = BlockGeneratorSpends::new(a, program, block_refs, max_cost, flags, constants)
+        .and_then(|spends| spends.finish(signature, bls_cache));
+    if result.is_err() {
+        a.restore_checkpoint(&checkpoint);
+    }
+    result
+}
+
+// interns the generator and returns the cost of it, based on the interned
+// form
+fn intern_generator(
+    program: &[u8],
+    constants: &ConsensusConstants,
+) -> Result<(Cost, InternedTree), ValidationErr> {
+    let mut decode_allocator = Allocator::new();
+    let program_node = node_from_bytes_backrefs(&mut decode_allocator, program)?;
+    let interned = intern_tree_limited(&decode_allocator, program_node, u32::MAX as usize)
+        .map_err(|_| ValidationErr::Err(ErrorCode::GeneratorRuntimeError))?;
+    let cost = interned_vbytes(&interned) * constants.cost_per_byte;
+    Ok((cost, interned))
+}
+
+// deserializes the generator into an allocator of its own, created based on
+// the flags, or the allocator it was interned into, with INTERNED_GENERATOR.
+// Returns the allocator, the cost of the generator's bytes and the generator
+fn load_generator(
+    program: &[u8],
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<(Allocator, Cost, NodePtr), ValidationErr> {
+    check_generator_quote(program, flags)?;
+    if flags.contains(ConsensusFlags::INTERNED_GENERATOR) {
+        let (
+            cost,
+            InternedTree {
+                allocator, root, ..
+            },
+        ) = intern_generator(program, constants)?;
+        Ok((allocator, cost, root))
+    } else {
+        let mut a = make_allocator(flags);
+        let byte_cost = program.len() as u64 * constants.cost_per_byte;
+        let program = node_from_bytes_backrefs(&mut a, program)?;
+        Ok((a, byte_cost, program))
+    }
+}
+
+// like load_generator(), but deserializes the generator into `a`. With
+// INTERNED_GENERATOR, the cost is still based on the interned form, but the
+// interned generator is copied into `a`
+fn load_generator_in(
+    a: &mut Allocator,
+    program: &[u8],
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<(Cost, NodePtr), ValidationErr> {
+    check_generator_quote(program, flags)?;
+    if flags.contains(ConsensusFlags::INTERNED_GENERATOR) {
+        let (cost, interned) = intern_generator(program, constants)?;
+        let buf = node_to_bytes_backrefs(&interned.allocator, interned.root)?;
+        Ok((cost, node_from_bytes_backrefs(a, &buf)?))
+    } else {
+        let byte_cost = program.len() as u64 * constants.cost_per_byte;
+        Ok((byte_cost, node_from_bytes_backrefs(a, program)?))
+    }
+}
+
+/// Runs a block generator like run_block_generator2(), but runs the puzzles of
+/// the spends one at a time, as the iterator is advanced. Each item is the
+/// conditions of the next spend, or the error that makes the block invalid.
+/// Once an error is returned, the iterator ends. The cost limit applies to the
+/// block as a whole, including the spends that have been returned so far.
+///
+/// The spends are only checked individually. The checks that depend on all
+/// spends in the block (e.g. announcements, messages and the signature) are
+/// made by finish(), which also runs any remaining spends. The NodePtr fields
+/// of the returned spends point into allocator().
+///
+/// This does not use less memory than run_block_generator2(). The checks made
+/// by finish() need the conditions of all spends, so every spend is retained,
+/// and each item is a copy of it. What this allows is rejecting a block early,
+/// or acting on the spends before the whole block has been run.
+pub struct BlockGeneratorSpends<'a, O: CostOracle = ConditionCosts> {
+    a: &'a mut Allocator,
+    dialect: DeadlineDialect<ChiaDialect>,
+    ret: SpendBundleConditions,
+    state: ParseState,
+    cache: TreeCache,
+    // the spends that haven't been run yet
+    spends: NodePtr,
+    spends_left: usize,
+    max_cost: u64,
+    cost_left: u64,
+    flags: ConsensusFlags,
+    constants: &'a ConsensusConstants,
+    // the error that made the block invalid, if any. finish() returns it
+    failed: Option<ValidationErr>,
+    costs: O,
+}
+
+impl<'a> BlockGeneratorSpends<'a> {
+    /// Deserializes the generator into `a`, and runs it to produce the list
+    /// of spends. This fails if the generator itself is invalid. None of the
+    /// puzzles are run yet. The spends, and the conditions of the puzzles,
+    /// are allocated in `a` too. See run_block_generator2_in() for how
+    /// INTERNED_GENERATOR affects `a`.
+    pub fn new<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+        a: &'a mut Allocator,
+        program: &[u8],
+        block_refs: I,
+        max_cost: u64,
+        flags: ConsensusFlags,
+        constants: &'a ConsensusConstants,
+    ) -> Result<Self, ValidationErr>
+    where
+        <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+    {
+        let (base_cost, program) = load_generator_in(a, program, flags, constants)?;
+        Self::with_generator(
+            a,
+            base_cost,
+            program,
+            block_refs,
+            max_cost,
+            flags,
+            constants,
+            None,
+            ConditionCosts::default(),
+        )
+    }
+}
+
+impl<'a, O: CostOracle> BlockGeneratorSpends<'a, O> {
+    // like new(), but with the generator, `program`, already in `a`.
+    // `base_cost` is the cost of the generator's bytes. The costs of the
+    // spends and their conditions are provided by `costs`
+    #[allow(clippy::too_many_arguments)]
+    fn with_generator<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+        a: &'a mut Allocator,
+        base_cost: Cost,
+        program: NodePtr,
+        block_refs: I,
+        max_cost: u64,
+        flags: ConsensusFlags,
+        constants: &'a ConsensusConstants,
+        deadline: Option<Instant>,
+        costs: O,
+    ) -> Result<Self, ValidationErr>
+    where
+        <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+    {
+        let mut cost_left = max_cost;
+        subtract_byte_cost(&mut cost_left, base_cost)?;
+
+        check_generator_node(a, program, flags)?;
+
+        let args = setup_generator_args(a, block_refs, flags)?;
+        let dialect = DeadlineDialect::new(ChiaDialect::new(flags.to_clvm_flags()), deadline);
+
+        let Reduction(clvm_cost, all_spends) = dialect.run(a, program, args, cost_left)?;
+
+        subtract_cost(&mut cost_left, clvm_cost)?;
+
+        let mut ret = SpendBundleConditions::default();
+
+        let all_spends = first(a, all_spends)?;
+        ret.deserialization_cost = base_cost;
+        ret.simple_generator = flags.contains(ConsensusFlags::SIMPLE_GENERATOR);
+        ret.execution_cost += clvm_cost;
+
+        // at this point all_spends is a list of:
+        // (parent-coin-id puzzle-reveal amount solution . extra)
+        // where extra may be nil, or additional extension data
+
+        let mut cache = TreeCache::default();
+
+        // first iterate over all puzzle reveals to find duplicate nodes, to know
+        // what to memoize during tree hash computations. This is managed by
+        // TreeCache
+        let mut iter = all_spends;
+        while let Some((spend, rest)) = a.next(iter) {
+            iter = rest;
+            let [_, puzzle, _] = extract_n::<3>(a, spend, ErrorCode::InvalidCondition)?;
+            cache.visit_tree(a, puzzle);
+        }
+
+        let spends_left: usize = if flags.contains(ConsensusFlags::LIMIT_SPENDS) {
+            MAX_SPENDS_PER_BLOCK
+        } else {
+            usize::MAX
+        };
+
+        Ok(Self {
+            a,
+            dialect,
+            ret,
+            state: ParseState::default(),
+            cache,
+            spends: all_spends,
+            spends_left,
+            max_cost,
+            cost_left,
+            flags,
+            constants,
+            failed: None,
+            costs,
+        })
+    }
+
+    /// The allocator the NodePtr fields of the spends point into.
+    pub fn allocator(&self) -> &Allocator {
+        self.a
+    }
+
+    // runs the next spend and appends its conditions to self.ret.spends.
+    // Returns None when there are no more spends, or once an error has been
+    // returned
+    fn next_spend(&mut self) -> Option<Result<(), ValidationErr>> {
+        if self.failed.is_some() {
+            return None;
+        }
+        let (spend, rest) = self.a.next(self.spends)?;
+        self.spends = rest;
+        let result = self.run_spend(spend);
+        if let Err(e) = &result {
+            self.failed = Some(e.clone());
+        }
+        Some(result)
+    }
+
+    fn run_spend(&mut self, spend: NodePtr) -> Result<(), ValidationErr> {
+        let a = &mut *self.a;
+        if self.spends_left == 0 {
+            return Err(ValidationErr::Err(ErrorCode::TooManySpends));
+        }
+        self.spends_left -= 1;
+        // process the spend
+        let [parent_id, puzzle, amount, solution, _spend_level_extra] =
+            extract_n::<5>(a, spend, ErrorCode::InvalidCondition)?;
+
+        let Reduction(clvm_cost, conditions) =
+            self.dialect.run(a, puzzle, solution, self.cost_left)?;
+
+        subtract_cost(&mut self.cost_left, clvm_cost)?;
+        self.ret.execution_cost += clvm_cost;
+
+        let buf = tree_hash_cached(a, puzzle, &mut self.cache);
+        let puzzle_hash = a.new_atom(&buf)?;
+
+        process_single_spend_with_costs::<EmptyVisitor, _>(
+            a,
+            &mut self.ret,
+            &mut self.state,
+            parent_id,
+            puzzle_hash,
+            amount,
+            conditions,
+            self.flags,
+            &mut self.cost_left,
+            clvm_cost,
+            self.constants,
+            &self.costs,
+        )?;
+        Ok(())
+    }
+
+    // runs the puzzles of all remaining spends in parallel, and then processes
+    // their conditions in order. The spends are consumed just like calling
+    // next_spend() until it returns None. Each puzzle's allocator has a heap
+    // limit of `heap_limit`. The allocator usage of running the puzzles whose
+    // conditions were copied into the block's allocator (excluding the puzzle
+    // and solution) is added to `intermediate`
+    #[cfg(feature = "rayon")]
+    fn run_spends_parallel(
+        &mut self,
+        heap_limit: usize,
+        intermediate: &mut AllocatorUsage,
+    ) -> Result<(), ValidationErr> {
+        let mut spends = Vec::new();
+        let mut iter = self.spends;
+        while let Some((spend, rest)) = self.a.next(iter) {
+            iter = rest;
+            spends.push(spend);
+        }
+
+        // A spend that doesn't fit in the cost left once the spends before it
+        // are accounted for is run again, the same way the serial path runs
+        // it. So are spends that fail, the ones that would fail before being
+        // run (e.g. exceeding the number of spends) and the ones that weren't
+        // run in parallel at all
+        let (results, _) = self.run_puzzles_parallel(heap_limit, &spends);
+
+        for (spend, result) in spends.into_iter().zip(results) {
+            self.spends = self.a.next(self.spends).expect("spend").1;
+            let result = match result {
+                Some((clvm_cost, puzzle_hash, conditions, usage))
+                    if clvm_cost <= self.cost_left =>
+                {
+                    intermediate.atoms += usage.atoms;
+                    intermediate.pairs += usage.pairs;
+                    intermediate.heap_bytes += usage.heap_bytes;
+                    self.process_spend(spend, clvm_cost, &puzzle_hash, &conditions)
+                }
+                _ => self.run_spend(spend),
+            };
+            if let Err(e) = result {
+                self.failed = Some(e.clone());
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    // runs the puzzles of `spends` on the rayon thread pool, each in an
+    // allocator of its own. Returns the cost, puzzle hash, serialized
+    // conditions and allocator usage of each puzzle, or None if it wasn't run,
+    // or failed. The puzzles share a budget of the cost left. A puzzle is run
+    // with what's left of it when it starts, and once it's used up, or a
+    // puzzle fails, no more puzzles are started. Also returns the work done,
+    // as the cost of the puzzles that succeeded plus the cost limit of the
+    // ones that failed
+    #[cfg(feature = "rayon")]
+    #[allow(clippy::type_complexity)]
+    fn run_puzzles_parallel(
+        &self,
+        heap_limit: usize,
+        spends: &[NodePtr],
+    ) -> (Vec<Option<(Cost, [u8; 32], Vec<u8>, AllocatorUsage)>>, Cost) {
+        let a = &*self.a;
+        let (flags, spends_left, deadline) = (self.flags, self.spends_left, self.dialect.deadline);
+        let budget = AtomicU64::new(self.cost_left);
+        let work = AtomicU64::new(0);
+        let results = spends
+            .par_iter()
+            .enumerate()
+            .map(|(idx, spend)| {
+                if idx >= spends_left {
+                    return None;
+                }
+                let max_cost = budget.load(Ordering::Relaxed);
+                if max_cost == 0 {
+                    return None;
+                }
+                let [_, puzzle, _, solution, _] =
+                    extract_n::<5>(a, *spend, ErrorCode::InvalidCondition).ok()?;
+                let puzzle = node_to_bytes_backrefs(a, puzzle).ok()?;
+                let solution = node_to_bytes_backrefs(a, solution).ok()?;
+
+                let mut spend_a = Allocator::new_limited(heap_limit);
+                let puzzle = node_from_bytes_backrefs(&mut spend_a, &puzzle).ok()?;
+                let solution = node_from_bytes_backrefs(&mut spend_a, &solution).ok()?;
+                let before = allocator_usage(&spend_a);
+                let dialect =
+                    DeadlineDialect::new(ChiaDialect::new(flags.to_clvm_flags()), deadline);
+                let Ok(Reduction(clvm_cost, conditions)) =
+                    dialect.run(&mut spend_a, puzzle, solution, max_cost)
+                else {
+                    // the spends after this one won't be processed if it
+                    // fails serially too, so there's no point in running them
+                    budget.store(0, Ordering::Relaxed);
+                    work.fetch_add(max_cost, Ordering::Relaxed);
+                    return None;
+                };
+                budget
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                        Some(left.saturating_sub(clvm_cost))
+                    })
+                    .expect("fetch_update");
+                work.fetch_add(clvm_cost, Ordering::Relaxed);
+                let after = allocator_usage(&spend_a);
+                let usage = AllocatorUsage {
+                    atoms: after.atoms - before.atoms,
+                    pairs: after.pairs - before.pairs,
+                    heap_bytes: after.heap_bytes - before.heap_bytes,
+                };
+                let puzzle_hash = tree_hash(&spend_a, puzzle).to_bytes();
+                let conditions = node_to_bytes_backrefs(&spend_a, conditions).ok()?;
+                Some((clvm_cost, puzzle_hash, conditions, usage))
+            })
+            .collect();
+        (results, work.into_inner())
+    }
+
+    // like run_spend(), but with the puzzle already run, in another allocator.
+    // `conditions` is the serialized output of the puzzle
+    #[cfg(feature = "rayon")]
+    fn process_spend(
+        &mut self,
+        spend: NodePtr,
+        clvm_cost: Cost,
+        puzzle_hash: &[u8; 32],
+        conditions: &[u8],
+    ) -> Result<(), ValidationErr> {
+        let a = &mut *self.a;
+        self.spends_left -= 1;
+        let [parent_id, _, amount, _, _] = extract_n::<5>(a, spend, ErrorCode::InvalidCondition)?;
+
+        subtract_cost(&mut self.cost_left, clvm_cost)?;
+        self.ret.execution_cost += clvm_cost;
+
+        let puzzle_hash = a.new_atom(puzzle_hash)?;
+        let conditions = node_from_bytes_backrefs(a, conditions)?;
+
+        process_single_spend_with_costs::<EmptyVisitor, _>(
+            a,
+            &mut self.ret,
+            &mut self.state,
+            parent_id,
+            puzzle_hash,
+            amount,
+            conditions,
+            self.flags,
+            &mut self.cost_left,
+            clvm_cost,
+            self.constants,
+            &self.costs,
+        )?;
+        Ok(())
+    }
+
+    /// Runs the remaining spends, if any, and performs the checks that depend
+    /// on all spends in the block, including validating the signature (unless
+    /// DONT_VALIDATE_SIGNATURE is set). Returns the same result as
+    /// run_block_generator2(). If the iterator returned an error, this fails
+    /// with the same error.
+    pub fn finish(
+        mut self,
+        signature: &Signature,
+        bls_cache: Option<&BlsCache>,
+    ) -> Result<SpendBundleConditions, ValidationErr> {
+        while let Some(result) = self.next_spend() {
+            result?;
+        }
+        if let Some(e) = self.failed.take() {
+            return Err(e);
+        }
+        if self.a.atom_len(self.spends) != 0 {
+            return Err(ValidationErr::Err(ErrorCode::GeneratorRuntimeError));
+        }
+        let flags = self.flags;
+        let mut ret = std::mem::take(&mut self.ret);
+
+        validate_conditions(self.a, &ret, &self.state, flags)?;
+        ret.pairings = validate_signature(&self.state, signature, flags, bls_cache)?;
+        ret.validated_signature = !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE);
+
+        ret.cost = self.max_cost - self.cost_left;
+        Ok(ret)
+    }
+}
+
+impl<O: CostOracle> Iterator for BlockGeneratorSpends<'_, O> {
+    type Item = Result<SpendConditions, ValidationErr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(
+            self.next_spend()?
+                .map(|()| self.ret.spends.last().expect("spend").clone()),
+        )
+    }
+}
+
+// this function is less capable of handling problematic generators as they are
+// returning serialized puzzles, which may not be possible. They will simply ignore many of the bad cases.
+pub fn get_coinspends_for_trusted_block<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    constants: &ConsensusConstants,
+    generator: &Program,
+    refs: I,
     flags: ConsensusFlags,
 ) -> Result<Vec<CoinSpend>, ValidationErr>
 where
@@ -544,15 +1454,22 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::conditions::MAX_SPENDS_PER_BLOCK;
+    use crate::allocator::allocator_usage;
+    use crate::conditions::{MAX_SPENDS_PER_BLOCK, spend_list};
     use crate::consensus_constants::TEST_CONSTANTS;
-    use crate::opcodes::{CREATE_COIN, CREATE_COIN_COST, NEW_CREATE_COIN_COST, SPEND_COST};
+    use crate::make_aggsig_final_message::u64_to_bytes;
+    use crate::opcodes::{
+        CREATE_COIN, CREATE_COIN_COST, NEW_CREATE_COIN_COST, SOFTFORK, SPEND_COST,
+    };
+    use crate::owned_conditions::{OwnedSpendBundleConditions, OwnedSpendConditions};
     use crate::solution_generator::solution_generator;
-    use chia_protocol::Bytes32;
+    use chia_bls::{SecretKey, sign};
+    use chia_protocol::{Bytes, Bytes32};
     use clvm_traits::ToClvm;
     use clvm_utils::tree_hash_atom;
     use clvmr::serde::node_to_bytes;
     use rstest::rstest;
+    use std::time::Duration;
 
     const IDENTITY_PUZZLE: &[u8] = &[1];
 
@@ -635,19 +1552,78 @@ mod tests {
     }
 
     #[rstest]
-    #[case(1, 1)]
-    #[case(3, 1)]
-    #[case(1, 3)]
-    #[case(5, 5)]
-    fn test_cost_conditions_with_create_coin(
-        #[case] num_spends: usize,
-        #[case] coins_per_spend: usize,
+    fn test_validated_signature(
+        #[values(ConsensusFlags::empty(), ConsensusFlags::DONT_VALIDATE_SIGNATURE)]
+        flags: ConsensusFlags,
+        #[values(false, true)] generator2: bool,
     ) {
-        let program = make_generator_with_create_coins(num_spends, coins_per_spend);
+        let program = make_generator(3);
         let blocks: &[&[u8]] = &[];
-        let num_coins = (num_spends * coins_per_spend) as u64;
+        let run = if generator2 {
+            run_block_generator2::<&&[u8], _>
+        } else {
+            run_block_generator::<&&[u8], _>
+        };
+        let (_, conds) = run(
+            &program,
+            blocks,
+            u64::MAX,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator");
+        assert_eq!(
+            conds.validated_signature,
+            !flags.contains(ConsensusFlags::DONT_VALIDATE_SIGNATURE)
+        );
+    }
 
-        let (_, without) = run_block_generator2(
+    #[rstest]
+    fn test_allocator_usage(#[values(false, true)] generator2: bool) {
+        let blocks: &[&[u8]] = &[];
+        let run = if generator2 {
+            run_block_generator2::<&&[u8], _>
+        } else {
+            run_block_generator::<&&[u8], _>
+        };
+        let mut last_usage = None;
+        for num_spends in [1, 10, 100] {
+            let program = make_generator_with_create_coins(num_spends, 3);
+            let (a, _) = run(
+                &program,
+                blocks,
+                u64::MAX,
+                ConsensusFlags::LIMIT_HEAP | ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+                &Signature::default(),
+                None,
+                &TEST_CONSTANTS,
+            )
+            .expect("run_block_generator");
+            let usage = allocator_usage(&a);
+            assert!(usage.heap_bytes >= program.len());
+            if let Some(last) = last_usage.replace(usage) {
+                assert!(usage.atoms > last.atoms);
+                assert!(usage.pairs > last.pairs);
+                assert!(usage.heap_bytes > last.heap_bytes);
+            }
+        }
+    }
+
+    #[rstest]
+    fn test_deserialization_cost(
+        #[values(1, 3, 10)] num_spends: usize,
+        #[values(false, true)] generator2: bool,
+    ) {
+        let program = make_generator_with_create_coins(num_spends, 2);
+        let blocks: &[&[u8]] = &[];
+        let run = if generator2 {
+            run_block_generator2::<&&[u8], _>
+        } else {
+            run_block_generator::<&&[u8], _>
+        };
+        let (_, conds) = run(
             &program,
             blocks,
             u64::MAX,
@@ -656,28 +1632,1470 @@ mod tests {
             None,
             &TEST_CONSTANTS,
         )
-        .expect("without COST_CONDITIONS");
+        .expect("run_block_generator");
+        assert_eq!(
+            conds.deserialization_cost,
+            program.len() as u64 * TEST_CONSTANTS.cost_per_byte
+        );
+        assert_eq!(
+            conds.deserialization_cost + conds.execution_cost + conds.condition_cost,
+            conds.cost
+        );
+    }
 
-        let (_, with) = run_block_generator2(
+    #[rstest]
+    fn test_simple_generator(
+        #[values(ConsensusFlags::empty(), ConsensusFlags::SIMPLE_GENERATOR)] flags: ConsensusFlags,
+        #[values(false, true)] generator2: bool,
+    ) {
+        let program = make_generator(3);
+        let blocks: &[&[u8]] = &[];
+        let run = if generator2 {
+            run_block_generator2::<&&[u8], _>
+        } else {
+            run_block_generator::<&&[u8], _>
+        };
+        let (a, conds) = run(
             &program,
             blocks,
             u64::MAX,
-            ConsensusFlags::DONT_VALIDATE_SIGNATURE | ConsensusFlags::COST_CONDITIONS,
+            flags | ConsensusFlags::DONT_VALIDATE_SIGNATURE,
             &Signature::default(),
             None,
             &TEST_CONSTANTS,
         )
-        .expect("with COST_CONDITIONS");
+        .expect("run_block_generator");
+        assert_eq!(conds.simple_generator, !flags.is_empty());
+        assert_eq!(
+            OwnedSpendBundleConditions::from(&a, conds).simple_generator,
+            !flags.is_empty()
+        );
+    }
 
-        assert_eq!(without.spends.len(), num_spends);
-        assert_eq!(with.spends.len(), num_spends);
+    #[test]
+    fn test_spend_costs() {
+        let program = make_generator_with_create_coins(2, 3);
+        let blocks: &[&[u8]] = &[];
+        let (_, conds) = run_block_generator2(
+            &program,
+            blocks,
+            u64::MAX,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator2");
 
-        assert_eq!(without.condition_cost, CREATE_COIN_COST * num_coins);
+        assert_eq!(conds.spends.len(), 2);
+        for spend in &conds.spends {
+            assert!(spend.execution_cost > 0);
+            assert!(spend.condition_cost > 0);
+        }
+        // the aggregate execution cost also includes running the generator
+        // itself, the condition cost is only made up of the spends
+        let execution_cost: u64 = conds.spends.iter().map(|s| s.execution_cost).sum();
+        let condition_cost: u64 = conds.spends.iter().map(|s| s.condition_cost).sum();
+        assert!(execution_cost < conds.execution_cost);
+        assert_eq!(condition_cost, conds.condition_cost);
+    }
+
+    #[rstest]
+    fn test_softfork_cost(
+        #[values(1, 3)] num_spends: usize,
+        #[values(0, 1, 1000)] reserved: u32,
+        #[values(false, true)] generator2: bool,
+    ) {
+        let puzzle_hash = Bytes32::from(tree_hash_atom(&[1]).to_bytes());
+        let mut a = Allocator::new();
+        let cond = (SOFTFORK, (reserved, 0)).to_clvm(&mut a).unwrap();
+        let nil = a.nil();
+        let conds = a.new_pair(cond, nil).unwrap();
+        let solution_bytes = node_to_bytes(&a, conds).unwrap();
+        let spends = (0..num_spends).map(|i| {
+            let mut parent = [0u8; 32];
+            parent[0..4].copy_from_slice(&(i as u32).to_be_bytes());
+            (
+                Coin::new(parent.into(), puzzle_hash, 0),
+                IDENTITY_PUZZLE,
+                solution_bytes.as_slice(),
+            )
+        });
+        let program = solution_generator(spends).expect("solution_generator");
+
+        let blocks: &[&[u8]] = &[];
+        let run = if generator2 {
+            run_block_generator2::<&&[u8], _>
+        } else {
+            run_block_generator::<&&[u8], _>
+        };
+        let flags = ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+        let (_, conds) = run(
+            &program,
+            blocks,
+            u64::MAX,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator");
+
+        // the SOFTFORK argument is scaled by 10000
+        let softfork_cost = u64::from(reserved) * 10000 * num_spends as u64;
+        assert_eq!(conds.softfork_cost, softfork_cost);
+        assert_eq!(conds.condition_cost, softfork_cost);
         assert_eq!(
-            with.condition_cost,
-            SPEND_COST * num_spends as u64 + NEW_CREATE_COIN_COST * num_coins
+            conds.deserialization_cost + conds.execution_cost + conds.condition_cost,
+            conds.cost
         );
 
-        assert_eq!(without.execution_cost, with.execution_cost);
+        // the reserved cost counts towards the cost limit of the block
+        let (_, exact) = run(
+            &program,
+            blocks,
+            conds.cost,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator");
+        assert_eq!(exact.cost, conds.cost);
+        let err = run(
+            &program,
+            blocks,
+            conds.cost - 1,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .unwrap_err();
+        // the reserved cost is charged last, as the cost of the condition
+        let expected = if reserved == 0 {
+            ErrorCode::ExecutionCostExceeded
+        } else {
+            ErrorCode::ConditionCostExceeded
+        };
+        assert_eq!(err.error_code(), expected);
+    }
+
+    #[rstest]
+    fn test_run_in_reused_allocator(
+        #[values(false, true)] generator2: bool,
+        #[values(ConsensusFlags::empty(), ConsensusFlags::INTERNED_GENERATOR)]
+        flags: ConsensusFlags,
+    ) {
+        use std::fs::read_to_string;
+
+        let load = |name: &str| -> Vec<u8> {
+            let test_file = read_to_string(format!("../../generator-tests/{name}.txt"))
+                .expect("test file not found");
+            let (generator, _) = test_file.split_once('\n').expect("invalid test file");
+            hex::decode(generator).expect("invalid hex encoded generator")
+        };
+        // the allocator usage is expected to differ, and the order of
+        // CREATE_COIN conditions isn't deterministic
+        let owned = |a: &Allocator, conds: SpendBundleConditions| {
+            let mut conds = OwnedSpendBundleConditions::from(a, conds);
+            for spend in &mut conds.spends {
+                spend.create_coin.sort();
+            }
+            conds.num_atoms = 0;
+            conds.num_pairs = 0;
+            conds.heap_size = 0;
+            conds
+        };
+        let blocks: &[&[u8]] = &[];
+        let flags = flags | ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+        let run = if generator2 {
+            run_block_generator2::<&&[u8], _>
+        } else {
+            run_block_generator::<&&[u8], _>
+        };
+        let run_in = if generator2 {
+            run_block_generator2_in::<&&[u8], _>
+        } else {
+            run_block_generator_in::<&&[u8], _>
+        };
+
+        let mut a = make_allocator(flags);
+        // the allocator doesn't have to be empty
+        let existing = a.new_atom(b"foobar").expect("new_atom");
+        let start = a.checkpoint();
+
+        for _ in 0..3 {
+            for name in ["block-834768", "block-834760"] {
+                let generator = load(name);
+                let (fresh_a, fresh) = run(
+                    &generator,
+                    blocks,
+                    11_000_000_000,
+                    flags,
+                    &Signature::default(),
+                    None,
+                    &TEST_CONSTANTS,
+                )
+                .expect("fresh allocator");
+                let reused = run_in(
+                    &mut a,
+                    &generator,
+                    blocks,
+                    11_000_000_000,
+                    flags,
+                    &Signature::default(),
+                    None,
+                    &TEST_CONSTANTS,
+                )
+                .expect("reused allocator");
+                assert_eq!(owned(&a, reused), owned(&fresh_a, fresh));
+                a.restore_checkpoint(&start);
+                assert_eq!(a.atom(existing).as_ref(), b"foobar");
+            }
+        }
+
+        // an invalid block leaves the allocator the way it was
+        let usage = allocator_usage(&a);
+        let err = run_in(
+            &mut a,
+            &load("double-spend"),
+            blocks,
+            11_000_000_000,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::DoubleSpend);
+        assert_eq!(allocator_usage(&a), usage);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[rstest]
+    fn test_parallel_heap_limit(#[values(30, 60, 1000)] heap_limit_kb: usize) {
+        use crate::allocator::make_allocator_with_limit;
+
+        // each puzzle concatenates its solution 4 times. Every puzzle fits in
+        // the heap limit on its own, but not all of them together
+        // (f (c () (concat 1 1 1 1)))
+        let puzzle = hex::decode("ff05ffff04ff80ffff0eff01ff01ff01ff0180808080").expect("hex");
+        let puzzle_hash =
+            Bytes32::from(clvm_utils::tree_hash_from_bytes(&puzzle).expect("tree_hash"));
+        let mut a = Allocator::new();
+        let solution = a.new_atom(&[0x55; 2000]).expect("new_atom");
+        let solution = node_to_bytes(&a, solution).expect("node_to_bytes");
+        let generator = solution_generator((0..10_u32).map(|i| {
+            let mut parent = [0u8; 32];
+            parent[0..4].copy_from_slice(&i.to_be_bytes());
+            (
+                Coin::new(parent.into(), puzzle_hash, 0),
+                puzzle.as_slice(),
+                solution.as_slice(),
+            )
+        }))
+        .expect("solution_generator");
+
+        let blocks: &[&[u8]] = &[];
+        let flags = ConsensusFlags::LIMIT_HEAP | ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+        let limit = heap_limit_kb * 1000;
+
+        let mut a = make_allocator_with_limit(flags, limit);
+        let serial = run_block_generator2_in(
+            &mut a,
+            &generator,
+            blocks,
+            11_000_000_000,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .map(|conds| conds.cost);
+
+        let mut a = make_allocator_with_limit(flags, limit);
+        let (base_cost, program) =
+            load_generator_in(&mut a, &generator, flags, &TEST_CONSTANTS).expect("load_generator");
+        let parallel = run_generator_parallel(
+            &mut a,
+            limit,
+            base_cost,
+            program,
+            blocks,
+            11_000_000_000,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+            None,
+        )
+        .map(|result| result.map(|conds| conds.cost));
+
+        if heap_limit_kb == 1000 {
+            assert!(serial.is_ok());
+            assert_eq!(parallel, Some(serial));
+        } else {
+            // the block runs out of memory when run serially, so the block
+            // must be run again, serially
+            assert_eq!(serial, Err(ValidationErr::Eval(EvalErr::OutOfMemory)));
+            assert_eq!(parallel, None);
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[rstest]
+    #[case("block-834768")]
+    #[case("block-834760")]
+    #[case("block-225758")]
+    #[case("new-agg-sigs")]
+    #[case("create-coin-hint")]
+    #[case("many-create-coin")]
+    #[case("double-spend")]
+    #[case("duplicate-outputs")]
+    #[case("invalid-conditions")]
+    #[case("assert-puzzle-announce-fail")]
+    #[case("infinite-recursion1")]
+    #[case("unknown-condition")]
+    fn test_parallel_matches_serial(
+        #[case] name: &str,
+        #[values(ConsensusFlags::empty(), ConsensusFlags::INTERNED_GENERATOR)]
+        flags: ConsensusFlags,
+    ) {
+        use std::fs::read_to_string;
+
+        let test_file = read_to_string(format!("../../generator-tests/{name}.txt"))
+            .expect("test file not found");
+        let (generator, _) = test_file.split_once('\n').expect("invalid test file");
+        let generator = hex::decode(generator).expect("invalid hex encoded generator");
+        compare_parallel(&generator, flags);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[rstest]
+    fn test_parallel_many_spends(
+        #[values(ConsensusFlags::empty(), ConsensusFlags::COST_CONDITIONS)] flags: ConsensusFlags,
+    ) {
+        compare_parallel(&make_generator_with_create_coins(1000, 3), flags);
+    }
+
+    // runs the generator both in parallel and serially, and makes sure the
+    // results are the same
+    #[cfg(feature = "rayon")]
+    fn compare_parallel(generator: &[u8], flags: ConsensusFlags) {
+        // the allocator usage is expected to differ, and the order of
+        // CREATE_COIN conditions isn't deterministic
+        let owned = |(a, conds): (Allocator, SpendBundleConditions)| {
+            let mut conds = OwnedSpendBundleConditions::from(&a, conds);
+            for spend in &mut conds.spends {
+                spend.create_coin.sort();
+            }
+            conds.num_atoms = 0;
+            conds.num_pairs = 0;
+            conds.heap_size = 0;
+            conds
+        };
+        let blocks: &[&[u8]] = &[];
+        let flags = flags | ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+        let run = |parallel: bool| {
+            let run = if parallel {
+                run_block_generator2_parallel::<&&[u8], _>
+            } else {
+                run_block_generator2::<&&[u8], _>
+            };
+            run(
+                generator,
+                blocks,
+                11_000_000_000,
+                flags,
+                &Signature::default(),
+                None,
+                &TEST_CONSTANTS,
+            )
+            .map(owned)
+        };
+        assert_eq!(run(true), run(false));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[rstest]
+    // the first failing spend is reported, no matter how the spends are
+    // scheduled, or how they fail
+    #[case(&[3, 7], &[], Some(ErrorCode::AssertMyAmountFailed))]
+    #[case(&[7], &[3], Some(ErrorCode::GeneratorRuntimeError))]
+    #[case(&[3], &[7], Some(ErrorCode::AssertMyAmountFailed))]
+    #[case(&[99], &[], Some(ErrorCode::AssertMyAmountFailed))]
+    #[case(&[], &[0, 50], Some(ErrorCode::GeneratorRuntimeError))]
+    #[case(&[], &[], None)]
+    fn test_parallel_first_error(
+        #[case] bad_conditions: &[usize],
+        #[case] raising: &[usize],
+        #[case] expected: Option<ErrorCode>,
+    ) {
+        use crate::opcodes::ASSERT_MY_AMOUNT;
+
+        // the spends in bad_conditions fail ASSERT_MY_AMOUNT, the ones in
+        // raising fail running the puzzle
+        const RAISE_PUZZLE: &[u8] = &[0xff, 0x08, 0x80];
+        let puzzle_hash = Bytes32::from(tree_hash_atom(IDENTITY_PUZZLE).to_bytes());
+        let mut a = Allocator::new();
+        let bad_solution = [(ASSERT_MY_AMOUNT, (1337, ()))].to_clvm(&mut a).unwrap();
+        let bad_solution = node_to_bytes(&a, bad_solution).unwrap();
+        let empty_solution: &[u8] = &[0x80];
+
+        let spends = (0..100_usize).map(|i| {
+            let mut parent = [0u8; 32];
+            parent[0..4].copy_from_slice(&(i as u32).to_be_bytes());
+            let puzzle = if raising.contains(&i) {
+                RAISE_PUZZLE
+            } else {
+                IDENTITY_PUZZLE
+            };
+            let solution = if bad_conditions.contains(&i) {
+                bad_solution.as_slice()
+            } else {
+                empty_solution
+            };
+            (Coin::new(parent.into(), puzzle_hash, 1), puzzle, solution)
+        });
+        // solution_generator() reverses the order of the spends
+        let spends: Vec<_> = spends.collect();
+        let generator = solution_generator(spends.into_iter().rev()).expect("solution_generator");
+
+        let blocks: &[&[u8]] = &[];
+        let flags = ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+        for _ in 0..10 {
+            let result = run_block_generator2_parallel(
+                &generator,
+                blocks,
+                11_000_000_000,
+                flags,
+                &Signature::default(),
+                None,
+                &TEST_CONSTANTS,
+            );
+            assert_eq!(
+                result.as_ref().err().map(ValidationErr::error_code),
+                expected
+            );
+            let serial = run_block_generator2(
+                &generator,
+                blocks,
+                11_000_000_000,
+                flags,
+                &Signature::default(),
+                None,
+                &TEST_CONSTANTS,
+            );
+            assert_eq!(result.err(), serial.err());
+        }
+    }
+
+    // a generator of `num_spends` spends of a puzzle that counts down from
+    // `iterations` (a serialized atom) in a loop, and then returns no
+    // conditions. Returns the generator and the puzzle
+    #[cfg(feature = "rayon")]
+    fn make_slow_spends(num_spends: u32, iterations: &str) -> (Vec<u8>, Vec<u8>) {
+        // (r SLOW-GENERATOR)
+        let puzzle = [
+            &[0xff, 0x06, 0xff][..],
+            &make_slow_generator(iterations),
+            &[0x80],
+        ]
+        .concat();
+        let puzzle_hash =
+            Bytes32::from(clvm_utils::tree_hash_from_bytes(&puzzle).expect("tree_hash"));
+        let generator = solution_generator((0..num_spends).map(|i| {
+            let mut parent = [0u8; 32];
+            parent[0..4].copy_from_slice(&i.to_be_bytes());
+            (
+                Coin::new(parent.into(), puzzle_hash, 0),
+                puzzle.as_slice(),
+                &[0x80][..],
+            )
+        }))
+        .expect("solution_generator");
+        (generator, puzzle)
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_cost_budget() {
+        const NUM_SPENDS: u32 = 100;
+        const NUM_THREADS: u64 = 4;
+        let (generator, puzzle) = make_slow_spends(NUM_SPENDS, "8203e8");
+        let blocks: &[&[u8]] = &[];
+        let flags = ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+
+        let mut a = Allocator::new();
+        let node = node_from_bytes(&mut a, &puzzle).expect("node_from_bytes");
+        let dialect = ChiaDialect::new(flags.to_clvm_flags());
+        let Reduction(spend_cost, _) =
+            run_program(&mut a, &dialect, node, NodePtr::NIL, u64::MAX).expect("run_program");
+
+        // the cost of everything but the spends
+        let mut a = Allocator::new();
+        let (base_cost, program) =
+            load_generator_in(&mut a, &generator, flags, &TEST_CONSTANTS).expect("load_generator");
+        let spends = BlockGeneratorSpends::with_generator(
+            &mut a,
+            base_cost,
+            program,
+            blocks,
+            u64::MAX,
+            flags,
+            &TEST_CONSTANTS,
+            None,
+            ConditionCosts::default(),
+        )
+        .expect("with_generator");
+        let generator_cost = u64::MAX - spends.cost_left;
+
+        // only 3 of the spends fit in the block
+        let max_cost = generator_cost + spend_cost * 7 / 2;
+        let mut a = Allocator::new();
+        let (base_cost, program) =
+            load_generator_in(&mut a, &generator, flags, &TEST_CONSTANTS).expect("load_generator");
+        let spends = BlockGeneratorSpends::with_generator(
+            &mut a,
+            base_cost,
+            program,
+            blocks,
+            max_cost,
+            flags,
+            &TEST_CONSTANTS,
+            None,
+            ConditionCosts::default(),
+        )
+        .expect("with_generator");
+        let mut nodes = Vec::new();
+        let mut iter = spends.spends;
+        while let Some((spend, rest)) = spends.a.next(iter) {
+            iter = rest;
+            nodes.push(spend);
+        }
+        assert_eq!(nodes.len(), NUM_SPENDS as usize);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(NUM_THREADS as usize)
+            .build()
+            .expect("thread pool");
+        let cost_left = spends.cost_left;
+        let (results, work) =
+            pool.install(move || spends.run_puzzles_parallel(u32::MAX as usize, &nodes));
+        // at most one puzzle per thread may be in progress when the budget is
+        // used up, the rest are never started
+        assert!(work <= (NUM_THREADS + 1) * cost_left);
+        assert!(results.iter().filter(|r| r.is_some()).count() <= 3 + NUM_THREADS as usize);
+
+        let run = |parallel: bool| {
+            let run = if parallel {
+                run_block_generator2_parallel::<&&[u8], _>
+            } else {
+                run_block_generator2::<&&[u8], _>
+            };
+            run(
+                &generator,
+                blocks,
+                max_cost,
+                flags,
+                &Signature::default(),
+                None,
+                &TEST_CONSTANTS,
+            )
+            .map(|(_, conds)| conds.cost)
+        };
+        let serial = run(false);
+        assert_eq!(
+            serial.as_ref().err().map(ValidationErr::error_code),
+            Some(ErrorCode::ExecutionCostExceeded)
+        );
+        assert_eq!(pool.install(|| run(true)), serial);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[rstest]
+    #[case("830f4240", Some(Duration::ZERO), Some(ErrorCode::Timeout))]
+    #[case("8203e8", Some(Duration::from_secs(3600)), None)]
+    #[case("8203e8", None, None)]
+    fn test_parallel_deadline(
+        #[case] iterations: &str,
+        #[case] time_limit: Option<Duration>,
+        #[case] expected_err: Option<ErrorCode>,
+    ) {
+        let (generator, _) = make_slow_spends(10, iterations);
+        let blocks: &[&[u8]] = &[];
+        let result = run_block_generator2_parallel_with_deadline(
+            &generator,
+            blocks,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+            time_limit.map(|limit| Instant::now() + limit),
+        );
+        match (expected_err, result) {
+            (Some(err), Err(e)) => {
+                assert_eq!(e.error_code(), err);
+            }
+            (None, Ok((_, conds))) => {
+                assert_eq!(conds.spends.len(), 10);
+            }
+            (_, Err(e)) => {
+                panic!("unexpected error: {e:?}");
+            }
+            _ => {
+                panic!("expected failure");
+            }
+        }
+    }
+
+    #[rstest]
+    fn test_cost_exceeded_phase(#[values(false, true)] generator2: bool) {
+        use std::fs::read_to_string;
+
+        let test_file =
+            read_to_string("../../generator-tests/block-834768.txt").expect("test file not found");
+        let (generator, _) = test_file.split_once('\n').expect("invalid test file");
+        let generator = hex::decode(generator).expect("invalid hex encoded generator");
+
+        let blocks: &[&[u8]] = &[];
+        let run = if generator2 {
+            run_block_generator2::<&&[u8], _>
+        } else {
+            run_block_generator::<&&[u8], _>
+        };
+        let flags = ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+        let (_, conds) = run(
+            &generator,
+            blocks,
+            u64::MAX,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator");
+        assert!(conds.condition_cost > 0);
+
+        // the conditions are charged last, so the block runs out of cost while
+        // parsing them
+        let err = run(
+            &generator,
+            blocks,
+            conds.cost - 1,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::ConditionCostExceeded);
+        assert_eq!(u32::from(err.error_code()), 23);
+
+        // the cost of the generator bytes alone exceeds the limit, so we never
+        // get to the conditions
+        let byte_cost = generator.len() as u64 * TEST_CONSTANTS.cost_per_byte;
+        let err = run(
+            &generator,
+            blocks,
+            byte_cost - 1,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::CostExceeded);
+        assert_eq!(u32::from(err.error_code()), 23);
+    }
+
+    #[rstest]
+    fn test_block_generator_spends(
+        #[values(0, 1, 5)] num_spends: usize,
+        #[values(0, 3)] coins_per_spend: usize,
+    ) {
+        let program = make_generator_with_create_coins(num_spends, coins_per_spend);
+        let blocks: &[&[u8]] = &[];
+        let flags = ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+
+        let (a, expected) = run_block_generator2(
+            &program,
+            blocks,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator2");
+        // the order of the CREATE_COIN conditions is not deterministic
+        let sorted = |mut spend: OwnedSpendConditions| {
+            spend.create_coin.sort();
+            spend
+        };
+        let sorted_bundle = |mut conds: OwnedSpendBundleConditions| {
+            conds.spends = conds.spends.into_iter().map(sorted).collect();
+            conds
+        };
+        let expected = sorted_bundle(OwnedSpendBundleConditions::from(&a, expected));
+
+        let mut a = make_allocator(flags);
+        let mut spends = BlockGeneratorSpends::new(
+            &mut a,
+            &program,
+            blocks,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            flags,
+            &TEST_CONSTANTS,
+        )
+        .expect("BlockGeneratorSpends");
+        let mut streamed = Vec::new();
+        while let Some(spend) = spends.next() {
+            let spend = spend.expect("spend");
+            streamed.push(sorted(OwnedSpendConditions::from(
+                spends.allocator(),
+                spend,
+            )));
+        }
+        assert_eq!(streamed, expected.spends);
+
+        let conds = spends.finish(&Signature::default(), None).expect("finish");
+        assert_eq!(
+            sorted_bundle(OwnedSpendBundleConditions::from(&a, conds)),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("double-spend")]
+    #[case("invalid-conditions")]
+    #[case("assert-puzzle-announce-fail")]
+    fn test_block_generator_spends_error(#[case] name: &str) {
+        use std::fs::read_to_string;
+
+        let test_file = read_to_string(format!("../../generator-tests/{name}.txt"))
+            .expect("test file not found");
+        let (generator, _) = test_file.split_once('\n').expect("invalid test file");
+        let generator = hex::decode(generator).expect("invalid hex encoded generator");
+        let blocks: &[&[u8]] = &[];
+        let flags = ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+
+        let expected = run_block_generator2(
+            &generator,
+            blocks,
+            11_000_000_000,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .unwrap_err();
+
+        let mut a = make_allocator(flags);
+        let mut spends = BlockGeneratorSpends::new(
+            &mut a,
+            &generator,
+            blocks,
+            11_000_000_000,
+            flags,
+            &TEST_CONSTANTS,
+        )
+        .expect("BlockGeneratorSpends");
+        let streamed = spends.by_ref().find_map(Result::err);
+        let err = spends.finish(&Signature::default(), None).unwrap_err();
+        // the error, including any coin ID, is preserved
+        assert_eq!(err, expected);
+        if let Some(streamed) = streamed {
+            assert_eq!(streamed, expected);
+        }
+    }
+
+    #[test]
+    fn test_block_generator_spends_cost_exceeded() {
+        let program = make_generator_with_create_coins(5, 3);
+        let blocks: &[&[u8]] = &[];
+        let flags = ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+        let (_, conds) = run_block_generator2(
+            &program,
+            blocks,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator2");
+
+        // the cost limit applies to all spends together. The last spend
+        // exceeds it, and the iterator stops after the error
+        let mut a = make_allocator(flags);
+        let mut spends = BlockGeneratorSpends::new(
+            &mut a,
+            &program,
+            blocks,
+            conds.cost - 1,
+            flags,
+            &TEST_CONSTANTS,
+        )
+        .expect("BlockGeneratorSpends");
+        for _ in 0..4 {
+            assert!(spends.next().expect("spend").is_ok());
+        }
+        let err = spends.next().expect("spend").unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::ConditionCostExceeded);
+        assert!(spends.next().is_none());
+
+        // finish() fails with the same error, not just the same error code
+        assert_eq!(spends.finish(&Signature::default(), None).unwrap_err(), err);
+    }
+
+    #[rstest]
+    fn test_pairings(
+        #[values(0, 1, 3, 10)] num_messages: u8,
+        #[values(false, true)] generator2: bool,
+        #[values(false, true)] with_cache: bool,
+    ) {
+        let sk = SecretKey::from_seed(&[0x11; 32]);
+        let pk = sk.public_key();
+        let puzzle_hash = Bytes32::from(tree_hash_atom(&[1]).to_bytes());
+
+        // every spend has a single AGG_SIG_UNSAFE with a distinct message
+        let mut a = Allocator::new();
+        let mut solutions = Vec::new();
+        let mut signature = Signature::default();
+        for i in 0..num_messages {
+            let msg = [b'm', b's', b'g', i];
+            signature.aggregate(&sign(&sk, msg));
+            let cond = (AGG_SIG_UNSAFE, (pk, (Bytes::from(msg.as_slice()), ())))
+                .to_clvm(&mut a)
+                .unwrap();
+            let nil = a.nil();
+            let conds = a.new_pair(cond, nil).unwrap();
+            solutions.push(node_to_bytes(&a, conds).unwrap());
+        }
+        let spends = solutions.iter().enumerate().map(|(i, solution)| {
+            let mut parent = [0u8; 32];
+            parent[0..4].copy_from_slice(&(i as u32).to_be_bytes());
+            (
+                Coin::new(parent.into(), puzzle_hash, 0),
+                IDENTITY_PUZZLE,
+                solution.as_slice(),
+            )
+        });
+        let program = solution_generator(spends).expect("solution_generator");
+
+        let blocks: &[&[u8]] = &[];
+        let run = if generator2 {
+            run_block_generator2::<&&[u8], _>
+        } else {
+            run_block_generator::<&&[u8], _>
+        };
+        let cache = BlsCache::default();
+        let bls_cache = if with_cache { Some(&cache) } else { None };
+
+        // one pairing per message, plus one for the aggregate signature. No
+        // messages doesn't require any pairing. The second time the block is
+        // run, the pairings of the messages are found in the cache (if there
+        // is one), and only the signature is paired
+        for run_idx in 0..2 {
+            let expected = if num_messages == 0 {
+                0
+            } else if with_cache && run_idx > 0 {
+                1
+            } else {
+                u64::from(num_messages) + 1
+            };
+            let (_, conds) = run(
+                &program,
+                blocks,
+                u64::MAX,
+                ConsensusFlags::empty(),
+                &signature,
+                bls_cache,
+                &TEST_CONSTANTS,
+            )
+            .expect("run_block_generator");
+            assert!(conds.validated_signature);
+            assert_eq!(conds.pairings, expected);
+        }
+
+        // when the signature isn't validated, no pairings are performed
+        let (_, conds) = run(
+            &program,
+            blocks,
+            u64::MAX,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &signature,
+            bls_cache,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator");
+        assert_eq!(conds.pairings, 0);
+    }
+
+    #[rstest]
+    fn test_batch_verify_sigs(
+        #[values(None, Some(0), Some(3))] bad_signature: Option<u8>,
+        #[values(false, true)] batch: bool,
+        #[values(false, true)] with_cache: bool,
+        #[values(false, true)] generator2: bool,
+    ) {
+        let sk = SecretKey::from_seed(&[0x11; 32]);
+        let pk = sk.public_key();
+        let puzzle_hash = Bytes32::from(tree_hash_atom(&[1]).to_bytes());
+
+        // every spend has an AGG_SIG_ME condition. The signature of one of them
+        // may be for the wrong message
+        let mut a = Allocator::new();
+        let mut spends = Vec::new();
+        let mut signature = Signature::default();
+        for i in 0..4_u8 {
+            let coin = Coin::new(Bytes32::from([i; 32]), puzzle_hash, 0);
+            let msg = [b'm', b's', b'g', i];
+            let mut signed_msg = msg.to_vec();
+            if bad_signature == Some(i) {
+                signed_msg[0] = b'x';
+            }
+            signed_msg.extend_from_slice(&coin.coin_id());
+            signed_msg.extend_from_slice(&TEST_CONSTANTS.agg_sig_me_additional_data);
+            signature.aggregate(&sign(&sk, signed_msg));
+
+            let cond = (AGG_SIG_ME, (pk, (Bytes::from(msg.as_slice()), ())))
+                .to_clvm(&mut a)
+                .unwrap();
+            let nil = a.nil();
+            let conds = a.new_pair(cond, nil).unwrap();
+            spends.push((coin, node_to_bytes(&a, conds).unwrap()));
+        }
+        let program = solution_generator(
+            spends
+                .iter()
+                .map(|(coin, solution)| (*coin, IDENTITY_PUZZLE, solution.as_slice())),
+        )
+        .expect("solution_generator");
+
+        let blocks: &[&[u8]] = &[];
+        let run = if generator2 {
+            run_block_generator2::<&&[u8], _>
+        } else {
+            run_block_generator::<&&[u8], _>
+        };
+        let cache = BlsCache::default();
+        let bls_cache = if with_cache { Some(&cache) } else { None };
+        let flags = if batch {
+            ConsensusFlags::BATCH_VERIFY_SIGS
+        } else {
+            ConsensusFlags::empty()
+        };
+
+        let result = run(
+            &program,
+            blocks,
+            u64::MAX,
+            flags,
+            &signature,
+            bls_cache,
+            &TEST_CONSTANTS,
+        );
+        if bad_signature.is_some() {
+            assert_eq!(
+                result.unwrap_err().error_code(),
+                ErrorCode::BadAggregateSignature
+            );
+        } else {
+            let (_, conds) = result.expect("run_block_generator");
+            assert!(conds.validated_signature);
+        }
+    }
+
+    #[rstest]
+    fn test_collect_sig_pairs(
+        #[values(false, true)] collect: bool,
+        #[values(false, true)] validate: bool,
+    ) {
+        let sk = SecretKey::from_seed(&[0x11; 32]);
+        let pk = sk.public_key();
+        let puzzle_hash = Bytes32::from(tree_hash_atom(&[1]).to_bytes());
+        let coin = Coin::new(Bytes32::from([0x33; 32]), puzzle_hash, 1234);
+
+        let mut a = Allocator::new();
+        let conds = [
+            (AGG_SIG_ME, (pk, (Bytes::from(b"foo".as_slice()), ()))),
+            (AGG_SIG_UNSAFE, (pk, (Bytes::from(b"bar".as_slice()), ()))),
+            (
+                AGG_SIG_PARENT_AMOUNT,
+                (pk, (Bytes::from(b"baz".as_slice()), ())),
+            ),
+        ]
+        .to_clvm(&mut a)
+        .unwrap();
+        let solution = node_to_bytes(&a, conds).unwrap();
+        let program = solution_generator([(coin, IDENTITY_PUZZLE, solution.as_slice())])
+            .expect("solution_generator");
+
+        // the messages, as signed
+        let mut me_msg = b"foo".to_vec();
+        me_msg.extend_from_slice(&coin.coin_id());
+        me_msg.extend_from_slice(&TEST_CONSTANTS.agg_sig_me_additional_data);
+        let unsafe_msg = b"bar".to_vec();
+        let mut parent_amount_msg = b"baz".to_vec();
+        parent_amount_msg.extend_from_slice(&coin.parent_coin_info);
+        parent_amount_msg.extend_from_slice(&u64_to_bytes(coin.amount));
+        parent_amount_msg.extend_from_slice(&TEST_CONSTANTS.agg_sig_parent_amount_additional_data);
+        let expected = vec![
+            (pk, Bytes::from(me_msg), AGG_SIG_ME),
+            (pk, Bytes::from(unsafe_msg), AGG_SIG_UNSAFE),
+            (pk, Bytes::from(parent_amount_msg), AGG_SIG_PARENT_AMOUNT),
+        ];
+
+        let mut signature = Signature::default();
+        for (_, msg, _) in &expected {
+            signature.aggregate(&sign(&sk, msg));
+        }
+
+        let mut flags = if validate {
+            ConsensusFlags::empty()
+        } else {
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE
+        };
+        if collect {
+            flags |= ConsensusFlags::COLLECT_SIG_PAIRS;
+        }
+        let blocks: &[&[u8]] = &[];
+        let (_, conds) = run_block_generator2(
+            &program,
+            blocks,
+            u64::MAX,
+            flags,
+            &signature,
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator2");
+        assert_eq!(conds.validated_signature, validate);
+        if collect {
+            assert_eq!(conds.sig_pairs, expected);
+        } else {
+            assert!(conds.sig_pairs.is_empty());
+        }
+    }
+
+    #[rstest]
+    #[case(1, 1)]
+    #[case(3, 1)]
+    #[case(1, 3)]
+    #[case(5, 5)]
+    fn test_cost_conditions_with_create_coin(
+        #[case] num_spends: usize,
+        #[case] coins_per_spend: usize,
+    ) {
+        let program = make_generator_with_create_coins(num_spends, coins_per_spend);
+        let blocks: &[&[u8]] = &[];
+        let num_coins = (num_spends * coins_per_spend) as u64;
+
+        let (_, without) = run_block_generator2(
+            &program,
+            blocks,
+            u64::MAX,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("without COST_CONDITIONS");
+
+        let (_, with) = run_block_generator2(
+            &program,
+            blocks,
+            u64::MAX,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE | ConsensusFlags::COST_CONDITIONS,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("with COST_CONDITIONS");
+
+        assert_eq!(without.spends.len(), num_spends);
+        assert_eq!(with.spends.len(), num_spends);
+
+        assert_eq!(without.condition_cost, CREATE_COIN_COST * num_coins);
+        assert_eq!(
+            with.condition_cost,
+            SPEND_COST * num_spends as u64 + NEW_CREATE_COIN_COST * num_coins
+        );
+
+        assert_eq!(without.execution_cost, with.execution_cost);
+    }
+
+    #[rstest]
+    #[case(1, 1)]
+    #[case(3, 1)]
+    #[case(5, 5)]
+    fn test_custom_condition_costs(#[case] num_spends: usize, #[case] coins_per_spend: usize) {
+        let program = make_generator_with_create_coins(num_spends, coins_per_spend);
+        let blocks: &[&[u8]] = &[];
+        let num_coins = (num_spends * coins_per_spend) as u64;
+        let flags = ConsensusFlags::DONT_VALIDATE_SIGNATURE | ConsensusFlags::COST_CONDITIONS;
+
+        let (_, default) = run_block_generator2_with_costs(
+            &program,
+            blocks,
+            u64::MAX,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+            ConditionCosts::default(),
+        )
+        .expect("default costs");
+        let (_, consensus) = run_block_generator2(
+            &program,
+            blocks,
+            u64::MAX,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator2");
+        assert_eq!(default.cost, consensus.cost);
+        assert_eq!(default.condition_cost, consensus.condition_cost);
+
+        let costs = ConditionCosts {
+            create_coin: NEW_CREATE_COIN_COST * 2,
+            ..Default::default()
+        };
+        let (_, doubled) = run_block_generator2_with_costs(
+            &program,
+            blocks,
+            u64::MAX,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+            costs,
+        )
+        .expect("doubled CREATE_COIN cost");
+        assert_eq!(
+            doubled.condition_cost,
+            SPEND_COST * num_spends as u64 + NEW_CREATE_COIN_COST * 2 * num_coins
+        );
+        assert_eq!(
+            doubled.condition_cost - default.condition_cost,
+            NEW_CREATE_COIN_COST * num_coins
+        );
+        assert_eq!(
+            doubled.cost - default.cost,
+            NEW_CREATE_COIN_COST * num_coins
+        );
+        assert_eq!(doubled.execution_cost, default.execution_cost);
+    }
+
+    #[rstest]
+    // nil
+    #[case(&[0x80], 1)]
+    // (1 . 2)
+    #[case(&[0xff, 0x01, 0x02], 3)]
+    // ("foobar" . nil)
+    #[case(&[0xff, 0x86, b'f', b'o', b'o', b'b', b'a', b'r', 0x80], 3)]
+    // ((1 . 2) . <back-reference to (1 . 2)>)
+    #[case(&[0xff, 0xff, 0x01, 0x02, 0xfe, 0x02], 5)]
+    fn test_check_deserialization_cost(#[case] program: &[u8], #[case] nodes: u64) {
+        check_deserialization_cost(program, nodes).expect("within budget");
+        assert_eq!(
+            check_deserialization_cost(program, nodes - 1)
+                .unwrap_err()
+                .error_code(),
+            ErrorCode::DeserializationCostExceeded
+        );
+    }
+
+    #[rstest]
+    #[case(&[])]
+    #[case(&[0xff, 0x01])]
+    #[case(&[0x86, b'f', b'o', b'o'])]
+    fn test_check_deserialization_cost_truncated(#[case] program: &[u8]) {
+        assert!(matches!(
+            check_deserialization_cost(program, u64::MAX),
+            Err(ValidationErr::Eval(_))
+        ));
+    }
+
+    #[rstest]
+    #[case(1_000_000, None)]
+    #[case(1000, Some(ErrorCode::DeserializationCostExceeded))]
+    fn test_deserialization_budget(
+        #[case] deserialization_budget: u64,
+        #[case] expected_err: Option<ErrorCode>,
+    ) {
+        let program = make_generator_with_create_coins(1, 1000);
+        let blocks: &[&[u8]] = &[];
+        let result = run_block_generator2_with_deserialization_budget(
+            &program,
+            blocks,
+            u64::MAX,
+            deserialization_budget,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        );
+        match (expected_err, result) {
+            (Some(err), Err(e)) => {
+                assert_eq!(e.error_code(), err);
+            }
+            (None, Ok(conds)) => {
+                assert_eq!(conds.1.spends.len(), 1);
+                assert_eq!(conds.1.spends[0].create_coin.len(), 1000);
+            }
+            _ => {
+                panic!("mismatch");
+            }
+        }
+
+        // the deserialization budget is checked before the generator is run,
+        // so it takes precedence over running out of cost
+        if expected_err.is_some() {
+            let err = run_block_generator2_with_deserialization_budget(
+                &program,
+                blocks,
+                0,
+                deserialization_budget,
+                ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+                &Signature::default(),
+                None,
+                &TEST_CONSTANTS,
+            )
+            .unwrap_err();
+            assert_eq!(err.error_code(), ErrorCode::DeserializationCostExceeded);
+        }
+    }
+
+    // the cost of a selection of operators, as implemented by the linked
+    // version of clvmr. This pins CLVM_COST_TABLE_VERSION to the cost table.
+    // If this test fails, clvmr changed the cost of an operator, and
+    // CLVM_COST_TABLE_VERSION must be bumped along with the expected costs
+    #[rstest]
+    #[case::apply(2, vec![vec![1], vec![]], 175)]
+    #[case::if_(3, vec![vec![1], vec![2], vec![3]], 94)]
+    #[case::cons(4, vec![vec![1], vec![2]], 91)]
+    #[case::listp(7, vec![vec![1]], 40)]
+    #[case::eq(9, vec![vec![1; 32], vec![1; 32]], 222)]
+    #[case::gr_bytes(10, vec![vec![1; 32], vec![2; 32]], 222)]
+    #[case::sha256(11, vec![vec![1; 32], vec![2; 32]], 844)]
+    #[case::substr(12, vec![vec![1; 32], vec![5], vec![10]], 62)]
+    #[case::strlen(13, vec![vec![1; 32]], 236)]
+    #[case::concat(14, vec![vec![1; 32], vec![2; 32]], 1285)]
+    #[case::add(16, vec![vec![0x7f; 32], vec![1]], 1199)]
+    #[case::subtract(17, vec![vec![0x7f; 32], vec![1]], 1199)]
+    #[case::multiply(18, vec![vec![0x7f; 32], vec![0x7f; 32]], 2050)]
+    #[case::div(19, vec![vec![0x7f; 32], vec![3]], 1481)]
+    #[case::divmod(20, vec![vec![0x7f; 32], vec![3]], 1685)]
+    #[case::gr(21, vec![vec![1], vec![2]], 543)]
+    #[case::ash(22, vec![vec![0x7f; 32], vec![3]], 1162)]
+    #[case::lsh(23, vec![vec![0x7f; 32], vec![3]], 843)]
+    #[case::logand(24, vec![vec![0x7f; 32], vec![0x55; 32]], 1181)]
+    #[case::logior(25, vec![vec![0x7f; 32], vec![0x55; 32]], 1181)]
+    #[case::logxor(26, vec![vec![0x7f; 32], vec![0x55; 32]], 1181)]
+    #[case::lognot(27, vec![vec![0x7f; 32]], 768)]
+    #[case::pubkey_for_exp(30, vec![vec![0x7f; 32]], 1_327_447)]
+    #[case::not(32, vec![vec![1]], 221)]
+    #[case::any(33, vec![vec![1], vec![]], 841)]
+    #[case::all(34, vec![vec![1], vec![1]], 841)]
+    #[case::coinid(48, vec![vec![1; 32], vec![2; 32], vec![100]], 861)]
+    #[case::modpow(60, vec![vec![0x7f; 32], vec![5], vec![0x6f; 32]], 40_104)]
+    #[case::modulo(61, vec![vec![0x7f; 32], vec![3]], 1171)]
+    #[case::keccak256(62, vec![vec![1; 32]], 615)]
+    #[case::sha256_tree(63, vec![vec![1; 32]], 677)]
+    fn test_cost_table(#[case] op: u8, #[case] args: Vec<Vec<u8>>, #[case] expected_cost: Cost) {
+        assert_eq!(CLVM_COST_TABLE_VERSION, 1);
+
+        // (op (q . arg0) (q . arg1) ...)
+        let mut a = Allocator::new();
+        let mut program = a.nil();
+        for arg in args.iter().rev() {
+            let quote = a.one();
+            let arg = a.new_atom(arg).unwrap();
+            let arg = a.new_pair(quote, arg).unwrap();
+            program = a.new_pair(arg, program).unwrap();
+        }
+        let op = a.new_atom(&[op]).unwrap();
+        let program = a.new_pair(op, program).unwrap();
+
+        let env = a.nil();
+        let dialect = ChiaDialect::new(
+            ClvmFlags::ENABLE_KECCAK_OPS_OUTSIDE_GUARD | ClvmFlags::ENABLE_SHA256_TREE,
+        );
+        let Reduction(cost, _) = run_program(&mut a, &dialect, program, env, u64::MAX).unwrap();
+        assert_eq!(cost, expected_cost);
+    }
+
+    #[rstest]
+    #[case(CLVM_COST_TABLE_VERSION, None)]
+    #[case(0, Some(ErrorCode::UnsupportedCostTable))]
+    #[case(CLVM_COST_TABLE_VERSION + 1, Some(ErrorCode::UnsupportedCostTable))]
+    fn test_run_block_generator2_pinned(
+        #[case] cost_table_version: u32,
+        #[case] expected_err: Option<ErrorCode>,
+    ) {
+        let program = make_generator_with_create_coins(1, 10);
+        let blocks: &[&[u8]] = &[];
+        let result = run_block_generator2_pinned(
+            &program,
+            blocks,
+            u64::MAX,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+            cost_table_version,
+        );
+        match (expected_err, result) {
+            (Some(err), Err(e)) => {
+                assert_eq!(e.error_code(), err);
+            }
+            (None, Ok((_, conds))) => {
+                let (_, expected) = run_block_generator2(
+                    &program,
+                    blocks,
+                    u64::MAX,
+                    ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+                    &Signature::default(),
+                    None,
+                    &TEST_CONSTANTS,
+                )
+                .expect("run_block_generator2");
+                assert_eq!(conds.cost, expected.cost);
+                assert_eq!(conds.spends.len(), 1);
+                assert_eq!(conds.spends[0].create_coin.len(), 10);
+            }
+            _ => {
+                panic!("mismatch");
+            }
+        }
+    }
+
+    #[rstest]
+    #[case("atom", Some(ErrorCode::InvalidCondition))]
+    #[case("nil", Some(ErrorCode::InvalidCondition))]
+    #[case("atom-spends", Some(ErrorCode::GeneratorRuntimeError))]
+    #[case("improper-spends", Some(ErrorCode::GeneratorRuntimeError))]
+    #[case("empty-spends", None)]
+    #[case("proper-spends", None)]
+    fn test_malformed_spend_list(#[case] output: &str, #[case] expected_err: Option<ErrorCode>) {
+        let mut a = Allocator::new();
+        let spend = (Bytes32::from([0x11; 32]), (1, (0, (0, 0))))
+            .to_clvm(&mut a)
+            .unwrap();
+        let terminator = a.new_small_number(8).unwrap();
+        let nil = a.nil();
+        let output_node = match output {
+            "atom" => terminator,
+            "nil" => nil,
+            "atom-spends" => a.new_pair(terminator, nil).unwrap(),
+            "improper-spends" => {
+                let spends = a.new_pair(spend, terminator).unwrap();
+                a.new_pair(spends, nil).unwrap()
+            }
+            "empty-spends" => a.new_pair(nil, nil).unwrap(),
+            "proper-spends" => {
+                let spends = a.new_pair(spend, nil).unwrap();
+                a.new_pair(spends, nil).unwrap()
+            }
+            _ => panic!("unknown case {output}"),
+        };
+        // spend_list() reports all malformed spend lists with the same error
+        assert_eq!(
+            spend_list(&a, output_node).err(),
+            expected_err.map(|_| ValidationErr::Err(ErrorCode::MalformedSpendList))
+        );
+
+        // the block generator reports them with the legacy error codes
+        // the generator is just the quoted output
+        let quote = a.one();
+        let generator = a.new_pair(quote, output_node).unwrap();
+        let program = node_to_bytes(&a, generator).unwrap();
+
+        let blocks: &[&[u8]] = &[];
+        let result = run_block_generator2(
+            &program,
+            blocks,
+            u64::MAX,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        );
+        match (expected_err, result) {
+            (Some(err), Err(e)) => {
+                assert_eq!(e.error_code(), err);
+            }
+            (None, Ok((_, conds))) => {
+                assert_eq!(conds.spends.len(), usize::from(output == "proper-spends"));
+            }
+            _ => {
+                panic!("mismatch");
+            }
+        }
+    }
+
+    // a generator that counts down from `iterations` (a serialized atom) in a
+    // loop, and then returns an empty list of spends
+    fn make_slow_generator(iterations: &str) -> Vec<u8> {
+        // (a (q . LOOP) (c (q . LOOP) (c (q . iterations) ())))
+        // LOOP = (a (i 5 (q . (a 2 (c 2 (c (- 5 (q . 1)) ())))) (q . (q . (())))) 1)
+        let loop_body = "ff02ffff03ff05ffff01ff02ff02ffff04ff02ffff04ffff11ff05ffff010180ff80808080ffff01ff01ff808080ff0180";
+        hex::decode(format!(
+            "ff02ffff01{loop_body}ffff04ffff01{loop_body}ffff04ffff01{iterations}ff80808080"
+        ))
+        .expect("hex")
+    }
+
+    #[rstest]
+    #[case("830f4240", Some(Duration::ZERO), Some(ErrorCode::Timeout))]
+    // the deadline is only checked every DEADLINE_CHECK_INTERVAL operators
+    #[case("0a", Some(Duration::ZERO), None)]
+    #[case("0a", Some(Duration::from_secs(3600)), None)]
+    #[case("822710", None, None)]
+    fn test_run_block_generator_deadline(
+        #[case] iterations: &str,
+        #[case] time_limit: Option<Duration>,
+        #[case] expected_err: Option<ErrorCode>,
+        #[values(false, true)] generator2: bool,
+    ) {
+        let program = make_slow_generator(iterations);
+        let blocks: &[&[u8]] = &[];
+        let run = if generator2 {
+            run_block_generator2_with_deadline::<&&[u8], _>
+        } else {
+            run_block_generator_with_deadline::<&&[u8], _>
+        };
+        let result = run(
+            &program,
+            blocks,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+            time_limit.map(|limit| Instant::now() + limit),
+        );
+        match (expected_err, result) {
+            (Some(err), Err(e)) => {
+                assert_eq!(e.error_code(), err);
+            }
+            (None, Ok((_, conds))) => {
+                assert!(conds.spends.is_empty());
+                assert!(conds.execution_cost > 0);
+            }
+            (_, Err(e)) => {
+                panic!("unexpected error: {e:?}");
+            }
+            _ => {
+                panic!("expected failure");
+            }
+        }
     }
 }
@@ -0,0 +1,326 @@
+use crate::allocator::make_allocator;
+use crate::conditions::{
+    ConditionCosts, EmptyVisitor, MAX_ANNOUNCEMENTS_PER_SPEND, MempoolVisitor, ParseState,
+    SpendBundleConditions, parse_conditions_with_costs, process_single_spend,
+};
+use crate::consensus_constants::ConsensusConstants;
+use crate::flags::ConsensusFlags;
+use crate::owned_conditions::OwnedSpendConditions;
+use crate::validation_error::{ErrorCode, ValidationErr};
+use chia_protocol::{Coin, Program};
+use clvm_traits::ToClvm;
+use clvmr::allocator::{Allocator, NodePtr};
+use clvmr::cost::Cost;
+use clvmr::serde::node_to_bytes;
+
+/// Builds the condition list for spending a coin, one condition at a time.
+/// Every condition is validated as it's added, along with the conditions
+/// added before it. This includes the encoding of its arguments (e.g. amounts
+/// must be canonical) as well as limits that apply to the spend as a whole,
+/// such as the number of announcements and the cost. A condition that fails
+/// validation is not added. Each condition is only parsed once, on top of the
+/// conditions added before it, and finalize() validates the whole spend again.
+/// Checks that depend on other spends (e.g. that announcements are asserted,
+/// or fees) are not performed.
+pub struct SpendBuilder<'a> {
+    a: Allocator,
+    parent_id: NodePtr,
+    puzzle_hash: NodePtr,
+    amount: NodePtr,
+    // the conditions added so far, in order
+    conditions: Vec<NodePtr>,
+    // the spend, with the conditions added so far, and what's left of the
+    // limits that apply to the spend as a whole
+    ret: SpendBundleConditions,
+    state: ParseState,
+    cost_left: Cost,
+    announce_countdown: u32,
+    flags: ConsensusFlags,
+    constants: &'a ConsensusConstants,
+}
+
+impl<'a> SpendBuilder<'a> {
+    pub fn new(
+        coin: &Coin,
+        flags: ConsensusFlags,
+        constants: &'a ConsensusConstants,
+    ) -> Result<Self, ValidationErr> {
+        let mut a = make_allocator(flags);
+        let parent_id = a.new_atom(coin.parent_coin_info.as_ref())?;
+        let puzzle_hash = a.new_atom(coin.puzzle_hash.as_ref())?;
+        let amount = a.new_number(coin.amount.into())?;
+        let mut builder = Self {
+            a,
+            parent_id,
+            puzzle_hash,
+            amount,
+            conditions: Vec::new(),
+            ret: SpendBundleConditions::default(),
+            state: ParseState::default(),
+            cost_left: 0,
+            announce_countdown: 0,
+            flags,
+            constants,
+        };
+        builder.reset()?;
+        Ok(builder)
+    }
+
+    /// Adds a condition, e.g. `(CREATE_COIN, (puzzle_hash, (amount, ())))`,
+    /// to the spend. If the condition is invalid, or makes the spend invalid,
+    /// the error is returned and the condition is not added.
+    pub fn add_condition<T: ToClvm<Allocator>>(
+        &mut self,
+        condition: T,
+    ) -> Result<(), ValidationErr> {
+        let condition = condition
+            .to_clvm(&mut self.a)
+            .map_err(|_| ValidationErr::Err(ErrorCode::InvalidCondition))?;
+        if let Err(e) = self.parse_condition(condition) {
+            // the condition may have been partially applied before it failed,
+            // so the spend is parsed again, without it
+            self.reset()?;
+            return Err(e);
+        }
+        self.conditions.push(condition);
+        Ok(())
+    }
+
+    /// The number of conditions added so far
+    pub fn len(&self) -> usize {
+        self.conditions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+
+    /// Returns the serialized condition list along with the parsed conditions
+    /// of the spend.
+    pub fn finalize(mut self) -> Result<(Program, OwnedSpendConditions), ValidationErr> {
+        let spend = self.validate()?;
+        let list = self.condition_list()?;
+        let program = Program::new(node_to_bytes(&self.a, list)?.into());
+        Ok((program, spend))
+    }
+
+    // starts the spend over, with the conditions in self.conditions
+    fn reset(&mut self) -> Result<(), ValidationErr> {
+        self.ret = SpendBundleConditions::default();
+        self.state = ParseState::default();
+        self.cost_left = self.constants.max_block_cost_clvm;
+        self.announce_countdown = MAX_ANNOUNCEMENTS_PER_SPEND;
+        let nil = self.a.nil();
+        process_single_spend::<EmptyVisitor>(
+            &self.a,
+            &mut self.ret,
+            &mut self.state,
+            self.parent_id,
+            self.puzzle_hash,
+            self.amount,
+            nil,
+            self.flags,
+            &mut self.cost_left,
+            0,
+            self.constants,
+        )?;
+        for c in self.conditions.clone() {
+            self.parse_condition(c)?;
+        }
+        Ok(())
+    }
+
+    // parses `condition` as the next condition of the spend
+    fn parse_condition(&mut self, condition: NodePtr) -> Result<(), ValidationErr> {
+        let list = self.a.new_pair(condition, self.a.nil())?;
+        let spend = self.ret.spends.pop().expect("internal error");
+        parse_conditions_with_costs(
+            &self.a,
+            &mut self.ret,
+            &mut self.state,
+            spend,
+            list,
+            self.flags,
+            &mut self.cost_left,
+            self.constants,
+            &mut EmptyVisitor {},
+            &ConditionCosts::default(),
+            &mut self.announce_countdown,
+        )?;
+        Ok(())
+    }
+
+    fn condition_list(&mut self) -> Result<NodePtr, ValidationErr> {
+        let mut list = self.a.nil();
+        for c in self.conditions.iter().rev() {
+            list = self.a.new_pair(*c, list)?;
+        }
+        Ok(list)
+    }
+
+    // validates all conditions of the spend at once
+    fn validate(&mut self) -> Result<OwnedSpendConditions, ValidationErr> {
+        let conditions = self.condition_list()?;
+        let mut ret = SpendBundleConditions::default();
+        let mut state = ParseState::default();
+        let mut cost_left = self.constants.max_block_cost_clvm;
+        process_single_spend::<MempoolVisitor>(
+            &self.a,
+            &mut ret,
+            &mut state,
+            self.parent_id,
+            self.puzzle_hash,
+            self.amount,
+            conditions,
+            self.flags,
+            &mut cost_left,
+            0,
+            self.constants,
+        )?;
+        let spend = ret.spends.pop().expect("internal error");
+        Ok(OwnedSpendConditions::from(&self.a, spend))
+    }
+}
+
+/// The amount of the change coin when spending coins worth `inputs_total` in
+/// total, creating coins worth `outputs_total` (excluding the change) and
+/// paying `fee`. The errors are the same as validating the resulting spend
+/// bundle would fail with, MintingCoin if the outputs exceed the inputs, and
+/// ReserveFeeConditionFailed if what's left isn't enough for the fee.
+pub fn compute_change(
+    inputs_total: u64,
+    outputs_total: u64,
+    fee: u64,
+) -> Result<u64, ValidationErr> {
+    let left = inputs_total
+        .checked_sub(outputs_total)
+        .ok_or(ValidationErr::Err(ErrorCode::MintingCoin))?;
+    left.checked_sub(fee)
+        .ok_or(ValidationErr::Err(ErrorCode::ReserveFeeConditionFailed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus_constants::TEST_CONSTANTS;
+    use crate::flags::MEMPOOL_MODE;
+    use crate::opcodes::{
+        ASSERT_MY_AMOUNT, CREATE_COIN, CREATE_COIN_ANNOUNCEMENT, ConditionOpcode, REMARK,
+    };
+    use chia_protocol::{Bytes, Bytes32};
+    use clvm_traits::FromClvm;
+    use clvmr::serde::node_from_bytes;
+    use rstest::rstest;
+
+    const PH: [u8; 32] = [0x22; 32];
+
+    fn coin() -> Coin {
+        Coin::new(Bytes32::from([0x11; 32]), Bytes32::from(PH), 1000)
+    }
+
+    #[rstest]
+    #[case(1000, 600, 100, Ok(300))]
+    #[case(1000, 600, 400, Ok(0))]
+    #[case(1000, 0, 0, Ok(1000))]
+    #[case(1000, 1000, 0, Ok(0))]
+    #[case(u64::MAX, 1, u64::MAX - 1, Ok(0))]
+    #[case(1000, 1001, 0, Err(ErrorCode::MintingCoin))]
+    #[case(0, 1, 0, Err(ErrorCode::MintingCoin))]
+    #[case(1000, 600, 401, Err(ErrorCode::ReserveFeeConditionFailed))]
+    #[case(1000, 1, u64::MAX, Err(ErrorCode::ReserveFeeConditionFailed))]
+    fn test_compute_change(
+        #[case] inputs_total: u64,
+        #[case] outputs_total: u64,
+        #[case] fee: u64,
+        #[case] expected: Result<u64, ErrorCode>,
+    ) {
+        assert_eq!(
+            compute_change(inputs_total, outputs_total, fee).map_err(|e| e.error_code()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_build_spend() {
+        let coin = coin();
+        let mut builder = SpendBuilder::new(&coin, MEMPOOL_MODE, &TEST_CONSTANTS).unwrap();
+        assert!(builder.is_empty());
+        builder
+            .add_condition((CREATE_COIN, (Bytes32::from(PH), (600_u64, ()))))
+            .expect("add_condition");
+        builder
+            .add_condition((CREATE_COIN, (Bytes32::from(PH), (400_u64, ()))))
+            .expect("add_condition");
+        builder
+            .add_condition((ASSERT_MY_AMOUNT, (1000_u64, ())))
+            .expect("add_condition");
+        assert_eq!(builder.len(), 3);
+
+        let (program, spend) = builder.finalize().expect("finalize");
+        assert_eq!(spend.coin_id, coin.coin_id());
+        assert_eq!(spend.coin_amount, 1000);
+        let mut create_coin = spend.create_coin;
+        create_coin.sort();
+        assert_eq!(
+            create_coin,
+            vec![
+                (Bytes32::from(PH), 400, None),
+                (Bytes32::from(PH), 600, None)
+            ]
+        );
+
+        // the condition list is serialized in the order it was built
+        let mut a = Allocator::new();
+        let list = node_from_bytes(&mut a, program.as_ref()).unwrap();
+        let conds = Vec::<(ConditionOpcode, NodePtr)>::from_clvm(&a, list).unwrap();
+        let ops: Vec<ConditionOpcode> = conds.iter().map(|c| c.0).collect();
+        assert_eq!(ops, vec![CREATE_COIN, CREATE_COIN, ASSERT_MY_AMOUNT]);
+    }
+
+    #[test]
+    fn test_reject_invalid_condition() {
+        let mut builder = SpendBuilder::new(&coin(), MEMPOOL_MODE, &TEST_CONSTANTS).unwrap();
+        builder.add_condition((REMARK, ())).expect("add_condition");
+
+        // the amount is not canonically encoded (it has a redundant leading
+        // zero)
+        let err = builder
+            .add_condition((
+                CREATE_COIN,
+                (Bytes32::from(PH), (Bytes::from(vec![0_u8, 1]), ())),
+            ))
+            .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::InvalidCoinAmount);
+        assert_eq!(builder.len(), 1);
+    }
+
+    #[test]
+    fn test_reject_wrong_amount() {
+        let mut builder = SpendBuilder::new(&coin(), MEMPOOL_MODE, &TEST_CONSTANTS).unwrap();
+        let err = builder
+            .add_condition((ASSERT_MY_AMOUNT, (999_u64, ())))
+            .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::AssertMyAmountFailed);
+        assert!(builder.is_empty());
+        // the builder can still be used after a failure
+        builder
+            .add_condition((ASSERT_MY_AMOUNT, (1000_u64, ())))
+            .expect("add_condition");
+    }
+
+    #[test]
+    fn test_reject_too_many_announcements() {
+        let mut builder =
+            SpendBuilder::new(&coin(), ConsensusFlags::empty(), &TEST_CONSTANTS).unwrap();
+        for i in 0..1024_u32 {
+            builder
+                .add_condition((CREATE_COIN_ANNOUNCEMENT, (i, ())))
+                .expect("add_condition");
+        }
+        let err = builder
+            .add_condition((CREATE_COIN_ANNOUNCEMENT, (1024_u32, ())))
+            .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::TooManyAnnouncements);
+        assert_eq!(builder.len(), 1024);
+    }
+}
@@ -0,0 +1,82 @@
+use chia_protocol::{Bytes32, Coin, Program};
+use chia_sha2::Sha256;
+use clvm_utils::tree_hash_from_bytes;
+
+// the tree hash is independent of how the program was serialized (e.g. with
+// or without back-references). A program that can't be parsed can't be part
+// of a valid spend, so it's hashed as-is.
+fn canonical_hash(program: &Program) -> [u8; 32] {
+    if let Ok(hash) = tree_hash_from_bytes(program.as_ref()) {
+        hash.to_bytes()
+    } else {
+        let mut hasher = Sha256::new();
+        hasher.update(program.as_ref());
+        hasher.finalize()
+    }
+}
+
+/// Computes a stable identifier for a single coin spend, for tracking spends
+/// in the mempool. The ID commits to the coin, the puzzle and the solution,
+/// but not to how the puzzle and solution are serialized. The signature is
+/// not included, since it may have been aggregated with other spends.
+pub fn spend_id(coin: &Coin, puzzle: &Program, solution: &Program) -> Bytes32 {
+    let mut hasher = Sha256::new();
+    hasher.update(coin.coin_id());
+    hasher.update(canonical_hash(puzzle));
+    hasher.update(canonical_hash(solution));
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clvmr::Allocator;
+    use clvmr::serde::{node_from_bytes, node_to_bytes, node_to_bytes_backrefs};
+    use hex_literal::hex;
+
+    fn coin(amount: u64) -> Coin {
+        Coin::new(
+            Bytes32::new([1; 32]),
+            Bytes32::new(hex!(
+                "4bf5122f344554c53bde2ebb8cd2b7e3d1600ad631c385a5d7cce23c7785459a"
+            )),
+            amount,
+        )
+    }
+
+    // (q . ((1 2 3) (1 2 3)))
+    const PUZZLE: &[u8] = &hex!("ff01ffff01ff02ff0380ffff01ff02ff038080");
+    // (())
+    const SOLUTION: &[u8] = &hex!("ff8080");
+
+    #[test]
+    fn test_spend_id_reserialized() {
+        let puzzle = Program::from(PUZZLE);
+        let solution = Program::from(SOLUTION);
+        let id = spend_id(&coin(123), &puzzle, &solution);
+
+        // the same spend always results in the same ID
+        assert_eq!(id, spend_id(&coin(123), &puzzle, &solution));
+
+        // re-serializing the puzzle, with and without back-references, results
+        // in the same ID
+        let mut a = Allocator::new();
+        let node = node_from_bytes(&mut a, PUZZLE).unwrap();
+        let plain = Program::from(node_to_bytes(&a, node).unwrap());
+        let backrefs = Program::from(node_to_bytes_backrefs(&a, node).unwrap());
+        assert_ne!(backrefs.as_ref(), PUZZLE);
+        assert_eq!(id, spend_id(&coin(123), &plain, &solution));
+        assert_eq!(id, spend_id(&coin(123), &backrefs, &solution));
+    }
+
+    #[test]
+    fn test_spend_id_differs() {
+        let puzzle = Program::from(PUZZLE);
+        let solution = Program::from(SOLUTION);
+        let id = spend_id(&coin(123), &puzzle, &solution);
+
+        assert_ne!(id, spend_id(&coin(124), &puzzle, &solution));
+        assert_ne!(id, spend_id(&coin(123), &solution, &puzzle));
+        assert_ne!(id, spend_id(&coin(123), &puzzle, &Program::default()));
+    }
+}
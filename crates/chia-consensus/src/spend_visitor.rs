@@ -5,6 +5,27 @@ use clvmr::allocator::Allocator;
 /// These are customization points for the condition parsing and validation. The
 /// mempool wants to record additional information than plain consensus
 /// validation, so it hooks into these.
+///
+/// parse_spends() (and the other functions parsing spends) are generic over
+/// the visitor. Two implementations are provided, `EmptyVisitor` (also known
+/// as `NullVisitor`), which does nothing, and `MempoolVisitor`, which
+/// determines which spends are eligible for dedup and fast-forward.
+///
+/// The callbacks are made in this order:
+///
+/// 1. For each spend, in the order they appear in the spend list:
+///    1. `new_spend()`, once the coin has been validated and its ID computed,
+///       but before any of its conditions are parsed. A new visitor is
+///       constructed for every spend.
+///    2. `condition()`, once per condition, in the order the puzzle output
+///       them, after the condition has been parsed but before it's applied to
+///       the spend. Unknown conditions that are ignored are not visited.
+///    3. `post_spend()`, once all conditions of the spend have been parsed.
+/// 2. `post_process()`, once, after all spends have been parsed, but before
+///    the block-level validation of the conditions (e.g. announcements and
+///    time-locks).
+///
+/// If parsing fails, no more callbacks are made.
 pub trait SpendVisitor {
     fn new_spend(spend: &mut SpendConditions) -> Self;
     fn condition(&mut self, spend: &mut SpendConditions, c: &Condition);
@@ -16,3 +37,95 @@ pub trait SpendVisitor {
         bundle: &mut SpendBundleConditions,
     ) -> Result<(), ValidationErr>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conditions::parse_spends;
+    use crate::consensus_constants::TEST_CONSTANTS;
+    use crate::flags::ConsensusFlags;
+    use crate::opcodes::CREATE_COIN;
+    use chia_bls::Signature;
+    use chia_protocol::Bytes32;
+    use clvm_traits::ToClvm;
+    use clvmr::NodePtr;
+    use std::cell::Cell;
+
+    thread_local! {
+        static SPENDS: Cell<usize> = const { Cell::new(0) };
+        static CREATE_COINS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // counts the CREATE_COIN conditions, per spend and in total
+    struct CreateCoinCounter {
+        create_coins: usize,
+    }
+
+    impl SpendVisitor for CreateCoinCounter {
+        fn new_spend(spend: &mut SpendConditions) -> Self {
+            assert!(spend.create_coin.is_empty());
+            SPENDS.set(SPENDS.get() + 1);
+            Self { create_coins: 0 }
+        }
+        fn condition(&mut self, _spend: &mut SpendConditions, c: &Condition) {
+            if matches!(c, Condition::CreateCoin(..)) {
+                self.create_coins += 1;
+            }
+        }
+        fn post_spend(&mut self, _a: &Allocator, spend: &mut SpendConditions) {
+            assert_eq!(spend.create_coin.len(), self.create_coins);
+            CREATE_COINS.set(CREATE_COINS.get() + self.create_coins);
+        }
+        fn post_process(
+            _a: &Allocator,
+            _state: &ParseState,
+            bundle: &mut SpendBundleConditions,
+        ) -> Result<(), ValidationErr> {
+            assert_eq!(SPENDS.get(), bundle.spends.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_create_coin_counter() {
+        let mut a = Allocator::new();
+        let puzzle_hash = Bytes32::from([0x22; 32]);
+
+        // spend i creates i coins
+        let mut spends = a.nil();
+        for i in 0..4_u8 {
+            let mut conditions = a.nil();
+            for amount in 0..u64::from(i) {
+                let cond = (CREATE_COIN, (puzzle_hash, (amount, ())))
+                    .to_clvm(&mut a)
+                    .unwrap();
+                conditions = a.new_pair(cond, conditions).unwrap();
+            }
+            let spend = (
+                Bytes32::from([i; 32]),
+                (puzzle_hash, (10_u64, (conditions, ()))),
+            )
+                .to_clvm(&mut a)
+                .unwrap();
+            spends = a.new_pair(spend, spends).unwrap();
+        }
+        let nil = a.nil();
+        let generator_output: NodePtr = a.new_pair(spends, nil).unwrap();
+
+        let conds = parse_spends::<CreateCoinCounter>(
+            &a,
+            generator_output,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            0,
+            ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("parse_spends");
+
+        assert_eq!(conds.spends.len(), 4);
+        assert_eq!(SPENDS.get(), 4);
+        assert_eq!(CREATE_COINS.get(), 1 + 2 + 3);
+    }
+}
@@ -1,19 +1,18 @@
 use crate::conditions::{
-    ELIGIBLE_FOR_DEDUP, MAX_SPENDS_PER_BLOCK, MempoolVisitor, ParseState, SpendBundleConditions,
-    process_single_spend, validate_conditions,
+    MAX_SPENDS_PER_BLOCK, MempoolVisitor, ParseState, SpendBundleConditions, SpendConditions,
+    compute_fingerprint, process_single_spend, validate_conditions,
 };
 use crate::consensus_constants::ConsensusConstants;
 use crate::flags::{ConsensusFlags, MEMPOOL_MODE};
 use crate::generator_cost::interned_vbytes;
-use crate::puzzle_fingerprint::compute_puzzle_fingerprint;
-use crate::run_block_generator::subtract_cost;
+use crate::run_block_generator::{subtract_byte_cost, subtract_cost};
 use crate::solution_generator::{build_generator, calculate_generator_length};
 use crate::spend_visitor::SpendVisitor;
 use crate::spendbundle_validation::get_flags_for_height_and_constants;
 use crate::validation_error::ErrorCode;
 use crate::validation_error::ValidationErr;
 use chia_bls::PublicKey;
-use chia_protocol::{Bytes, SpendBundle};
+use chia_protocol::{Bytes, CoinSpend, SpendBundle};
 
 use clvm_utils::tree_hash;
 use clvmr::allocator::Allocator;
@@ -91,7 +90,7 @@ pub fn run_spendbundle(
     let mut ret = SpendBundleConditions::default();
     let mut state = ParseState::default();
     let base_cost = calculate_base_cost(spend_bundle, flags, constants)?;
-    subtract_cost(&mut cost_left, base_cost)?;
+    subtract_byte_cost(&mut cost_left, base_cost)?;
 
     if flags.contains(ConsensusFlags::LIMIT_SPENDS)
         && spend_bundle.coin_spends.len() > MAX_SPENDS_PER_BLOCK
@@ -128,12 +127,7 @@ pub fn run_spendbundle(
             clvm_cost,
             constants,
         )?;
-
-        if (spend.flags & ELIGIBLE_FOR_DEDUP) != 0
-            && flags.contains(ConsensusFlags::COMPUTE_FINGERPRINT)
-        {
-            spend.fingerprint = compute_puzzle_fingerprint(a, conditions)?;
-        }
+        compute_fingerprint(a, spend, conditions, flags)?;
     }
 
     MempoolVisitor::post_process(a, &state, &mut ret)?;
@@ -144,6 +138,58 @@ pub fn run_spendbundle(
     Ok((ret, state.pkm_pairs))
 }
 
+/// Runs the puzzle of a single `spend` and parses its conditions, the same way
+/// they would be if the spend was the only one in a block. Conditions that
+/// refer to other spends (announcements, messages, concurrent spends) fail,
+/// just like they would in such a block. The signature is not validated. The
+/// returned conditions refer to nodes in `a`. Unlike a block, there's no cost
+/// for the size of the spend, `max_cost` only limits the cost of running the
+/// puzzle and of the conditions.
+pub fn validate_coin_spend(
+    a: &mut Allocator,
+    spend: &CoinSpend,
+    max_cost: u64,
+    flags: ConsensusFlags,
+    constants: &ConsensusConstants,
+) -> Result<SpendConditions, ValidationErr> {
+    let mut cost_left = max_cost;
+    let dialect = ChiaDialect::new(flags.to_clvm_flags());
+    let mut ret = SpendBundleConditions::default();
+    let mut state = ParseState::default();
+
+    let puz = node_from_bytes(a, spend.puzzle_reveal.as_slice())?;
+    let sol = node_from_bytes(a, spend.solution.as_slice())?;
+    let parent = a.new_atom(spend.coin.parent_coin_info.as_slice())?;
+    let amount = a.new_number(spend.coin.amount.into())?;
+    let Reduction(clvm_cost, conditions) = run_program(a, &dialect, puz, sol, cost_left)?;
+    subtract_cost(&mut cost_left, clvm_cost)?;
+
+    let buf = tree_hash(a, puz);
+    if spend.coin.puzzle_hash != buf.into() {
+        return Err(ValidationErr::Err(ErrorCode::WrongPuzzleHash));
+    }
+    let puzzle_hash = a.new_atom(&buf)?;
+    let ret_spend = process_single_spend::<MempoolVisitor>(
+        a,
+        &mut ret,
+        &mut state,
+        parent,
+        puzzle_hash,
+        amount,
+        conditions,
+        flags,
+        &mut cost_left,
+        clvm_cost,
+        constants,
+    )?;
+    compute_fingerprint(a, ret_spend, conditions, flags)?;
+
+    MempoolVisitor::post_process(a, &state, &mut ret)?;
+    validate_conditions(a, &ret, &state, flags)?;
+
+    Ok(ret.spends.pop().expect("one spend"))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::consensus_constants::TEST_CONSTANTS;
@@ -165,7 +211,9 @@ mod tests {
     fn assert_run_spendbundle_matches_parse_spends(spend_bundle: &SpendBundle) {
         use crate::conditions::parse_spends;
 
-        let flags = MEMPOOL_MODE | ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+        let flags = MEMPOOL_MODE
+            | ConsensusFlags::DONT_VALIDATE_SIGNATURE
+            | ConsensusFlags::COMPUTE_FINGERPRINT;
 
         let mut a1 = make_allocator(ConsensusFlags::LIMIT_HEAP);
         let (sb_conds, _) = run_spendbundle(
@@ -223,6 +271,10 @@ mod tests {
             ps_conds.spends.len(),
             "number of spends differ"
         );
+        assert_eq!(
+            sb_conds.softfork_cost, ps_conds.softfork_cost,
+            "softfork cost differ"
+        );
         for (i, (s1, s2)) in sb_conds
             .spends
             .iter()
@@ -234,6 +286,10 @@ mod tests {
                 "spend {i} flags differ: run_spendbundle={:#x}, parse_spends={:#x}",
                 s1.flags, s2.flags
             );
+            assert_eq!(
+                s1.fingerprint, s2.fingerprint,
+                "spend {i} fingerprint differ"
+            );
         }
     }
 
@@ -401,6 +457,98 @@ mod tests {
         node_to_bytes(&a2, list).unwrap()
     }
 
+    #[rstest]
+    #[case("none", None)]
+    #[case("height-relative", None)]
+    #[case("create-announcement", None)]
+    // the spend doesn't leave anything for the fee
+    #[case("reserve-fee", Some(ErrorCode::ReserveFeeConditionFailed))]
+    #[case("wrong-amount", Some(ErrorCode::AssertMyAmountFailed))]
+    #[case("missing-announcement", Some(ErrorCode::AssertCoinAnnouncementFailed))]
+    fn test_validate_coin_spend(
+        #[case] extra: &str,
+        #[case] expected_err: Option<ErrorCode>,
+        #[values(ConsensusFlags::empty(), MEMPOOL_MODE)] flags: ConsensusFlags,
+    ) {
+        use crate::opcodes::{
+            ASSERT_COIN_ANNOUNCEMENT, ASSERT_HEIGHT_RELATIVE, ASSERT_MY_AMOUNT,
+            CREATE_COIN_ANNOUNCEMENT, RESERVE_FEE,
+        };
+        use crate::owned_conditions::OwnedSpendConditions;
+
+        let mut a = Allocator::new();
+        let extra_condition = match extra {
+            "none" => None,
+            "height-relative" => {
+                let height = a.new_small_number(10).unwrap();
+                Some(serialize_condition(ASSERT_HEIGHT_RELATIVE, &[height], &a))
+            }
+            "create-announcement" => {
+                let msg = a.new_atom(b"hello").unwrap();
+                Some(serialize_condition(CREATE_COIN_ANNOUNCEMENT, &[msg], &a))
+            }
+            "reserve-fee" => {
+                let fee = a.new_small_number(100).unwrap();
+                Some(serialize_condition(RESERVE_FEE, &[fee], &a))
+            }
+            "wrong-amount" => {
+                let amount = a.new_small_number(124).unwrap();
+                Some(serialize_condition(ASSERT_MY_AMOUNT, &[amount], &a))
+            }
+            "missing-announcement" => {
+                let id = a.new_atom(&[0x42; 32]).unwrap();
+                Some(serialize_condition(ASSERT_COIN_ANNOUNCEMENT, &[id], &a))
+            }
+            _ => panic!("unknown case {extra}"),
+        };
+        let extra_conditions: Vec<&[u8]> = extra_condition.iter().map(Vec::as_slice).collect();
+        let spend = make_coin_spend([1u8; 32], 123, &extra_conditions);
+        let flags = flags | ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+
+        let mut a1 = make_allocator(ConsensusFlags::LIMIT_HEAP);
+        let result = validate_coin_spend(&mut a1, &spend, 11_000_000_000, flags, &TEST_CONSTANTS);
+
+        // the same spend, as the only one in a block
+        let generator = solution_generator([(
+            spend.coin,
+            spend.puzzle_reveal.as_slice(),
+            spend.solution.as_slice(),
+        )])
+        .expect("solution_generator");
+        let blocks: &[&[u8]] = &[];
+        let block_result = run_block_generator2(
+            &generator,
+            blocks,
+            11_000_000_000,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        );
+
+        match (expected_err, result, block_result) {
+            (Some(err), Err(e1), Err(e2)) => {
+                assert_eq!(e1.error_code(), err);
+                assert_eq!(e2.error_code(), err);
+            }
+            (None, Ok(single), Ok((a2, block))) => {
+                assert_eq!(*single.coin_id, spend.coin.coin_id());
+                let mut single = OwnedSpendConditions::from(&a1, single);
+                let mut block = OwnedSpendConditions::from(&a2, block.spends[0].clone());
+                // the flags and fingerprint depend on which visitor parsed the
+                // conditions
+                single.flags = 0;
+                block.flags = 0;
+                single.fingerprint = Bytes::default();
+                block.fingerprint = Bytes::default();
+                assert_eq!(single, block);
+            }
+            (_, result, block_result) => {
+                panic!("mismatch: {:?} {:?}", result.err(), block_result.err());
+            }
+        }
+    }
+
     #[test]
     fn test_post_process_single_ff_eligible_spend() {
         let spend_a = make_coin_spend([1u8; 32], 123, &[]);
@@ -480,6 +628,38 @@ mod tests {
         assert_run_spendbundle_matches_parse_spends(&bundle);
     }
 
+    #[rstest]
+    fn test_fingerprints(#[values(false, true)] compute_fingerprint: bool) {
+        use chia_bls::SecretKey;
+
+        let spend_a = make_coin_spend([1u8; 32], 123, &[]);
+
+        // AGG_SIG_UNSAFE makes a spend ineligible for dedup
+        let mut a = Allocator::new();
+        let pk = SecretKey::from_seed(&[1; 32]).public_key();
+        let pk_node = a.new_atom(&pk.to_bytes()).unwrap();
+        let msg_node = a.new_atom(b"foobar").unwrap();
+        let agg_sig = serialize_condition(crate::opcodes::AGG_SIG_UNSAFE, &[pk_node, msg_node], &a);
+        let spend_b = make_coin_spend([2u8; 32], 123, &[&agg_sig]);
+
+        let bundle = SpendBundle::new(vec![spend_a, spend_b], Signature::default());
+        let mut alloc = make_allocator(ConsensusFlags::LIMIT_HEAP);
+        let mut flags = MEMPOOL_MODE | ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+        if compute_fingerprint {
+            flags |= ConsensusFlags::COMPUTE_FINGERPRINT;
+        }
+        let (conds, _) =
+            run_spendbundle(&mut alloc, &bundle, 11_000_000_000, flags, &TEST_CONSTANTS)
+                .expect("run_spendbundle");
+
+        assert_eq!(conds.spends.len(), 2);
+        assert_ne!(conds.spends[0].flags & ELIGIBLE_FOR_DEDUP, 0);
+        assert_eq!(conds.spends[1].flags & ELIGIBLE_FOR_DEDUP, 0);
+        // only spends eligible for dedup have a fingerprint
+        assert_eq!(conds.spends[0].fingerprint != [0; 32], compute_fingerprint);
+        assert_eq!(conds.spends[1].fingerprint, [0; 32]);
+    }
+
     // given a block generator and block-refs, convert run the generator to
     // produce the SpendBundle for the block without runningi, or validating,
     // the puzzles.
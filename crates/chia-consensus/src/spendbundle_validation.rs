@@ -1,13 +1,19 @@
 use crate::allocator::make_allocator;
 use crate::consensus_constants::ConsensusConstants;
 use crate::flags::ConsensusFlags;
+use crate::make_aggsig_final_message::{check_agg_sig_me_coin_id, make_aggsig_final_message};
+use crate::opcodes::{
+    AGG_SIG_AMOUNT, AGG_SIG_ME, AGG_SIG_PARENT, AGG_SIG_PARENT_AMOUNT, AGG_SIG_PARENT_PUZZLE,
+    AGG_SIG_PUZZLE, AGG_SIG_PUZZLE_AMOUNT,
+};
 use crate::owned_conditions::OwnedSpendBundleConditions;
 use crate::spendbundle_conditions::run_spendbundle;
 use crate::validation_error::{ErrorCode, ValidationErr};
 use chia_bls::GTElement;
-use chia_bls::{aggregate_verify_gt, hash_to_g2};
-use chia_protocol::SpendBundle;
+use chia_bls::{PublicKey, Signature, aggregate_verify, aggregate_verify_gt, hash_to_g2};
+use chia_protocol::{Bytes, SpendBundle};
 use chia_sha2::Sha256;
+use std::collections::HashSet;
 
 // type definition makes clippy happy
 pub type ValidationPair = ([u8; 32], GTElement);
@@ -23,7 +29,7 @@ pub fn validate_clvm_and_signature(
 ) -> Result<(OwnedSpendBundleConditions, Vec<ValidationPair>), ValidationErr> {
     let mut a = make_allocator(ConsensusFlags::LIMIT_HEAP);
     let (sbc, pkm_pairs) = run_spendbundle(&mut a, spend_bundle, max_cost, flags, constants)?;
-    let conditions = OwnedSpendBundleConditions::from(&a, sbc);
+    let mut conditions = OwnedSpendBundleConditions::from(&a, sbc);
 
     // Collect all pairs in a single vector to avoid multiple iterations
     let mut pairs = Vec::new();
@@ -49,11 +55,113 @@ pub fn validate_clvm_and_signature(
     if !result {
         return Err(ValidationErr::Err(ErrorCode::BadAggregateSignature));
     }
+    // run_spendbundle() leaves the signature validation to us
+    conditions.validated_signature = true;
 
     // Collect results
     Ok((conditions, pairs))
 }
 
+/// Validates `signature` against the AGG_SIG_* conditions in `conditions`,
+/// without running any puzzles. This is meant for conditions that were
+/// computed earlier (e.g. with `DONT_VALIDATE_SIGNATURE`) and cached. The
+/// messages to be signed are reconstructed from the conditions and
+/// `constants`. Fails with AggSigMeCoinIdMismatch if the coin ID of a spend
+/// with AGG_SIG_ME conditions doesn't match its parent, puzzle hash and amount.
+pub fn validate_signatures_only(
+    conditions: &OwnedSpendBundleConditions,
+    signature: &Signature,
+    constants: &ConsensusConstants,
+) -> Result<(), ValidationErr> {
+    let mut pairs = Vec::<(PublicKey, Vec<u8>)>::new();
+    for (pk, msg) in &conditions.agg_sig_unsafe {
+        pairs.push((*pk, msg.to_vec()));
+    }
+    for spend in &conditions.spends {
+        check_agg_sig_me_coin_id(spend)?;
+        for (opcode, agg_sigs) in [
+            (AGG_SIG_PARENT, &spend.agg_sig_parent),
+            (AGG_SIG_PUZZLE, &spend.agg_sig_puzzle),
+            (AGG_SIG_AMOUNT, &spend.agg_sig_amount),
+            (AGG_SIG_PUZZLE_AMOUNT, &spend.agg_sig_puzzle_amount),
+            (AGG_SIG_PARENT_AMOUNT, &spend.agg_sig_parent_amount),
+            (AGG_SIG_PARENT_PUZZLE, &spend.agg_sig_parent_puzzle),
+            (AGG_SIG_ME, &spend.agg_sig_me),
+        ] {
+            for (pk, msg) in agg_sigs {
+                let mut msg = msg.to_vec();
+                make_aggsig_final_message(opcode, &mut msg, spend, constants);
+                pairs.push((*pk, msg));
+            }
+        }
+    }
+    if !aggregate_verify(
+        signature,
+        pairs.iter().map(|(pk, msg)| (pk, msg.as_slice())),
+    ) {
+        return Err(ValidationErr::Err(ErrorCode::BadAggregateSignature));
+    }
+    Ok(())
+}
+
+/// Re-checks previously validated `conditions` in the context of a new chain,
+/// after a reorg. The new chain may be on a network with different
+/// `new_constants` (e.g. a different genesis challenge), and the bundle would
+/// be included in a block whose previous transaction block is at
+/// `new_height`. `signature` is validated against the messages as computed
+/// with `new_constants`, and the absolute height time-locks are checked
+/// against `new_height`. Relative time-locks and time-locks on timestamps
+/// depend on coin records and block timestamps, and are checked by
+/// check_time_locks().
+pub fn revalidate_after_reorg(
+    conditions: &OwnedSpendBundleConditions,
+    signature: &Signature,
+    new_constants: &ConsensusConstants,
+    new_height: u32,
+) -> Result<(), ValidationErr> {
+    if new_height < conditions.height_absolute {
+        return Err(ValidationErr::Err(ErrorCode::AssertHeightAbsoluteFailed));
+    }
+    if let Some(before_height_absolute) = conditions.before_height_absolute {
+        if new_height >= before_height_absolute {
+            return Err(ValidationErr::Err(
+                ErrorCode::AssertBeforeHeightAbsoluteFailed,
+            ));
+        }
+    }
+    validate_signatures_only(conditions, signature, new_constants)
+}
+
+/// Debug check that the AGG_SIG_ME messages in `pkm_pairs`, as returned by
+/// `run_spendbundle()`, were computed with the same genesis challenge
+/// (`agg_sig_me_additional_data`) as `constants`. Every AGG_SIG_ME condition in
+/// `conditions` is expected to have a matching public key and message pair.
+/// Returns false if any of them is missing, or if the coin ID of a spend doesn't
+/// match its parent, puzzle hash and amount.
+pub fn agg_sig_me_matches_constants(
+    conditions: &OwnedSpendBundleConditions,
+    pkm_pairs: &[(PublicKey, Bytes)],
+    constants: &ConsensusConstants,
+) -> bool {
+    let pairs: HashSet<(&PublicKey, &[u8])> = pkm_pairs
+        .iter()
+        .map(|(pk, msg)| (pk, msg.as_slice()))
+        .collect();
+    for spend in &conditions.spends {
+        if check_agg_sig_me_coin_id(spend).is_err() {
+            return false;
+        }
+        for (pk, msg) in &spend.agg_sig_me {
+            let mut msg = msg.to_vec();
+            make_aggsig_final_message(AGG_SIG_ME, &mut msg, spend, constants);
+            if !pairs.contains(&(pk, msg.as_slice())) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
 /// The prev_tx_height is the previous transaction block height of the most
 /// recent transaction block prior / to the signage point index of the current
 /// block. (i.e. not necessarily the / transaction block preceeding the current
@@ -109,12 +217,9 @@ mod tests {
     use crate::consensus_constants::TEST_CONSTANTS;
     use crate::flags::MEMPOOL_MODE;
     use crate::make_aggsig_final_message::u64_to_bytes;
-    use crate::opcodes::{
-        AGG_SIG_AMOUNT, AGG_SIG_ME, AGG_SIG_PARENT, AGG_SIG_PARENT_AMOUNT, AGG_SIG_PARENT_PUZZLE,
-        AGG_SIG_PUZZLE, AGG_SIG_PUZZLE_AMOUNT, AGG_SIG_UNSAFE, ConditionOpcode,
-    };
-    use chia_bls::{G2Element, PublicKey, SecretKey, Signature, sign};
-    use chia_protocol::{Bytes, Coin, CoinSpend, Program};
+    use crate::opcodes::{AGG_SIG_UNSAFE, ConditionOpcode};
+    use chia_bls::{G2Element, SecretKey, sign};
+    use chia_protocol::{Bytes32, Coin, CoinSpend, Program};
     use clvm_utils::tree_hash_atom;
     use hex::FromHex;
     use hex_literal::hex;
@@ -223,6 +328,50 @@ mod tests {
         );
     }
 
+    #[rstest]
+    fn test_get_flags_fork_boundary(
+        #[values(1, 100, 5_000_000)] fork_height: u32,
+        #[values(
+            ConsensusFlags::COST_CONDITIONS,
+            ConsensusFlags::DISABLE_OP,
+            ConsensusFlags::LIMIT_SPENDS
+        )]
+        fork_flag: ConsensusFlags,
+    ) {
+        // the fork activates once the previous transaction block height reaches
+        // the fork height. Not one block before, and not one block after
+        let mut constants = TEST_CONSTANTS.clone();
+        if fork_flag == ConsensusFlags::COST_CONDITIONS {
+            constants.hard_fork2_height = fork_height;
+        } else if fork_flag == ConsensusFlags::DISABLE_OP {
+            constants.soft_fork8_height = fork_height;
+        } else {
+            constants.soft_fork9_height = fork_height;
+        }
+
+        let flags = |height| get_flags_for_height_and_constants(height, &constants);
+        assert!(!flags(fork_height - 1).contains(fork_flag));
+        assert!(flags(fork_height).contains(fork_flag));
+        assert!(flags(fork_height + 1).contains(fork_flag));
+    }
+
+    #[test]
+    fn test_get_flags_hard_fork2_boundary() {
+        let mut constants = TEST_CONSTANTS.clone();
+        constants.hard_fork2_height = 6_000_000;
+        let hard_fork2_flags = ConsensusFlags::ENABLE_KECCAK_OPS_OUTSIDE_GUARD
+            | ConsensusFlags::COST_CONDITIONS
+            | ConsensusFlags::ENABLE_SECP_OPS
+            | ConsensusFlags::RELAXED_BLS;
+
+        let pre_fork = get_flags_for_height_and_constants(5_999_999, &constants);
+        let post_fork = get_flags_for_height_and_constants(6_000_000, &constants);
+        assert!(!pre_fork.intersects(hard_fork2_flags));
+        assert!(post_fork.contains(hard_fork2_flags));
+        // nothing else changes at the boundary
+        assert_eq!(post_fork - hard_fork2_flags, pre_fork);
+    }
+
     #[test]
     fn test_invalid_puzzle_hash() {
         let solution = hex!(
@@ -275,13 +424,49 @@ ff01\
             coin_spends: vec![spend],
             aggregated_signature: Signature::default(),
         };
-        validate_clvm_and_signature(
+        let (conds, _) = validate_clvm_and_signature(
             &spend_bundle,
             TEST_CONSTANTS.max_block_cost_clvm,
             &TEST_CONSTANTS,
             MEMPOOL_MODE,
         )
         .expect("SpendBundle should be valid for this test");
+        assert!(conds.validated_signature);
+    }
+
+    #[test]
+    fn test_agg_sig_me_matches_constants() {
+        let (pk, _sk) = keys();
+        let solution = mk_agg_sig_solution(AGG_SIG_ME, &pk);
+        let spend_bundle = SpendBundle {
+            coin_spends: vec![mk_spend(&[1_u8], &solution)],
+            aggregated_signature: Signature::default(),
+        };
+        let mut a = make_allocator(ConsensusFlags::LIMIT_HEAP);
+        let (sbc, pkm_pairs) = run_spendbundle(
+            &mut a,
+            &spend_bundle,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            MEMPOOL_MODE,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_spendbundle");
+        let conditions = OwnedSpendBundleConditions::from(&a, sbc);
+        assert_eq!(conditions.spends[0].agg_sig_me.len(), 1);
+        assert!(agg_sig_me_matches_constants(
+            &conditions,
+            &pkm_pairs,
+            &TEST_CONSTANTS
+        ));
+
+        // a different network's constants
+        let mut other_constants = TEST_CONSTANTS.clone();
+        other_constants.agg_sig_me_additional_data = Bytes32::from([0x42; 32]);
+        assert!(!agg_sig_me_matches_constants(
+            &conditions,
+            &pkm_pairs,
+            &other_constants
+        ));
     }
 
     #[test]
@@ -336,7 +521,7 @@ ff843B9ACA00\
             validate_clvm_and_signature(&spend_bundle, max_cost - 1, &TEST_CONSTANTS, MEMPOOL_MODE);
         assert!(matches!(
             result,
-            Err(ValidationErr::Err(ErrorCode::CostExceeded))
+            Err(ValidationErr::Err(ErrorCode::ConditionCostExceeded))
         ));
     }
 
@@ -374,6 +559,153 @@ ff843B9ACA00\
         .expect("SpendBundle should be valid for this test");
     }
 
+    #[rstest]
+    fn test_validate_signatures_only(
+        #[values(
+            AGG_SIG_AMOUNT,
+            AGG_SIG_PARENT,
+            AGG_SIG_PUZZLE,
+            AGG_SIG_PUZZLE_AMOUNT,
+            AGG_SIG_PARENT_AMOUNT,
+            AGG_SIG_PARENT_PUZZLE,
+            AGG_SIG_UNSAFE,
+            AGG_SIG_ME
+        )]
+        cond: ConditionOpcode,
+    ) {
+        let (pk, sk) = keys();
+        let solution = mk_agg_sig_solution(cond, &pk);
+        let spend = mk_spend(&[1_u8], &solution);
+        let sig = mk_agg_sig(cond, &sk, &spend, b"hello");
+        let spend_bundle = SpendBundle {
+            coin_spends: vec![spend.clone()],
+            aggregated_signature: sig.clone(),
+        };
+        // the cached conditions
+        let (conditions, _) = validate_clvm_and_signature(
+            &spend_bundle,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            &TEST_CONSTANTS,
+            MEMPOOL_MODE,
+        )
+        .expect("SpendBundle should be valid for this test");
+
+        validate_signatures_only(&conditions, &sig, &TEST_CONSTANTS)
+            .expect("signature should be valid");
+
+        // signing a different message
+        let tampered = mk_agg_sig(cond, &sk, &spend, b"goodbye");
+        assert_eq!(
+            validate_signatures_only(&conditions, &tampered, &TEST_CONSTANTS),
+            Err(ValidationErr::Err(ErrorCode::BadAggregateSignature))
+        );
+        assert_eq!(
+            validate_signatures_only(&conditions, &Signature::default(), &TEST_CONSTANTS),
+            Err(ValidationErr::Err(ErrorCode::BadAggregateSignature))
+        );
+    }
+
+    #[test]
+    fn test_agg_sig_me_coin_id() {
+        let (pk, sk) = keys();
+        let solution = mk_agg_sig_solution(AGG_SIG_ME, &pk);
+        let spend = mk_spend(&[1_u8], &solution);
+        let sig = mk_agg_sig(AGG_SIG_ME, &sk, &spend, b"hello");
+        let spend_bundle = SpendBundle {
+            coin_spends: vec![spend.clone()],
+            aggregated_signature: sig.clone(),
+        };
+        let mut a = make_allocator(ConsensusFlags::LIMIT_HEAP);
+        let (sbc, pkm_pairs) = run_spendbundle(
+            &mut a,
+            &spend_bundle,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            MEMPOOL_MODE,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_spendbundle");
+        let mut conditions = OwnedSpendBundleConditions::from(&a, sbc);
+        validate_signatures_only(&conditions, &sig, &TEST_CONSTANTS)
+            .expect("signature should be valid");
+
+        let coin_id = spend.coin.coin_id();
+        assert_eq!(conditions.spends[0].coin_id, coin_id);
+        check_agg_sig_me_coin_id(&conditions.spends[0]).expect("coin id should match");
+
+        // the message signed is "hello" + coin ID + agg_sig_me_additional_data
+        let mut expected = b"hello".to_vec();
+        expected.extend(coin_id.as_slice());
+        expected.extend(TEST_CONSTANTS.agg_sig_me_additional_data.as_slice());
+        assert_eq!(pkm_pairs, vec![(pk, Bytes::from(expected))]);
+
+        // a coin ID that's not the ID of the spent coin
+        conditions.spends[0].coin_id = Bytes32::from([0x42; 32]);
+        assert_eq!(
+            check_agg_sig_me_coin_id(&conditions.spends[0]),
+            Err(ValidationErr::Spend(
+                ErrorCode::AggSigMeCoinIdMismatch,
+                Bytes32::from([0x42; 32])
+            ))
+        );
+        assert_eq!(
+            validate_signatures_only(&conditions, &sig, &TEST_CONSTANTS),
+            Err(ValidationErr::Spend(
+                ErrorCode::AggSigMeCoinIdMismatch,
+                Bytes32::from([0x42; 32])
+            ))
+        );
+        assert!(!agg_sig_me_matches_constants(
+            &conditions,
+            &pkm_pairs,
+            &TEST_CONSTANTS
+        ));
+    }
+
+    #[test]
+    fn test_revalidate_after_reorg() {
+        let (pk, sk) = keys();
+        let solution = mk_agg_sig_solution(AGG_SIG_ME, &pk);
+        let spend = mk_spend(&[1_u8], &solution);
+        let sig = mk_agg_sig(AGG_SIG_ME, &sk, &spend, b"hello");
+        let spend_bundle = SpendBundle {
+            coin_spends: vec![spend],
+            aggregated_signature: sig.clone(),
+        };
+        let (mut conditions, _) = validate_clvm_and_signature(
+            &spend_bundle,
+            TEST_CONSTANTS.max_block_cost_clvm,
+            &TEST_CONSTANTS,
+            MEMPOOL_MODE,
+        )
+        .expect("SpendBundle should be valid for this test");
+        conditions.height_absolute = 100;
+        conditions.before_height_absolute = Some(200);
+
+        // the bundle is still valid after a reorg on the same network, as
+        // long as the time-locks are satisfied
+        revalidate_after_reorg(&conditions, &sig, &TEST_CONSTANTS, 100).expect("valid");
+        revalidate_after_reorg(&conditions, &sig, &TEST_CONSTANTS, 199).expect("valid");
+        assert_eq!(
+            revalidate_after_reorg(&conditions, &sig, &TEST_CONSTANTS, 99),
+            Err(ValidationErr::Err(ErrorCode::AssertHeightAbsoluteFailed))
+        );
+        assert_eq!(
+            revalidate_after_reorg(&conditions, &sig, &TEST_CONSTANTS, 200),
+            Err(ValidationErr::Err(
+                ErrorCode::AssertBeforeHeightAbsoluteFailed
+            ))
+        );
+
+        // the new chain has a different genesis challenge, so AGG_SIG_ME
+        // signs a different message
+        let mut new_constants = TEST_CONSTANTS.clone();
+        new_constants.agg_sig_me_additional_data = Bytes32::from([0x42; 32]);
+        assert_eq!(
+            revalidate_after_reorg(&conditions, &sig, &new_constants, 150),
+            Err(ValidationErr::Err(ErrorCode::BadAggregateSignature))
+        );
+    }
+
     #[rstest]
     fn test_failures(
         #[values(
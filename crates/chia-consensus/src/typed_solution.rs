@@ -0,0 +1,267 @@
+use crate::error::Result;
+use crate::validation_error::{ErrorCode, ValidationErr};
+use chia_bls::PublicKey;
+use chia_protocol::{CoinSpend, Program};
+use chia_puzzle_types::singleton::{SingletonArgs, SingletonSolution, SingletonStruct};
+use chia_puzzle_types::standard::{StandardArgs, StandardSolution};
+use chia_puzzles::{P2_DELEGATED_PUZZLE_OR_HIDDEN_PUZZLE_HASH, SINGLETON_TOP_LAYER_V1_1_HASH};
+use clvm_traits::{
+    FromClvm, MatchByte, destructure_list, destructure_quote, match_list, match_quote,
+};
+use clvm_utils::{CurriedProgram, tree_hash};
+use clvmr::allocator::{Allocator, NodePtr};
+use clvmr::serde::node_from_bytes_backrefs;
+
+/// The puzzle and solution of a spend of a recognized puzzle, parsed into
+/// their components.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypedSolution {
+    /// p2_delegated_puzzle_or_hidden_puzzle, the standard puzzle
+    Standard {
+        synthetic_key: PublicKey,
+        solution: StandardSolution<Program, Program>,
+    },
+    /// singleton_top_layer_v1_1
+    Singleton {
+        singleton_struct: SingletonStruct,
+        inner_puzzle: Program,
+        solution: SingletonSolution<Program>,
+    },
+}
+
+/// Recognizes the puzzle of `coin_spend` and parses its solution according to
+/// the puzzle's known structure. Returns `None` if the puzzle isn't
+/// recognized. Fails if the puzzle is recognized but the solution doesn't
+/// match its structure.
+pub fn typed_solution(coin_spend: &CoinSpend) -> Result<Option<TypedSolution>> {
+    let mut a = Allocator::new();
+    let puzzle = node_from_bytes_backrefs(&mut a, coin_spend.puzzle_reveal.as_ref())?;
+    let solution = node_from_bytes_backrefs(&mut a, coin_spend.solution.as_ref())?;
+
+    if let Ok(standard) = CurriedProgram::<NodePtr, StandardArgs>::from_clvm(&a, puzzle) {
+        if tree_hash(&a, standard.program) == P2_DELEGATED_PUZZLE_OR_HIDDEN_PUZZLE_HASH.into() {
+            return Ok(Some(TypedSolution::Standard {
+                synthetic_key: standard.args.synthetic_key,
+                solution: StandardSolution::from_clvm(&a, solution)?,
+            }));
+        }
+    }
+
+    if let Ok(singleton) = CurriedProgram::<NodePtr, SingletonArgs<Program>>::from_clvm(&a, puzzle)
+    {
+        if singleton.args.singleton_struct.mod_hash == SINGLETON_TOP_LAYER_V1_1_HASH.into()
+            && tree_hash(&a, singleton.program) == SINGLETON_TOP_LAYER_V1_1_HASH.into()
+        {
+            return Ok(Some(TypedSolution::Singleton {
+                singleton_struct: singleton.args.singleton_struct,
+                inner_puzzle: singleton.args.inner_puzzle,
+                solution: SingletonSolution::from_clvm(&a, solution)?,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Uncurries `puzzle` and checks that its curried arguments are `expected`,
+/// in order. Fails with CurriedArgsMismatch if the puzzle isn't curried, or if
+/// its arguments are different. Arguments are compared by tree hash.
+pub fn check_curried_args(
+    puzzle: &Program,
+    expected: &[Program],
+) -> std::result::Result<(), ValidationErr> {
+    let mismatch = || ValidationErr::Err(ErrorCode::CurriedArgsMismatch);
+    let mut a = Allocator::new();
+    let puzzle = node_from_bytes_backrefs(&mut a, puzzle.as_ref())?;
+    let curried =
+        CurriedProgram::<NodePtr, NodePtr>::from_clvm(&a, puzzle).map_err(|_| mismatch())?;
+
+    // the curried arguments are in the form:
+    // (c (q . arg1) (c (q . arg2) 1))
+    let mut args = curried.args;
+    for expected_arg in expected {
+        let destructure_list!(_, destructure_quote!(arg), rest) =
+            <match_list!(MatchByte<4>, match_quote!(NodePtr), NodePtr)>::from_clvm(&a, args)
+                .map_err(|_| mismatch())?;
+        let expected_arg = node_from_bytes_backrefs(&mut a, expected_arg.as_ref())?;
+        if tree_hash(&a, arg) != tree_hash(&a, expected_arg) {
+            return Err(mismatch());
+        }
+        args = rest;
+    }
+    if MatchByte::<1>::from_clvm(&a, args).is_err() {
+        // there are more curried arguments than expected
+        return Err(mismatch());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::opcodes::CREATE_COIN;
+    use chia_bls::SecretKey;
+    use chia_protocol::{Bytes32, Coin};
+    use chia_puzzle_types::{LineageProof, Proof};
+    use chia_puzzles::{P2_DELEGATED_PUZZLE_OR_HIDDEN_PUZZLE, SINGLETON_TOP_LAYER_V1_1};
+    use clvm_traits::{ToClvm, clvm_curried_args, clvm_quote};
+    use clvmr::serde::node_to_bytes;
+
+    fn mk_spend<P: ToClvm<Allocator>, S: ToClvm<Allocator>>(puzzle: P, solution: S) -> CoinSpend {
+        let mut a = Allocator::new();
+        let puzzle = puzzle.to_clvm(&mut a).unwrap();
+        let solution = solution.to_clvm(&mut a).unwrap();
+        CoinSpend::new(
+            Coin::new(Bytes32::from([0x11; 32]), tree_hash(&a, puzzle).into(), 1),
+            node_to_bytes(&a, puzzle).unwrap().into(),
+            node_to_bytes(&a, solution).unwrap().into(),
+        )
+    }
+
+    fn to_program<T: ToClvm<Allocator>>(value: T) -> Program {
+        let mut a = Allocator::new();
+        let node = value.to_clvm(&mut a).unwrap();
+        node_to_bytes(&a, node).unwrap().into()
+    }
+
+    #[test]
+    fn test_standard_spend() {
+        let synthetic_key = SecretKey::from_seed(&[1; 32]).public_key();
+        let conditions = [(CREATE_COIN, (Bytes32::from([0x22; 32]), (1_u64, ())))];
+        let puzzle = CurriedProgram {
+            program: Program::from(P2_DELEGATED_PUZZLE_OR_HIDDEN_PUZZLE.to_vec()),
+            args: StandardArgs::new(synthetic_key),
+        };
+        let spend = mk_spend(puzzle, StandardSolution::from_conditions(conditions));
+
+        let Some(TypedSolution::Standard {
+            synthetic_key: key,
+            solution,
+        }) = typed_solution(&spend).expect("typed_solution")
+        else {
+            panic!("expected a standard spend");
+        };
+        assert_eq!(key, synthetic_key);
+        assert_eq!(solution.original_public_key, None);
+        assert_eq!(
+            solution.delegated_puzzle,
+            to_program(clvm_quote!(conditions))
+        );
+        assert_eq!(solution.solution, to_program(()));
+    }
+
+    #[test]
+    fn test_standard_spend_invalid_solution() {
+        let puzzle = CurriedProgram {
+            program: Program::from(P2_DELEGATED_PUZZLE_OR_HIDDEN_PUZZLE.to_vec()),
+            args: StandardArgs::new(PublicKey::default()),
+        };
+        // the solution is not a list of 3 items
+        let spend = mk_spend(puzzle, (1, ()));
+        assert!(matches!(typed_solution(&spend), Err(Error::FromClvm(_))));
+    }
+
+    #[test]
+    fn test_singleton_spend() {
+        let singleton_struct = SingletonStruct::new(Bytes32::from([0x33; 32]));
+        let inner_puzzle = to_program(1);
+        let puzzle = CurriedProgram {
+            program: Program::from(SINGLETON_TOP_LAYER_V1_1.to_vec()),
+            args: SingletonArgs::new(Bytes32::from([0x33; 32]), inner_puzzle.clone()),
+        };
+        let solution = SingletonSolution {
+            lineage_proof: Proof::Lineage(LineageProof {
+                parent_parent_coin_info: Bytes32::from([0x44; 32]),
+                parent_inner_puzzle_hash: Bytes32::from([0x55; 32]),
+                parent_amount: 1,
+            }),
+            amount: 1,
+            inner_solution: to_program(()),
+        };
+        let spend = mk_spend(puzzle, solution.clone());
+
+        assert_eq!(
+            typed_solution(&spend).expect("typed_solution"),
+            Some(TypedSolution::Singleton {
+                singleton_struct,
+                inner_puzzle,
+                solution,
+            })
+        );
+    }
+
+    #[test]
+    fn test_unknown_puzzle() {
+        let spend = mk_spend(1, ());
+        assert_eq!(typed_solution(&spend).expect("typed_solution"), None);
+
+        // curried like the standard puzzle, but it isn't
+        let puzzle = CurriedProgram {
+            program: 1,
+            args: StandardArgs::new(PublicKey::default()),
+        };
+        let spend = mk_spend(puzzle, ());
+        assert_eq!(typed_solution(&spend).expect("typed_solution"), None);
+    }
+
+    #[test]
+    fn test_check_curried_args() {
+        let key = SecretKey::from_seed(&[1; 32]).public_key();
+        let other_key = SecretKey::from_seed(&[2; 32]).public_key();
+        let puzzle = to_program(CurriedProgram {
+            program: Program::from(P2_DELEGATED_PUZZLE_OR_HIDDEN_PUZZLE.to_vec()),
+            args: StandardArgs::new(key),
+        });
+
+        check_curried_args(&puzzle, &[to_program(key)]).expect("check_curried_args");
+
+        let mismatch = |expected: &[Program]| {
+            check_curried_args(&puzzle, expected)
+                .unwrap_err()
+                .error_code()
+        };
+        assert_eq!(
+            mismatch(&[to_program(other_key)]),
+            ErrorCode::CurriedArgsMismatch
+        );
+        // too few or too many arguments
+        assert_eq!(mismatch(&[]), ErrorCode::CurriedArgsMismatch);
+        assert_eq!(
+            mismatch(&[to_program(key), to_program(key)]),
+            ErrorCode::CurriedArgsMismatch
+        );
+    }
+
+    #[test]
+    fn test_check_curried_args_multiple() {
+        let puzzle = to_program(CurriedProgram {
+            program: 1,
+            args: clvm_curried_args!(42, "foo", (1, 2)),
+        });
+        check_curried_args(
+            &puzzle,
+            &[to_program(42), to_program("foo"), to_program((1, 2))],
+        )
+        .expect("check_curried_args");
+        assert_eq!(
+            check_curried_args(
+                &puzzle,
+                &[to_program(42), to_program((1, 2)), to_program("foo")],
+            )
+            .unwrap_err()
+            .error_code(),
+            ErrorCode::CurriedArgsMismatch
+        );
+    }
+
+    #[test]
+    fn test_check_curried_args_not_curried() {
+        assert_eq!(
+            check_curried_args(&to_program(1), &[])
+                .unwrap_err()
+                .error_code(),
+            ErrorCode::CurriedArgsMismatch
+        );
+    }
+}
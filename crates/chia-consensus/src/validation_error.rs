@@ -1,5 +1,7 @@
+use chia_protocol::Bytes32;
 use clvmr::allocator::{Allocator, Atom, NodePtr, SExp};
 use clvmr::error::EvalErr;
+use std::fmt;
 use thiserror::Error;
 
 #[cfg(feature = "py-bindings")]
@@ -40,6 +42,9 @@ pub enum ErrorCode {
     ExtendsUnknownBlock,
     CoinbaseNotYetSpendable,
     /// Renamed from "BlockCostExceedsMax" since it's more generic than that.
+    /// The cost of the generator's bytes exceeded the cost limit. This is also
+    /// the legacy code that ExecutionCostExceeded and ConditionCostExceeded
+    /// are reported as, and the name ValidationErr messages print for them
     CostExceeded,
     BadAdditionRoot,
     BadRemovalRoot,
@@ -165,30 +170,141 @@ pub enum ErrorCode {
     MessageNotSentOrReceived,
     ComplexGeneratorReceived,
     TooManySpends,
+    ReserveFeeNotCanonical,
+    ReserveFeeOutOfRange,
+    FeeTooHigh,
+    InvalidHint,
+    BlockedPuzzleHash,
+    AnnouncementTooLarge,
+    SpendBeforeCreation,
+    ReserveFeeTooHigh,
+    DeserializationCostExceeded,
+    TooManyOutputs,
+    CurriedArgsMismatch,
+    UnsupportedCostTable,
+    SpendBundleTooLarge,
+    SolutionTooLarge,
+    MalformedSpendList,
+    AggSigMeCoinIdMismatch,
+    Timeout,
+    CanonicalRoundtripMismatch,
+    PuzzleHashRateExceeded,
+    TooManyAnnouncementsForCost,
+    /// Like CostExceeded, but running the CLVM program exceeded the cost limit
+    ExecutionCostExceeded,
+    /// Like CostExceeded, but the cost of the conditions exceeded the cost
+    /// limit
+    ConditionCostExceeded,
+    ImpossibleTimeLockConstraints,
 }
 
 #[derive(Debug, PartialEq, Error)]
 pub enum ValidationErr {
-    #[error("validation error: {0:?}")]
+    #[error("validation error: {}", LegacyName(*.0))]
     Err(ErrorCode),
     #[error("eval error: {0}")]
     Eval(EvalErr),
+    /// a validation error caused by a specific spend. The coin ID is the coin
+    /// being spent.
+    #[error("validation error: {} (coin {})", LegacyName(*.0), .1)]
+    Spend(ErrorCode, Bytes32),
+    /// the fee of the spend bundle is lower than the sum of its RESERVE_FEE
+    /// conditions. This is a ReserveFeeConditionFailed error.
+    #[error("validation error: ReserveFeeConditionFailed (reserved {required}, fee {available})")]
+    ReserveFee { required: u64, available: u64 },
+}
+
+// prints the name of an error code, in ValidationErr messages. The phase-
+// specific cost errors are printed as CostExceeded, like before they were
+// split out of it, since callers match on the message
+struct LegacyName(ErrorCode);
+
+impl fmt::Display for LegacyName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ErrorCode::ExecutionCostExceeded | ErrorCode::ConditionCostExceeded => {
+                write!(f, "{:?}", ErrorCode::CostExceeded)
+            }
+            code => write!(f, "{code:?}"),
+        }
+    }
 }
 
 impl From<EvalErr> for ValidationErr {
     fn from(e: EvalErr) -> Self {
         match e {
-            EvalErr::CostExceeded => ValidationErr::Err(ErrorCode::CostExceeded),
+            EvalErr::CostExceeded => ValidationErr::Err(ErrorCode::ExecutionCostExceeded),
             other => ValidationErr::Eval(other),
         }
     }
 }
 
+// EvalErr isn't Clone, so neither can ValidationErr derive it
+impl Clone for ValidationErr {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Err(code) => Self::Err(*code),
+            Self::Eval(e) => Self::Eval(clone_eval_err(e)),
+            Self::Spend(code, coin_id) => Self::Spend(*code, *coin_id),
+            Self::ReserveFee {
+                required,
+                available,
+            } => Self::ReserveFee {
+                required: *required,
+                available: *available,
+            },
+        }
+    }
+}
+
+fn clone_eval_err(e: &EvalErr) -> EvalErr {
+    match e {
+        EvalErr::SerializationError => EvalErr::SerializationError,
+        EvalErr::SerializationBackreferenceError => EvalErr::SerializationBackreferenceError,
+        EvalErr::OutOfMemory => EvalErr::OutOfMemory,
+        EvalErr::PathIntoAtom => EvalErr::PathIntoAtom,
+        EvalErr::TooManyPairs => EvalErr::TooManyPairs,
+        EvalErr::TooManyAtoms => EvalErr::TooManyAtoms,
+        EvalErr::CostExceeded => EvalErr::CostExceeded,
+        EvalErr::UnknownSoftforkExtension => EvalErr::UnknownSoftforkExtension,
+        EvalErr::SoftforkCostMismatch => EvalErr::SoftforkCostMismatch,
+        EvalErr::InternalError(n, msg) => EvalErr::InternalError(*n, msg.clone()),
+        EvalErr::Raise(n) => EvalErr::Raise(*n),
+        EvalErr::InvalidNilTerminator(n) => EvalErr::InvalidNilTerminator(*n),
+        EvalErr::DivisionByZero(n) => EvalErr::DivisionByZero(*n),
+        EvalErr::ValueStackLimitReached(n) => EvalErr::ValueStackLimitReached(*n),
+        EvalErr::EnvironmentStackLimitReached(n) => EvalErr::EnvironmentStackLimitReached(*n),
+        EvalErr::ShiftTooLarge(n) => EvalErr::ShiftTooLarge(*n),
+        EvalErr::Reserved(n) => EvalErr::Reserved(*n),
+        EvalErr::Invalid(n) => EvalErr::Invalid(*n),
+        EvalErr::Unimplemented(n) => EvalErr::Unimplemented(*n),
+        EvalErr::InvalidOpArg(n, msg) => EvalErr::InvalidOpArg(*n, msg.clone()),
+        EvalErr::InvalidAllocArg(n, msg) => EvalErr::InvalidAllocArg(*n, msg.clone()),
+        EvalErr::BLSPairingIdentityFailed(n) => EvalErr::BLSPairingIdentityFailed(*n),
+        EvalErr::BLSVerifyFailed(n) => EvalErr::BLSVerifyFailed(*n),
+        EvalErr::Secp256Failed(n) => EvalErr::Secp256Failed(*n),
+        EvalErr::SoftforkStackDepthExceeded => EvalErr::SoftforkStackDepthExceeded,
+    }
+}
+
 impl ValidationErr {
     pub fn error_code(&self) -> ErrorCode {
         match self {
-            ValidationErr::Err(code) => *code,
+            ValidationErr::Err(code) | ValidationErr::Spend(code, _) => *code,
             ValidationErr::Eval(_) => ErrorCode::GeneratorRuntimeError,
+            ValidationErr::ReserveFee { .. } => ErrorCode::ReserveFeeConditionFailed,
+        }
+    }
+
+    /// The number of mojos the fee falls short of the reserved fee, if this is
+    /// a ReserveFee error. This is 0 if `available` covers `required`.
+    pub fn reserve_fee_shortfall(&self) -> Option<u64> {
+        match self {
+            ValidationErr::ReserveFee {
+                required,
+                available,
+            } => Some(required.saturating_sub(*available)),
+            _ => None,
         }
     }
 }
@@ -219,17 +335,19 @@ pub fn first(a: &Allocator, n: NodePtr) -> Result<NodePtr, ValidationErr> {
     }
 }
 
-// from chia-blockchain/chia/util/errors.py
+// from chia-blockchain/chia/util/errors.py. The errors chia-blockchain has no
+// code for are reported under the closest existing one, their own codes are
+// returned by ErrorCode::code()
 impl From<ErrorCode> for u32 {
     fn from(err: ErrorCode) -> u32 {
         match err {
             ErrorCode::Unknown => 1,
             ErrorCode::InvalidBlockSolution => 2,
-            ErrorCode::InvalidCoinSolution => 3,
+            ErrorCode::InvalidCoinSolution | ErrorCode::CurriedArgsMismatch => 3,
             ErrorCode::DuplicateOutput => 4,
             ErrorCode::DoubleSpend => 5,
             ErrorCode::UnknownUnspent => 6,
-            ErrorCode::BadAggregateSignature => 7,
+            ErrorCode::BadAggregateSignature | ErrorCode::AggSigMeCoinIdMismatch => 7,
             ErrorCode::WrongPuzzleHash => 8,
             ErrorCode::BadFarmerCoinAmount => 9,
             ErrorCode::InvalidCondition
@@ -240,7 +358,9 @@ impl From<ErrorCode> for u32 {
             | ErrorCode::InvalidMessage
             | ErrorCode::InvalidCoinAmount
             | ErrorCode::InvalidCoinAnnouncement
-            | ErrorCode::InvalidPuzzleAnnouncement => 10,
+            | ErrorCode::InvalidPuzzleAnnouncement
+            | ErrorCode::InvalidHint
+            | ErrorCode::AnnouncementTooLarge => 10,
             ErrorCode::AssertMyCoinIdFailed => 11,
             ErrorCode::AssertPuzzleAnnouncementFailed | ErrorCode::AssertCoinAnnouncementFailed => {
                 12
@@ -249,13 +369,16 @@ impl From<ErrorCode> for u32 {
             ErrorCode::AssertHeightAbsoluteFailed => 14,
             ErrorCode::AssertSecondsAbsoluteFailed => 15,
             ErrorCode::CoinAmountExceedsMaximum => 16,
-            ErrorCode::SexpError => 17,
+            ErrorCode::SexpError | ErrorCode::CanonicalRoundtripMismatch => 17,
             ErrorCode::InvalidFeeLowFee => 18,
             ErrorCode::MempoolConflict => 19,
             ErrorCode::MintingCoin => 20,
             ErrorCode::ExtendsUnknownBlock => 21,
             ErrorCode::CoinbaseNotYetSpendable => 22,
-            ErrorCode::CostExceeded => 23,
+            ErrorCode::CostExceeded
+            | ErrorCode::ExecutionCostExceeded
+            | ErrorCode::ConditionCostExceeded
+            | ErrorCode::DeserializationCostExceeded => 23,
             ErrorCode::BadAdditionRoot => 24,
             ErrorCode::BadRemovalRoot => 25,
             ErrorCode::InvalidPospaceHash => 26,
@@ -280,7 +403,10 @@ impl From<ErrorCode> for u32 {
             ErrorCode::InvalidPoolTarget => 45,
             ErrorCode::InvalidCoinbaseParent => 46,
             ErrorCode::InvalidFeesCoinParent => 47,
-            ErrorCode::ReserveFeeConditionFailed => 48,
+            ErrorCode::ReserveFeeConditionFailed
+            | ErrorCode::ReserveFeeNotCanonical
+            | ErrorCode::ReserveFeeOutOfRange
+            | ErrorCode::ReserveFeeTooHigh => 48,
             ErrorCode::NotBlockButHasData => 49,
             ErrorCode::IsTransactionBlockButNoData => 50,
             ErrorCode::InvalidPrevBlockHash => 51,
@@ -347,7 +473,10 @@ impl From<ErrorCode> for u32 {
             ErrorCode::AssertMyParentIdFailed => 114,
             ErrorCode::AssertMyPuzzleHashFailed => 115,
             ErrorCode::AssertMyAmountFailed => 116,
-            ErrorCode::GeneratorRuntimeError => 117,
+            ErrorCode::GeneratorRuntimeError
+            | ErrorCode::MalformedSpendList
+            | ErrorCode::Timeout
+            | ErrorCode::UnsupportedCostTable => 117,
             ErrorCode::InvalidCostResult => 118,
             ErrorCode::InvalidTransactionsGeneratorRefsRoot => 119,
             ErrorCode::FutureGeneratorRefs => 120,
@@ -356,7 +485,15 @@ impl From<ErrorCode> for u32 {
             ErrorCode::InvalidFeeTooCloseToZero => 123,
             ErrorCode::CoinAmountNegative => 124,
             ErrorCode::InternalProtocolError => 125,
-            ErrorCode::InvalidSpendBundle => 126,
+            ErrorCode::InvalidSpendBundle
+            | ErrorCode::FeeTooHigh
+            | ErrorCode::BlockedPuzzleHash
+            | ErrorCode::SpendBeforeCreation
+            | ErrorCode::TooManyOutputs
+            | ErrorCode::SpendBundleTooLarge
+            | ErrorCode::SolutionTooLarge
+            | ErrorCode::PuzzleHashRateExceeded
+            | ErrorCode::ImpossibleTimeLockConstraints => 126,
             ErrorCode::FailedGettingGeneratorMultiprocessing => 127,
             ErrorCode::AssertBeforeSecondsAbsoluteFailed => 128,
             ErrorCode::AssertBeforeSecondsRelativeFailed => 129,
@@ -374,7 +511,7 @@ impl From<ErrorCode> for u32 {
             ErrorCode::EphemeralRelativeCondition => 141,
             ErrorCode::InvalidSoftforkCondition => 142,
             ErrorCode::InvalidSoftforkCost => 143,
-            ErrorCode::TooManyAnnouncements => 144,
+            ErrorCode::TooManyAnnouncements | ErrorCode::TooManyAnnouncementsForCost => 144,
             ErrorCode::InvalidMessageMode => 145,
             ErrorCode::InvalidCoinId => 146,
             ErrorCode::MessageNotSentOrReceived => 147,
@@ -384,6 +521,447 @@ impl From<ErrorCode> for u32 {
     }
 }
 
+impl ErrorCode {
+    /// A number identifying this error, that never changes across releases.
+    /// Unlike the enum discriminant, it's not affected by adding variants. For
+    /// most errors, this is the same as the chia-blockchain error code (i.e.
+    /// `u32::from()`). The errors chia-blockchain reports under a shared code
+    /// (INVALID_CONDITION, ASSERT_ANNOUNCE_CONSUMED_FAILED and
+    /// BLOCK_COST_EXCEEDS_MAX) have their own codes, from 1001 and up. The
+    /// errors chia-blockchain doesn't have (yet) are numbered from 150, and
+    /// reported to it under an existing code.
+    pub fn code(self) -> u32 {
+        match self {
+            ErrorCode::InvalidConditionOpcode => 1001,
+            ErrorCode::InvalidParentId => 1002,
+            ErrorCode::InvalidPuzzleHash => 1003,
+            ErrorCode::InvalidPublicKey => 1004,
+            ErrorCode::InvalidMessage => 1005,
+            ErrorCode::InvalidCoinAmount => 1006,
+            ErrorCode::InvalidCoinAnnouncement => 1007,
+            ErrorCode::InvalidPuzzleAnnouncement => 1008,
+            ErrorCode::AssertCoinAnnouncementFailed => 1009,
+            ErrorCode::ExecutionCostExceeded => 1010,
+            ErrorCode::ConditionCostExceeded => 1011,
+            ErrorCode::ReserveFeeNotCanonical => 150,
+            ErrorCode::ReserveFeeOutOfRange => 151,
+            ErrorCode::FeeTooHigh => 152,
+            ErrorCode::InvalidHint => 153,
+            ErrorCode::BlockedPuzzleHash => 154,
+            ErrorCode::AnnouncementTooLarge => 155,
+            ErrorCode::SpendBeforeCreation => 156,
+            ErrorCode::ReserveFeeTooHigh => 157,
+            ErrorCode::DeserializationCostExceeded => 158,
+            ErrorCode::TooManyOutputs => 159,
+            ErrorCode::CurriedArgsMismatch => 160,
+            // 161 belonged to an error that was merged into DuplicateOutput
+            // before it was released
+            ErrorCode::UnsupportedCostTable => 162,
+            ErrorCode::SpendBundleTooLarge => 163,
+            ErrorCode::SolutionTooLarge => 164,
+            ErrorCode::MalformedSpendList => 165,
+            ErrorCode::AggSigMeCoinIdMismatch => 166,
+            ErrorCode::Timeout => 167,
+            ErrorCode::CanonicalRoundtripMismatch => 168,
+            ErrorCode::PuzzleHashRateExceeded => 169,
+            ErrorCode::TooManyAnnouncementsForCost => 170,
+            ErrorCode::ImpossibleTimeLockConstraints => 171,
+            other => u32::from(other),
+        }
+    }
+
+    /// The error identified by `code`, as returned by code(). Returns None if
+    /// no error has this code.
+    pub fn from_code(code: u32) -> Option<ErrorCode> {
+        let err = match code {
+            1 => ErrorCode::Unknown,
+            2 => ErrorCode::InvalidBlockSolution,
+            3 => ErrorCode::InvalidCoinSolution,
+            4 => ErrorCode::DuplicateOutput,
+            5 => ErrorCode::DoubleSpend,
+            6 => ErrorCode::UnknownUnspent,
+            7 => ErrorCode::BadAggregateSignature,
+            8 => ErrorCode::WrongPuzzleHash,
+            9 => ErrorCode::BadFarmerCoinAmount,
+            10 => ErrorCode::InvalidCondition,
+            11 => ErrorCode::AssertMyCoinIdFailed,
+            12 => ErrorCode::AssertPuzzleAnnouncementFailed,
+            13 => ErrorCode::AssertHeightRelativeFailed,
+            14 => ErrorCode::AssertHeightAbsoluteFailed,
+            15 => ErrorCode::AssertSecondsAbsoluteFailed,
+            16 => ErrorCode::CoinAmountExceedsMaximum,
+            17 => ErrorCode::SexpError,
+            18 => ErrorCode::InvalidFeeLowFee,
+            19 => ErrorCode::MempoolConflict,
+            20 => ErrorCode::MintingCoin,
+            21 => ErrorCode::ExtendsUnknownBlock,
+            22 => ErrorCode::CoinbaseNotYetSpendable,
+            23 => ErrorCode::CostExceeded,
+            24 => ErrorCode::BadAdditionRoot,
+            25 => ErrorCode::BadRemovalRoot,
+            26 => ErrorCode::InvalidPospaceHash,
+            27 => ErrorCode::InvalidCoinbaseSignature,
+            28 => ErrorCode::InvalidPlotSignature,
+            29 => ErrorCode::TimestampTooFarInPast,
+            30 => ErrorCode::TimestampTooFarInFuture,
+            31 => ErrorCode::InvalidTransactionsFilterHash,
+            32 => ErrorCode::InvalidPospaceChallenge,
+            33 => ErrorCode::InvalidPospace,
+            34 => ErrorCode::InvalidHeight,
+            35 => ErrorCode::InvalidCoinbaseAmount,
+            36 => ErrorCode::InvalidMerkleRoot,
+            37 => ErrorCode::InvalidBlockFeeAmount,
+            38 => ErrorCode::InvalidWeight,
+            39 => ErrorCode::InvalidTotalIters,
+            40 => ErrorCode::BlockIsNotFinished,
+            41 => ErrorCode::InvalidNumIterations,
+            42 => ErrorCode::InvalidPot,
+            43 => ErrorCode::InvalidPotChallenge,
+            44 => ErrorCode::InvalidTransactionsGeneratorHash,
+            45 => ErrorCode::InvalidPoolTarget,
+            46 => ErrorCode::InvalidCoinbaseParent,
+            47 => ErrorCode::InvalidFeesCoinParent,
+            48 => ErrorCode::ReserveFeeConditionFailed,
+            49 => ErrorCode::NotBlockButHasData,
+            50 => ErrorCode::IsTransactionBlockButNoData,
+            51 => ErrorCode::InvalidPrevBlockHash,
+            52 => ErrorCode::InvalidTransactionsInfoHash,
+            53 => ErrorCode::InvalidFoliageBlockHash,
+            54 => ErrorCode::InvalidRewardCoins,
+            55 => ErrorCode::InvalidBlockCost,
+            56 => ErrorCode::NoEndOfSlotInfo,
+            57 => ErrorCode::InvalidPrevChallengeSlotHash,
+            58 => ErrorCode::InvalidSubEpochSummaryHash,
+            59 => ErrorCode::NoSubEpochSummaryHash,
+            60 => ErrorCode::ShouldNotMakeChallengeBlock,
+            61 => ErrorCode::ShouldMakeChallengeBlock,
+            62 => ErrorCode::InvalidChallengeChainData,
+            65 => ErrorCode::InvalidCcEosVdf,
+            66 => ErrorCode::InvalidRcEosVdf,
+            67 => ErrorCode::InvalidChallengeSlotHashRc,
+            68 => ErrorCode::InvalidPriorPointRc,
+            69 => ErrorCode::InvalidDeficit,
+            70 => ErrorCode::InvalidSubEpochSummary,
+            71 => ErrorCode::InvalidPrevSubEpochSummaryHash,
+            72 => ErrorCode::InvalidRewardChainHash,
+            73 => ErrorCode::InvalidSubEpochOverflow,
+            74 => ErrorCode::InvalidNewDifficulty,
+            75 => ErrorCode::InvalidNewSubSlotIters,
+            76 => ErrorCode::InvalidCcSpVdf,
+            77 => ErrorCode::InvalidRcSpVdf,
+            78 => ErrorCode::InvalidCcSignature,
+            79 => ErrorCode::InvalidRcSignature,
+            80 => ErrorCode::CannotMakeCcBlock,
+            81 => ErrorCode::InvalidRcSpPrevIp,
+            82 => ErrorCode::InvalidRcIpPrevIp,
+            83 => ErrorCode::InvalidIsTransactionBlock,
+            84 => ErrorCode::InvalidUrsbHash,
+            85 => ErrorCode::OldPoolTarget,
+            86 => ErrorCode::InvalidPoolSignature,
+            87 => ErrorCode::InvalidFoliageBlockPresence,
+            88 => ErrorCode::InvalidCcIpVdf,
+            89 => ErrorCode::InvalidRcIpVdf,
+            90 => ErrorCode::IpShouldBeNone,
+            91 => ErrorCode::InvalidRewardBlockHash,
+            92 => ErrorCode::InvalidMadeNonOverflowInfusions,
+            93 => ErrorCode::NoOverflowsInFirstSubSlotNewEpoch,
+            94 => ErrorCode::MempoolNotInitialized,
+            95 => ErrorCode::ShouldNotHaveIcc,
+            96 => ErrorCode::ShouldHaveIcc,
+            97 => ErrorCode::InvalidIccVdf,
+            98 => ErrorCode::InvalidIccHashCc,
+            99 => ErrorCode::InvalidIccHashRc,
+            100 => ErrorCode::InvalidIccEosVdf,
+            101 => ErrorCode::InvalidSpIndex,
+            102 => ErrorCode::TooManyBlocks,
+            103 => ErrorCode::InvalidCcChallenge,
+            104 => ErrorCode::InvalidPrefarm,
+            105 => ErrorCode::AssertSecondsRelativeFailed,
+            106 => ErrorCode::BadCoinbaseSignature,
+            108 => ErrorCode::NoTransactionsWhileSyncing,
+            109 => ErrorCode::AlreadyIncludingTransaction,
+            110 => ErrorCode::IncompatibleNetworkId,
+            111 => ErrorCode::PreSoftForkMaxGeneratorSize,
+            112 => ErrorCode::InvalidRequiredIters,
+            113 => ErrorCode::TooManyGeneratorRefs,
+            114 => ErrorCode::AssertMyParentIdFailed,
+            115 => ErrorCode::AssertMyPuzzleHashFailed,
+            116 => ErrorCode::AssertMyAmountFailed,
+            117 => ErrorCode::GeneratorRuntimeError,
+            118 => ErrorCode::InvalidCostResult,
+            119 => ErrorCode::InvalidTransactionsGeneratorRefsRoot,
+            120 => ErrorCode::FutureGeneratorRefs,
+            121 => ErrorCode::GeneratorRefHasNoGenerator,
+            122 => ErrorCode::DoubleSpendInFork,
+            123 => ErrorCode::InvalidFeeTooCloseToZero,
+            124 => ErrorCode::CoinAmountNegative,
+            125 => ErrorCode::InternalProtocolError,
+            126 => ErrorCode::InvalidSpendBundle,
+            127 => ErrorCode::FailedGettingGeneratorMultiprocessing,
+            128 => ErrorCode::AssertBeforeSecondsAbsoluteFailed,
+            129 => ErrorCode::AssertBeforeSecondsRelativeFailed,
+            130 => ErrorCode::AssertBeforeHeightAbsoluteFailed,
+            131 => ErrorCode::AssertBeforeHeightRelativeFailed,
+            132 => ErrorCode::AssertConcurrentSpendFailed,
+            133 => ErrorCode::AssertConcurrentPuzzleFailed,
+            134 => ErrorCode::ImpossibleSecondsRelativeConstraints,
+            135 => ErrorCode::ImpossibleSecondsAbsoluteConstraints,
+            136 => ErrorCode::ImpossibleHeightRelativeConstraints,
+            137 => ErrorCode::ImpossibleHeightAbsoluteConstraints,
+            138 => ErrorCode::AssertMyBirthSecondsFailed,
+            139 => ErrorCode::AssertMyBirthHeightFailed,
+            140 => ErrorCode::AssertEphemeralFailed,
+            141 => ErrorCode::EphemeralRelativeCondition,
+            142 => ErrorCode::InvalidSoftforkCondition,
+            143 => ErrorCode::InvalidSoftforkCost,
+            144 => ErrorCode::TooManyAnnouncements,
+            145 => ErrorCode::InvalidMessageMode,
+            146 => ErrorCode::InvalidCoinId,
+            147 => ErrorCode::MessageNotSentOrReceived,
+            148 => ErrorCode::ComplexGeneratorReceived,
+            149 => ErrorCode::TooManySpends,
+            150 => ErrorCode::ReserveFeeNotCanonical,
+            151 => ErrorCode::ReserveFeeOutOfRange,
+            152 => ErrorCode::FeeTooHigh,
+            153 => ErrorCode::InvalidHint,
+            154 => ErrorCode::BlockedPuzzleHash,
+            155 => ErrorCode::AnnouncementTooLarge,
+            156 => ErrorCode::SpendBeforeCreation,
+            157 => ErrorCode::ReserveFeeTooHigh,
+            158 => ErrorCode::DeserializationCostExceeded,
+            159 => ErrorCode::TooManyOutputs,
+            160 => ErrorCode::CurriedArgsMismatch,
+            // 161 is unused, see code()
+            162 => ErrorCode::UnsupportedCostTable,
+            163 => ErrorCode::SpendBundleTooLarge,
+            164 => ErrorCode::SolutionTooLarge,
+            165 => ErrorCode::MalformedSpendList,
+            166 => ErrorCode::AggSigMeCoinIdMismatch,
+            167 => ErrorCode::Timeout,
+            168 => ErrorCode::CanonicalRoundtripMismatch,
+            169 => ErrorCode::PuzzleHashRateExceeded,
+            170 => ErrorCode::TooManyAnnouncementsForCost,
+            171 => ErrorCode::ImpossibleTimeLockConstraints,
+            1001 => ErrorCode::InvalidConditionOpcode,
+            1002 => ErrorCode::InvalidParentId,
+            1003 => ErrorCode::InvalidPuzzleHash,
+            1004 => ErrorCode::InvalidPublicKey,
+            1005 => ErrorCode::InvalidMessage,
+            1006 => ErrorCode::InvalidCoinAmount,
+            1007 => ErrorCode::InvalidCoinAnnouncement,
+            1008 => ErrorCode::InvalidPuzzleAnnouncement,
+            1009 => ErrorCode::AssertCoinAnnouncementFailed,
+            1010 => ErrorCode::ExecutionCostExceeded,
+            1011 => ErrorCode::ConditionCostExceeded,
+            _ => return None,
+        };
+        Some(err)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorCode::Unknown => "unknown error",
+            ErrorCode::InvalidBlockSolution => "invalid block solution",
+            ErrorCode::InvalidCoinSolution => "invalid coin solution",
+            ErrorCode::DuplicateOutput => "duplicate output",
+            ErrorCode::DoubleSpend => "double spend",
+            ErrorCode::UnknownUnspent => "unknown unspent",
+            ErrorCode::BadAggregateSignature => "bad aggregate signature",
+            ErrorCode::WrongPuzzleHash => "wrong puzzle hash",
+            ErrorCode::BadFarmerCoinAmount => "bad farmer coin amount",
+            ErrorCode::InvalidCondition => "invalid condition",
+            ErrorCode::InvalidConditionOpcode => "invalid condition opcode",
+            ErrorCode::InvalidParentId => "invalid parent ID",
+            ErrorCode::InvalidPuzzleHash => "invalid puzzle hash",
+            ErrorCode::InvalidPublicKey => "invalid public key",
+            ErrorCode::InvalidMessage => "invalid message",
+            ErrorCode::InvalidCoinAmount => "invalid coin amount",
+            ErrorCode::InvalidCoinAnnouncement => "invalid coin announcement",
+            ErrorCode::InvalidPuzzleAnnouncement => "invalid puzzle announcement",
+            ErrorCode::AssertMyCoinIdFailed => "assert my coin ID failed",
+            ErrorCode::AssertPuzzleAnnouncementFailed => "assert puzzle announcement failed",
+            ErrorCode::AssertCoinAnnouncementFailed => "assert coin announcement failed",
+            ErrorCode::AssertHeightRelativeFailed => "assert height relative failed",
+            ErrorCode::AssertHeightAbsoluteFailed => "assert height absolute failed",
+            ErrorCode::AssertSecondsAbsoluteFailed => "assert seconds absolute failed",
+            ErrorCode::CoinAmountExceedsMaximum => "coin amount exceeds maximum",
+            ErrorCode::SexpError => "invalid CLVM serialization",
+            ErrorCode::InvalidFeeLowFee => "fee too low",
+            ErrorCode::MempoolConflict => "mempool conflict",
+            ErrorCode::MintingCoin => "minting coin",
+            ErrorCode::ExtendsUnknownBlock => "extends unknown block",
+            ErrorCode::CoinbaseNotYetSpendable => "coinbase not yet spendable",
+            ErrorCode::CostExceeded => "cost exceeded",
+            ErrorCode::BadAdditionRoot => "bad addition root",
+            ErrorCode::BadRemovalRoot => "bad removal root",
+            ErrorCode::InvalidPospaceHash => "invalid proof of space hash",
+            ErrorCode::InvalidCoinbaseSignature => "invalid coinbase signature",
+            ErrorCode::InvalidPlotSignature => "invalid plot signature",
+            ErrorCode::TimestampTooFarInPast => "timestamp too far in past",
+            ErrorCode::TimestampTooFarInFuture => "timestamp too far in future",
+            ErrorCode::InvalidTransactionsFilterHash => "invalid transactions filter hash",
+            ErrorCode::InvalidPospaceChallenge => "invalid proof of space challenge",
+            ErrorCode::InvalidPospace => "invalid proof of space",
+            ErrorCode::InvalidHeight => "invalid height",
+            ErrorCode::InvalidCoinbaseAmount => "invalid coinbase amount",
+            ErrorCode::InvalidMerkleRoot => "invalid merkle root",
+            ErrorCode::InvalidBlockFeeAmount => "invalid block fee amount",
+            ErrorCode::InvalidWeight => "invalid weight",
+            ErrorCode::InvalidTotalIters => "invalid total iters",
+            ErrorCode::BlockIsNotFinished => "block is not finished",
+            ErrorCode::InvalidNumIterations => "invalid num iterations",
+            ErrorCode::InvalidPot => "invalid PoT",
+            ErrorCode::InvalidPotChallenge => "invalid PoT challenge",
+            ErrorCode::InvalidTransactionsGeneratorHash => "invalid transactions generator hash",
+            ErrorCode::InvalidPoolTarget => "invalid pool target",
+            ErrorCode::InvalidCoinbaseParent => "invalid coinbase parent",
+            ErrorCode::InvalidFeesCoinParent => "invalid fees coin parent",
+            ErrorCode::ReserveFeeConditionFailed => "reserve fee condition failed",
+            ErrorCode::NotBlockButHasData => "not block but has data",
+            ErrorCode::IsTransactionBlockButNoData => "is transaction block but no data",
+            ErrorCode::InvalidPrevBlockHash => "invalid prev block hash",
+            ErrorCode::InvalidTransactionsInfoHash => "invalid transactions info hash",
+            ErrorCode::InvalidFoliageBlockHash => "invalid foliage block hash",
+            ErrorCode::InvalidRewardCoins => "invalid reward coins",
+            ErrorCode::InvalidBlockCost => "invalid block cost",
+            ErrorCode::NoEndOfSlotInfo => "no end of slot info",
+            ErrorCode::InvalidPrevChallengeSlotHash => "invalid prev challenge slot hash",
+            ErrorCode::InvalidSubEpochSummaryHash => "invalid sub epoch summary hash",
+            ErrorCode::NoSubEpochSummaryHash => "no sub epoch summary hash",
+            ErrorCode::ShouldNotMakeChallengeBlock => "should not make challenge block",
+            ErrorCode::ShouldMakeChallengeBlock => "should make challenge block",
+            ErrorCode::InvalidChallengeChainData => "invalid challenge chain data",
+            ErrorCode::InvalidCcEosVdf => "invalid CC EOS VDF",
+            ErrorCode::InvalidRcEosVdf => "invalid RC EOS VDF",
+            ErrorCode::InvalidChallengeSlotHashRc => "invalid challenge slot hash RC",
+            ErrorCode::InvalidPriorPointRc => "invalid prior point RC",
+            ErrorCode::InvalidDeficit => "invalid deficit",
+            ErrorCode::InvalidSubEpochSummary => "invalid sub epoch summary",
+            ErrorCode::InvalidPrevSubEpochSummaryHash => "invalid prev sub epoch summary hash",
+            ErrorCode::InvalidRewardChainHash => "invalid reward chain hash",
+            ErrorCode::InvalidSubEpochOverflow => "invalid sub epoch overflow",
+            ErrorCode::InvalidNewDifficulty => "invalid new difficulty",
+            ErrorCode::InvalidNewSubSlotIters => "invalid new sub slot iters",
+            ErrorCode::InvalidCcSpVdf => "invalid CC SP VDF",
+            ErrorCode::InvalidRcSpVdf => "invalid RC SP VDF",
+            ErrorCode::InvalidCcSignature => "invalid CC signature",
+            ErrorCode::InvalidRcSignature => "invalid RC signature",
+            ErrorCode::CannotMakeCcBlock => "cannot make CC block",
+            ErrorCode::InvalidRcSpPrevIp => "invalid RC SP prev IP",
+            ErrorCode::InvalidRcIpPrevIp => "invalid RC IP prev IP",
+            ErrorCode::InvalidIsTransactionBlock => "invalid is transaction block",
+            ErrorCode::InvalidUrsbHash => "invalid URSB hash",
+            ErrorCode::OldPoolTarget => "old pool target",
+            ErrorCode::InvalidPoolSignature => "invalid pool signature",
+            ErrorCode::InvalidFoliageBlockPresence => "invalid foliage block presence",
+            ErrorCode::InvalidCcIpVdf => "invalid CC IP VDF",
+            ErrorCode::InvalidRcIpVdf => "invalid RC IP VDF",
+            ErrorCode::IpShouldBeNone => "IP should be none",
+            ErrorCode::InvalidRewardBlockHash => "invalid reward block hash",
+            ErrorCode::InvalidMadeNonOverflowInfusions => "invalid made non overflow infusions",
+            ErrorCode::NoOverflowsInFirstSubSlotNewEpoch => {
+                "no overflows in first sub slot new epoch"
+            }
+            ErrorCode::MempoolNotInitialized => "mempool not initialized",
+            ErrorCode::ShouldNotHaveIcc => "should not have ICC",
+            ErrorCode::ShouldHaveIcc => "should have ICC",
+            ErrorCode::InvalidIccVdf => "invalid ICC VDF",
+            ErrorCode::InvalidIccHashCc => "invalid ICC hash CC",
+            ErrorCode::InvalidIccHashRc => "invalid ICC hash RC",
+            ErrorCode::InvalidIccEosVdf => "invalid ICC EOS VDF",
+            ErrorCode::InvalidSpIndex => "invalid SP index",
+            ErrorCode::TooManyBlocks => "too many blocks",
+            ErrorCode::InvalidCcChallenge => "invalid CC challenge",
+            ErrorCode::InvalidPrefarm => "invalid prefarm",
+            ErrorCode::AssertSecondsRelativeFailed => "assert seconds relative failed",
+            ErrorCode::BadCoinbaseSignature => "bad coinbase signature",
+            ErrorCode::NoTransactionsWhileSyncing => "no transactions while syncing",
+            ErrorCode::AlreadyIncludingTransaction => "already including transaction",
+            ErrorCode::IncompatibleNetworkId => "incompatible network ID",
+            ErrorCode::PreSoftForkMaxGeneratorSize => "generator too large",
+            ErrorCode::InvalidRequiredIters => "invalid required iters",
+            ErrorCode::TooManyGeneratorRefs => "too many generator refs",
+            ErrorCode::AssertMyParentIdFailed => "assert my parent ID failed",
+            ErrorCode::AssertMyPuzzleHashFailed => "assert my puzzle hash failed",
+            ErrorCode::AssertMyAmountFailed => "assert my amount failed",
+            ErrorCode::GeneratorRuntimeError => "generator runtime error",
+            ErrorCode::InvalidCostResult => "invalid cost result",
+            ErrorCode::InvalidTransactionsGeneratorRefsRoot => {
+                "invalid transactions generator refs root"
+            }
+            ErrorCode::FutureGeneratorRefs => "future generator refs",
+            ErrorCode::GeneratorRefHasNoGenerator => "generator ref has no generator",
+            ErrorCode::DoubleSpendInFork => "double spend in fork",
+            ErrorCode::InvalidFeeTooCloseToZero => "invalid fee too close to zero",
+            ErrorCode::CoinAmountNegative => "coin amount negative",
+            ErrorCode::InternalProtocolError => "internal protocol error",
+            ErrorCode::InvalidSpendBundle => "invalid spend bundle",
+            ErrorCode::FailedGettingGeneratorMultiprocessing => {
+                "failed getting generator multiprocessing"
+            }
+            ErrorCode::AssertBeforeSecondsAbsoluteFailed => "assert before seconds absolute failed",
+            ErrorCode::AssertBeforeSecondsRelativeFailed => "assert before seconds relative failed",
+            ErrorCode::AssertBeforeHeightAbsoluteFailed => "assert before height absolute failed",
+            ErrorCode::AssertBeforeHeightRelativeFailed => "assert before height relative failed",
+            ErrorCode::AssertConcurrentSpendFailed => "assert concurrent spend failed",
+            ErrorCode::AssertConcurrentPuzzleFailed => "assert concurrent puzzle failed",
+            ErrorCode::ImpossibleSecondsRelativeConstraints => {
+                "impossible seconds relative constraints"
+            }
+            ErrorCode::ImpossibleSecondsAbsoluteConstraints => {
+                "impossible seconds absolute constraints"
+            }
+            ErrorCode::ImpossibleHeightRelativeConstraints => {
+                "impossible height relative constraints"
+            }
+            ErrorCode::ImpossibleHeightAbsoluteConstraints => {
+                "impossible height absolute constraints"
+            }
+            ErrorCode::AssertMyBirthSecondsFailed => "assert my birth seconds failed",
+            ErrorCode::AssertMyBirthHeightFailed => "assert my birth height failed",
+            ErrorCode::AssertEphemeralFailed => "assert ephemeral failed",
+            ErrorCode::EphemeralRelativeCondition => "ephemeral relative condition",
+            ErrorCode::InvalidSoftforkCondition => "invalid softfork condition",
+            ErrorCode::InvalidSoftforkCost => "invalid softfork cost",
+            ErrorCode::TooManyAnnouncements => "too many announcements",
+            ErrorCode::InvalidMessageMode => "invalid message mode",
+            ErrorCode::InvalidCoinId => "invalid coin ID",
+            ErrorCode::MessageNotSentOrReceived => "message not sent or received",
+            ErrorCode::ComplexGeneratorReceived => "complex generator received",
+            ErrorCode::TooManySpends => "too many spends",
+            ErrorCode::ReserveFeeNotCanonical => "reserve fee not canonical",
+            ErrorCode::ReserveFeeOutOfRange => "reserve fee out of range",
+            ErrorCode::FeeTooHigh => "fee too high",
+            ErrorCode::InvalidHint => "invalid hint",
+            ErrorCode::BlockedPuzzleHash => "blocked puzzle hash",
+            ErrorCode::AnnouncementTooLarge => "announcement too large",
+            ErrorCode::SpendBeforeCreation => "spend before creation",
+            ErrorCode::ReserveFeeTooHigh => "reserve fee too high",
+            ErrorCode::DeserializationCostExceeded => "deserialization cost exceeded",
+            ErrorCode::TooManyOutputs => "too many outputs",
+            ErrorCode::CurriedArgsMismatch => "curried args mismatch",
+            ErrorCode::UnsupportedCostTable => "unsupported cost table",
+            ErrorCode::SpendBundleTooLarge => "spend bundle too large",
+            ErrorCode::SolutionTooLarge => "solution too large",
+            ErrorCode::MalformedSpendList => "malformed spend list",
+            ErrorCode::AggSigMeCoinIdMismatch => "agg sig me coin id mismatch",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::CanonicalRoundtripMismatch => "canonical round-trip mismatch",
+            ErrorCode::PuzzleHashRateExceeded => "puzzle hash rate exceeded",
+            ErrorCode::TooManyAnnouncementsForCost => {
+                "too many announcements for the cost of the spend"
+            }
+            ErrorCode::ExecutionCostExceeded => "execution cost exceeded",
+            ErrorCode::ConditionCostExceeded => "condition cost exceeded",
+            ErrorCode::ImpossibleTimeLockConstraints => {
+                "impossible combination of relative and absolute time-locks"
+            }
+        })
+    }
+}
+
 pub fn rest(a: &Allocator, n: NodePtr) -> Result<NodePtr, ValidationErr> {
     match a.sexp(n) {
         SExp::Pair(_, right) => Ok(right),
@@ -422,3 +1000,103 @@ pub fn check_nil(a: &Allocator, n: NodePtr) -> Result<(), ValidationErr> {
         Err(ValidationErr::Err(ErrorCode::InvalidCondition))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_error_code_round_trip() {
+        let mut seen = HashSet::<ErrorCode>::new();
+        let mut messages = HashSet::<String>::new();
+        for code in 0..2000 {
+            let Some(err) = ErrorCode::from_code(code) else {
+                continue;
+            };
+            assert_eq!(err.code(), code);
+            assert!(seen.insert(err), "duplicate code {code}");
+            let msg = err.to_string();
+            assert!(!msg.is_empty());
+            assert!(messages.insert(msg), "duplicate message for {err:?}");
+        }
+        // every variant has a code. The discriminant of the last variant is
+        // one less than the number of variants
+        assert_eq!(
+            seen.len(),
+            ErrorCode::ImpossibleTimeLockConstraints as usize + 1
+        );
+    }
+
+    #[test]
+    fn test_error_code_stable() {
+        // these values must never change
+        assert_eq!(ErrorCode::Unknown.code(), 1);
+        assert_eq!(ErrorCode::InvalidCondition.code(), 10);
+        assert_eq!(ErrorCode::InvalidConditionOpcode.code(), 1001);
+        assert_eq!(ErrorCode::AssertCoinAnnouncementFailed.code(), 1009);
+        assert_eq!(ErrorCode::GeneratorRuntimeError.code(), 117);
+        assert_eq!(ErrorCode::from_code(0), None);
+        assert_eq!(ErrorCode::from_code(107), None);
+        assert_eq!(ErrorCode::CostExceeded.to_string(), "cost exceeded");
+    }
+
+    #[test]
+    fn test_chia_blockchain_codes() {
+        // every error is reported to chia-blockchain under a code its Err enum
+        // has (i.e. one of the codes from errors.py, up to TooManySpends)
+        for code in 0..2000 {
+            let Some(err) = ErrorCode::from_code(code) else {
+                continue;
+            };
+            let legacy = u32::from(err);
+            assert!(legacy <= 149, "{err:?} is reported as {legacy}");
+            assert!(ErrorCode::from_code(legacy).is_some());
+        }
+        assert_eq!(u32::from(ErrorCode::ReserveFeeNotCanonical), 48);
+        assert_eq!(u32::from(ErrorCode::ReserveFeeOutOfRange), 48);
+        assert_eq!(ErrorCode::ReserveFeeNotCanonical.code(), 150);
+        assert_eq!(ErrorCode::ReserveFeeOutOfRange.code(), 151);
+        assert_eq!(ErrorCode::from_code(161), None);
+    }
+
+    #[test]
+    fn test_cost_exceeded_legacy_code() {
+        // the phase-specific errors are reported to chia-blockchain as
+        // BLOCK_COST_EXCEEDS_MAX, but have their own stable codes
+        for err in [
+            ErrorCode::CostExceeded,
+            ErrorCode::ExecutionCostExceeded,
+            ErrorCode::ConditionCostExceeded,
+        ] {
+            assert_eq!(u32::from(err), 23);
+        }
+        // the message is the same as before the errors were split out of
+        // CostExceeded
+        for err in [
+            ValidationErr::Err(ErrorCode::CostExceeded),
+            ValidationErr::Err(ErrorCode::ExecutionCostExceeded),
+            ValidationErr::Err(ErrorCode::ConditionCostExceeded),
+            ValidationErr::from(EvalErr::CostExceeded),
+        ] {
+            assert_eq!(err.to_string(), "validation error: CostExceeded");
+        }
+        assert_eq!(
+            ValidationErr::Spend(ErrorCode::ConditionCostExceeded, Bytes32::default()).to_string(),
+            format!(
+                "validation error: CostExceeded (coin {})",
+                Bytes32::default()
+            )
+        );
+        assert_eq!(
+            ValidationErr::Err(ErrorCode::GeneratorRuntimeError).to_string(),
+            "validation error: GeneratorRuntimeError"
+        );
+        assert_eq!(ErrorCode::ExecutionCostExceeded.code(), 1010);
+        assert_eq!(ErrorCode::ConditionCostExceeded.code(), 1011);
+        assert_eq!(
+            ValidationErr::from(EvalErr::CostExceeded).error_code(),
+            ErrorCode::ExecutionCostExceeded
+        );
+    }
+}
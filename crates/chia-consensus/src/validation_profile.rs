@@ -0,0 +1,246 @@
+use crate::conditions::SpendBundleConditions;
+use crate::consensus_constants::{ConsensusConstants, MAINNET_CONSTANTS};
+use crate::flags::{ConsensusFlags, MEMPOOL_MODE};
+use crate::run_block_generator::run_block_generator2;
+use crate::spendbundle_validation::get_flags_for_height_and_constants;
+use crate::validation_error::ValidationErr;
+use chia_bls::Signature;
+use clvmr::allocator::Allocator;
+
+/// The flags, constants and cost limit to validate a block (or spend bundle)
+/// with. These are typically passed around together, and grouping them avoids
+/// mixing up e.g. the flags for one height with the constants of another
+/// network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationProfile {
+    pub flags: ConsensusFlags,
+    pub max_cost: u64,
+    pub constants: ConsensusConstants,
+}
+
+impl ValidationProfile {
+    pub fn new(flags: ConsensusFlags, max_cost: u64, constants: ConsensusConstants) -> Self {
+        Self {
+            flags,
+            max_cost,
+            constants,
+        }
+    }
+
+    /// The consensus rules for a mainnet block whose previous transaction
+    /// block is at `prev_tx_height`.
+    pub fn mainnet_consensus(prev_tx_height: u32) -> Self {
+        let flags = get_flags_for_height_and_constants(prev_tx_height, &MAINNET_CONSTANTS);
+        Self::new(
+            flags,
+            MAINNET_CONSTANTS.max_block_cost_clvm,
+            MAINNET_CONSTANTS.clone(),
+        )
+    }
+
+    /// The rules for admitting a spend bundle into the mempool on mainnet,
+    /// when the peak transaction block is at `prev_tx_height`. A spend bundle
+    /// may use at most half of the block cost.
+    pub fn mainnet_mempool(prev_tx_height: u32) -> Self {
+        let flags = get_flags_for_height_and_constants(prev_tx_height, &MAINNET_CONSTANTS);
+        Self::new(
+            flags | MEMPOOL_MODE,
+            MAINNET_CONSTANTS.max_block_cost_clvm / 2,
+            MAINNET_CONSTANTS.clone(),
+        )
+    }
+
+    /// Relaxed rules for running blocks in a local simulator, on the network
+    /// described by `constants`, whose previous transaction block is at
+    /// `prev_tx_height`. These are NOT consensus rules; a block accepted by
+    /// this profile may be invalid on a real network.
+    /// Signatures are not validated, so spends can be made without the private
+    /// keys. Time-locks (e.g. ASSERT_HEIGHT_ABSOLUTE) are not checked when
+    /// running the generator, only returned in the conditions. A simulator
+    /// relaxes them by not calling check_time_locks().
+    pub fn simulator(prev_tx_height: u32, constants: ConsensusConstants) -> Self {
+        let flags = get_flags_for_height_and_constants(prev_tx_height, &constants);
+        Self::new(
+            flags | ConsensusFlags::DONT_VALIDATE_SIGNATURE,
+            constants.max_block_cost_clvm,
+            constants,
+        )
+    }
+}
+
+/// Like `run_block_generator2()`, but with the flags, cost limit and
+/// constants taken from `profile`.
+pub fn run_block_generator2_with_profile<GenBuf: AsRef<[u8]>, I: IntoIterator<Item = GenBuf>>(
+    program: &[u8],
+    block_refs: I,
+    profile: &ValidationProfile,
+    signature: &Signature,
+) -> Result<(Allocator, SpendBundleConditions), ValidationErr>
+where
+    <I as IntoIterator>::IntoIter: DoubleEndedIterator,
+{
+    run_block_generator2(
+        program,
+        block_refs,
+        profile.max_cost,
+        profile.flags,
+        signature,
+        None,
+        &profile.constants,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus_constants::TEST_CONSTANTS;
+    use crate::opcodes::AGG_SIG_UNSAFE;
+    use crate::owned_conditions::OwnedSpendBundleConditions;
+    use crate::solution_generator::solution_generator;
+    use crate::validation_error::ErrorCode;
+    use chia_bls::{SecretKey, sign};
+    use chia_protocol::{Bytes, Coin};
+    use clvm_traits::ToClvm;
+    use clvm_utils::tree_hash_atom;
+    use clvmr::serde::node_to_bytes;
+    use rstest::rstest;
+    use std::fs::read_to_string;
+
+    // the order of created coins is not deterministic, so they are sorted
+    fn owned(a: &Allocator, conds: SpendBundleConditions) -> OwnedSpendBundleConditions {
+        let mut ret = OwnedSpendBundleConditions::from(a, conds);
+        for spend in &mut ret.spends {
+            spend.create_coin.sort();
+        }
+        ret
+    }
+
+    #[rstest]
+    #[case("block-225758")]
+    #[case("block-834768")]
+    #[case("create-coin-hint")]
+    #[case("many-create-coin")]
+    fn test_run_block_generator2_with_profile(#[case] name: &str) {
+        let filename = format!("../../generator-tests/{name}.txt");
+        let test_file = read_to_string(filename).expect("test file not found");
+        let (generator, _expected) = test_file.split_once('\n').expect("invalid test file");
+        let generator = hex::decode(generator).expect("invalid hex encoded generator");
+
+        let mut block_refs = Vec::<Vec<u8>>::new();
+        let filename = format!("../../generator-tests/{name}.env");
+        if let Ok(env_hex) = read_to_string(&filename) {
+            block_refs.push(hex::decode(env_hex).expect("hex decode env-file"));
+        }
+
+        let flags = ConsensusFlags::DONT_VALIDATE_SIGNATURE;
+        let (a, expected) = run_block_generator2(
+            &generator,
+            &block_refs,
+            11_000_000_000,
+            flags,
+            &Signature::default(),
+            None,
+            &TEST_CONSTANTS,
+        )
+        .expect("run_block_generator2");
+        let expected = owned(&a, expected);
+
+        let profile = ValidationProfile::new(flags, 11_000_000_000, TEST_CONSTANTS.clone());
+        let (a, conds) = run_block_generator2_with_profile(
+            &generator,
+            &block_refs,
+            &profile,
+            &Signature::default(),
+        )
+        .expect("run_block_generator2_with_profile");
+        assert_eq!(owned(&a, conds), expected);
+
+        // the cost limit is taken from the profile
+        let profile = ValidationProfile::new(flags, expected.cost - 1, TEST_CONSTANTS.clone());
+        assert!(
+            run_block_generator2_with_profile(
+                &generator,
+                &block_refs,
+                &profile,
+                &Signature::default(),
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        let height = MAINNET_CONSTANTS.soft_fork8_height;
+        let consensus = ValidationProfile::mainnet_consensus(height);
+        let mempool = ValidationProfile::mainnet_mempool(height);
+
+        let flags = get_flags_for_height_and_constants(height, &MAINNET_CONSTANTS);
+        assert_eq!(consensus.flags, flags);
+        assert_eq!(mempool.flags, flags | MEMPOOL_MODE);
+        assert!(!consensus.flags.contains(ConsensusFlags::NO_UNKNOWN_CONDS));
+        assert!(mempool.flags.contains(ConsensusFlags::NO_UNKNOWN_CONDS));
+
+        assert_eq!(consensus.max_cost, 11_000_000_000);
+        assert_eq!(mempool.max_cost, 5_500_000_000);
+        assert_eq!(consensus.constants, MAINNET_CONSTANTS);
+        assert_eq!(mempool.constants, MAINNET_CONSTANTS);
+    }
+
+    #[test]
+    fn test_simulator() {
+        let sk = SecretKey::from_seed(&[1; 32]);
+        let msg = Bytes::from(b"foobar".to_vec());
+
+        // a single spend of the identity puzzle, with an AGG_SIG_UNSAFE
+        // condition
+        let mut a = Allocator::new();
+        let conditions = [(AGG_SIG_UNSAFE, (sk.public_key(), (msg.clone(), ())))]
+            .to_clvm(&mut a)
+            .unwrap();
+        let solution = node_to_bytes(&a, conditions).unwrap();
+        let puzzle_hash = tree_hash_atom(&[1]).into();
+        let coin = Coin::new([0x11; 32].into(), puzzle_hash, 1);
+        let generator =
+            solution_generator([(coin, &[1_u8][..], solution.as_slice())]).expect("generator");
+        let block_refs: &[&[u8]] = &[];
+
+        let height = TEST_CONSTANTS.hard_fork2_height;
+        let consensus = ValidationProfile::new(
+            get_flags_for_height_and_constants(height, &TEST_CONSTANTS),
+            TEST_CONSTANTS.max_block_cost_clvm,
+            TEST_CONSTANTS.clone(),
+        );
+        let simulator = ValidationProfile::simulator(height, TEST_CONSTANTS.clone());
+        assert_eq!(
+            simulator.flags,
+            consensus.flags | ConsensusFlags::DONT_VALIDATE_SIGNATURE
+        );
+        assert_eq!(simulator.max_cost, consensus.max_cost);
+
+        // with the correct signature, both accept the block
+        let signature = sign(&sk, &msg);
+        for profile in [&consensus, &simulator] {
+            run_block_generator2_with_profile(&generator, block_refs, profile, &signature)
+                .expect("valid block");
+        }
+
+        // with an invalid signature, only the simulator accepts it
+        let err = run_block_generator2_with_profile(
+            &generator,
+            block_refs,
+            &consensus,
+            &Signature::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::BadAggregateSignature);
+        let (a, conds) = run_block_generator2_with_profile(
+            &generator,
+            block_refs,
+            &simulator,
+            &Signature::default(),
+        )
+        .expect("simulator");
+        let conds = owned(&a, conds);
+        assert_eq!(conds.agg_sig_unsafe, vec![(sk.public_key(), msg)]);
+    }
+}
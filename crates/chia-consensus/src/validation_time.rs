@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+/// A linear model of how long it takes to validate a block (or spend bundle)
+/// given its cost and the number of signatures it requires. The estimate is:
+///
+/// `overhead + cost / cost_per_microsecond + num_signatures * per_signature`
+///
+/// The default coefficients are rough figures for a typical machine. For
+/// accurate estimates they should be calibrated against the `run-generator`
+/// benchmark on the target hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValidationTimeModel {
+    /// fixed time spent on every validation, regardless of its size
+    pub overhead: Duration,
+    /// how much cost is executed per microsecond
+    pub cost_per_microsecond: u64,
+    /// time spent validating each signature (i.e. computing its pairing)
+    pub per_signature: Duration,
+}
+
+impl Default for ValidationTimeModel {
+    fn default() -> Self {
+        Self {
+            overhead: Duration::from_micros(100),
+            cost_per_microsecond: 7_000,
+            per_signature: Duration::from_micros(700),
+        }
+    }
+}
+
+impl ValidationTimeModel {
+    /// Estimates the time it takes to validate something with the specified
+    /// cost and number of signatures, without running it.
+    pub fn estimate(&self, cost: u64, num_signatures: usize) -> Duration {
+        let execution = Duration::from_micros(cost / self.cost_per_microsecond.max(1));
+        self.overhead.saturating_add(execution).saturating_add(
+            self.per_signature
+                .saturating_mul(u32::try_from(num_signatures).unwrap_or(u32::MAX)),
+        )
+    }
+}
+
+/// Estimates the time it takes to validate a block with the specified cost
+/// and number of signatures, using the default `ValidationTimeModel`.
+pub fn estimate_validation_time(cost: u64, num_signatures: usize) -> Duration {
+    ValidationTimeModel::default().estimate(cost, num_signatures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0, 0, 100)]
+    #[case(7_000, 0, 101)]
+    #[case(0, 1, 800)]
+    #[case(11_000_000_000, 10, 1_578_528)]
+    fn test_estimate_validation_time(
+        #[case] cost: u64,
+        #[case] num_signatures: usize,
+        #[case] expected_micros: u64,
+    ) {
+        assert_eq!(
+            estimate_validation_time(cost, num_signatures),
+            Duration::from_micros(expected_micros)
+        );
+    }
+
+    #[test]
+    fn test_estimate_is_monotonic() {
+        let base = estimate_validation_time(1_000_000_000, 10);
+        assert!(estimate_validation_time(2_000_000_000, 10) > base);
+        assert!(estimate_validation_time(1_000_000_000, 11) > base);
+        assert!(estimate_validation_time(2_000_000_000, 11) > base);
+    }
+
+    #[test]
+    fn test_custom_model() {
+        let model = ValidationTimeModel {
+            overhead: Duration::ZERO,
+            cost_per_microsecond: 1_000,
+            per_signature: Duration::from_millis(1),
+        };
+        assert_eq!(
+            model.estimate(5_000_000, 3),
+            Duration::from_millis(5) + Duration::from_millis(3)
+        );
+        // a zero rate is treated as 1, rather than dividing by zero
+        let model = ValidationTimeModel {
+            cost_per_microsecond: 0,
+            ..model
+        };
+        assert_eq!(model.estimate(10, 0), Duration::from_micros(10));
+    }
+
+    #[test]
+    fn test_saturates() {
+        let model = ValidationTimeModel {
+            overhead: Duration::MAX,
+            ..Default::default()
+        };
+        assert_eq!(model.estimate(u64::MAX, usize::MAX), Duration::MAX);
+    }
+}
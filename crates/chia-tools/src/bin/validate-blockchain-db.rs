@@ -1,7 +1,7 @@
 use clap::Parser;
 
 use chia_consensus::consensus_constants::ConsensusConstants;
-use chia_consensus::consensus_constants::TEST_CONSTANTS;
+use chia_consensus::consensus_constants::MAINNET_CONSTANTS;
 use chia_consensus::flags::ConsensusFlags;
 use chia_consensus::run_block_generator::{run_block_generator, run_block_generator2};
 use chia_protocol::{Bytes32, Coin};
@@ -49,10 +49,6 @@ struct Args {
     testnet: bool,
 }
 
-const MAINNET_CONSTANTS: ConsensusConstants = ConsensusConstants {
-    testnet: false,
-    ..TEST_CONSTANTS
-};
 const TESTNET11_CONSTANTS: ConsensusConstants = ConsensusConstants {
     agg_sig_me_additional_data: Bytes32::new(hex!(
         "37a90eb5185a9c4439a91ddc98bbadce7b4feba060d50116a067de66bf236615"
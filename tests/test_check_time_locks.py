@@ -131,6 +131,8 @@ def make_test_conds(
         555,
         666,
         999999,
+        0,
+        False,
     )
 
 
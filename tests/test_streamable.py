@@ -109,6 +109,8 @@ def test_hash_spend_bundle_conditions() -> None:
         555,
         666,
         999999,
+        11111,
+        False,
     )
     a2 = SpendBundleConditions(
         [],
@@ -127,6 +129,8 @@ def test_hash_spend_bundle_conditions() -> None:
         333,
         444,
         888888,
+        11111,
+        False,
     )
     b = hash(a1)
     c = hash(a2)
@@ -468,6 +472,8 @@ def test_json_spend_bundle_conditions() -> None:
         555,
         666,
         999999,
+        11111,
+        False,
     )
 
     assert a.to_json_dict() == {
@@ -487,6 +493,8 @@ def test_json_spend_bundle_conditions() -> None:
         "num_atoms": 555,
         "num_pairs": 666,
         "heap_size": 999999,
+        "deserialization_cost": 11111,
+        "simple_generator": False,
     }
 
 
@@ -509,6 +517,8 @@ def test_from_json_spend_bundle_conditions() -> None:
         555,
         666,
         999999,
+        11111,
+        False,
     )
     b = SpendBundleConditions.from_json_dict(
         {
@@ -529,6 +539,8 @@ def test_from_json_spend_bundle_conditions() -> None:
             "num_atoms": 555,
             "num_pairs": 666,
             "heap_size": 999999,
+            "deserialization_cost": 11111,
+            "simple_generator": False,
         }
     )
     assert a == b
@@ -589,6 +601,8 @@ def test_copy_spend_bundle_conditions() -> None:
         555,
         666,
         999999,
+        11111,
+        False,
     )
     b = copy.copy(a)
 
@@ -71,6 +71,8 @@ def make_spend_bundle_conditions(
         555,
         666,
         999999,
+        11111,
+        False,
     )
 
 
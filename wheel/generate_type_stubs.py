@@ -348,6 +348,8 @@ def additions_and_removals(
     program: ReadableBuffer, block_refs: Sequence[ReadableBuffer], flags: int, constants: ConsensusConstants
 ) -> tuple[list[tuple[Coin, Optional[bytes]]], list[tuple[bytes32, Coin]]]: ...
 
+def flag_names(bits: int) -> list[str]: ...
+
 def check_time_locks(
     removal_coin_records: dict[bytes32, CoinRecord],
     bundle_conds: SpendBundleConditions,
@@ -435,10 +437,12 @@ def compute_plot_group_id_v2(strength: uint8, plot_pk: G1Element, pool_pk: G1Ele
 
 NO_UNKNOWN_CONDS: int = ...
 STRICT_ARGS_COUNT: int = ...
+STRICT_HINTS: int = ...
 LIMIT_HEAP: int = ...
 ENABLE_GC: int = ...
 ENABLE_KECCAK_OPS_OUTSIDE_GUARD: int = ...
 MEMPOOL_MODE: int = ...
+STRICT_CANONICAL: int = ...
 DONT_VALIDATE_SIGNATURE: int = ...
 COMPUTE_FINGERPRINT: int = ...
 COST_CONDITIONS: int = ...
@@ -684,6 +688,8 @@ def quality_string_from_proof(plot_id: bytes32, size: int, plot_strength: int, p
             "num_atoms: int",
             "num_pairs: int",
             "heap_size: int",
+            "deserialization_cost: int",
+            "simple_generator: bool",
         ],
     )
 
@@ -38,6 +38,8 @@ def additions_and_removals(
     program: ReadableBuffer, block_refs: Sequence[ReadableBuffer], flags: int, constants: ConsensusConstants
 ) -> tuple[list[tuple[Coin, Optional[bytes]]], list[tuple[bytes32, Coin]]]: ...
 
+def flag_names(bits: int) -> list[str]: ...
+
 def check_time_locks(
     removal_coin_records: dict[bytes32, CoinRecord],
     bundle_conds: SpendBundleConditions,
@@ -125,10 +127,12 @@ def compute_plot_group_id_v2(strength: uint8, plot_pk: G1Element, pool_pk: G1Ele
 
 NO_UNKNOWN_CONDS: int = ...
 STRICT_ARGS_COUNT: int = ...
+STRICT_HINTS: int = ...
 LIMIT_HEAP: int = ...
 ENABLE_GC: int = ...
 ENABLE_KECCAK_OPS_OUTSIDE_GUARD: int = ...
 MEMPOOL_MODE: int = ...
+STRICT_CANONICAL: int = ...
 DONT_VALIDATE_SIGNATURE: int = ...
 COMPUTE_FINGERPRINT: int = ...
 COST_CONDITIONS: int = ...
@@ -493,6 +497,8 @@ class SpendBundleConditions:
     num_atoms: int
     num_pairs: int
     heap_size: int
+    deserialization_cost: int
+    simple_generator: bool
     def __new__(
         cls,
         spends: Sequence[SpendConditions],
@@ -510,7 +516,9 @@ class SpendBundleConditions:
         condition_cost: int,
         num_atoms: int,
         num_pairs: int,
-        heap_size: int
+        heap_size: int,
+        deserialization_cost: int,
+        simple_generator: bool
     ) -> Self: ...
     def __hash__(self) -> int: ...
     def __repr__(self) -> str: ...
@@ -544,7 +552,9 @@ class SpendBundleConditions:
         condition_cost: Union[ int, _Unspec] = _Unspec(),
         num_atoms: Union[ int, _Unspec] = _Unspec(),
         num_pairs: Union[ int, _Unspec] = _Unspec(),
-        heap_size: Union[ int, _Unspec] = _Unspec()) -> SpendBundleConditions: ...
+        heap_size: Union[ int, _Unspec] = _Unspec(),
+        deserialization_cost: Union[ int, _Unspec] = _Unspec(),
+        simple_generator: Union[ bool, _Unspec] = _Unspec()) -> SpendBundleConditions: ...
     def truncate(self, field: str, length: int) -> None: ...
 
 @final
@@ -8,7 +8,7 @@ use chia_consensus::build_compressed_block::BlockBuilder;
 use chia_consensus::build_interned_block::InternedBlockBuilder;
 use chia_consensus::check_time_locks::py_check_time_locks;
 use chia_consensus::consensus_constants::ConsensusConstants;
-use chia_consensus::flags::{ConsensusFlags, MEMPOOL_MODE};
+use chia_consensus::flags::{ConsensusFlags, MEMPOOL_MODE, STRICT_CANONICAL, flag_names};
 use chia_consensus::merkle_set::compute_merkle_set_root as compute_merkle_root_impl;
 use chia_consensus::merkle_tree::{MerkleSet, validate_merkle_proof};
 use chia_consensus::owned_conditions::{OwnedSpendBundleConditions, OwnedSpendConditions};
@@ -209,11 +209,11 @@ pub fn get_puzzle_and_solution_for_coin<'a>(
                 result,
                 &Coin::new(find_parent, find_ph, find_amount),
             ) {
-                Err(ValidationErr::Err(_)) => Err(EvalErr::InvalidOpArg(
+                Err(ValidationErr::Eval(e)) => Err(e),
+                Err(_) => Err(EvalErr::InvalidOpArg(
                     NodePtr::NIL,
                     "coin not found".to_string(),
                 )),
-                Err(ValidationErr::Eval(e)) => Err(e),
                 Ok(pair) => Ok(pair),
             }
         })
@@ -260,11 +260,11 @@ pub fn get_puzzle_and_solution_for_coin2<'a>(
             let Reduction(_cost, result) =
                 run_program(&mut allocator, dialect, generator, args, max_cost)?;
             match parse_puzzle_solution(&allocator, result, find_coin) {
-                Err(ValidationErr::Err(_)) => Err(EvalErr::InvalidOpArg(
+                Err(ValidationErr::Eval(e)) => Err(e),
+                Err(_) => Err(EvalErr::InvalidOpArg(
                     NodePtr::NIL,
                     "coin not found".to_string(),
                 )),
-                Err(ValidationErr::Eval(e)) => Err(e),
                 Ok(pair) => Ok(pair),
             }
         })
@@ -863,12 +863,15 @@ pub fn chia_rs(py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(compute_plot_group_id_v2, m)?)?;
 
     // flags affecting consensus
+    m.add_function(wrap_pyfunction!(flag_names, m)?)?;
     m.add("NO_UNKNOWN_CONDS", ConsensusFlags::NO_UNKNOWN_CONDS.bits())?;
     m.add(
         "STRICT_ARGS_COUNT",
         ConsensusFlags::STRICT_ARGS_COUNT.bits(),
     )?;
+    m.add("STRICT_HINTS", ConsensusFlags::STRICT_HINTS.bits())?;
     m.add("MEMPOOL_MODE", MEMPOOL_MODE.bits())?;
+    m.add("STRICT_CANONICAL", STRICT_CANONICAL.bits())?;
     m.add(
         "DONT_VALIDATE_SIGNATURE",
         ConsensusFlags::DONT_VALIDATE_SIGNATURE.bits(),